@@ -15,7 +15,8 @@ pub enum AppState {
     // ShowingResult
 }
 fn main() {
-    let gnc_app_plugin = surveyor_gnc::SurveyorGNC::new();
+    let config = surveyor_types::config::load_config();
+    let gnc_app_plugin = surveyor_gnc::SurveyorGNC::from_config(&config).expect("Invalid FSW configuration");
     let mut app = App::new();
     app.add_plugins(gnc_app_plugin)
         .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
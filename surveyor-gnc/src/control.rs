@@ -1,34 +1,29 @@
 
 use bevy_ecs::prelude::*;
 use nalgebra as na;
-use surveyor_types::config::ThrusterConfig;
+use hifitime::Epoch;
+use surveyor_types::config::{AttitudeControllerConfig, MagnetorquerConfig, RcsModulatorConfig, RcsModulatorType, ReactionWheelConfig, ThrusterConfig};
 
-use crate::{guidance::AttitudeTarget, navigation::AttitudeEstimatorOutput};
+use crate::optimization::solve_lp;
+use crate::{clock::SystemClock, guidance::AttitudeTarget, navigation::AttitudeEstimatorOutput, sensors::MagnetometerOutput};
 
 #[derive(Debug, Component)]
 pub struct ControlAllocator{
     pub use_rcs: bool,
-    pub rcs_controller: Option<RCSController>,
-    pub vernier_attitude_controller: Option<VernierAttitudeController>,
+    /// Use reaction wheels for attitude control (RCS is then only used for momentum unloading)
+    pub use_reaction_wheels: bool,
+    /// Unload the reaction wheels with the magnetorquers instead of the RCS
+    pub use_magnetorquers: bool,
+    /// Use the vernier engines for attitude control while they are firing
+    pub use_verniers: bool,
 }
 impl Default for ControlAllocator {
     fn default() -> Self {
         Self {
             use_rcs: true,
-            rcs_controller: None,
-            vernier_attitude_controller: None,
-        }
-    }
-}
-impl ControlAllocator {
-    pub fn new(rcs_controller: Option<RCSController>,
-        _tvc_controller: Option<TVCController>,
-        vernier_attitude_controller: Option<VernierAttitudeController>) -> Self
-    {
-        Self {
-            use_rcs: true,
-            rcs_controller,
-            vernier_attitude_controller,
+            use_reaction_wheels: false,
+            use_magnetorquers: false,
+            use_verniers: false,
         }
     }
 }
@@ -36,47 +31,142 @@ impl ControlAllocator {
 /* RCS */
 
 /// RCS Controller
+///
+/// The thrusters can only push, so the duty cycles are found with a linear program that minimizes
+/// the (absolute) torque and force errors plus the propellant used, with each duty cycle limited
+/// to [0, 1]. Requests that the thrusters cannot meet are matched as closely as possible.
 #[derive(Debug, Component)]
 pub struct RCSController {
-    /// Pseudo-Inverse of distribution matrix for the RCS thrusters in body frame
-    /// Each column of the distribution matrix is a thruster direction
-    /// Maps a N-dimensional thrust vector to a 3-dimensional torque vector
-    /// The inverse maps a 3-dimensional torque vector to a N-dimensional thrust vector
-    pub distribution_matrix_inv: na::MatrixXx3<f64>,
+    /// Torque in body frame produced by each thruster firing continuously (one column per thruster)
+    pub torque_matrix: na::Matrix3xX<f64>,
+    /// Force in body frame produced by each thruster firing continuously
+    pub force_matrix: na::Matrix3xX<f64>,
     /// Maximum thrust of each RCS thruster in Newtons
     pub max_thrusts: Vec<f64>,
+    /// Failed thrusters are disabled and the remaining thrusters are used instead (see
+    /// `set_thruster_enabled`)
+    pub thruster_enabled: Vec<bool>,
+    /// Cost of one newton of thrust relative to one N m (or N) of torque (or force) error
+    pub propellant_weight: f64,
+    /// Largest torque about each body axis with the enabled thrusters (see `axis_torque_limits`)
+    axis_torque_limits: na::Vector3<f64>,
 }
 
 impl RCSController {
     pub fn new(config: &Vec<ThrusterConfig>) -> Self {
         let num_thrusters = config.len();
-        let mut distribution_matrix = na::Matrix3xX::zeros(num_thrusters);
-        let mut column_index = 0;
+        let mut torque_matrix = na::Matrix3xX::zeros(num_thrusters);
+        let mut force_matrix = na::Matrix3xX::zeros(num_thrusters);
         let mut max_thrusts = Vec::new();
-        for thruster in config.iter() {
+        for (column_index, thruster) in config.iter().enumerate() {
             let thrust_b = thruster.geometry.q_cf2b.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0));
-            let thrust_b = na::Unit::new_normalize(thrust_b);
-            let thrust = thruster.max_thrust;
-
-            let torque_dir_b: na::Matrix<f64, na::Const<3>, na::Const<1>, na::ArrayStorage<f64, 3, 1>> = thruster.geometry.cf_offset_com_b.cross(&thrust_b);
-            distribution_matrix.set_column(column_index, &torque_dir_b);
-            max_thrusts.push(thrust);
-            column_index += 1;
+            let force_b = na::Unit::new_normalize(thrust_b).into_inner() * thruster.max_thrust;
+            torque_matrix.set_column(column_index, &thruster.geometry.cf_offset_com_b.cross(&force_b));
+            force_matrix.set_column(column_index, &force_b);
+            max_thrusts.push(thruster.max_thrust);
         }
-        let distribution_matrix_inv = distribution_matrix.pseudo_inverse(1e-6).expect("Failed to compute pseudo-inverse");
-        Self {
-            distribution_matrix_inv,
+        let mut controller = Self {
+            torque_matrix,
+            force_matrix,
             max_thrusts,
+            thruster_enabled: vec![true; num_thrusters],
+            propellant_weight: 1e-3,
+            axis_torque_limits: na::Vector3::zeros(),
+        };
+        controller.axis_torque_limits = controller.compute_axis_torque_limits();
+        controller
+    }
+    /// Enables or disables (e.g. after a failure) the thruster at the given index
+    pub fn set_thruster_enabled(&mut self, thruster: usize, enabled: bool) -> Result<(), String> {
+        let thruster_enabled = self.thruster_enabled.get_mut(thruster)
+            .ok_or_else(|| format!("RCS thruster id {} not found", thruster))?;
+        if *thruster_enabled != enabled {
+            *thruster_enabled = enabled;
+            self.axis_torque_limits = self.compute_axis_torque_limits();
+        }
+        Ok(())
+    }
+    /// Computes the duty cycle of each thruster for the requested torque and, optionally, force
+    /// (both in body frame). The net force is left free if no force is requested.
+    pub fn allocate(&self, torque_b: &na::Vector3<f64>, force_b: Option<&na::Vector3<f64>>) -> Vec<f64> {
+        let num_thrusters = self.max_thrusts.len();
+        let num_axes = if force_b.is_some() { 6 } else { 3 };
+        // Variables are the duty cycles followed by the positive and negative parts of the error
+        // on each axis: D u - e+ + e- = request
+        let mut a = na::DMatrix::zeros(num_axes, num_thrusters + 2 * num_axes);
+        a.view_mut((0, 0), (3, num_thrusters)).copy_from(&self.torque_matrix);
+        let mut request = na::DVector::zeros(num_axes);
+        request.fixed_rows_mut::<3>(0).copy_from(torque_b);
+        if let Some(force_b) = force_b {
+            a.view_mut((3, 0), (3, num_thrusters)).copy_from(&self.force_matrix);
+            request.fixed_rows_mut::<3>(3).copy_from(force_b);
+        }
+        a.view_mut((0, num_thrusters), (num_axes, num_axes)).fill_diagonal(-1.0);
+        a.view_mut((0, num_thrusters + num_axes), (num_axes, num_axes)).fill_diagonal(1.0);
+
+        let cost = na::DVector::from_iterator(num_thrusters + 2 * num_axes,
+            self.max_thrusts.iter().map(|thrust| thrust * self.propellant_weight)
+                .chain(std::iter::repeat_n(1.0, 2 * num_axes)));
+        let upper = self.thruster_enabled.iter().map(|&enabled| if enabled { 1.0 } else { 0.0 })
+            .chain(std::iter::repeat_n(f64::INFINITY, 2 * num_axes))
+            .collect::<Vec<_>>();
+        match solve_lp(&cost, &a, &request, &upper) {
+            Ok(solution) => solution.rows(0, num_thrusters).iter().map(|duty_cycle| duty_cycle.clamp(0.0, 1.0)).collect(),
+            Err(err) => {
+                log::error!("RCS allocation failed: {}", err);
+                vec![0.0; num_thrusters]
+            }
         }
     }
+    /// Torque in body frame produced by the thrusters at the given duty cycles
+    pub fn torque_b(&self, duty_cycles: &[f64]) -> na::Vector3<f64> {
+        &self.torque_matrix * na::DVector::from_column_slice(duty_cycles)
+    }
+    /// Largest torque that the enabled thrusters can produce in either direction about each body
+    /// axis. Only recomputed when a thruster is enabled or disabled.
+    pub fn axis_torque_limits(&self) -> na::Vector3<f64> {
+        self.axis_torque_limits
+    }
+    fn compute_axis_torque_limits(&self) -> na::Vector3<f64> {
+        // A request that no combination of thrusters can meet
+        let saturating_torque = self.torque_matrix.column_iter().map(|column| column.norm()).sum::<f64>() + 1.0;
+        na::Vector3::from_fn(|axis, _| {
+            [1.0, -1.0].iter().map(|sign| {
+                let request = na::Vector3::ith(axis, sign * saturating_torque);
+                sign * self.torque_b(&self.allocate(&request, None))[axis]
+            }).fold(f64::INFINITY, f64::min).max(0.0)
+        })
+    }
+    /// On-time (s) of each thruster that produces the pulses on each body axis (given as signed
+    /// on-times). Each axis pulse fires the thrusters that produce the largest torque about the
+    /// axis, and the on-times of the thrusters used by several axes add up.
+    pub fn pulse_on_times(&self, axis_on_times: &na::Vector3<f64>, axis_torque_limits: &na::Vector3<f64>, period: f64) -> Vec<f64> {
+        let mut on_times = vec![0.0; self.max_thrusts.len()];
+        for axis in 0..3 {
+            if axis_on_times[axis] == 0.0 {
+                continue;
+            }
+            let request = na::Vector3::ith(axis, axis_on_times[axis].signum() * axis_torque_limits[axis]);
+            for (on_time, duty_cycle) in on_times.iter_mut().zip(self.allocate(&request, None)) {
+                *on_time += duty_cycle * axis_on_times[axis].abs();
+            }
+        }
+        on_times.iter().map(|on_time| on_time.min(period)).collect()
+    }
 }
 impl Default for RCSController {
     fn default() -> Self {
         // TODO: Get rid of this and configure using a config struct
-        Self {
-            distribution_matrix_inv: na::MatrixXx3::identity(3),
-            max_thrusts: vec![1.0, 1.0, 1.0]
-        }
+        let mut controller = Self {
+            torque_matrix: na::Matrix3xX::identity(3),
+            force_matrix: na::Matrix3xX::zeros(3),
+            max_thrusts: vec![1.0, 1.0, 1.0],
+            thruster_enabled: vec![true; 3],
+            propellant_weight: 1e-3,
+            axis_torque_limits: na::Vector3::zeros(),
+        };
+        controller.axis_torque_limits = controller.compute_axis_torque_limits();
+        controller
     }
 }
 
@@ -84,12 +174,16 @@ impl Default for RCSController {
 pub struct RCSTorqueRequest {
     /// Torque vector in body frame
     pub torque_b: na::Vector3<f64>,
+    /// Force vector in body frame to be produced along with the torque (the net force of the
+    /// thrusters is unconstrained if None)
+    pub force_b: Option<na::Vector3<f64>>,
 }
 
 impl Default for RCSTorqueRequest {
     fn default() -> Self {
         Self {
             torque_b: na::Vector3::zeros(),
+            force_b: None,
         }
     }
 }
@@ -99,14 +193,114 @@ impl Default for RCSTorqueRequest {
 pub struct RCSControllerOutput {
     /// Thrust vector in body frame
     pub duty_cycles: Vec<f64>,
+    /// On-time (s) of each thruster from the start of the control period when the thrusters are
+    /// pulsed by the modulator (the duty cycles are then the fraction of the period that each
+    /// thruster is on)
+    pub on_times: Option<Vec<f64>>,
 }
 
 impl Default for RCSControllerOutput {
     fn default() -> Self {
         Self {
             duty_cycles: Vec::new(),
+            on_times: None,
+        }
+    }
+}
+
+/// Number of steps used to simulate the modulator over each control period
+const MODULATOR_STEPS_PER_PERIOD: usize = 20;
+
+/// Converts the continuous RCS torque demand about each body axis into on/off thruster pulses
+/// using a pulse-width pulse-frequency modulator or a Schmitt trigger. The modulator is simulated
+/// over the control period, so pulses can be shorter than the period. Pulses last at least the
+/// minimum pulse width (even if that spans several control periods).
+#[derive(Debug, Component)]
+pub struct RcsModulator {
+    pub config: RcsModulatorConfig,
+    /// State of the PWPF filter on each axis
+    filter: na::Vector3<f64>,
+    /// Trigger output on each axis (-1, 0 or 1)
+    trigger: na::Vector3<f64>,
+    /// How long the current pulse on each axis has been on (s)
+    pulse_time: na::Vector3<f64>,
+    last_time: Option<Epoch>,
+    /// Length of the last control period, which is used for the next one (s)
+    period: f64,
+}
+
+impl RcsModulator {
+    pub fn new(config: RcsModulatorConfig) -> Self {
+        Self {
+            config,
+            filter: na::Vector3::zeros(),
+            trigger: na::Vector3::zeros(),
+            pulse_time: na::Vector3::zeros(),
+            last_time: None,
+            period: 0.0,
         }
     }
+    pub fn reset(&mut self) {
+        self.filter = na::Vector3::zeros();
+        self.trigger = na::Vector3::zeros();
+        self.pulse_time = na::Vector3::zeros();
+        self.last_time = None;
+        self.period = 0.0;
+    }
+    /// Length of the control period that the last pulses were computed for (s)
+    pub fn period(&self) -> f64 {
+        self.period
+    }
+    /// Signed on-time (s) about each body axis over the next control period for the given torque
+    /// demand. The demand is normalized by the torque that the thrusters can produce about each
+    /// axis. Nothing is fired until the length of the control period is known.
+    pub fn update(&mut self, torque_b: &na::Vector3<f64>, axis_torque_limits: &na::Vector3<f64>, time: Epoch) -> na::Vector3<f64> {
+        self.period = match self.last_time.replace(time) {
+            Some(last_time) if time > last_time => (time - last_time).to_seconds(),
+            Some(last_time) if time < last_time => {
+                // Start over if time goes backwards
+                self.reset();
+                self.last_time = Some(time);
+                0.0
+            }
+            _ => 0.0,
+        };
+        let mut on_times = na::Vector3::zeros();
+        if self.period <= 0.0 {
+            return on_times;
+        }
+        let dt = self.period / MODULATOR_STEPS_PER_PERIOD as f64;
+        for axis in 0..3 {
+            let limit = axis_torque_limits[axis];
+            let demand = if limit > 0.0 { (torque_b[axis] / limit).clamp(-1.0, 1.0) } else { 0.0 };
+            let on_threshold = self.config.on_threshold.0[axis];
+            let off_threshold = self.config.off_threshold.0[axis];
+            // Exact discretization of the first-order filter
+            let decay = (-dt / self.config.filter_time_constant.0[axis]).exp();
+            for _ in 0..MODULATOR_STEPS_PER_PERIOD {
+                let input = match self.config.modulator_type {
+                    RcsModulatorType::Pwpf => {
+                        let filter_input = self.config.filter_gain.0[axis] * (demand - self.trigger[axis]);
+                        self.filter[axis] = decay * self.filter[axis] + (1.0 - decay) * filter_input;
+                        self.filter[axis]
+                    }
+                    RcsModulatorType::Schmitt => demand,
+                };
+                if self.trigger[axis] == 0.0 {
+                    if input.abs() >= on_threshold {
+                        self.trigger[axis] = input.signum();
+                        self.pulse_time[axis] = 0.0;
+                    }
+                } else if input * self.trigger[axis] <= off_threshold
+                    && self.pulse_time[axis] >= self.config.min_pulse_width - 1e-9 {
+                    self.trigger[axis] = 0.0;
+                }
+                on_times[axis] += self.trigger[axis] * dt;
+                self.pulse_time[axis] += self.trigger[axis].abs() * dt;
+            }
+        }
+        on_times
+    }
 }
 
 #[derive(Debug, Event)]
@@ -122,137 +316,192 @@ impl Default for AttitudeTorqueRequest {
     }
 }
 
+/// PID attitude controller. The attitude error is turned into a rate-limited body rate target,
+/// which is tracked by a PI rate loop with a torque limit. The integrator is frozen on axes where
+/// the torque is saturated (anti-windup) and reset when the kind of target changes.
+#[derive(Debug, Component)]
+pub struct AttitudeController {
+    pub config: AttitudeControllerConfig,
+    /// Integrated body rate error (rad)
+    pub integral_b: na::Vector3<f64>,
+    last_time: Option<Epoch>,
+    last_target: Option<std::mem::Discriminant<AttitudeTarget>>,
+}
+impl Default for AttitudeController {
+    fn default() -> Self {
+        Self::new(AttitudeControllerConfig::default())
+    }
+}
+impl AttitudeController {
+    pub fn new(config: AttitudeControllerConfig) -> Self {
+        Self {
+            config,
+            integral_b: na::Vector3::zeros(),
+            last_time: None,
+            last_target: None,
+        }
+    }
+    pub fn reset(&mut self) {
+        self.integral_b = na::Vector3::zeros();
+        self.last_time = None;
+    }
+    /// Changes the gains and limits. The integrator is reset.
+    pub fn set_config(&mut self, config: AttitudeControllerConfig) {
+        self.config = config;
+        self.reset();
+    }
+    /// Body rate commanded for an attitude error given as the rotation vector (body frame) from
+    /// the current to the target attitude
+    fn rate_target(&self, error_b: na::Vector3<f64>) -> na::Vector3<f64> {
+        if error_b.norm() < self.config.attitude_deadband {
+            return na::Vector3::zeros();
+        }
+        let max_rate = self.config.max_rate.0;
+        self.config.attitude_gain.0.component_mul(&error_b).zip_map(&max_rate, |omega, max| omega.clamp(-max, max))
+    }
+    /// Torque needed to track the target with the current attitude and body rates
+    pub fn update(&mut self, target: &AttitudeTarget, estimate: &AttitudeEstimatorOutput, time: Epoch) -> na::Vector3<f64> {
+        let target_kind = std::mem::discriminant(target);
+        if self.last_target != Some(target_kind) {
+            self.reset();
+            self.last_target = Some(target_kind);
+        }
+        let dt = match self.last_time.replace(time) {
+            Some(last_time) if time >= last_time => (time - last_time).to_seconds(),
+            // Start over if time goes backwards
+            Some(_) => { self.integral_b = na::Vector3::zeros(); 0.0 },
+            None => 0.0,
+        };
+        let target_omega_b = match target {
+            AttitudeTarget::None => return na::Vector3::zeros(),
+            AttitudeTarget::Align{ align_with_b, align_to_i } => {
+                let target_vec_b = estimate.q_i2b.inverse_transform_vector(&align_to_i.0);
+                let error_b = na::UnitQuaternion::rotation_between(&align_with_b.0, &target_vec_b)
+                    .map(|q_err| q_err.scaled_axis())
+                    // Pointing the opposite way: turn half a revolution about any perpendicular axis
+                    .unwrap_or_else(|| {
+                        let axis = align_with_b.cross(&na::Vector3::x());
+                        let axis = if axis.norm() > 1e-6 { axis } else { align_with_b.cross(&na::Vector3::y()) };
+                        axis.normalize() * std::f64::consts::PI
+                    });
+                self.rate_target(error_b)
+            },
+            AttitudeTarget::Attitude(target_q_i2b) => {
+                let q_err = estimate.q_i2b.inverse() * target_q_i2b;
+                self.rate_target(q_err.scaled_axis())
+            },
+            AttitudeTarget::BodyRate(target_omega_b) => *target_omega_b,
+        };
+        let rate_deadband = &self.config.rate_deadband.0;
+        let omega_error_b = (target_omega_b - estimate.omega_b).zip_map(rate_deadband, |error, deadband| {
+            if error.abs() < deadband { 0.0 } else { error }
+        });
+
+        let integral_torque = |integral_b: &na::Vector3<f64>| {
+            self.config.integral_gain.0.component_mul(integral_b)
+                .zip_map(&self.config.max_integral_torque.0, |torque, max| torque.clamp(-max, max))
+        };
+        let rate_torque = self.config.rate_gain.0.component_mul(&omega_error_b);
+        let unsaturated = rate_torque + integral_torque(&self.integral_b);
+        let max_torque = self.config.max_torque.0;
+        // Anti-windup: only integrate on axes where the torque is not saturated in the same direction
+        // as the error
+        for i in 0..3 {
+            let saturated = unsaturated[i].abs() >= max_torque[i] && unsaturated[i] * omega_error_b[i] > 0.0;
+            if !saturated {
+                self.integral_b[i] += omega_error_b[i] * dt;
+            }
+        }
+        // Keep the integrator within the range that the integral torque limit can use
+        for i in 0..3 {
+            let gain = self.config.integral_gain.0[i];
+            if gain != 0.0 {
+                let max_integral = self.config.max_integral_torque.0[i] / gain.abs();
+                self.integral_b[i] = self.integral_b[i].clamp(-max_integral, max_integral);
+            }
+        }
+        (rate_torque + integral_torque(&self.integral_b)).zip_map(&max_torque, |torque, max| torque.clamp(-max, max))
+    }
+}
+
 pub fn update_attitude_controller(
     mut attitude_target_reader: EventReader<AttitudeTarget>,
     mut torque_request_query: EventWriter<AttitudeTorqueRequest>,
     mut attitude_estimate: EventReader<AttitudeEstimatorOutput>,
+    mut controller_query: Query<&mut AttitudeController>,
+    clock: Res<SystemClock>,
 )
 {
+    let mut controller = controller_query.single_mut();
     if let Some(sensor_data) = attitude_estimate.read().last()
     {
         let attitude_target = attitude_target_reader.read().last().unwrap_or(&AttitudeTarget::None);
-        let torque_request = match attitude_target {
-            AttitudeTarget::None => {
-                AttitudeTorqueRequest::default()
-            },
-            AttitudeTarget::Align{ align_with_b, align_to_i } => {
-                // Implement alignment control
-
-                let target_vec_b = sensor_data.q_i2b.transform_vector(&align_to_i);
-                let axis = align_with_b.cross(&target_vec_b);
-                let angle = align_with_b.angle(&target_vec_b);
-
-                if angle.abs() < 1e-4
-                {
-                    AttitudeTorqueRequest::default()
-                }else {
-                    const K_P: f64 = 5.0;
-
-                    const MAX_OMEGA_B_PER_AXIS: f64 = 0.2; // rad/s
-                    let target_omega_b = - axis * angle * K_P;
-                    let target_omega_b = target_omega_b.map(|omega| omega.clamp(-MAX_OMEGA_B_PER_AXIS, MAX_OMEGA_B_PER_AXIS));
-
-                    // screen_print!("Target omega_b: {:.2?}", target_omega_b);
-                    // screen_print!("Angle: {} deg", angle.to_degrees());
-                    let omega_b = sensor_data.omega_b;
-                    let omega_error_b = target_omega_b - omega_b;
-                    const DEADBAND_B: f64 = 1e-4; // rad/s
-
-                    if omega_error_b.norm() < DEADBAND_B {
-                        AttitudeTorqueRequest::default()
-                    }else{
-                        const K_D: f64 = 10.0;
-                        let torque_b = K_D * omega_error_b;
-
-                        AttitudeTorqueRequest{
-                            torque_b,
-                        }
-                    }
-
-                }
-            },
-            AttitudeTarget::Attitude(target_q_i2b) => {
-                // Implement quaternion feedback control
-
-                let q_i2b = sensor_data.q_i2b;
-                let q_err = q_i2b.inverse() * target_q_i2b;
-
-                if let Some(axis) = q_err.axis()
-                {
-                    let axis = axis.into_inner();
-                    let angle = q_err.angle();
-
-                    const K_P: f64 = 1.0;
-                    const MAX_OMEGA_B_PER_AXIS: f64 = 0.1; // rad/s
-                    let target_omega_b = - axis * angle * K_P;
-                    let target_omega_b = target_omega_b.map(|omega| omega.clamp(-MAX_OMEGA_B_PER_AXIS, MAX_OMEGA_B_PER_AXIS));
-
-                    // screen_print!("Target omega_b: {:.2?}", target_omega_b);
-                    // screen_print!("Angle: {} deg", angle.to_degrees());
-                    let omega_b = sensor_data.omega_b;
-                    let omega_error_b = target_omega_b - omega_b;
-                    const DEADBAND_B: f64 = 1e-4; // rad/s
-
-                    if omega_error_b.norm() < DEADBAND_B {
-                        AttitudeTorqueRequest::default()
-                    }else{
-                        const K_D: f64 = 10.0;
-                        let torque_b = K_D * omega_error_b;
-
-                        AttitudeTorqueRequest{
-                            torque_b,
-                        }
-                    }
-                }else{
-                    AttitudeTorqueRequest::default()
-                }
-            }
-            AttitudeTarget::BodyRate(target_omega_b) => {
-                // Implement body rate feedback control
-                let omega_b = sensor_data.omega_b;
-
-                let error = target_omega_b - omega_b;
-                // TODO: Get deadband and gain from config
-                const DEADBAND_B: f64 = 1e-4; // rad/s
-                if error.norm() < DEADBAND_B {
-                    AttitudeTorqueRequest::default()
-                }
-                else {
-                    // Compute torque to bring omega_b to zero
-                    const K_P: f64 = 10.0;
-                    AttitudeTorqueRequest{
-                        torque_b: K_P * error,
-                    }
-                }
-            }
-        };
-        torque_request_query.send(torque_request);
+        let torque_b = controller.update(attitude_target, sensor_data, clock.time);
+        torque_request_query.send(AttitudeTorqueRequest{ torque_b });
     }
 }
 
 pub fn update_control_allocator(
     control_allocator_query: Query<&ControlAllocator>,
+    mut rw_query: Query<&mut ReactionWheelController>,
     mut torque_request_reader: EventReader<AttitudeTorqueRequest>,
     mut rcs_torque_request_writer: EventWriter<RCSTorqueRequest>,
-    _tvc_torque_request_writer: EventWriter<TVCTorqueRequest>,
+    mut rw_torque_request_writer: EventWriter<ReactionWheelTorqueRequest>,
+    mut mtq_torque_request_writer: EventWriter<MagnetorquerTorqueRequest>,
+    mtq_query: Query<&MagnetorquerController>,
+    vernier_query: Query<&VernierAttitudeController>,
+    mut vernier_torque_request_writer: EventWriter<VernierTorqueRequest>,
 )
 {
     // The control allocator gets configured elsewhere
     let control_allocator = control_allocator_query.single();
     torque_request_reader.read().last().map(
         |torque_request|{
-            // if let Some(_control_allocator) = control_allocator.rcs_controller.as_ref() {
-            if control_allocator.use_rcs {
-                // Pass through the torque request to the RCS controller
-                let rcs_torque_request = RCSTorqueRequest {
+            // The vernier engines take over attitude control while they are firing
+            let verniers_firing = vernier_query.get_single().map(|vernier| vernier.is_firing()).unwrap_or(false);
+            if control_allocator.use_verniers && verniers_firing {
+                vernier_torque_request_writer.send(VernierTorqueRequest {
                     torque_b: torque_request.torque_b,
-                };
-                rcs_torque_request_writer.send(rcs_torque_request);
+                });
+                return;
             }
-            if let Some(_vernier_attitude_controller) = control_allocator.vernier_attitude_controller.as_ref() {
-                // Pass through the torque request to the TVC controller
-                // tvc_torque_request_writer.send(tvc_torque_request);
-                todo!("Not implemented yet");
+            match rw_query.get_single_mut() {
+                Ok(mut rw_controller) if control_allocator.use_reaction_wheels => {
+                    // The RCS (or the magnetorquers) dumps the momentum stored in the wheels while
+                    // the wheels absorb the unloading torque on top of the attitude control torque
+                    let unloading_torque_b = rw_controller.update_momentum_unloading();
+                    let absorbed_torque_b = if control_allocator.use_magnetorquers {
+                        mtq_torque_request_writer.send(MagnetorquerTorqueRequest {
+                            torque_b: unloading_torque_b,
+                        });
+                        // Only the torque the magnetorquers can actually produce is absorbed
+                        mtq_query.get_single().map(|mtq_controller| {
+                            mtq_controller.torque_b(&mtq_controller.allocate_torque_request(&unloading_torque_b))
+                        }).unwrap_or_default()
+                    } else if control_allocator.use_rcs {
+                        rcs_torque_request_writer.send(RCSTorqueRequest {
+                            torque_b: unloading_torque_b,
+                            force_b: None,
+                        });
+                        unloading_torque_b
+                    } else {
+                        na::Vector3::zeros()
+                    };
+                    rw_torque_request_writer.send(ReactionWheelTorqueRequest {
+                        torque_b: torque_request.torque_b - absorbed_torque_b,
+                    });
+                },
+                _ => {
+                    // if let Some(_control_allocator) = control_allocator.rcs_controller.as_ref() {
+                    if control_allocator.use_rcs {
+                        // Pass through the torque request to the RCS controller
+                        let rcs_torque_request = RCSTorqueRequest {
+                            torque_b: torque_request.torque_b,
+                            force_b: None,
+                        };
+                        rcs_torque_request_writer.send(rcs_torque_request);
+                    }
+                }
             }
         }
     );
@@ -261,122 +510,326 @@ pub fn update_control_allocator(
 
 pub fn update_rcs_controller(
     mut rcs_controller_input_reader: EventReader<RCSTorqueRequest>,
-    mut query: Query<(&RCSController, &mut RCSControllerOutput)>,
+    mut query: Query<(&RCSController, &mut RCSControllerOutput, Option<&mut RcsModulator>)>,
     mut rcs_output_writer: EventWriter<RCSControllerOutput>,
+    clock: Res<SystemClock>,
 ) {
-    let (rcs_controller, mut output) = query.single_mut();
+    let (rcs_controller, mut output, modulator) = query.single_mut();
     if let Some(rcs_controller_input) = rcs_controller_input_reader.read().last()
     {
-        let duty_cycles =&rcs_controller.distribution_matrix_inv * rcs_controller_input.torque_b;
-        // Clip duty cycles to [0,1]
-        let duty_cycles = duty_cycles.map(|duty_cycle| duty_cycle.clamp(0.0, 1.0));
-        output.duty_cycles = duty_cycles.as_slice().to_vec();
+        // The modulator pulses the thrusters about each body axis, which cannot produce a force
+        if modulator.is_some() && rcs_controller_input.force_b.is_some() {
+            log::error!("RCS force requests cannot be pulsed by the modulator. Rejecting the request.");
+            return;
+        }
+        let duty_cycles = rcs_controller.allocate(&rcs_controller_input.torque_b, rcs_controller_input.force_b.as_ref());
+        match modulator {
+            // The modulator pulses the thrusters to produce the allocated torque on average
+            Some(mut modulator) => {
+                let axis_torque_limits = rcs_controller.axis_torque_limits();
+                let axis_on_times = modulator.update(&rcs_controller.torque_b(&duty_cycles), &axis_torque_limits, clock.time);
+                let period = modulator.period();
+                let on_times = rcs_controller.pulse_on_times(&axis_on_times, &axis_torque_limits, period);
+                output.duty_cycles = on_times.iter().map(|on_time| if period > 0.0 { on_time / period } else { 0.0 }).collect();
+                output.on_times = Some(on_times);
+            }
+            None => {
+                output.duty_cycles = duty_cycles;
+                output.on_times = None;
+            }
+        }
         rcs_output_writer.send(output.clone());
     }
 }
 
-/* Vernier Engines and TVC */
+/* Reaction Wheels */
+
+/// Parameters for dumping the momentum stored in the reaction wheels using the RCS
+#[derive(Debug, Clone)]
+pub struct MomentumUnloading {
+    /// Unloading starts when the wheel momentum exceeds this value (N-m-s)
+    pub start_threshold: f64,
+    /// Unloading stops once the wheel momentum drops below this value (N-m-s)
+    pub stop_threshold: f64,
+    /// Gain mapping the wheel momentum to the unloading torque (1/s)
+    pub gain: f64,
+    /// Whether the unloading is in progress
+    pub active: bool,
+}
+impl Default for MomentumUnloading {
+    fn default() -> Self {
+        Self {
+            start_threshold: 1.0,
+            stop_threshold: 0.1,
+            gain: 0.05,
+            active: false,
+        }
+    }
+}
+
+/// Reaction Wheel Controller
 #[derive(Debug, Component)]
-pub struct TVCController {
-    // Matrix with 3 rows and N columns (for N thrusters)
-    // Each column contains the direction of a thruster in body frame
+pub struct ReactionWheelController {
+    /// Spin axes of the wheels in body frame (one column per wheel)
     pub distribution_matrix: na::Matrix3xX<f64>,
-    pub max_angles: Vec<f64>,
-    pub current_angles: Vec<f64>,
-}
-
-impl TVCController {
-    pub fn new(thrusters: Vec<ThrusterConfig>) -> Self {
-        // Find number of thrusters with TVC
-        let num_thrusters = thrusters.iter().filter(|thruster| thruster.tvc.is_some()).count();
-
-        // Fill distribution matrix with zeros
-        let mut distribution_matrix = na::Matrix3xX::zeros(num_thrusters);
-
-        // Each column represents the torque generated by a gimballing the thruster
-        // We do not care about torque from nominal position of thruster here
-        let mut column_index = 0;
-        let mut max_angles = Vec::new();
-        for thruster in thrusters.iter() {
-            if let Some(tvc) = thruster.tvc.as_ref() {
-                let gimbal_cf = tvc.axis_cf.as_ref();
-                let gimbal_b = thruster.geometry.q_cf2b.transform_vector(gimbal_cf);
-
-                max_angles.push(tvc.max_deflection);
-                distribution_matrix.set_column(column_index, &gimbal_b);
-                column_index += 1;
-            }
-        }
+    /// Pseudo-inverse of the distribution matrix
+    /// Maps a 3-dimensional torque vector to a N-dimensional wheel torque vector
+    pub distribution_matrix_inv: na::MatrixXx3<f64>,
+    /// Rotor inertia of each wheel in kg-m^2
+    pub inertias: Vec<f64>,
+    /// Maximum motor torque of each wheel in N-m
+    pub max_torques: Vec<f64>,
+    /// Latest wheel speeds reported by the wheel tachometers in rad/s
+    pub wheel_speeds: Vec<f64>,
+    pub unloading: MomentumUnloading,
+}
 
+impl ReactionWheelController {
+    pub fn new(config: &Vec<ReactionWheelConfig>) -> Self {
+        let num_wheels = config.len();
+        let mut distribution_matrix = na::Matrix3xX::zeros(num_wheels);
+        for (column_index, wheel) in config.iter().enumerate() {
+            let axis_b = wheel.geometry.q_cf2b.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0));
+            distribution_matrix.set_column(column_index, &axis_b);
+        }
+        let distribution_matrix_inv = distribution_matrix.clone().pseudo_inverse(1e-6).expect("Failed to compute pseudo-inverse");
         Self {
             distribution_matrix,
-            current_angles: vec![0.0; num_thrusters],
-            max_angles,
+            distribution_matrix_inv,
+            inertias: config.iter().map(|wheel| wheel.inertia).collect(),
+            max_torques: config.iter().map(|wheel| wheel.max_torque).collect(),
+            wheel_speeds: vec![0.0; num_wheels],
+            unloading: MomentumUnloading::default(),
+        }
+    }
+    /// Total angular momentum stored in the wheels in body frame
+    pub fn wheel_momentum_b(&self) -> na::Vector3<f64> {
+        let momentum = na::DVector::from_iterator(self.inertias.len(),
+            self.inertias.iter().zip(self.wheel_speeds.iter()).map(|(inertia, speed)| inertia * speed));
+        &self.distribution_matrix * momentum
+    }
+    /// Maps a body torque request to wheel motor torques
+    /// The motor torques are scaled down together if any wheel saturates so that the
+    /// direction of the torque is preserved
+    pub fn allocate_torque_request(&self, torque_b: &na::Vector3<f64>) -> Vec<f64> {
+        // The spacecraft feels the reaction to the torque applied to the wheels
+        let torques = -(&self.distribution_matrix_inv * torque_b);
+        let scale = torques.iter().zip(self.max_torques.iter())
+            .map(|(torque, max_torque)| torque.abs() / max_torque)
+            .fold(1.0, f64::max);
+        torques.iter().map(|torque| torque / scale).collect()
+    }
+    /// Returns the torque to be applied by the RCS to dump the wheel momentum
+    pub fn update_momentum_unloading(&mut self) -> na::Vector3<f64> {
+        let momentum_b = self.wheel_momentum_b();
+        let momentum = momentum_b.norm();
+        if momentum > self.unloading.start_threshold {
+            self.unloading.active = true;
+        } else if momentum < self.unloading.stop_threshold {
+            self.unloading.active = false;
+        }
+        if self.unloading.active {
+            -self.unloading.gain * momentum_b
+        } else {
+            na::Vector3::zeros()
         }
     }
 }
 
+/// Wheel speed reported by the truth-side wheel tachometer
+#[derive(Debug, Clone, Event)]
+pub struct ReactionWheelTelemetry {
+    pub wheel_id: usize,
+    pub speed: f64,
+}
+
 #[derive(Debug, Event)]
-pub struct TVCTorqueRequest {
+pub struct ReactionWheelTorqueRequest {
+    /// Torque vector in body frame
     pub torque_b: na::Vector3<f64>,
 }
 
-#[derive(Debug, Event)]
-pub struct TVCControllerOutput {
-    pub angles: Vec<f64>,
+/// Contains the motor torque commands for each reaction wheel
+#[derive(Debug, Clone, Event, Default)]
+pub struct ReactionWheelControllerOutput {
+    pub torques: Vec<f64>,
 }
 
-/// Receives torque request and maps it to a TVC angle
-pub fn update_tvc_controller(mut tvc_torque_request_reader: EventReader<TVCTorqueRequest>,
-    // mut engine_query: Query<&EngineController>,  // will be used to read current engine thrust and scale the distribution matrix
-    mut tvc_query: Query<&mut TVCController>,
-    mut tvc_output_writer: EventWriter<TVCControllerOutput>,)
-{
-    let mut tvc_controller = tvc_query.single_mut();
-    if let Some(tvc_torque_request) = tvc_torque_request_reader.read().last()
-    {
-        // TODO: Gimbal through CoM first
+pub fn update_reaction_wheel_telemetry(
+    mut telemetry_reader: EventReader<ReactionWheelTelemetry>,
+    mut query: Query<&mut ReactionWheelController>,
+) {
+    if let Ok(mut rw_controller) = query.get_single_mut() {
+        for telemetry in telemetry_reader.read() {
+            if let Some(speed) = rw_controller.wheel_speeds.get_mut(telemetry.wheel_id) {
+                *speed = telemetry.speed;
+            } else {
+                log::error!("Reaction wheel id {} not found", telemetry.wheel_id);
+            }
+        }
+    }
+}
 
-        // Compute the angle from the torque request
-        let angles = tvc_torque_request.torque_b.transpose() * &tvc_controller.distribution_matrix;
+pub fn update_reaction_wheel_controller(
+    mut rw_torque_request_reader: EventReader<ReactionWheelTorqueRequest>,
+    query: Query<&ReactionWheelController>,
+    mut rw_output_writer: EventWriter<ReactionWheelControllerOutput>,
+) {
+    if let (Ok(rw_controller), Some(torque_request)) = (query.get_single(), rw_torque_request_reader.read().last()) {
+        rw_output_writer.send(ReactionWheelControllerOutput {
+            torques: rw_controller.allocate_torque_request(&torque_request.torque_b),
+        });
+    }
+}
 
-        // Clip the angles to the maximum
-        let angles: Vec<_> = angles.as_slice()
-                                    .iter()
-                                    .zip(tvc_controller.max_angles.iter())
-                                    .map(|(angle, max_angle)| angle.clamp(-*max_angle, *max_angle))
-                                    .collect();
+/* Magnetorquers */
 
-        // Will be used by the engine controller to compute the thrust vector
-        tvc_controller.current_angles = angles.clone();
+/// Magnetorquer Controller
+#[derive(Debug, Component)]
+pub struct MagnetorquerController {
+    /// Dipole axes of the magnetorquers in body frame (one column per magnetorquer)
+    pub distribution_matrix: na::Matrix3xX<f64>,
+    /// Pseudo-inverse of the distribution matrix
+    /// Maps a 3-dimensional dipole vector to a N-dimensional dipole command vector
+    pub distribution_matrix_inv: na::MatrixXx3<f64>,
+    /// Maximum dipole moment of each magnetorquer in A-m^2
+    pub max_dipoles: Vec<f64>,
+    /// Latest magnetic field measured by the magnetometers in body frame in Tesla
+    pub field_b: Option<na::Vector3<f64>>,
+}
 
-        // Send the angle to the TVC controller
-        let tvc_output = TVCControllerOutput {
-            angles
+impl MagnetorquerController {
+    pub fn new(config: &Vec<MagnetorquerConfig>) -> Self {
+        let mut distribution_matrix = na::Matrix3xX::zeros(config.len());
+        for (column_index, torquer) in config.iter().enumerate() {
+            let axis_b = torquer.geometry.q_cf2b.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0));
+            distribution_matrix.set_column(column_index, &axis_b);
+        }
+        let distribution_matrix_inv = distribution_matrix.clone().pseudo_inverse(1e-6).expect("Failed to compute pseudo-inverse");
+        Self {
+            distribution_matrix,
+            distribution_matrix_inv,
+            max_dipoles: config.iter().map(|torquer| torquer.max_dipole).collect(),
+            field_b: None,
+        }
+    }
+    /// Maps a body torque request to magnetorquer dipole commands
+    /// Only the component of the torque perpendicular to the magnetic field can be produced.
+    /// The dipoles are scaled down together if any magnetorquer saturates.
+    pub fn allocate_torque_request(&self, torque_b: &na::Vector3<f64>) -> Vec<f64> {
+        let field_b = match self.field_b {
+            Some(field_b) if field_b.norm_squared() > 0.0 => field_b,
+            _ => return vec![0.0; self.max_dipoles.len()],
         };
+        // m = (B x T) / |B|^2 gives the smallest dipole with m x B equal to the perpendicular part of T
+        let dipole_b = field_b.cross(torque_b) / field_b.norm_squared();
+        let dipoles = &self.distribution_matrix_inv * dipole_b;
+        let scale = dipoles.iter().zip(self.max_dipoles.iter())
+            .map(|(dipole, max_dipole)| dipole.abs() / max_dipole)
+            .fold(1.0, f64::max);
+        dipoles.iter().map(|dipole| dipole / scale).collect()
+    }
+    /// Torque produced by the given dipole commands in the latest measured field
+    pub fn torque_b(&self, dipoles: &[f64]) -> na::Vector3<f64> {
+        let dipole_b = &self.distribution_matrix * na::DVector::from_column_slice(dipoles);
+        self.field_b.map_or(na::Vector3::zeros(), |field_b| dipole_b.cross(&field_b))
+    }
+}
 
-        tvc_output_writer.send(tvc_output);
+#[derive(Debug, Event)]
+pub struct MagnetorquerTorqueRequest {
+    /// Torque vector in body frame
+    pub torque_b: na::Vector3<f64>,
+}
+
+/// Contains the dipole commands for each magnetorquer
+#[derive(Debug, Clone, Event, Default)]
+pub struct MagnetorquerControllerOutput {
+    pub dipoles: Vec<f64>,
+}
+
+/// Keeps the latest valid magnetometer measurement. Runs before the control allocator so that the
+/// unloading torque is split with the same field the dipoles are computed from.
+pub fn update_magnetorquer_field(
+    mut magnetometer_reader: EventReader<MagnetometerOutput>,
+    mut query: Query<&mut MagnetorquerController>,
+) {
+    if let Ok(mut mtq_controller) = query.get_single_mut() {
+        if let Some(magnetometer_output) = magnetometer_reader.read().filter(|output| output.valid).last() {
+            mtq_controller.field_b = Some(magnetometer_output.field_b);
+        }
+    }
+}
+
+pub fn update_magnetorquer_controller(
+    mut mtq_torque_request_reader: EventReader<MagnetorquerTorqueRequest>,
+    query: Query<&MagnetorquerController>,
+    mut mtq_output_writer: EventWriter<MagnetorquerControllerOutput>,
+) {
+    if let Ok(mtq_controller) = query.get_single() {
+        if let Some(torque_request) = mtq_torque_request_reader.read().last() {
+            mtq_output_writer.send(MagnetorquerControllerOutput {
+                dipoles: mtq_controller.allocate_torque_request(&torque_request.torque_b),
+            });
+        }
     }
 }
 
+/* Vernier Engines and TVC */
+
+/// Thrust of each vernier engine around which the vernier attitude controller throttles and
+/// gimbals the engines. Zero shuts the engines down.
+#[derive(Debug, Clone, Event)]
+pub struct VernierThrustCommand {
+    pub base_thrust: f64,
+}
+
+#[derive(Debug, Event)]
+pub struct VernierTorqueRequest {
+    pub torque_b: na::Vector3<f64>,
+}
+
+/// Thrust (N) and TVC deflection (rad) of each vernier engine. Engines without TVC always have a
+/// zero deflection.
+#[derive(Debug, Component, Clone, Default, Event)]
+pub struct VernierEngineControllerOutput {
+    /// Ignites the engines if they are not running (or shuts them down if false)
+    pub ignition: bool,
+    pub thrust_levels: Vec<f64>,
+    pub tvc_angles: Vec<f64>,
+}
+
+/// Status of a vernier engine echoed back by the propulsion subsystem
+#[derive(Debug, Clone, Event)]
+pub struct EngineTelemetry {
+    pub engine_id: usize,
+    pub ignited: bool,
+    /// Thrust (N) and TVC deflection (rad) that the engine is currently set to
+    pub commanded_thrust: f64,
+    pub tvc_angle: f64,
+    /// Whether the last engine command was accepted by the propulsion subsystem
+    pub command_accepted: bool,
+}
 
-// Controller for the vernier engine system
-// Will output thrust levels for each engine as well as gimbal angles
+/// Attitude control with the vernier engines while they are firing. Torques are produced by
+/// throttling the engines differentially around the base thrust and by deflecting the engines that
+/// have TVC. The TVC also trims out the torque that the engines produce at the base thrust, so
+/// that the net thrust goes through the center of mass.
 #[derive(Debug, Component)]
-#[allow(dead_code)]
 pub struct VernierAttitudeController {
     pub config: Vec<ThrusterConfig>,
     pub current_thrust: Vec<f64>,
-
-    // Matrix with 3 rows and N columns (for N thrusters)
-    diff_thrust_distribution_matrix_inv: na::MatrixXx3<f64>,
-    // Each column represents the torque generated by a gimballing the thruster
-    // We do not care about torque from nominal position of thruster here
-    gimbal_distribution_matrix_inv: na::MatrixXx3<f64>,
+    pub current_tvc_angles: Vec<f64>,
+    /// Whether each engine is running according to the engine telemetry
+    pub engines_ignited: Vec<bool>,
+    /// Torque from each engine per newton of thrust at zero deflection
+    thrust_torque_b: na::Matrix3xX<f64>,
+    /// Component of each engine's thrust direction along the net thrust direction
+    thrust_axial: na::RowDVector<f64>,
+    /// Torque per newton and per radian of deflection of the gimballed engines
+    gimbal_torque_b: na::Matrix3xX<f64>,
     /// Indices of thrusters that have TVC
     gimballed_thrusters: Vec<usize>,
-    num_gimbal_thrusters: usize,
-    num_thrusters: usize,
 
     // Base thrust level that is determined by the acceleration command
     // We perform attitude control by modulating the thrust levels of each thruster
@@ -385,86 +838,562 @@ pub struct VernierAttitudeController {
 }
 
 impl VernierAttitudeController {
-    pub fn new(thrusters: &Vec<ThrusterConfig>) -> Self
+    pub fn new(thrusters: &[ThrusterConfig]) -> Self
     {
-        // Find number of thrusters with TVC
         let num_thrusters = thrusters.len();
-        let num_gimbal_thrusters = thrusters.iter().filter(|thruster| thruster.tvc.is_some()).count();
-
-        // Fill distribution matrix with zeros
-        let mut diff_thrust_distribution_matrix = na::Matrix3xX::zeros(num_thrusters);
-        let mut gimbal_distribution_matrix = na::Matrix3xX::zeros(num_gimbal_thrusters);
-
-        // Populate both distribution matrices
-        let mut column_index = 0;
-        let mut gimbal_column_index = 0;
-        let mut gimbal_thruster_indices = Vec::new();
-        for thruster in thrusters.iter() {
-            let thrust_dir_b = thruster.geometry.q_cf2b.transform_vector(&na::Vector3::new(0.0, 0.0, 1.0));
-            if let Some(tvc) = thruster.tvc.as_ref() {
-
-                let gimbal_cf = tvc.axis_cf.as_ref();
-                let gimbal_b = thruster.geometry.q_cf2b.transform_vector(gimbal_cf);
-                let gimbal_b = na::Unit::new_normalize(gimbal_b);
-                // We rotate the thrust vector [0,0,1], by "max_deflection" degrees about the gimbal axis
-                // to get the component of thrust that is perpendicular to the gimbal axis
-                // This is the component that will generate torque
-
-                let max_deflection = tvc.max_deflection.to_radians();
-                let mut gimbal_thrust_b = na::Rotation3::from_axis_angle(&gimbal_b, max_deflection) * thrust_dir_b;
-                // Remove the component of thrust that is parallel to the ungimballed thrust vector
-                gimbal_thrust_b = gimbal_thrust_b - gimbal_thrust_b.dot(&thrust_dir_b) * thrust_dir_b;
-
-                // Torque is position vector cross thrust vector
-                let torque_b = thruster.geometry.cf_offset_com_b.cross(&gimbal_thrust_b);
-                gimbal_distribution_matrix.set_column(gimbal_column_index, &torque_b);
-
-                gimbal_thruster_indices.push(column_index);
-                gimbal_column_index += 1;
-            }
-            let torque_b = thruster.geometry.cf_offset_com_b.cross(&thrust_dir_b);
-            diff_thrust_distribution_matrix.set_column(column_index, &torque_b);
-            column_index += 1;
+        let gimballed_thrusters = thrusters.iter().enumerate()
+            .filter(|(_, thruster)| thruster.tvc.is_some()).map(|(i, _)| i).collect::<Vec<_>>();
+
+        let mut thrust_torque_b = na::Matrix3xX::zeros(num_thrusters);
+        let mut gimbal_torque_b = na::Matrix3xX::zeros(gimballed_thrusters.len());
+        let mut thrust_dirs_b = Vec::new();
+        for (column_index, thruster) in thrusters.iter().enumerate() {
+            let thrust_dir_b = thruster.geometry.q_cf2b.transform_vector(&na::Vector3::z());
+            thrust_torque_b.set_column(column_index, &thruster.geometry.cf_offset_com_b.cross(&thrust_dir_b));
+            thrust_dirs_b.push(thrust_dir_b);
+        }
+        for (gimbal_column_index, &i) in gimballed_thrusters.iter().enumerate() {
+            let thruster = &thrusters[i];
+            let tvc = thruster.tvc.as_ref().unwrap();
+            // The nozzle is rotated by minus the deflection about the gimbal axis, which moves the
+            // thrust direction by z x axis per radian (for small deflections)
+            let gimbal_thrust_cf = na::Vector3::z().cross(&tvc.axis_cf.0);
+            let gimbal_thrust_b = thruster.geometry.q_cf2b.transform_vector(&gimbal_thrust_cf);
+            gimbal_torque_b.set_column(gimbal_column_index, &thruster.geometry.cf_offset_com_b.cross(&gimbal_thrust_b));
         }
+        let net_thrust_dir_b = thrust_dirs_b.iter().sum::<na::Vector3<f64>>().try_normalize(1e-9).unwrap_or(na::Vector3::z());
+        let thrust_axial = na::RowDVector::from_iterator(num_thrusters, thrust_dirs_b.iter().map(|dir| dir.dot(&net_thrust_dir_b)));
 
         Self {
-            config: thrusters.clone(),
+            config: thrusters.to_vec(),
             current_thrust: vec![0.0; num_thrusters],
-            diff_thrust_distribution_matrix_inv: diff_thrust_distribution_matrix.pseudo_inverse(1e-6).expect("Failed to compute pseudo-inverse"),
-            gimbal_distribution_matrix_inv: gimbal_distribution_matrix.pseudo_inverse(1e-6).expect("Failed to compute pseudo-inverse"),
-            gimballed_thrusters: gimbal_thruster_indices,
-            num_gimbal_thrusters,
-            num_thrusters,
+            current_tvc_angles: vec![0.0; num_thrusters],
+            engines_ignited: vec![false; num_thrusters],
+            thrust_torque_b,
+            thrust_axial,
+            gimbal_torque_b,
+            gimballed_thrusters,
             base_thrust_level: 0.0,
         }
     }
-    pub fn allocate_torque_request(&mut self, torque_request_b: &na::Vector3<f64>)
+    /// Whether the engines are firing (and can be used for attitude control)
+    pub fn is_firing(&self) -> bool {
+        self.base_thrust_level > 0.0 && !self.engines_ignited.is_empty() && self.engines_ignited.iter().all(|&ignited| ignited)
+    }
+    pub fn base_thrust(&self) -> f64 {
+        self.base_thrust_level
+    }
+    pub fn set_base_thrust(&mut self, base_thrust: f64) {
+        self.base_thrust_level = base_thrust.max(0.0);
+    }
+    /// Torque produced by the engines at the base thrust with no deflection
+    pub fn trim_torque_b(&self) -> na::Vector3<f64> {
+        &self.thrust_torque_b * na::DVector::repeat(self.config.len(), self.base_thrust_level)
+    }
+    /// Computes the thrust and TVC deflection of each engine that produce the requested torque
+    /// (on top of cancelling the trim torque) without changing the net thrust
+    pub fn allocate_torque_request(&mut self, torque_request_b: &na::Vector3<f64>) -> VernierEngineControllerOutput
     {
+        let num_thrusters = self.config.len();
+        let num_gimbals = self.gimballed_thrusters.len();
         // We cannot allocate torque if there is no thrust
-        if self.base_thrust_level > 0.
-        {
-            // Compute the torque generated by each thruster
-            let diff_thrust_levels = &self.diff_thrust_distribution_matrix_inv / self.base_thrust_level * torque_request_b;
-            let mut gimbal_levels = &self.gimbal_distribution_matrix_inv * torque_request_b;
-
-            // Clamp gimbals to +/- 1.0
-            gimbal_levels = gimbal_levels.map(|angle| angle.clamp(-1.0, 1.0));
+        if self.base_thrust_level <= 0.0 || num_thrusters == 0 {
+            self.current_thrust = vec![0.0; num_thrusters];
+            self.current_tvc_angles = vec![0.0; num_thrusters];
+            return self.output();
+        }
 
-            let max_deflections = self.config.iter().filter_map(|thruster| thruster.tvc.as_ref()).map(|tvc| tvc.max_deflection.to_radians()).collect::<Vec<_>>();
-            let _gimbal_angles = gimbal_levels.iter().zip(max_deflections.iter()).map(|(angle, max_angle)| angle * max_angle).collect::<Vec<_>>();
+        // Solve for the thrust changes and deflections with the smallest norm that produce the torque
+        // while keeping the thrust along the net thrust direction unchanged
+        let mut allocation = na::DMatrix::zeros(4, num_thrusters + num_gimbals);
+        allocation.view_mut((0, 0), (3, num_thrusters)).copy_from(&self.thrust_torque_b);
+        allocation.view_mut((0, num_thrusters), (3, num_gimbals)).copy_from(&(&self.gimbal_torque_b * self.base_thrust_level));
+        allocation.view_mut((3, 0), (1, num_thrusters)).copy_from(&self.thrust_axial);
+        let torque_b = torque_request_b - self.trim_torque_b();
+        let request = na::DVector::from_column_slice(&[torque_b.x, torque_b.y, torque_b.z, 0.0]);
+        let solution = allocation.pseudo_inverse(1e-9).expect("Failed to compute pseudo-inverse") * request;
 
-            // Map -1 to +1 for thrust levels to min to max thrust
-            let _diff_thrust_levels = diff_thrust_levels.map(|level| level * 0.5 + 0.5);
+        self.current_thrust = self.config.iter().enumerate()
+            .map(|(i, thruster)| (self.base_thrust_level + solution[i]).clamp(thruster.min_thrust, thruster.max_thrust))
+            .collect();
+        self.current_tvc_angles = vec![0.0; num_thrusters];
+        for (j, &i) in self.gimballed_thrusters.iter().enumerate() {
+            // The deflections were computed for the base thrust. An engine without thrust cannot
+            // produce any torque by deflecting.
+            let deflection = if self.current_thrust[i] > 0.0 {
+                solution[num_thrusters + j] * self.base_thrust_level / self.current_thrust[i]
+            } else { 0.0 };
+            let max_deflection = self.config[i].tvc.as_ref().unwrap().max_deflection.to_radians();
+            self.current_tvc_angles[i] = deflection.clamp(-max_deflection, max_deflection);
+        }
+        self.output()
+    }
+    pub fn output(&self) -> VernierEngineControllerOutput {
+        VernierEngineControllerOutput {
+            ignition: self.base_thrust_level > 0.0,
+            thrust_levels: self.current_thrust.clone(),
+            tvc_angles: self.current_tvc_angles.clone(),
+        }
+    }
+}
 
+pub fn update_engine_telemetry(
+    mut telemetry_reader: EventReader<EngineTelemetry>,
+    mut query: Query<&mut VernierAttitudeController>,
+) {
+    if let Ok(mut vernier_controller) = query.get_single_mut() {
+        for telemetry in telemetry_reader.read() {
+            if let Some(ignited) = vernier_controller.engines_ignited.get_mut(telemetry.engine_id) {
+                *ignited = telemetry.ignited;
+            } else {
+                log::error!("Vernier engine id {} not found", telemetry.engine_id);
+            }
+            if !telemetry.command_accepted {
+                log::warn!("Vernier engine {} rejected the last command", telemetry.engine_id);
+            }
         }
     }
 }
 
-#[derive(Debug, Event)]
-pub struct VernierTorqueRequest {
-    pub torque_b: na::Vector3<f64>,
+/// Sets the base thrust of the vernier engines and allocates torque requests to them
+pub fn update_vernier_controller(
+    mut thrust_command_reader: EventReader<VernierThrustCommand>,
+    mut torque_request_reader: EventReader<VernierTorqueRequest>,
+    mut query: Query<(&mut VernierAttitudeController, &mut VernierEngineControllerOutput)>,
+    mut vernier_output_writer: EventWriter<VernierEngineControllerOutput>,
+) {
+    let thrust_command = thrust_command_reader.read().last().cloned();
+    let torque_request = torque_request_reader.read().last().map(|request| request.torque_b);
+    let Ok((mut controller, mut output)) = query.get_single_mut() else {
+        return;
+    };
+    if let Some(thrust_command) = &thrust_command {
+        controller.set_base_thrust(thrust_command.base_thrust);
+    }
+    if thrust_command.is_some() || torque_request.is_some() {
+        *output = controller.allocate_torque_request(&torque_request.unwrap_or_default());
+        vernier_output_writer.send(output.clone());
+    }
 }
 
-pub struct VernierEngineControllerOutput {
-    pub thrust_levels: Vec<f64>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+    use hifitime::Duration;
+
+    fn create_wheels() -> Vec<ReactionWheelConfig> {
+        // Three orthogonal wheels along the body X, Y and Z axes
+        ["[0.7071068, 0.0, 0.7071068, 0.0]", "[0.7071068, -0.7071068, 0.0, 0.0]", "[1.0, 0.0, 0.0, 0.0]"].iter().map(|q_cf2b|
+            ReactionWheelConfig::from_str(&format!(r#"
+                <ReactionWheel name="RW">
+                    <geometry>
+                        <q_cf2b>{}</q_cf2b>
+                        <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                    </geometry>
+                    <inertia>0.1</inertia>
+                    <max_speed>600.0</max_speed>
+                    <max_torque>0.2</max_torque>
+                </ReactionWheel>"#, q_cf2b)).unwrap()
+        ).collect()
+    }
+
+    fn estimate(q_i2b: na::UnitQuaternion<f64>, omega_b: na::Vector3<f64>) -> AttitudeEstimatorOutput {
+        AttitudeEstimatorOutput { q_i2b, omega_b, valid: true, ..Default::default() }
+    }
+
+    #[test]
+    fn test_attitude_controller() {
+        let mut controller = AttitudeController::default();
+        let t0 = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_i2b = na::UnitQuaternion::identity();
+
+        // Body rate tracking with the rate gain, limited to the maximum torque
+        let target = AttitudeTarget::BodyRate(na::Vector3::new(0.01, 0.0, 1.0));
+        let torque = controller.update(&target, &estimate(q_i2b, na::Vector3::zeros()), t0);
+        assert!((torque - na::Vector3::new(0.1, 0.0, 1.0)).norm() < 1e-12);
+
+        // Small attitude errors command a body rate proportional to the error, large ones are
+        // rate limited
+        let target = AttitudeTarget::Attitude(na::UnitQuaternion::from_euler_angles(0.01, 0.0, 0.0));
+        let torque = controller.update(&target, &estimate(q_i2b, na::Vector3::zeros()), t0);
+        assert!((torque - na::Vector3::new(0.1, 0.0, 0.0)).norm() < 1e-9);
+        let target = AttitudeTarget::Attitude(na::UnitQuaternion::from_euler_angles(0.0, 1.0, 0.0));
+        let torque = controller.update(&target, &estimate(q_i2b, na::Vector3::zeros()), t0);
+        assert!((torque - na::Vector3::new(0.0, 1.0, 0.0)).norm() < 1e-9);
+
+        // Alignment targets are given in the inertial frame. With the body X-axis along inertial
+        // Y, inertial -X is along body Y, so the spacecraft turns about +Z.
+        let q_i2b_rotated = na::UnitQuaternion::from_euler_angles(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        let align = |align_to_i: na::Vector3<f64>| AttitudeTarget::Align {
+            align_with_b: surveyor_types::math::Vector3(na::Vector3::x()),
+            align_to_i: surveyor_types::math::Vector3(align_to_i),
+        };
+        let torque = controller.update(&align(na::Vector3::y()), &estimate(q_i2b_rotated, na::Vector3::zeros()), t0);
+        assert!(torque.norm() < 1e-9);
+        let torque = controller.update(&align(-na::Vector3::x()), &estimate(q_i2b_rotated, na::Vector3::zeros()), t0);
+        assert!((torque - na::Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-9);
+        // Small errors are proportional to the angle
+        let torque = controller.update(&align(na::Vector3::new(-0.01, 1.0, 0.0).normalize()), &estimate(q_i2b_rotated, na::Vector3::zeros()), t0);
+        assert!((torque - na::Vector3::new(0.0, 0.0, 10.0 * 0.01f64.atan())).norm() < 1e-9);
+
+        // Errors within the deadbands produce no torque
+        let target = AttitudeTarget::Attitude(na::UnitQuaternion::from_euler_angles(1e-5, 0.0, 0.0));
+        let torque = controller.update(&target, &estimate(q_i2b, na::Vector3::new(5e-5, 0.0, 0.0)), t0);
+        assert_eq!(torque, na::Vector3::zeros());
+        assert_eq!(controller.update(&AttitudeTarget::None, &estimate(q_i2b, na::Vector3::x()), t0), na::Vector3::zeros());
+    }
+
+    #[test]
+    fn test_attitude_controller_integrator() {
+        let config = AttitudeControllerConfig {
+            integral_gain: surveyor_types::math::Vector3(na::Vector3::repeat(1.0)),
+            max_torque: surveyor_types::math::Vector3(na::Vector3::repeat(0.5)),
+            ..Default::default()
+        };
+        let mut controller = AttitudeController::new(config.clone());
+        let t0 = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_i2b = na::UnitQuaternion::identity();
+
+        // A constant rate error is integrated over time
+        let target = AttitudeTarget::BodyRate(na::Vector3::new(0.01, 0.0, 0.0));
+        let omega_b = na::Vector3::zeros();
+        for i in 0..=10 {
+            controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(i as f64));
+        }
+        assert!((controller.integral_b.x - 0.1).abs() < 1e-9);
+        // ... up to the integral torque limit
+        for i in 11..=100 {
+            controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(i as f64));
+        }
+        let torque = controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(101.0));
+        assert!((torque.x - 0.2).abs() < 1e-9);
+
+        // No integration while the torque is saturated
+        let mut controller = AttitudeController::new(config.clone());
+        let target = AttitudeTarget::BodyRate(na::Vector3::new(0.0, 1.0, 0.0));
+        for i in 0..10 {
+            let torque = controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(i as f64));
+            assert_eq!(torque.y, 0.5);
+        }
+        assert_eq!(controller.integral_b, na::Vector3::zeros());
+
+        // The integrator is reset when the target changes or the gains are changed
+        let mut controller = AttitudeController::new(config.clone());
+        let target = AttitudeTarget::BodyRate(na::Vector3::new(0.01, 0.0, 0.0));
+        controller.update(&target, &estimate(q_i2b, omega_b), t0);
+        controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(1.0));
+        assert!(controller.integral_b.x > 0.0);
+        controller.set_config(config);
+        assert_eq!(controller.integral_b, na::Vector3::zeros());
+        controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(2.0));
+        controller.update(&target, &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(3.0));
+        controller.update(&AttitudeTarget::Attitude(q_i2b), &estimate(q_i2b, omega_b), t0 + Duration::from_seconds(4.0));
+        assert_eq!(controller.integral_b, na::Vector3::zeros());
+    }
+
+    /// Vernier engines in a tripod around the center of mass (offset along Y by `com_offset`) with
+    /// TVC on engine A
+    fn create_verniers(com_offset: f64) -> Vec<ThrusterConfig> {
+        [("A", [0.0, 1.0], true), ("B", [0.8660254037844386, -0.5], false), ("C", [-0.8660254037844386, -0.5], false)]
+            .iter().map(|(name, [x, y], tvc)| ThrusterConfig::from_str(&format!(r#"
+                <thruster type="VernierRocket" name="{}">
+                    <min_thrust>133.45</min_thrust>
+                    <max_thrust>462.61</max_thrust>
+                    <geometry>
+                        <q_cf2b>[0.0, 0.0, 0.0, 1.0]</q_cf2b>
+                        <cf_offset_com_b>[{}, {}, -0.05]</cf_offset_com_b>
+                    </geometry>
+                    {}
+                </thruster>"#, name, x, y - com_offset,
+                if *tvc { "<tvc><max_deflection>6.0</max_deflection><axis_cf>[0.0, 1.0, 0.0]</axis_cf></tvc>" } else { "" })).unwrap()
+            ).collect()
+    }
+
+    /// Net force and torque produced by the engines (the same way as the truth-side model)
+    fn vernier_force_torque(engines: &[ThrusterConfig], output: &VernierEngineControllerOutput) -> (na::Vector3<f64>, na::Vector3<f64>) {
+        engines.iter().enumerate().fold((na::Vector3::zeros(), na::Vector3::zeros()), |(force, torque), (i, engine)| {
+            let axis = engine.tvc.as_ref().map(|tvc| tvc.axis_cf.0).unwrap_or(na::Vector3::x_axis());
+            let thrust_cf = na::UnitQuaternion::from_axis_angle(&axis, output.tvc_angles[i])
+                .inverse_transform_vector(&na::Vector3::new(0.0, 0.0, output.thrust_levels[i]));
+            let thrust_b = engine.geometry.q_cf2b.transform_vector(&thrust_cf);
+            (force + thrust_b, torque + engine.geometry.cf_offset_com_b.cross(&thrust_b))
+        })
+    }
+
+    #[test]
+    fn test_vernier_allocation() {
+        let engines = create_verniers(0.0);
+        let mut controller = VernierAttitudeController::new(&engines);
+        // No thrust, no torque
+        let output = controller.allocate_torque_request(&na::Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(output.thrust_levels, vec![0.0; 3]);
+
+        controller.set_base_thrust(300.0);
+        let torque_request = na::Vector3::new(5.0, -3.0, 2.0);
+        let output = controller.allocate_torque_request(&torque_request);
+        let (force, torque) = vernier_force_torque(&engines, &output);
+        // Roll torque comes from the TVC, the others from differential throttling. The net thrust
+        // stays at the base thrust.
+        assert!((torque - torque_request).norm() < 0.01);
+        assert!((force.z - 900.0).abs() < 0.01);
+        assert!(output.tvc_angles[0].abs() > 0.0 && output.tvc_angles[1] == 0.0);
+        assert!(output.ignition);
+        // Torque requests are only routed to the engines once they report that they are running
+        assert!(!controller.is_firing());
+        controller.engines_ignited = vec![true; 3];
+        assert!(controller.is_firing());
+
+        // Limits
+        let output = controller.allocate_torque_request(&na::Vector3::new(0.0, 0.0, 100.0));
+        assert!((output.tvc_angles[0].abs() - 6f64.to_radians()).abs() < 1e-12);
+        let output = controller.allocate_torque_request(&na::Vector3::new(1000.0, 0.0, 0.0));
+        assert!(output.thrust_levels.iter().all(|thrust| (133.45..=462.61).contains(thrust)));
+
+        // An engine that is throttled down to zero thrust is not deflected
+        let mut engines = create_verniers(0.0);
+        engines.iter_mut().for_each(|engine| engine.min_thrust = 0.0);
+        let mut controller = VernierAttitudeController::new(&engines);
+        controller.set_base_thrust(1.0);
+        let output = controller.allocate_torque_request(&na::Vector3::new(-1000.0, 0.0, 10.0));
+        assert_eq!(output.thrust_levels[0], 0.0);
+        assert_eq!(output.tvc_angles[0], 0.0);
+
+        // With the center of mass off the thrust axis the engines are trimmed so that there is no
+        // net torque
+        let engines = create_verniers(0.1);
+        let mut controller = VernierAttitudeController::new(&engines);
+        controller.set_base_thrust(300.0);
+        assert!(controller.trim_torque_b().norm() > 10.0);
+        let (force, torque) = vernier_force_torque(&engines, &controller.allocate_torque_request(&na::Vector3::zeros()));
+        assert!(torque.norm() < 0.01);
+        assert!((force.z - 900.0).abs() < 0.01);
+    }
+
+    fn create_rcs_thrusters() -> Vec<ThrusterConfig> {
+        // Roll jets on one leg and pairs of opposing jets on the other two legs (as in
+        // simulation.xml)
+        [("[0.7071068, 0.0, 0.7071068, 0.0]", "[-0.1, 1.0, -0.5]"), ("[0.7071068, 0.0, -0.7071068, 0.0]", "[-0.1, 1.0, -0.5]"),
+         ("[1.0, 0.0, 0.0, 0.0]", "[0.8660254, -0.5, -0.6]"), ("[0.0, 1.0, 0.0, 0.0]", "[0.8660254, -0.5, -0.4]"),
+         ("[1.0, 0.0, 0.0, 0.0]", "[-0.8660254, -0.5, -0.6]"), ("[0.0, 1.0, 0.0, 0.0]", "[-0.8660254, -0.5, -0.4]")]
+            .iter().map(|(q_cf2b, offset)| ThrusterConfig::from_str(&format!(r#"
+                <thruster name="RCS">
+                    <min_thrust>0.0</min_thrust>
+                    <max_thrust>0.25</max_thrust>
+                    <geometry>
+                        <q_cf2b>{}</q_cf2b>
+                        <cf_offset_com_b>{}</cf_offset_com_b>
+                    </geometry>
+                </thruster>"#, q_cf2b, offset)).unwrap()
+            ).collect()
+    }
+
+    #[test]
+    fn test_rcs_allocation() {
+        let mut rcs_controller = RCSController::new(&create_rcs_thrusters());
+        let duty_cycles_vector = |duty_cycles: &[f64]| na::DVector::from_column_slice(duty_cycles);
+
+        // Torques that need thrusters firing "backwards" in a pseudo-inverse solution are met exactly
+        let torque_request = na::Vector3::new(0.05, 0.02, -0.01);
+        let duty_cycles = rcs_controller.allocate(&torque_request, None);
+        assert!(duty_cycles.iter().all(|duty_cycle| (0.0..=1.0).contains(duty_cycle)));
+        let torque = &rcs_controller.torque_matrix * duty_cycles_vector(&duty_cycles);
+        assert!((torque - torque_request).norm() < 1e-9);
+
+        // Saturated requests are matched as closely as possible
+        let duty_cycles = rcs_controller.allocate(&na::Vector3::new(1.0, 0.0, 0.0), None);
+        let torque = &rcs_controller.torque_matrix * duty_cycles_vector(&duty_cycles);
+        assert!((torque - na::Vector3::new(0.25, 0.0, 0.0)).norm() < 1e-9);
+
+        // Combined force and torque requests
+        let duty_cycles = rcs_controller.allocate(&na::Vector3::new(0.05, 0.0, 0.0), Some(&na::Vector3::new(0.0, 0.0, -0.1)));
+        let force = &rcs_controller.force_matrix * duty_cycles_vector(&duty_cycles);
+        let torque = &rcs_controller.torque_matrix * duty_cycles_vector(&duty_cycles);
+        assert!((force - na::Vector3::new(0.0, 0.0, -0.1)).norm() < 1e-9);
+        assert!((torque - na::Vector3::new(0.05, 0.0, 0.0)).norm() < 1e-9);
+
+        assert!(rcs_controller.set_thruster_enabled(6, false).is_err());
+
+        // Failed thrusters are not used and the redundant thrusters take over
+        let thrusters = create_rcs_thrusters();
+        let mut rcs_controller = RCSController::new(&thrusters.iter().chain(thrusters.iter()).cloned().collect());
+        for thruster in 0..6 {
+            rcs_controller.set_thruster_enabled(thruster, false).unwrap();
+        }
+        let duty_cycles = rcs_controller.allocate(&torque_request, None);
+        assert!(duty_cycles[..6].iter().all(|duty_cycle| *duty_cycle == 0.0));
+        let torque = &rcs_controller.torque_matrix * duty_cycles_vector(&duty_cycles);
+        assert!((torque - torque_request).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_rcs_modulator() {
+        let t0 = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let period = 0.1;
+        let limits = na::Vector3::repeat(0.5);
+        let run = |modulator: &mut RcsModulator, torque_b: na::Vector3<f64>, periods: std::ops::Range<usize>| {
+            periods.map(|i| modulator.update(&torque_b, &limits, t0 + Duration::from_seconds(i as f64 * period))).collect::<Vec<_>>()
+        };
+
+        // The PWPF modulator fires pulses that produce (a bit less than, due to its dead zone) the
+        // demanded torque on average
+        let mut modulator = RcsModulator::new(RcsModulatorConfig::default());
+        let on_times = run(&mut modulator, na::Vector3::new(0.15, -0.15, 0.0), 0..200);
+        assert_eq!(on_times[0], na::Vector3::zeros());
+        let average = on_times[100..].iter().sum::<na::Vector3<f64>>() / (100.0 * period);
+        assert!((0.2..0.3).contains(&average.x) && average.y == -average.x && average.z == 0.0);
+        // It actually pulses (instead of staying on)
+        assert!(on_times[100..].iter().all(|on_time| on_time.x < period - 1e-9));
+        let on_times = run(&mut modulator, na::Vector3::new(0.4, 0.0, 0.0), 200..300);
+        let average = on_times[50..].iter().sum::<na::Vector3<f64>>() / (50.0 * period);
+        assert!((0.7..0.8).contains(&average.x));
+        assert!(on_times.iter().all(|on_time| on_time.x <= period + 1e-12));
+
+        // The Schmitt trigger turns on above the on threshold and stays on down to the off threshold
+        let config = RcsModulatorConfig {
+            modulator_type: RcsModulatorType::Schmitt,
+            min_pulse_width: 0.25,
+            ..Default::default()
+        };
+        let mut modulator = RcsModulator::new(config);
+        assert!(run(&mut modulator, na::Vector3::new(0.2, 0.0, 0.0), 0..3).iter().all(|on_time| on_time.x == 0.0));
+        assert!((run(&mut modulator, na::Vector3::new(0.25, 0.0, 0.0), 3..4)[0].x - period).abs() < 1e-9);
+        assert!((run(&mut modulator, na::Vector3::new(0.1, 0.0, 0.0), 4..5)[0].x - period).abs() < 1e-9);
+        // Pulses last for at least the minimum pulse width
+        let on_times = run(&mut modulator, na::Vector3::zeros(), 5..8);
+        assert!((on_times[0].x - 0.05).abs() < 1e-9 && on_times[1].x == 0.0);
+        let mut modulator = RcsModulator::new(modulator.config.clone());
+        run(&mut modulator, na::Vector3::new(0.25, 0.0, 0.0), 0..2);
+        let on_times = run(&mut modulator, na::Vector3::zeros(), 2..5);
+        assert!((on_times[0].x - period).abs() < 1e-9 && (on_times[1].x - 0.05).abs() < 1e-9 && on_times[2].x == 0.0);
+
+        // Axis pulses are converted into thruster on-times
+        let mut rcs_controller = RCSController::new(&create_rcs_thrusters());
+        let limits = rcs_controller.axis_torque_limits();
+        assert!((limits.x - 0.25).abs() < 1e-9);
+        // The limits follow the enabled thrusters
+        rcs_controller.set_thruster_enabled(0, false).unwrap();
+        assert_eq!(rcs_controller.axis_torque_limits(), rcs_controller.compute_axis_torque_limits());
+        // Without one of the roll jets there is no roll torque in one direction
+        assert!(limits.z > 0.1 && rcs_controller.axis_torque_limits().z < 1e-9);
+        rcs_controller.set_thruster_enabled(0, true).unwrap();
+        assert_eq!(rcs_controller.axis_torque_limits(), limits);
+        let on_times = rcs_controller.pulse_on_times(&na::Vector3::new(0.05, 0.0, 0.0), &limits, period);
+        let impulse = rcs_controller.torque_b(&on_times);
+        assert!((impulse - na::Vector3::new(0.25 * 0.05, 0.0, 0.0)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_reaction_wheel_allocation() {
+        let rw_controller = ReactionWheelController::new(&create_wheels());
+        // Wheels spin in the opposite direction to the requested body torque
+        let torques = rw_controller.allocate_torque_request(&na::Vector3::new(0.1, -0.05, 0.0));
+        assert!((torques[0] + 0.1).abs() < 1e-6);
+        assert!((torques[1] - 0.05).abs() < 1e-6);
+        assert!(torques[2].abs() < 1e-6);
+
+        // Saturated requests are scaled down while preserving the direction
+        let torques = rw_controller.allocate_torque_request(&na::Vector3::new(0.8, 0.4, 0.0));
+        assert!((torques[0] + 0.2).abs() < 1e-6);
+        assert!((torques[1] + 0.1).abs() < 1e-6);
+    }
+
+    fn create_magnetorquers() -> Vec<MagnetorquerConfig> {
+        // Three orthogonal magnetorquers along the body X, Y and Z axes
+        ["[0.7071068, 0.0, 0.7071068, 0.0]", "[0.7071068, -0.7071068, 0.0, 0.0]", "[1.0, 0.0, 0.0, 0.0]"].iter().map(|q_cf2b|
+            MagnetorquerConfig::from_str(&format!(r#"
+                <Magnetorquer name="MTQ">
+                    <geometry>
+                        <q_cf2b>{}</q_cf2b>
+                        <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                    </geometry>
+                    <max_dipole>10.0</max_dipole>
+                </Magnetorquer>"#, q_cf2b)).unwrap()
+        ).collect()
+    }
+
+    #[test]
+    fn test_magnetorquer_allocation() {
+        let mut mtq_controller = MagnetorquerController::new(&create_magnetorquers());
+        // No torque can be produced before the field has been measured
+        assert_eq!(mtq_controller.allocate_torque_request(&na::Vector3::new(0.0, 0.0, 1e-4)), vec![0.0; 3]);
+
+        mtq_controller.field_b = Some(na::Vector3::new(0.0, 30e-6, 0.0));
+        let torque_b = na::Vector3::new(0.0, 0.0, 1e-4);
+        let dipoles = mtq_controller.allocate_torque_request(&torque_b);
+        let dipole_b = &mtq_controller.distribution_matrix * na::DVector::from_vec(dipoles);
+        assert!((dipole_b.cross(&mtq_controller.field_b.unwrap()) - torque_b).norm() < 1e-9);
+        assert!((mtq_controller.torque_b(&mtq_controller.allocate_torque_request(&torque_b)) - torque_b).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_magnetorquer_unloading_closed_loop() {
+        let mut app = bevy_app::App::new();
+        app.add_event::<AttitudeTorqueRequest>()
+            .add_event::<RCSTorqueRequest>()
+            .add_event::<ReactionWheelTorqueRequest>()
+            .add_event::<MagnetorquerTorqueRequest>()
+            .add_event::<VernierTorqueRequest>()
+            .add_event::<MagnetometerOutput>()
+            .add_event::<ReactionWheelControllerOutput>()
+            .add_event::<MagnetorquerControllerOutput>()
+            .add_systems(bevy_app::Update, (update_magnetorquer_field, update_control_allocator,
+                update_reaction_wheel_controller, update_magnetorquer_controller).chain());
+        let wheels = create_wheels();
+        let mut rw_controller = ReactionWheelController::new(&wheels);
+        // 1.2 N-m-s stored along X, at 45 deg to the field: only the momentum perpendicular to the
+        // field can be dumped, and the magnetorquers saturate
+        rw_controller.wheel_speeds = vec![12.0, 0.0, 0.0];
+        let field_b = na::Vector3::new(30e-6, 30e-6, 0.0);
+        let rw_distribution = rw_controller.distribution_matrix.clone();
+        let mtq_controller = MagnetorquerController::new(&create_magnetorquers());
+        let mtq_distribution = mtq_controller.distribution_matrix.clone();
+        let allocator = ControlAllocator { use_reaction_wheels: true, use_magnetorquers: true, ..Default::default() };
+        let rw = app.world.spawn(rw_controller).id();
+        app.world.spawn((mtq_controller, allocator));
+
+        let momentum_b = |app: &bevy_app::App| app.world.get::<ReactionWheelController>(rw).unwrap().wheel_momentum_b();
+        let parallel = |momentum: na::Vector3<f64>| momentum.dot(&field_b.normalize());
+        let perpendicular = |momentum: na::Vector3<f64>| (momentum - parallel(momentum) * field_b.normalize()).norm();
+        let initial_momentum_b = momentum_b(&app);
+        let mut rw_reader = app.world.resource::<Events<ReactionWheelControllerOutput>>().get_reader();
+        let mut mtq_reader = app.world.resource::<Events<MagnetorquerControllerOutput>>().get_reader();
+        let dt = 10.0;
+        for _ in 0..150 {
+            app.world.send_event(MagnetometerOutput { field_b, valid: true, ..Default::default() });
+            // Holding attitude with no disturbances
+            app.world.send_event(AttitudeTorqueRequest { torque_b: na::Vector3::zeros() });
+            app.update();
+            let wheel_torques = rw_reader.read(app.world.resource::<Events<ReactionWheelControllerOutput>>()).last().unwrap().torques.clone();
+            let dipoles = mtq_reader.read(app.world.resource::<Events<MagnetorquerControllerOutput>>()).last().unwrap().dipoles.clone();
+            // The wheels cancel the magnetorquer torque, so the spacecraft feels no torque
+            let mtq_torque_b = (&mtq_distribution * na::DVector::from_vec(dipoles)).cross(&field_b);
+            let rw_torque_b = -(&rw_distribution * na::DVector::from_vec(wheel_torques.clone()));
+            assert!((rw_torque_b + mtq_torque_b).norm() < 1e-10);
+            let mut rw_controller = app.world.get_mut::<ReactionWheelController>(rw).unwrap();
+            for (i, torque) in wheel_torques.iter().enumerate() {
+                rw_controller.wheel_speeds[i] += torque / wheels[i].inertia * dt;
+            }
+        }
+        // The momentum perpendicular to the field is dumped while the parallel part stays in the wheels
+        assert!(perpendicular(momentum_b(&app)) < 0.3 && perpendicular(initial_momentum_b) > 0.8);
+        assert!((parallel(momentum_b(&app)) - parallel(initial_momentum_b)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_momentum_unloading() {
+        let mut rw_controller = ReactionWheelController::new(&create_wheels());
+        rw_controller.wheel_speeds = vec![20.0, 0.0, 0.0];
+        // 2 N-m-s stored in the X wheel is above the threshold
+        let torque_b = rw_controller.update_momentum_unloading();
+        assert!(rw_controller.unloading.active);
+        assert!(torque_b.x < 0.0);
+
+        // Unloading continues until the momentum drops below the lower threshold
+        rw_controller.wheel_speeds = vec![5.0, 0.0, 0.0];
+        assert!(rw_controller.update_momentum_unloading().x < 0.0);
+        rw_controller.wheel_speeds = vec![0.5, 0.0, 0.0];
+        assert_eq!(rw_controller.update_momentum_unloading(), na::Vector3::zeros());
+        assert!(!rw_controller.unloading.active);
+    }
 }
@@ -1,6 +1,15 @@
 use bevy_ecs::prelude::*;
+use hifitime::Epoch;
+use nalgebra as na;
+use surveyor_types::config::{DescentGuidanceConfig, DescentGuidanceLaw, PoweredDescentConfig, ThrusterConfig};
 use surveyor_types::math::Vector3;
 
+use crate::clock::SystemClock;
+use crate::control::VernierThrustCommand;
+use crate::navigation::{moon_angular_velocity_j2000, moon_j2000_to_fixed, AttitudeEstimatorOutput, NavigationState};
+use crate::optimization::{solve_socp, SecondOrderCone};
+use crate::sensors::{AmrMark, RadvsOutput};
+
 // pub struct AttitudeControllerOutput {
 //     pub torque_b: Option<na::Vector3<f64>>,
 // }
@@ -13,6 +22,21 @@ pub enum GuidanceMode {
     Manual,
     Detumble,
     Pointing(AttitudeTarget),
+    /// Descent and landing, with the attitude and the vernier thrust set by the descent guidance
+    Descent,
+}
+
+/// Phase of the descent, which is advanced by the descent guidance
+#[derive(Debug, Clone, Copy, Component, PartialEq, Eq)]
+pub enum TrajectoryPhase {
+    /// Holding the retro attitude until retro ignition
+    BeforeRetroBurn,
+    /// Gravity turn that tracks the descent contour
+    DescentContour,
+    /// Vertical descent at constant speed
+    TerminalDescent,
+    /// The engines have been shut down
+    Landed,
 }
 
 #[derive(Debug, Clone, Component, Event, PartialEq)]
@@ -39,6 +63,8 @@ pub fn update_guidance(query: Query<&GuidanceMode>, attitude_target_writer: Even
         GuidanceMode::Manual => update_manual_mode(attitude_target_writer),
         GuidanceMode::Detumble => update_detumble_mode(attitude_target_writer),
         GuidanceMode::Pointing(target) => update_pointing_mode(attitude_target_writer, target),
+        // The targets are set by `update_descent_guidance`
+        GuidanceMode::Descent => {}
     }
 }
 // TODO: Make it so that it only changes the target when the mode changes
@@ -58,3 +84,718 @@ pub fn update_detumble_mode(mut attitude_target_writer: EventWriter<AttitudeTarg
 pub fn update_pointing_mode(mut attitude_target_writer: EventWriter<AttitudeTarget>, target: &AttitudeTarget) {
     attitude_target_writer.send(target.clone());
 }
+
+/// Position, surface-relative velocity and altitude used by the descent guidance
+#[derive(Debug, Clone)]
+pub struct DescentState {
+    pub pos_i: na::Vector3<f64>,
+    pub vel_i: na::Vector3<f64>,
+    pub altitude: f64,
+}
+
+/// Attitude target and thrust of each vernier engine (N) commanded by the descent guidance
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescentCommand {
+    pub attitude_target: AttitudeTarget,
+    pub base_thrust: f64,
+}
+
+/// Standard gravity used to convert the specific impulse into an exhaust velocity (m/s^2)
+const STANDARD_GRAVITY: f64 = 9.80665;
+/// Specific impulse of the Surveyor vernier engines, used if none is configured (s)
+const VERNIER_ISP: f64 = 287.0;
+/// Below this time-to-go the target velocity is held until the end of the phase instead of
+/// steering to the target position with gains that grow without bound (s)
+const MIN_TIME_TO_GO: f64 = 1.0;
+/// Flight times tried by G-FOLD when there is no solution to start from, as multiples of the flight
+/// time at a constant deceleration. This bounds the number of problems solved in one update.
+const GFOLD_FLIGHT_TIME_FACTORS: [f64; 4] = [0.6, 0.8, 1.0, 1.3];
+/// Fraction of the thrust range that G-FOLD keeps free at either end, so that the next solution
+/// can correct for errors in following the last one
+const GFOLD_THRUST_MARGIN: f64 = 0.1;
+
+/// Fuel-optimal powered descent to a target state (G-FOLD), following Acikmese and Ploen (2007).
+/// The thrust is constant over each of a number of intervals and gravity is uniform. Positions are
+/// relative to the landing site and velocities to the surface, neglecting the Coriolis and
+/// centrifugal accelerations over the short flight.
+#[derive(Debug, Clone)]
+pub struct PoweredDescentProblem {
+    pub pos: na::Vector3<f64>,
+    pub vel: na::Vector3<f64>,
+    pub target_pos: na::Vector3<f64>,
+    pub target_vel: na::Vector3<f64>,
+    pub gravity: na::Vector3<f64>,
+    /// Local vertical at the landing site
+    pub up: na::Vector3<f64>,
+    /// Smallest elevation of the spacecraft seen from the landing site (rad), or zero for none
+    pub glide_slope_angle: f64,
+    /// Largest angle between the thrust and the vertical (rad)
+    pub max_tilt_angle: f64,
+    /// Initial mass (kg), range of the total thrust (N) and exhaust velocity (m/s)
+    pub mass: f64,
+    pub min_thrust: f64,
+    pub max_thrust: f64,
+    pub exhaust_velocity: f64,
+    pub flight_time: f64,
+    /// At least one (see `PoweredDescentConfig::validate`)
+    pub num_intervals: usize,
+}
+
+/// Thrust acceleration over each interval of a G-FOLD trajectory
+#[derive(Debug, Clone)]
+pub struct PoweredDescentPlan {
+    /// Length of each interval (s)
+    pub interval: f64,
+    pub accelerations: Vec<na::Vector3<f64>>,
+    /// Propellant used (kg)
+    pub propellant: f64,
+}
+
+impl PoweredDescentProblem {
+    /// Position after `k` intervals as an affine function `A x + b` of the thrust accelerations
+    /// and their bounds `x`
+    fn position(&self, k: usize) -> (na::DMatrix<f64>, na::Vector3<f64>) {
+        let interval = self.flight_time / self.num_intervals as f64;
+        let time = k as f64 * interval;
+        let mut a = na::DMatrix::zeros(3, 4 * self.num_intervals);
+        for j in 0..k {
+            a.view_mut((0, 3 * j), (3, 3)).fill_diagonal(interval * interval * ((k - j) as f64 - 0.5));
+        }
+        (a, self.pos + self.vel * time + self.gravity * (time * time / 2.0))
+    }
+    /// Solves the problem as a second-order cone program in the thrust acceleration `u` and its
+    /// bound `sigma` over each interval, which minimizes the propellant used. The thrust limits are
+    /// linearized about the mass at full thrust, which relaxes the lower limit slightly.
+    pub fn solve(&self) -> Result<PoweredDescentPlan, String> {
+        let n = self.num_intervals;
+        let num_vars = 4 * n;
+        let interval = self.flight_time / n as f64;
+        let flow_per_thrust = 1.0 / self.exhaust_velocity;
+        if self.mass <= flow_per_thrust * self.max_thrust * self.flight_time {
+            return Err("Not enough mass for the flight time".to_string());
+        }
+        let sigma = |k: usize| 3 * n + k;
+        let unit = |i: usize| na::DVector::from_fn(num_vars, |j, _| if i == j { 1.0 } else { 0.0 });
+
+        // Final position and velocity
+        let (pos_a, pos_b) = self.position(n);
+        let mut a = na::DMatrix::zeros(6, num_vars);
+        a.view_mut((0, 0), (3, num_vars)).copy_from(&pos_a);
+        for j in 0..n {
+            a.view_mut((3, 3 * j), (3, 3)).fill_diagonal(interval);
+        }
+        let mut b = na::DVector::zeros(6);
+        b.rows_mut(0, 3).copy_from(&(self.target_pos - pos_b));
+        b.rows_mut(3, 3).copy_from(&(self.target_vel - self.vel - self.gravity * self.flight_time));
+
+        let mut cones = Vec::new();
+        let mut log_mass_change = na::DVector::zeros(num_vars);
+        for k in 0..n {
+            let mut thrust_a = na::DMatrix::zeros(3, num_vars);
+            thrust_a.view_mut((0, 3 * k), (3, 3)).fill_diagonal(1.0);
+            cones.push(SecondOrderCone::new(thrust_a, na::DVector::zeros(3), unit(sigma(k)), 0.0));
+            let mut tilt_c = unit(sigma(k)) * -self.max_tilt_angle.cos();
+            tilt_c.rows_mut(3 * k, 3).copy_from(&self.up);
+            cones.push(SecondOrderCone::linear(tilt_c, 0.0));
+            // The log of the mass is ln(m0) + log_mass_change^T x and the limits on sigma are
+            // linear in it about the mass at full thrust
+            let reference_log_mass = (self.mass - flow_per_thrust * self.max_thrust * k as f64 * interval).ln();
+            let offset = 1.0 - (self.mass.ln() - reference_log_mass);
+            let min_sigma = self.min_thrust * (-reference_log_mass).exp();
+            let max_sigma = self.max_thrust * (-reference_log_mass).exp();
+            cones.push(SecondOrderCone::linear(unit(sigma(k)) + &log_mass_change * min_sigma, -min_sigma * offset));
+            cones.push(SecondOrderCone::linear(-unit(sigma(k)) - &log_mass_change * max_sigma, max_sigma * offset));
+            log_mass_change[sigma(k)] = -flow_per_thrust * interval;
+        }
+        if self.glide_slope_angle > 0.0 {
+            let horizontal = (na::Matrix3::identity() - self.up * self.up.transpose()) * self.glide_slope_angle.tan();
+            for k in 1..n {
+                let (pos_a, pos_b) = self.position(k);
+                let cone_a = na::DMatrix::from_fn(3, num_vars, |i, j| (horizontal.row(i) * pos_a.column(j))[0]);
+                let cone_c = pos_a.transpose() * na::DVector::from_column_slice(self.up.as_slice());
+                cones.push(SecondOrderCone::new(cone_a, na::DVector::from_column_slice((horizontal * pos_b).as_slice()),
+                                                cone_c, self.up.dot(&pos_b)));
+            }
+        }
+        let c = na::DVector::from_fn(num_vars, |i, _| if i >= 3 * n { interval } else { 0.0 });
+        let x = solve_socp(&c, &a, &b, &cones)?;
+        Ok(PoweredDescentPlan {
+            interval,
+            accelerations: (0..n).map(|k| na::Vector3::new(x[3 * k], x[3 * k + 1], x[3 * k + 2])).collect(),
+            propellant: self.mass * (1.0 - (-flow_per_thrust * c.dot(&x)).exp()),
+        })
+    }
+}
+
+/// State at the end of a phase flown with E-guidance or G-FOLD, in the inertial frame at the current
+/// time with the velocity relative to the surface
+#[derive(Debug, Clone)]
+struct PoweredDescentTarget {
+    landing_site_i: na::Vector3<f64>,
+    pos_i: na::Vector3<f64>,
+    vel_i: na::Vector3<f64>,
+    time: Epoch,
+}
+
+/// Surveyor descent guidance (see `DescentGuidanceConfig`)
+///
+/// The solid retro-rocket is not modelled, so the vernier engines start tracking the descent
+/// contour at retro ignition. The descent contour and terminal descent phases can be flown with
+/// E-guidance or G-FOLD instead for trade studies.
+#[derive(Debug, Component)]
+pub struct DescentGuidance {
+    pub config: DescentGuidanceConfig,
+    /// Direction of the total vernier thrust in the body frame
+    thrust_axis_b: na::Vector3<f64>,
+    /// Range of thrust that all the engines can produce (N)
+    min_thrust: f64,
+    max_thrust: f64,
+    num_engines: usize,
+    /// Exhaust velocity of the engines (m/s)
+    exhaust_velocity: f64,
+    /// Gravitational parameter of the Moon (m^3/s^2)
+    mu: f64,
+    retro_ignition_time: Option<Epoch>,
+    /// Mass estimated from the commanded thrust (kg) and the time and total thrust of the last
+    /// command
+    mass: f64,
+    last_command: Option<(Epoch, f64)>,
+    powered_descent: PoweredDescentConfig,
+    /// Landing site in the Moon-fixed frame
+    landing_site_f: Option<na::Vector3<f64>>,
+    /// Time at which the target of the current phase is to be reached and the last G-FOLD solution
+    /// with the time it starts at
+    target_time: Option<Epoch>,
+    plan: Option<(Epoch, PoweredDescentPlan)>,
+    last_solve_time: Option<Epoch>,
+}
+
+impl DescentGuidance {
+    pub fn new(config: DescentGuidanceConfig, engines: &[ThrusterConfig]) -> Self {
+        let thrust_axis_b = engines.iter()
+            .map(|engine| engine.geometry.q_cf2b.transform_vector(&na::Vector3::z()))
+            .sum::<na::Vector3<f64>>().try_normalize(1e-9).unwrap_or(na::Vector3::z());
+        Self {
+            thrust_axis_b,
+            min_thrust: engines.iter().map(|engine| engine.min_thrust).fold(0.0, f64::max),
+            max_thrust: engines.iter().map(|engine| engine.max_thrust).fold(f64::INFINITY, f64::min),
+            num_engines: engines.len(),
+            exhaust_velocity: config.isp.unwrap_or(VERNIER_ISP) * STANDARD_GRAVITY,
+            mu: 4.9028695e12,
+            retro_ignition_time: None,
+            mass: config.mass,
+            last_command: None,
+            powered_descent: config.powered_descent.clone().unwrap_or_default(),
+            landing_site_f: config.powered_descent.as_ref().and_then(|powered_descent| powered_descent.landing_site_f.as_ref().map(|site| site.0)),
+            target_time: None,
+            plan: None,
+            last_solve_time: None,
+            config,
+        }
+    }
+    /// Schedules retro ignition after the first AMR mark
+    pub fn on_amr_mark(&mut self, time: Epoch) {
+        if self.retro_ignition_time.is_none() {
+            self.retro_ignition_time = Some(time + hifitime::Duration::from_seconds(self.config.retro_delay));
+        }
+    }
+    pub fn retro_ignition_time(&self) -> Option<Epoch> {
+        self.retro_ignition_time
+    }
+    /// Estimated mass of the spacecraft (kg)
+    pub fn mass(&self) -> f64 {
+        self.mass
+    }
+    /// Speed on the descent contour and its slope (1/s) at the given altitude. The contour is
+    /// interpolated linearly and held constant beyond its end points.
+    pub fn contour_speed(&self, altitude: f64) -> (f64, f64) {
+        let contour = &self.config.contour;
+        match contour.iter().position(|point| point.altitude >= altitude) {
+            None => (contour.last().map_or(0.0, |point| point.speed), 0.0),
+            Some(0) => (contour[0].speed, 0.0),
+            Some(i) => {
+                let (lower, upper) = (&contour[i - 1], &contour[i]);
+                let slope = (upper.speed - lower.speed) / (upper.altitude - lower.altitude);
+                (lower.speed + slope * (altitude - lower.altitude), slope)
+            }
+        }
+    }
+    /// Thrust of each engine for the given acceleration along the thrust axis
+    fn engine_thrust(&self, acceleration: f64) -> f64 {
+        (self.mass * acceleration / self.num_engines.max(1) as f64).clamp(self.min_thrust, self.max_thrust)
+    }
+    fn align_thrust(&self, direction_i: na::Vector3<f64>) -> AttitudeTarget {
+        AttitudeTarget::Align { align_with_b: Vector3(self.thrust_axis_b), align_to_i: Vector3(direction_i) }
+    }
+    /// Guidance law that flies the given phase
+    pub fn law(&self, phase: TrajectoryPhase) -> DescentGuidanceLaw {
+        match phase {
+            TrajectoryPhase::DescentContour => self.config.descent_law,
+            TrajectoryPhase::TerminalDescent => self.config.terminal_law,
+            _ => DescentGuidanceLaw::Surveyor,
+        }
+    }
+    /// End state of the phase above the landing site, which is chosen when it is first needed. The
+    /// site turns with the Moon, so it is converted to the inertial frame at every update.
+    fn powered_descent_target(&mut self, phase: TrajectoryPhase, state: &DescentState, time: Epoch) -> PoweredDescentTarget {
+        let q_i2f = moon_j2000_to_fixed(time);
+        let terminal_speed = self.config.terminal_speed;
+        let landing_site_f = *self.landing_site_f.get_or_insert_with(|| {
+            // Below the point where the horizontal velocity is removed at a constant deceleration
+            let up_i = state.pos_i.normalize();
+            let horizontal_vel_i = state.vel_i - up_i * state.vel_i.dot(&up_i);
+            let flight_time = 2.0 * state.altitude / (state.vel_i.norm() + terminal_speed);
+            q_i2f * (state.pos_i + horizontal_vel_i * flight_time / 2.0).normalize() * (state.pos_i.norm() - state.altitude)
+        });
+        let landing_site_i = q_i2f.inverse_transform_vector(&landing_site_f);
+        let site_up_i = landing_site_i.normalize();
+        let altitude = if phase == TrajectoryPhase::TerminalDescent { self.config.cutoff_altitude } else { self.config.terminal_altitude };
+        let pos_i = landing_site_i + site_up_i * altitude;
+        let target_time = *self.target_time.get_or_insert_with(|| {
+            let flight_time = 2.0 * (pos_i - state.pos_i).norm() / (state.vel_i.norm() + terminal_speed);
+            time + hifitime::Duration::from_seconds(flight_time)
+        });
+        PoweredDescentTarget { landing_site_i, pos_i, vel_i: -site_up_i * terminal_speed, time: target_time }
+    }
+    /// E-guidance: the acceleration varies linearly so that the target position and velocity are
+    /// reached at the target time. Returns the thrust acceleration.
+    fn e_guidance(target: &PoweredDescentTarget, state: &DescentState, gravity_i: &na::Vector3<f64>, time: Epoch) -> na::Vector3<f64> {
+        let time_to_go = (target.time - time).to_seconds();
+        let position_error = target.pos_i - state.pos_i - state.vel_i * time_to_go;
+        let velocity_error = target.vel_i - state.vel_i;
+        position_error * (6.0 / (time_to_go * time_to_go)) - velocity_error * (2.0 / time_to_go) - gravity_i
+    }
+    /// G-FOLD: follows the thrust acceleration of the last solution, which is updated at the
+    /// replanning interval for the remaining flight time of that solution. The flight time is only
+    /// searched for the least propellant when there is no solution to start from. E-guidance is
+    /// flown until the next replan if no solution can be found, including when the target can no
+    /// longer be reached at the planned time.
+    fn gfold(&mut self, target: &PoweredDescentTarget, state: &DescentState, gravity_i: &na::Vector3<f64>, time: Epoch) -> na::Vector3<f64> {
+        let replan_interval = self.powered_descent.replan_interval;
+        let time_to_go = (target.time - time).to_seconds();
+        let replan = self.last_solve_time.is_none_or(|solve_time| (time - solve_time).to_seconds() >= replan_interval)
+            && (self.plan.is_none() || time_to_go > replan_interval);
+        if replan {
+            self.last_solve_time = Some(time);
+            let thrust_margin = GFOLD_THRUST_MARGIN * (self.max_thrust - self.min_thrust);
+            let problem = |flight_time: f64| PoweredDescentProblem {
+                pos: state.pos_i - target.landing_site_i,
+                vel: state.vel_i,
+                target_pos: target.pos_i - target.landing_site_i,
+                target_vel: target.vel_i,
+                gravity: *gravity_i,
+                up: target.landing_site_i.normalize(),
+                glide_slope_angle: self.powered_descent.glide_slope_angle,
+                max_tilt_angle: self.powered_descent.max_tilt_angle,
+                mass: self.mass,
+                min_thrust: (self.min_thrust + thrust_margin) * self.num_engines as f64,
+                max_thrust: (self.max_thrust - thrust_margin) * self.num_engines as f64,
+                exhaust_velocity: self.exhaust_velocity,
+                flight_time,
+                num_intervals: self.powered_descent.num_nodes,
+            };
+            let search_flight_time = || GFOLD_FLIGHT_TIME_FACTORS.iter()
+                .filter_map(|factor| problem(factor * time_to_go.max(MIN_TIME_TO_GO)).solve().ok())
+                .min_by(|plan, other| plan.propellant.total_cmp(&other.propellant))
+                .ok_or_else(|| "No feasible flight time".to_string());
+            let solution = match self.plan {
+                Some(_) => problem(time_to_go).solve(),
+                None => search_flight_time(),
+            };
+            match solution {
+                Ok(plan) => {
+                    let flight_time = plan.interval * plan.accelerations.len() as f64;
+                    self.target_time = Some(time + hifitime::Duration::from_seconds(flight_time));
+                    self.plan = Some((time, plan));
+                }
+                Err(err) => {
+                    log::warn!("G-FOLD failed at {}: {}", time, err);
+                    self.plan = None;
+                }
+            }
+        }
+        match &self.plan {
+            Some((start_time, plan)) => {
+                let k = ((time - *start_time).to_seconds() / plan.interval) as usize;
+                plan.accelerations[k.min(plan.accelerations.len() - 1)]
+            }
+            None => Self::e_guidance(target, state, gravity_i, time),
+        }
+    }
+    /// Points the thrust along the thrust acceleration
+    fn thrust_command(&self, thrust_acc_i: na::Vector3<f64>, up_i: na::Vector3<f64>) -> DescentCommand {
+        let magnitude = thrust_acc_i.norm();
+        let direction_i = if magnitude > 1e-9 { thrust_acc_i / magnitude } else { up_i };
+        DescentCommand { attitude_target: self.align_thrust(direction_i), base_thrust: self.engine_thrust(magnitude) }
+    }
+    /// Advances the trajectory phase and returns the commands for the current state
+    pub fn update(&mut self, phase: &mut TrajectoryPhase, state: &DescentState, time: Epoch) -> DescentCommand {
+        if let Some((last_time, thrust)) = self.last_command {
+            self.mass -= thrust / self.exhaust_velocity * (time - last_time).to_seconds();
+        }
+        let command = self.command(phase, state, time);
+        self.last_command = Some((time, command.base_thrust * self.num_engines as f64));
+        command
+    }
+    fn command(&mut self, phase: &mut TrajectoryPhase, state: &DescentState, time: Epoch) -> DescentCommand {
+        let top_altitude = self.config.contour.last().map_or(f64::INFINITY, |point| point.altitude);
+        let retro_ignited = self.retro_ignition_time.is_some_and(|ignition_time| time >= ignition_time);
+        let next_phase = match *phase {
+            TrajectoryPhase::BeforeRetroBurn if retro_ignited || state.altitude <= top_altitude => Some(TrajectoryPhase::DescentContour),
+            TrajectoryPhase::DescentContour if state.altitude <= self.config.terminal_altitude => Some(TrajectoryPhase::TerminalDescent),
+            TrajectoryPhase::TerminalDescent if state.altitude <= self.config.cutoff_altitude => Some(TrajectoryPhase::Landed),
+            _ => None,
+        };
+        if let Some(next_phase) = next_phase {
+            log::info!("Trajectory phase {:?} at {} (altitude {:.1} m)", next_phase, time, state.altitude);
+            *phase = next_phase;
+            self.target_time = None;
+            self.plan = None;
+            self.last_solve_time = None;
+        }
+
+        let up_i = state.pos_i.normalize();
+        let gravity = self.mu / state.pos_i.norm_squared();
+        let speed = state.vel_i.norm();
+        let retro_direction_i = if speed > 1e-3 { -state.vel_i / speed } else { up_i };
+        match *phase {
+            TrajectoryPhase::BeforeRetroBurn => DescentCommand {
+                attitude_target: self.align_thrust(retro_direction_i),
+                base_thrust: 0.0,
+            },
+            TrajectoryPhase::DescentContour | TrajectoryPhase::TerminalDescent if self.law(*phase) != DescentGuidanceLaw::Surveyor => {
+                let target = self.powered_descent_target(*phase, state, time);
+                let gravity_i = -up_i * gravity;
+                let thrust_acc_i = if (target.time - time).to_seconds() < MIN_TIME_TO_GO {
+                    (target.vel_i - state.vel_i) / MIN_TIME_TO_GO - gravity_i
+                } else if self.law(*phase) == DescentGuidanceLaw::Gfold {
+                    self.gfold(&target, state, &gravity_i, time)
+                } else {
+                    Self::e_guidance(&target, state, &gravity_i, time)
+                };
+                self.thrust_command(thrust_acc_i, up_i)
+            }
+            TrajectoryPhase::DescentContour => {
+                // Gravity turn: the thrust opposes the velocity, so only the thrust and the
+                // component of gravity along the velocity change the speed
+                let (contour_speed, contour_slope) = self.contour_speed(state.altitude);
+                let gravity_along_velocity = gravity * retro_direction_i.dot(&up_i);
+                let contour_deceleration = -contour_slope * state.vel_i.dot(&up_i);
+                let acceleration = gravity_along_velocity + contour_deceleration + self.config.speed_gain * (speed - contour_speed);
+                DescentCommand {
+                    attitude_target: self.align_thrust(retro_direction_i),
+                    base_thrust: self.engine_thrust(acceleration),
+                }
+            }
+            TrajectoryPhase::TerminalDescent => {
+                let descent_rate = -state.vel_i.dot(&up_i);
+                let acceleration = gravity + self.config.speed_gain * (descent_rate - self.config.terminal_speed);
+                DescentCommand {
+                    attitude_target: self.align_thrust(up_i),
+                    base_thrust: self.engine_thrust(acceleration),
+                }
+            }
+            TrajectoryPhase::Landed => DescentCommand { attitude_target: AttitudeTarget::None, base_thrust: 0.0 },
+        }
+    }
+}
+
+/// Runs the descent guidance in the descent mode. The RADVS altitude and velocity are used when
+/// available and the navigation state otherwise.
+pub fn update_descent_guidance(mode_query: Query<&GuidanceMode>,
+                               mut guidance_query: Query<(&mut DescentGuidance, &mut TrajectoryPhase)>,
+                               mut amr_mark_reader: EventReader<AmrMark>,
+                               mut navigation_reader: EventReader<NavigationState>,
+                               mut attitude_reader: EventReader<AttitudeEstimatorOutput>,
+                               mut radvs_reader: EventReader<RadvsOutput>,
+                               mut attitude_target_writer: EventWriter<AttitudeTarget>,
+                               mut thrust_command_writer: EventWriter<VernierThrustCommand>,
+                               clock: Res<SystemClock>) {
+    let Ok((mut guidance, mut phase)) = guidance_query.get_single_mut() else {
+        return;
+    };
+    // The retro ignition is scheduled even if the mark comes before the descent mode is entered
+    for mark in amr_mark_reader.read() {
+        guidance.on_amr_mark(mark.time);
+    }
+    let navigation = navigation_reader.read().last().cloned();
+    let attitude = attitude_reader.read().last().cloned();
+    let radvs = radvs_reader.read().last().cloned();
+    if mode_query.get_single().ok() != Some(&GuidanceMode::Descent) {
+        return;
+    }
+    let Some(navigation) = navigation.filter(|navigation| navigation.valid) else {
+        return;
+    };
+    // The navigation velocity is inertial and the RADVS velocity relative to the surface
+    let surface_vel_i = navigation.vel_i - moon_angular_velocity_j2000(clock.time).cross(&navigation.pos_i);
+    let mut state = DescentState { pos_i: navigation.pos_i, vel_i: surface_vel_i, altitude: navigation.altitude };
+    if let (Some(radvs), Some(attitude)) = (radvs, attitude.filter(|attitude| attitude.valid)) {
+        if radvs.range_valid {
+            let down_b = attitude.q_i2b.inverse_transform_vector(&-navigation.pos_i.normalize());
+            state.altitude = radvs.slant_range * radvs.range_beam_b.dot(&down_b);
+        }
+        if radvs.velocity_valid {
+            state.vel_i = attitude.q_i2b * radvs.vel_b;
+        }
+    }
+    let command = guidance.update(&mut phase, &state, clock.time);
+    attitude_target_writer.send(command.attitude_target);
+    thrust_command_writer.send(VernierThrustCommand { base_thrust: command.base_thrust });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surveyor_types::config::GeometryParams;
+    use surveyor_types::math::UnitQuaternion;
+
+    fn create_vernier_engines() -> Vec<ThrusterConfig> {
+        [na::Vector3::new(0.5, 0.0, -0.5), na::Vector3::new(-0.25, 0.43, -0.5), na::Vector3::new(-0.25, -0.43, -0.5)].iter()
+            .map(|&offset| ThrusterConfig {
+                name: String::new(),
+                geometry: GeometryParams { q_cf2b: UnitQuaternion(na::UnitQuaternion::identity()), cf_offset_com_b: Vector3(offset) },
+                max_thrust: 462.61,
+                min_thrust: 133.45,
+                tvc: None,
+            }).collect()
+    }
+
+    /// Vertical descent of a point mass that is always pointed as commanded
+    #[test]
+    fn test_descent_guidance() {
+        let mut guidance = DescentGuidance::new(DescentGuidanceConfig::default(), &create_vernier_engines());
+        let mut phase = TrajectoryPhase::BeforeRetroBurn;
+        let radius = 1737400.0;
+        let mut time = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let mut altitude = 15000.0;
+        let mut descent_rate = 150.0;
+        guidance.on_amr_mark(time);
+        let dt = 0.05;
+        let mut phases = vec![phase];
+        while altitude > 0.0 {
+            let state = DescentState {
+                pos_i: na::Vector3::x() * (radius + altitude),
+                vel_i: -na::Vector3::x() * descent_rate,
+                altitude,
+            };
+            let command = guidance.update(&mut phase, &state, time);
+            if phases.last() != Some(&phase) {
+                phases.push(phase);
+                if phase == TrajectoryPhase::Landed {
+                    // The engines are shut down at the terminal speed
+                    assert!((descent_rate - 1.524).abs() < 0.1, "{}", descent_rate);
+                }
+            }
+            let thrust_acc = match &command.attitude_target {
+                AttitudeTarget::Align { align_with_b, align_to_i } => {
+                    assert_eq!(align_with_b.0, na::Vector3::z());
+                    assert!((align_to_i.0 - na::Vector3::x()).norm() < 1e-9);
+                    3.0 * command.base_thrust / guidance.mass()
+                }
+                _ => {
+                    assert_eq!(phase, TrajectoryPhase::Landed);
+                    assert_eq!(command.base_thrust, 0.0);
+                    0.0
+                }
+            };
+            if phase == TrajectoryPhase::BeforeRetroBurn {
+                assert_eq!(command.base_thrust, 0.0);
+            }
+            let gravity = guidance.mu / (radius + altitude).powi(2);
+            descent_rate += (gravity - thrust_acc) * dt;
+            altitude -= descent_rate * dt;
+            time += hifitime::Duration::from_seconds(dt);
+            if phase == TrajectoryPhase::DescentContour && altitude < 5000.0 && altitude > 100.0 {
+                // The contour is tracked once the initial speed error has been removed
+                assert!((descent_rate - guidance.contour_speed(altitude).0).abs() < 2.0);
+            }
+        }
+        assert_eq!(phases, vec![TrajectoryPhase::BeforeRetroBurn, TrajectoryPhase::DescentContour,
+                                TrajectoryPhase::TerminalDescent, TrajectoryPhase::Landed]);
+        // Free fall from the cutoff altitude
+        assert!(descent_rate < 5.0, "{}", descent_rate);
+
+        // Gravity turn: the thrust opposes the surface-relative velocity
+        let state = DescentState {
+            pos_i: na::Vector3::x() * (radius + 5000.0),
+            vel_i: na::Vector3::new(-60.0, 80.0, 0.0),
+            altitude: 5000.0,
+        };
+        let mut phase = TrajectoryPhase::DescentContour;
+        let command = guidance.update(&mut phase, &state, time);
+        assert_eq!(phase, TrajectoryPhase::DescentContour);
+        let AttitudeTarget::Align { align_to_i, .. } = command.attitude_target else {
+            panic!("Expected an alignment target");
+        };
+        assert!((align_to_i.0 - na::Vector3::new(0.6, -0.8, 0.0)).norm() < 1e-9);
+        assert!(command.base_thrust >= 133.45 && command.base_thrust <= 462.61);
+    }
+    /// The RADVS measurements are rotated from the body frame with the estimated attitude
+    #[test]
+    fn test_descent_guidance_system() {
+        let mut app = bevy_app::App::new();
+        app.add_event::<AmrMark>()
+            .add_event::<NavigationState>()
+            .add_event::<AttitudeEstimatorOutput>()
+            .add_event::<RadvsOutput>()
+            .add_event::<AttitudeTarget>()
+            .add_event::<VernierThrustCommand>()
+            .insert_resource(SystemClock::default())
+            .add_systems(bevy_app::Update, update_descent_guidance);
+        app.world.spawn(GuidanceMode::Descent);
+        app.world.spawn((DescentGuidance::new(DescentGuidanceConfig::default(), &create_vernier_engines()), TrajectoryPhase::DescentContour));
+
+        let radius = 1737400.0;
+        let pos_i = na::Vector3::x() * (radius + 5000.0);
+        let vel_i = na::Vector3::new(-60.0, 80.0, 0.0);
+        let q_i2b = na::UnitQuaternion::from_euler_angles(0.3, -0.2, 1.0);
+        let mut run = |slant_range: f64| {
+            // The navigation filter is off in altitude and velocity, the RADVS is not
+            app.world.send_event(NavigationState { pos_i, vel_i: na::Vector3::new(-100.0, 0.0, 0.0), altitude: 5000.0, valid: true, ..Default::default() });
+            app.world.send_event(AttitudeEstimatorOutput { q_i2b, valid: true, ..Default::default() });
+            app.world.send_event(RadvsOutput {
+                vel_b: q_i2b.inverse_transform_vector(&vel_i),
+                slant_range,
+                range_beam_b: q_i2b.inverse_transform_vector(&-na::Vector3::x()),
+                velocity_valid: true,
+                range_valid: true,
+                ..Default::default()
+            });
+            app.update();
+            let events = app.world.resource::<Events<AttitudeTarget>>();
+            let target = events.get_reader().read(events).last().unwrap().clone();
+            (target, *app.world.query::<&TrajectoryPhase>().single(&app.world))
+        };
+
+        // Gravity turn against the RADVS velocity
+        let (target, phase) = run(4000.0);
+        assert_eq!(phase, TrajectoryPhase::DescentContour);
+        let AttitudeTarget::Align { align_to_i, .. } = target else {
+            panic!("Expected an alignment target");
+        };
+        assert!((align_to_i.0 - na::Vector3::new(0.6, -0.8, 0.0)).norm() < 1e-9);
+        // The RADVS altitude triggers the terminal descent
+        let (_, phase) = run(10.0);
+        assert_eq!(phase, TrajectoryPhase::TerminalDescent);
+    }
+    /// The specific impulse and the parameters of the powered descent are optional
+    #[test]
+    fn test_descent_guidance_config() {
+        use hard_xml::XmlRead;
+        let config = DescentGuidanceConfig::from_str(r#"
+            <DescentGuidance>
+                <mass>300.0</mass>
+                <retro_delay>8.0</retro_delay>
+                <speed_gain>0.5</speed_gain>
+                <ContourPoint altitude="12.2" speed="1.524"/>
+                <terminal_altitude>12.2</terminal_altitude>
+                <terminal_speed>1.524</terminal_speed>
+                <cutoff_altitude>4.267</cutoff_altitude>
+                <descent_law>GFOLD</descent_law>
+            </DescentGuidance>"#).unwrap();
+        assert_eq!(config.isp, None);
+        assert_eq!(config.descent_law, DescentGuidanceLaw::Gfold);
+        assert_eq!(config.terminal_law, DescentGuidanceLaw::Surveyor);
+        let guidance = DescentGuidance::new(config, &create_vernier_engines());
+        assert_eq!(guidance.exhaust_velocity, VERNIER_ISP * STANDARD_GRAVITY);
+        // Only the names in simulation.xml are accepted
+        for name in ["Surveyor", "EGuidance", "GFOLD"] {
+            assert!(name.parse::<DescentGuidanceLaw>().is_ok(), "{}", name);
+        }
+        for name in ["Contour", "Polynomial", "Convex", "Gfold"] {
+            assert!(name.parse::<DescentGuidanceLaw>().is_err(), "{}", name);
+        }
+    }
+    /// Descents from 1.5 km with a horizontal offset to a landing site with E-guidance and G-FOLD
+    #[test]
+    fn test_powered_descent() {
+        let radius = 1737400.0;
+
+        // The G-FOLD trajectory reaches the target within the thrust limits
+        let problem = PoweredDescentProblem {
+            pos: na::Vector3::new(1500.0, 0.0, 0.0),
+            vel: na::Vector3::new(-60.0, 20.0, 0.0),
+            target_pos: na::Vector3::new(12.2, 0.0, 0.0),
+            target_vel: na::Vector3::new(-1.524, 0.0, 0.0),
+            gravity: na::Vector3::new(-1.62, 0.0, 0.0),
+            up: na::Vector3::x(),
+            glide_slope_angle: 0.035,
+            max_tilt_angle: 0.785,
+            mass: 300.0,
+            min_thrust: 3.0 * 133.45,
+            max_thrust: 3.0 * 462.61,
+            exhaust_velocity: 287.0 * STANDARD_GRAVITY,
+            flight_time: 50.0,
+            num_intervals: 20,
+        };
+        let plan = problem.solve().unwrap();
+        let (mut pos, mut vel, mut mass) = (problem.pos, problem.vel, problem.mass);
+        for acceleration in &plan.accelerations {
+            let thrust = acceleration.norm() * mass;
+            assert!(thrust > 0.99 * problem.min_thrust && thrust < 1.01 * problem.max_thrust, "{}", thrust);
+            pos += vel * plan.interval + (acceleration + problem.gravity) * (plan.interval * plan.interval / 2.0);
+            vel += (acceleration + problem.gravity) * plan.interval;
+            mass -= thrust / problem.exhaust_velocity * plan.interval;
+            assert!(pos.x >= pos.yz().norm() * problem.glide_slope_angle.tan() - 1e-3);
+            assert!(acceleration.x >= acceleration.norm() * problem.max_tilt_angle.cos() - 1e-6);
+        }
+        assert!((pos - problem.target_pos).norm() < 1e-3 && (vel - problem.target_vel).norm() < 1e-3);
+        assert!((problem.mass - mass - plan.propellant).abs() < 0.1);
+        // There is no solution if the flight time is too short
+        assert!(PoweredDescentProblem { flight_time: 10.0, ..problem }.solve().is_err());
+
+        // The descent is flown in the Moon-fixed frame. The landing site moves about 250 m in the
+        // inertial frame over the descent.
+        let start_time = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_i2f = moon_j2000_to_fixed(start_time);
+        let omega_f = na::Vector3::z() * crate::navigation::MOON_ROTATION_RATE;
+        let landing_site_f = q_i2f * na::Vector3::new(radius, 400.0, 0.0);
+        let site_up_f = landing_site_f.normalize();
+        let horizontal_offset = |pos_f: &na::Vector3<f64>| {
+            let offset_f = pos_f - landing_site_f;
+            (offset_f - site_up_f * offset_f.dot(&site_up_f)).norm()
+        };
+        for law in [DescentGuidanceLaw::EGuidance, DescentGuidanceLaw::Gfold] {
+            let config = DescentGuidanceConfig {
+                descent_law: law,
+                terminal_law: law,
+                powered_descent: Some(PoweredDescentConfig {
+                    landing_site_f: Some(Vector3(landing_site_f)),
+                    replan_interval: 5.0,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            let mut guidance = DescentGuidance::new(config, &create_vernier_engines());
+            let mut phase = TrajectoryPhase::DescentContour;
+            let mut time = start_time;
+            let mut pos_f = q_i2f * na::Vector3::new(radius + 1500.0, 0.0, 0.0);
+            let mut vel_f = q_i2f * na::Vector3::new(-60.0, 20.0, 0.0);
+            let dt = 0.05;
+            while phase != TrajectoryPhase::Landed {
+                let q_f2i = moon_j2000_to_fixed(time).inverse();
+                let state = DescentState { pos_i: q_f2i * pos_f, vel_i: q_f2i * vel_f, altitude: pos_f.norm() - radius };
+                let last_phase = phase;
+                let command = guidance.update(&mut phase, &state, time);
+                if last_phase == TrajectoryPhase::DescentContour && phase == TrajectoryPhase::TerminalDescent {
+                    // Arrives at the terminal gate above the landing site
+                    assert!(horizontal_offset(&pos_f) < 2.0, "{:?}: {}", law, pos_f - landing_site_f);
+                    assert!((vel_f + site_up_f * 1.524).norm() < 1.0, "{:?}: {}", law, vel_f);
+                }
+                let thrust_acc_f = match &command.attitude_target {
+                    AttitudeTarget::Align { align_to_i, .. } => q_f2i.inverse_transform_vector(&align_to_i.0) * 3.0 * command.base_thrust / guidance.mass(),
+                    _ => na::Vector3::zeros(),
+                };
+                let gravity_f = -pos_f * guidance.mu / pos_f.norm().powi(3);
+                let inertial_acc_f = -2.0 * omega_f.cross(&vel_f) - omega_f.cross(&omega_f.cross(&pos_f));
+                vel_f += (thrust_acc_f + gravity_f + inertial_acc_f) * dt;
+                pos_f += vel_f * dt;
+                time += hifitime::Duration::from_seconds(dt);
+                assert!((time - start_time).to_seconds() < 300.0, "{:?} did not land", law);
+            }
+            assert!(horizontal_offset(&pos_f) < 2.0, "{:?}: {}", law, pos_f - landing_site_f);
+            assert!((vel_f.norm() - 1.524).abs() < 0.5, "{:?}: {}", law, vel_f);
+        }
+    }
+}
@@ -8,15 +8,20 @@ pub mod navigation;
 pub mod guidance;
 pub mod control;
 pub mod clock;
+pub mod optimization;
 
 use bevy::core::Name;
 use bevy_ecs::prelude::*;
 use clock::TimeTickEvent;
-use control::{update_attitude_controller, update_control_allocator, update_rcs_controller, RCSController};
-use guidance::update_guidance;
+use control::{update_attitude_controller, update_control_allocator, update_rcs_controller, AttitudeController, RCSController};
+use control::{update_reaction_wheel_controller, update_reaction_wheel_telemetry, ReactionWheelController};
+use control::{update_magnetorquer_controller, update_magnetorquer_field, MagnetorquerController};
+use control::{update_engine_telemetry, update_vernier_controller, VernierAttitudeController};
+use guidance::{update_descent_guidance, update_guidance, DescentGuidance};
+pub use guidance::TrajectoryPhase;
 
-use navigation::{update_simple_attitude_estimator, update_sensor_aggregator};
-use sensors::{update_imu, update_star_tracker};
+use navigation::{update_attitude_estimator, update_navigation_filter, update_sensor_aggregator};
+use sensors::{update_imu, update_magnetometer, update_star_tracker, update_sun_sensors, update_amr, update_radvs, update_camera};
 
 use dashmap::DashMap;
 
@@ -26,6 +31,30 @@ use bevy_ecs::schedule::IntoSystemConfigs;
 
 pub struct SurveyorGNC {
     pub entities: DashMap<String, Entity>,
+    /// Sensors and actuators as known to the FSW (see `SpacecraftConfig::fsw_subsystems`)
+    pub imus: Vec<ImuConfig>,
+    pub star_trackers: Vec<StarTrackerConfig>,
+    pub star_sensors: Vec<StarSensorConfig>,
+    pub sun_sensors: Vec<SunSensorConfig>,
+    pub amrs: Vec<AmrConfig>,
+    pub radvs_beams: Vec<RadvsBeamConfig>,
+    pub cameras: Vec<CameraConfig>,
+    pub rcs_thrusters: Vec<ThrusterConfig>,
+    pub vernier_engines: Vec<ThrusterConfig>,
+    /// Reaction wheels available to the attitude controller (Surveyor itself did not have any)
+    pub reaction_wheels: Vec<ReactionWheelConfig>,
+    /// Magnetometers and magnetorquers used to unload the reaction wheels
+    pub magnetometers: Vec<MagnetometerConfig>,
+    pub magnetorquers: Vec<MagnetorquerConfig>,
+    /// Gains and limits of the attitude controller
+    pub attitude_controller: AttitudeControllerConfig,
+    /// Pulses the RCS thrusters instead of throttling them continuously
+    pub rcs_modulator: Option<RcsModulatorConfig>,
+    /// Descent and landing guidance used in the descent mode
+    pub descent_guidance: DescentGuidanceConfig,
+    /// Settings of the attitude estimator and the navigation filter
+    pub attitude_estimator: AttitudeEstimatorParams,
+    pub navigation_filter: NavigationFilterParams,
 }
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone)]
@@ -61,28 +90,55 @@ impl Plugin for SurveyorGNC {
             .add_event::<sensors::StarSensorInput>()
             .add_systems(Update, update_imu.in_set(SurveyorGncSystemSet::Sensors))
             .add_systems(Update, update_star_tracker.in_set(SurveyorGncSystemSet::Sensors))
+            .add_event::<sensors::MagnetometerOutput>()
+            .add_event::<sensors::MagnetometerInput>()
             .add_systems(Update, update_star_sensor.in_set(SurveyorGncSystemSet::Sensors))
+            .add_systems(Update, update_magnetometer.in_set(SurveyorGncSystemSet::Sensors))
+            .add_event::<sensors::CoarseSunSensorInput>()
+            .add_event::<sensors::FineSunSensorInput>()
+            .add_event::<sensors::SunSensorOutput>()
+            .add_systems(Update, update_sun_sensors.in_set(SurveyorGncSystemSet::Sensors))
+            .add_event::<sensors::AmrInput>()
+            .add_event::<sensors::AmrOutput>()
+            .add_event::<sensors::AmrMark>()
+            .add_systems(Update, update_amr.in_set(SurveyorGncSystemSet::Sensors))
+            .add_event::<sensors::RadvsDopplerInput>()
+            .add_event::<sensors::RadvsRangeInput>()
+            .add_event::<sensors::RadvsOutput>()
+            .add_systems(Update, update_radvs.in_set(SurveyorGncSystemSet::Sensors))
+            .add_event::<sensors::CameraInput>()
+            .add_event::<sensors::CameraImage>()
+            .add_systems(Update, update_camera.in_set(SurveyorGncSystemSet::Sensors))
 
             // Navigation
             .add_event::<navigation::SensorData>()
             .add_event::<navigation::AttitudeEstimatorOutput>()
+            .add_event::<navigation::NavigationState>()
             .add_systems(Update,
-                (update_sensor_aggregator, update_simple_attitude_estimator)
+                (update_sensor_aggregator, update_attitude_estimator, update_navigation_filter)
                     .chain().in_set(SurveyorGncSystemSet::Navigation)
             )
 
             // Guidance
             .add_event::<guidance::AttitudeTarget>()
-            .add_systems(Update, update_guidance.in_set(SurveyorGncSystemSet::Guidance))
+            .add_systems(Update, (update_guidance, update_descent_guidance).chain().in_set(SurveyorGncSystemSet::Guidance))
 
             // Control
             .add_event::<control::AttitudeTorqueRequest>()
             .add_event::<control::RCSTorqueRequest>()
-            .add_event::<control::TVCTorqueRequest>()
-            .add_event::<control::TVCControllerOutput>()
+            .add_event::<control::VernierThrustCommand>()
             .add_event::<control::VernierTorqueRequest>()
+            .add_event::<control::VernierEngineControllerOutput>()
             .add_event::<control::RCSControllerOutput>()
-            .add_systems(Update, (update_attitude_controller, update_control_allocator, update_rcs_controller).chain()
+            .add_event::<control::ReactionWheelTelemetry>()
+            .add_event::<control::EngineTelemetry>()
+            .add_event::<control::ReactionWheelTorqueRequest>()
+            .add_event::<control::ReactionWheelControllerOutput>()
+            .add_event::<control::MagnetorquerTorqueRequest>()
+            .add_event::<control::MagnetorquerControllerOutput>()
+            .add_systems(Update, (update_reaction_wheel_telemetry, update_engine_telemetry, update_attitude_controller, update_magnetorquer_field, update_control_allocator,
+                                  update_rcs_controller, update_reaction_wheel_controller, update_magnetorquer_controller,
+                                  update_vernier_controller).chain()
                 .in_set(SurveyorGncSystemSet::Control)
             );
 
@@ -94,24 +150,7 @@ impl Plugin for SurveyorGNC {
 
         let traj = app.world.spawn((Name::new("TrajectoryPhase"), TrajectoryPhase::BeforeRetroBurn,)).id();
 
-        let imu_config_xml = vec![r#"
-            <Imu name="A">
-                <geometry>
-                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
-                </geometry>
-            </Imu>
-        "#, r#"
-        <Imu name="B">
-                <geometry>
-                    <q_cf2b>[0.0, 0.0, 0.0, 1.0]</q_cf2b>
-                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
-                </geometry>
-            </Imu>
-        "#];
-
-        let imu_configs = imu_config_xml.iter().map(|c| ImuConfig::from_str(c).unwrap()).collect::<Vec<_>>();
-        for imu_config in imu_configs {
+        for imu_config in &self.imus {
             let imu = app.world.spawn((
                 Name::new(imu_config.name.clone()),
                 sensors::IMU,
@@ -120,126 +159,130 @@ impl Plugin for SurveyorGNC {
             self.entities.insert(imu_config.name.to_string(), imu);
         }
 
-        let st_config_xml = r#"<StarTracker name="A">
-                <geometry>
-                    <!-- Points boresight along -Y (body frame) -->
-                    <q_cf2b>[0.7071068, 0.0, 0.7071068, 0.0]</q_cf2b>
-                    <!-- Random -->
-                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
-                </geometry>
-            </StarTracker>"#;
-        let st_config = StarTrackerConfig::from_str(st_config_xml).unwrap();
-        let star_tracker = app.world.spawn((
-            Name::new(st_config.name.clone()),
-            sensors::StarTracker,
-            GeometryConfig::from_geometry_params(&st_config.geometry)
-        )).id();
-
-
-        let star_sensor_config_xml = r#"
-            <StarSensor name="Canopus">
-                <geometry>
-                    <!-- Points boresight along -Y (body frame) -->
-                    <q_cf2b>[0.7071068, 0.0, 0.7071068, 0.0]</q_cf2b>
-                    <!-- Random -->
-                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
-                </geometry>
-                <!-- 45 deg FoV (22.5 deg half angle)-->
-                <fov_deg>22.5</fov_deg>
-                <!-- Canopus:
-                    Right ascension	06h 23m 57.10988s
-                    Declination	−52° 41' 44.3810"
-                -->
-                <right_ascension_deg>95.9875</right_ascension_deg>
-                <declinaton_deg>52.695661389</declinaton_deg>
-            </StarSensor>
-        "#;
-        let st_config = StarSensorConfig::from_str(star_sensor_config_xml).unwrap();
-        let star_sensor = app.world.spawn((
-            Name::new(st_config.name.clone()),
-            sensors::StarSensor,
-            GeometryConfig::from_geometry_params(&st_config.geometry)
-        )).id();
+        for st_config in &self.star_trackers {
+            let star_tracker = app.world.spawn((
+                Name::new(st_config.name.clone()),
+                sensors::StarTracker,
+                GeometryConfig::from_geometry_params(&st_config.geometry)
+            )).id();
+            self.entities.insert(format!("ST_{}", st_config.name), star_tracker);
+        }
+
+        for star_sensor_config in &self.star_sensors {
+            let star_sensor = app.world.spawn((
+                Name::new(star_sensor_config.name.clone()),
+                sensors::StarSensor,
+                GeometryConfig::from_geometry_params(&star_sensor_config.geometry)
+            )).id();
+            self.entities.insert(format!("StarSensor_{}", star_sensor_config.name), star_sensor);
+        }
+
+        for ss_config in &self.sun_sensors {
+            let geometry = GeometryConfig::from_geometry_params(&ss_config.geometry);
+            let sun_sensor = match ss_config.sensor_type {
+                SunSensorType::Coarse => app.world.spawn((Name::new(ss_config.name.clone()), sensors::CoarseSunSensor, geometry)),
+                SunSensorType::Fine => app.world.spawn((Name::new(ss_config.name.clone()), sensors::FineSunSensor, geometry)),
+            }.id();
+            self.entities.insert(ss_config.name.to_string(), sun_sensor);
+        }
+
+        // The AMR only provides a slant range, so its geometry is not needed by the FSW
+        for amr_config in &self.amrs {
+            let amr = app.world.spawn((Name::new(amr_config.name.clone()), sensors::Amr)).id();
+            self.entities.insert(amr_config.name.to_string(), amr);
+        }
+
+        for beam_config in &self.radvs_beams {
+            let geometry = GeometryConfig::from_geometry_params(&beam_config.geometry);
+            let beam = match beam_config.beam_type {
+                RadvsBeamType::Doppler => app.world.spawn((Name::new(beam_config.name.clone()), sensors::RadvsDopplerBeam, geometry)),
+                RadvsBeamType::Range => app.world.spawn((Name::new(beam_config.name.clone()), sensors::RadvsRangeBeam, geometry)),
+            }.id();
+            self.entities.insert(beam_config.name.to_string(), beam);
+        }
+
+        for cam_config in &self.cameras {
+            let geometry = GeometryConfig::from_geometry_params(&cam_config.geometry);
+            let camera = app.world.spawn((Name::new(cam_config.name.clone()),
+                sensors::Camera { focal_length_px: cam_config.focal_length_px() }, geometry)).id();
+            self.entities.insert(cam_config.name.to_string(), camera);
+        }
+
+        // The sensor aggregator knows about all the sensors configured above
+        let fine_sun_sensors = self.sun_sensors.iter().filter(|ss| ss.sensor_type == SunSensorType::Fine);
+        let has_coarse_sun_sensors = self.sun_sensors.iter().any(|ss| ss.sensor_type == SunSensorType::Coarse);
+        let mut aggregator_config = navigation::SensorAggregatorConfig::default();
+        aggregator_config.imus.sensors = self.imus.iter().map(|imu| imu.name.clone()).collect();
+        aggregator_config.star_trackers.sensors = self.star_trackers.iter().map(|st| st.name.clone()).collect();
+        aggregator_config.star_sensors.sensors = self.star_sensors.iter().map(|ss| ss.name.clone()).collect();
+        aggregator_config.magnetometers.sensors = self.magnetometers.iter().map(|mag| mag.name.clone()).collect();
+        aggregator_config.sun_sensors.sensors = fine_sun_sensors.map(|ss| ss.name.clone())
+            .chain(has_coarse_sun_sensors.then(|| sensors::COARSE_SUN_SENSOR_NAME.to_string())).collect();
+        aggregator_config.amrs.sensors = self.amrs.iter().map(|amr| amr.name.clone()).collect();
+        aggregator_config.radvs.sensors = if self.radvs_beams.is_empty() { vec![] } else { vec![sensors::RADVS_NAME.to_string()] };
+        aggregator_config.cameras.sensors = self.cameras.iter().map(|cam| cam.name.clone()).collect();
+        let sensor_aggregator = app.world.spawn((Name::new("SensorAggregator"), navigation::SensorAggregator::new(aggregator_config))).id();
+        self.entities.insert("SensorAggregator".to_string(), sensor_aggregator);
 
         let guidance = app.world.spawn((Name::new("SurveyorGNCMode"), guidance::GuidanceMode::Idle)).id();
+        let mut estimator_config = navigation::AttitudeEstimatorConfig::from_params(&self.attitude_estimator);
+        // Without a star tracker the attitude has to be determined from the star and sun vectors
+        if self.attitude_estimator.attitude_source.is_none() && self.star_trackers.is_empty() {
+            estimator_config.attitude_source = navigation::AttitudeSource::Quest;
+        }
+        let attitude_estimator = app.world.spawn((Name::new("AttitudeEstimator"), navigation::AttitudeEstimator::new(estimator_config))).id();
+        self.entities.insert("AttitudeEstimator".to_string(), attitude_estimator);
+        let filter_config = navigation::NavigationFilterConfig::from_params(&self.navigation_filter);
+        let navigation_filter = app.world.spawn((Name::new("NavigationFilter"), navigation::NavigationFilter::new(filter_config))).id();
+        self.entities.insert("NavigationFilter".to_string(), navigation_filter);
+
+        let attitude_controller = app.world.spawn((Name::new("AttitudeController"),
+            AttitudeController::new(self.attitude_controller.clone()))).id();
+        self.entities.insert("AttitudeController".to_string(), attitude_controller);
+
+        let rcs_controller = RCSController::new(&self.rcs_thrusters);
+
+        let mut control_allocator = control::ControlAllocator::default();
+        if !self.reaction_wheels.is_empty() {
+            let rw_controller = ReactionWheelController::new(&self.reaction_wheels);
+            let rw_controller = app.world.spawn((Name::new("ReactionWheelController"), rw_controller)).id();
+            self.entities.insert("ReactionWheelController".to_string(), rw_controller);
+            control_allocator.use_reaction_wheels = true;
+        }
+        for magnetometer_config in &self.magnetometers {
+            let magnetometer = app.world.spawn((
+                Name::new(magnetometer_config.name.clone()),
+                sensors::Magnetometer,
+                GeometryConfig::from_geometry_params(&magnetometer_config.geometry)
+            )).id();
+            self.entities.insert(magnetometer_config.name.to_string(), magnetometer);
+        }
+        if !self.magnetorquers.is_empty() {
+            let mtq_controller = MagnetorquerController::new(&self.magnetorquers);
+            let mtq_controller = app.world.spawn((Name::new("MagnetorquerController"), mtq_controller)).id();
+            self.entities.insert("MagnetorquerController".to_string(), mtq_controller);
+            control_allocator.use_magnetorquers = !self.magnetometers.is_empty();
+        }
 
-        // todo: fix this to use correct config
-        let rcs_config_xml = vec![r#"
-            <thruster type="RCS" name="roll1">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the +X direction -->
-                    <q_cf2b>[0.7071068, 0.0, 0.7071068, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[-0.1, 1.0, -0.5]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#, r#"
-            <thruster type="RCS" name="roll2">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the -X direction -->
-                    <q_cf2b>[0.7071068, 0.0, -0.7071068, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[-0.1, 1.0, -0.5]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#, r#"
-            <thruster type="RCS" name="leg2A">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the +Z direction -->
-                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[0.8660254037844386, -0.5, -0.6]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#, r#"
-            <thruster type="RCS" name="leg2B">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the -Z direction -->
-                    <q_cf2b>[0.0, 1.0, 0.0, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[0.8660254037844386, -0.5, -0.4]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#, r#"
-            <thruster type="RCS" name="leg3A">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the +Z direction -->
-                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[-0.8660254037844386, -0.5, -0.6]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#, r#"
-            <thruster type="RCS" name="leg3B">
-                <min_thrust>0.0</min_thrust>
-                <max_thrust>0.25</max_thrust>
-                <geometry>
-                    <!-- thruster pointed in the -Z direction -->
-                    <q_cf2b>[0.0, 1.0, 0.0, 0.0]</q_cf2b>
-                    <cf_offset_com_b>[-0.8660254037844386, -0.5, -0.4]</cf_offset_com_b>
-                </geometry>
-            </thruster>
-            "#,
-        ];
-        use hard_xml::XmlRead;
-        let rcs_config = rcs_config_xml.iter().map(|c| ThrusterConfig::from_str(c).unwrap()).collect::<Vec<_>>();
-        let rcs_controller = RCSController::new(&rcs_config);
+        if !self.vernier_engines.is_empty() {
+            let vernier_controller = app.world.spawn((Name::new("VernierAttitudeController"),
+                VernierAttitudeController::new(&self.vernier_engines), control::VernierEngineControllerOutput::default())).id();
+            self.entities.insert("VernierAttitudeController".to_string(), vernier_controller);
+            control_allocator.use_verniers = true;
+            app.world.entity_mut(traj).insert(DescentGuidance::new(self.descent_guidance.clone(), &self.vernier_engines));
+        }
 
-        let control_allocator = app.world.spawn((Name::new("ControlAllocator"), control::ControlAllocator::default())).id();
+        let control_allocator = app.world.spawn((Name::new("ControlAllocator"), control_allocator)).id();
         let rcs_controller = app.world.spawn((Name::new("RCSController"), rcs_controller, control::RCSControllerOutput::default())).id();
+        if let Some(rcs_modulator) = &self.rcs_modulator {
+            app.world.entity_mut(rcs_controller).insert(control::RcsModulator::new(rcs_modulator.clone()));
+        }
 
         self.entities.insert("TrajectoryPhase".to_string(), traj);
 
-        self.entities.insert("ST_A".to_string(), star_tracker);
         self.entities.insert("Guidance".to_string(), guidance);
         self.entities.insert("ControlAllocator".to_string(), control_allocator);
         self.entities.insert("RCSController".to_string(), rcs_controller);
-        self.entities.insert("StarSensor_A".to_string(), star_sensor);
     }
 }
 
@@ -248,7 +291,111 @@ impl SurveyorGNC {
 
         Self {
             entities: DashMap::new(),
+            imus: Vec::new(),
+            star_trackers: Vec::new(),
+            star_sensors: Vec::new(),
+            sun_sensors: Vec::new(),
+            amrs: Vec::new(),
+            radvs_beams: Vec::new(),
+            cameras: Vec::new(),
+            rcs_thrusters: Vec::new(),
+            vernier_engines: Vec::new(),
+            reaction_wheels: Vec::new(),
+            magnetometers: Vec::new(),
+            magnetorquers: Vec::new(),
+            attitude_controller: AttitudeControllerConfig::default(),
+            rcs_modulator: None,
+            descent_guidance: DescentGuidanceConfig::default(),
+            attitude_estimator: AttitudeEstimatorParams::default(),
+            navigation_filter: NavigationFilterParams::default(),
+        }
+    }
+    /// FSW configured from the same file as the simulation
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let mut gnc = Self::from_spacecraft_config(&config.spacecraft)?;
+        if let Some(attitude_controller) = &config.gnc.attitude_controller {
+            gnc = gnc.with_attitude_controller(attitude_controller.clone());
+        }
+        if let Some(rcs_modulator) = &config.gnc.rcs_modulator {
+            gnc = gnc.with_rcs_modulator(rcs_modulator.clone());
+        }
+        if let Some(descent_guidance) = &config.gnc.descent_guidance {
+            gnc = gnc.with_descent_guidance(descent_guidance.clone())?;
+        }
+        if let Some(attitude_estimator) = &config.gnc.attitude_estimator {
+            gnc = gnc.with_attitude_estimator(attitude_estimator.clone());
         }
+        if let Some(navigation_filter) = &config.gnc.navigation_filter {
+            gnc = gnc.with_navigation_filter(navigation_filter.clone());
+        }
+        Ok(gnc)
+    }
+    /// FSW for the sensors and actuators of the configured spacecraft, including any deliberate
+    /// knowledge errors from its `FSW` section
+    pub fn from_spacecraft_config(config: &SpacecraftConfig) -> Result<Self, String> {
+        let mut gnc = Self::new();
+        for subsystem in config.fsw_subsystems()? {
+            match subsystem {
+                SubsystemConfig::Propulsion(c) => gnc.vernier_engines = c.thrusters,
+                SubsystemConfig::Rcs(c) => gnc.rcs_thrusters = c.thrusters,
+                SubsystemConfig::Imu(c) => gnc.imus = c.sensors,
+                SubsystemConfig::StarTracker(c) => gnc.star_trackers = c.sensors,
+                SubsystemConfig::StarSensor(c) => gnc.star_sensors = c.sensors,
+                SubsystemConfig::ReactionWheel(c) => gnc.reaction_wheels = c.wheels,
+                SubsystemConfig::Magnetometer(c) => gnc.magnetometers = c.sensors,
+                SubsystemConfig::Magnetorquer(c) => gnc.magnetorquers = c.torquers,
+                SubsystemConfig::SunSensor(c) => gnc.sun_sensors = c.sensors,
+                SubsystemConfig::Amr(c) => gnc.amrs = c.sensors,
+                SubsystemConfig::Radvs(c) => gnc.radvs_beams = c.beams,
+                SubsystemConfig::Camera(c) => gnc.cameras = c.cameras,
+            }
+        }
+        Ok(gnc)
+    }
+    /// Sets the gains and limits of the attitude controller
+    pub fn with_attitude_controller(mut self, attitude_controller: AttitudeControllerConfig) -> Self {
+        self.attitude_controller = attitude_controller;
+        self
+    }
+    /// Pulses the RCS thrusters with the given modulator
+    pub fn with_rcs_modulator(mut self, rcs_modulator: RcsModulatorConfig) -> Self {
+        self.rcs_modulator = Some(rcs_modulator);
+        self
+    }
+    /// Sets the descent contour and the other parameters of the descent guidance. Fails if the
+    /// powered descent parameters are invalid.
+    pub fn with_descent_guidance(mut self, descent_guidance: DescentGuidanceConfig) -> Result<Self, String> {
+        if let Some(powered_descent) = &descent_guidance.powered_descent {
+            powered_descent.validate()?;
+        }
+        self.descent_guidance = descent_guidance;
+        Ok(self)
+    }
+    /// Sets the attitude source and the noise of the attitude estimator
+    pub fn with_attitude_estimator(mut self, attitude_estimator: AttitudeEstimatorParams) -> Self {
+        self.attitude_estimator = attitude_estimator;
+        self
+    }
+    /// Sets the noise of the navigation filter
+    pub fn with_navigation_filter(mut self, navigation_filter: NavigationFilterParams) -> Self {
+        self.navigation_filter = navigation_filter;
+        self
+    }
+    /// Adds reaction wheels to the FSW. The RCS is then only used for momentum unloading
+    pub fn with_reaction_wheels(mut self, reaction_wheels: Vec<ReactionWheelConfig>) -> Self {
+        self.reaction_wheels = reaction_wheels;
+        self
+    }
+    /// Adds magnetometers to the FSW
+    pub fn with_magnetometers(mut self, magnetometers: Vec<MagnetometerConfig>) -> Self {
+        self.magnetometers = magnetometers;
+        self
+    }
+    /// Adds magnetorquers to the FSW. These replace the RCS for momentum unloading when
+    /// magnetometers are also available
+    pub fn with_magnetorquers(mut self, magnetorquers: Vec<MagnetorquerConfig>) -> Self {
+        self.magnetorquers = magnetorquers;
+        self
     }
 }
 use surveyor_types::config::*;
@@ -259,21 +406,17 @@ use crate::sensors::update_star_sensor;
 #[derive(Event)]
 pub enum GncCommand {
     SetGuidanceMode(guidance::GuidanceMode),
-}
-
-/// Refactor to move this out of the main FSW module
-/// Maybe make it part of the guidance module?
-#[derive(Debug, Clone, Component)]
-pub enum TrajectoryPhase {
-    BeforeRetroBurn,
-    DescentContour,
-    TerminalDescent,
-    Landed,
+    /// Changes the gains and limits of the attitude controller
+    SetAttitudeController(AttitudeControllerConfig),
+    /// Enables or disables (e.g. after a failure) an RCS thruster for attitude control
+    SetRcsThrusterEnabled { thruster: usize, enabled: bool },
 }
 
 // System to process commands
 pub fn process_gnc_command(mut command: EventReader<GncCommand>,
-                           mut guidance_query: Query<&mut guidance::GuidanceMode>)
+                           mut guidance_query: Query<&mut guidance::GuidanceMode>,
+                           mut attitude_controller_query: Query<&mut AttitudeController>,
+                           mut rcs_controller_query: Query<&mut RCSController>)
 {
     let mut guidance_mode = guidance_query.single_mut();
     for command in command.read() {
@@ -281,6 +424,14 @@ pub fn process_gnc_command(mut command: EventReader<GncCommand>,
             GncCommand::SetGuidanceMode(new_mode) => {
                 *guidance_mode = new_mode.clone();
             }
+            GncCommand::SetAttitudeController(config) => {
+                attitude_controller_query.single_mut().set_config(config.clone());
+            }
+            GncCommand::SetRcsThrusterEnabled { thruster, enabled } => {
+                if let Err(err) = rcs_controller_query.single_mut().set_thruster_enabled(*thruster, *enabled) {
+                    log::error!("{}", err);
+                }
+            }
         }
     }
 }
@@ -305,5 +456,144 @@ mod tests {
         app.update();
         let guidance_mode = app.world.query::<&mut guidance::GuidanceMode>().single(&app.world);
         assert_eq!(*guidance_mode, guidance::GuidanceMode::Manual);
+
+        // Change the attitude controller gains at runtime
+        let config = AttitudeControllerConfig {
+            rate_gain: surveyor_types::math::Vector3(nalgebra::Vector3::new(1.0, 2.0, 3.0)),
+            ..Default::default()
+        };
+        app.world.send_event(GncCommand::SetAttitudeController(config.clone()));
+        app.update();
+        let controller = app.world.query::<&AttitudeController>().single(&app.world);
+        assert_eq!(controller.config, config);
+
+        // Disable a failed RCS thruster
+        *app.world.query::<&mut RCSController>().single_mut(&mut app.world) = RCSController::default();
+        app.world.send_event(GncCommand::SetRcsThrusterEnabled { thruster: 1, enabled: false });
+        app.update();
+        let rcs_controller = app.world.query::<&RCSController>().single(&app.world);
+        assert_eq!(rcs_controller.thruster_enabled, vec![true, false, true]);
+    }
+
+    /// Test that the FSW uses the spacecraft geometry with the knowledge errors applied unless it
+    /// lists its own subsystems
+    #[test]
+    fn test_fsw_config()
+    {
+        use hard_xml::XmlRead;
+        let mut config = SpacecraftConfig::from_str(r#"
+            <SpacecraftConfig>
+                <FSW>
+                    <KnowledgeError subsystem="Imu" target="B">
+                        <misalignment>[0.0, 0.0, 0.01]</misalignment>
+                        <offset>[0.0, 0.1, 0.0]</offset>
+                    </KnowledgeError>
+                    <AmrSubsystem>
+                        <Amr name="Assumed">
+                            <geometry>
+                                <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                                <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                            </geometry>
+                            <mark_range>96560.0</mark_range>
+                            <max_range>150000.0</max_range>
+                        </Amr>
+                    </AmrSubsystem>
+                </FSW>
+                <ImuSubsystem>
+                    <Imu name="A">
+                        <geometry>
+                            <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                            <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                        </geometry>
+                    </Imu>
+                    <Imu name="B">
+                        <geometry>
+                            <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                            <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                        </geometry>
+                    </Imu>
+                </ImuSubsystem>
+                <AmrSubsystem>
+                    <Amr name="AMR">
+                        <geometry>
+                            <q_cf2b>[0.0, 1.0, 0.0, 0.0]</q_cf2b>
+                            <cf_offset_com_b>[0.0, 0.0, -0.5]</cf_offset_com_b>
+                        </geometry>
+                        <mark_range>96560.0</mark_range>
+                        <max_range>150000.0</max_range>
+                    </Amr>
+                </AmrSubsystem>
+            </SpacecraftConfig>"#).unwrap();
+        let gnc = SurveyorGNC::from_spacecraft_config(&config).unwrap();
+        assert_eq!(gnc.imus.len(), 2);
+        assert_eq!(gnc.imus[0].geometry.q_cf2b.0, nalgebra::UnitQuaternion::identity());
+        assert!((gnc.imus[1].geometry.q_cf2b.0.angle() - 0.01).abs() < 1e-12);
+        assert_eq!(gnc.imus[1].geometry.cf_offset_com_b.0, nalgebra::Vector3::new(0.0, 0.1, 0.0));
+        assert_eq!(gnc.amrs.len(), 1);
+        assert_eq!(gnc.amrs[0].name, "Assumed");
+
+        // Knowledge errors must match a sensor or actuator that the FSW takes from the spacecraft
+        for (subsystem, target) in [("Imu", "C"), ("Amr", "AMR")] {
+            let knowledge_errors = &mut config.fsw.as_mut().unwrap().knowledge_errors;
+            knowledge_errors.push(KnowledgeErrorConfig::from_str(&format!(r#"
+                <KnowledgeError subsystem="{}" target="{}">
+                    <offset>[0.0, 0.1, 0.0]</offset>
+                </KnowledgeError>"#, subsystem, target)).unwrap());
+            assert!(SurveyorGNC::from_spacecraft_config(&config).is_err());
+            config.fsw.as_mut().unwrap().knowledge_errors.pop();
+        }
+
+        let mut app = App::new();
+        app.add_plugins(gnc);
+        app.update();
+        let mut names = app.world.query_filtered::<&Name, With<sensors::IMU>>().iter(&app.world)
+            .map(|name| name.to_string()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["A", "B"]);
+    }
+
+    /// Test that the simulation configuration is accepted and a G-FOLD trajectory without nodes is not
+    #[test]
+    fn test_descent_guidance_config()
+    {
+        use hard_xml::XmlRead;
+        let mut config = Config::from_str(include_str!("../../surveyor-physics/simulation.xml")).unwrap();
+        assert!(SurveyorGNC::from_config(&config).is_ok());
+        let descent_guidance = config.gnc.descent_guidance.as_mut().unwrap();
+        descent_guidance.powered_descent.get_or_insert_with(Default::default).num_nodes = 0;
+        assert!(SurveyorGNC::new().with_descent_guidance(descent_guidance.clone()).is_err());
+        assert!(SurveyorGNC::from_config(&config).is_err());
+    }
+
+    /// Test that the attitude estimator and navigation filter are set up from the configuration,
+    /// with the attitude determined from vectors when there is no star tracker
+    #[test]
+    fn test_navigation_config()
+    {
+        use hard_xml::XmlRead;
+        let mut app = App::new();
+        app.add_plugins(SurveyorGNC::new());
+        app.update();
+        let estimator = app.world.query::<&navigation::AttitudeEstimator>().single(&app.world);
+        assert_eq!(estimator.config.attitude_source, navigation::AttitudeSource::Quest);
+
+        let attitude_estimator = AttitudeEstimatorParams::from_str(r#"
+            <AttitudeEstimator attitude_source="Triad">
+                <sun_sensor_std>0.02</sun_sensor_std>
+            </AttitudeEstimator>"#).unwrap();
+        let navigation_filter = NavigationFilterParams::from_str(r#"
+            <NavigationFilter>
+                <doppler_std>0.5</doppler_std>
+            </NavigationFilter>"#).unwrap();
+        let mut app = App::new();
+        app.add_plugins(SurveyorGNC::new().with_attitude_estimator(attitude_estimator).with_navigation_filter(navigation_filter));
+        app.update();
+        let estimator = app.world.query::<&navigation::AttitudeEstimator>().single(&app.world);
+        assert_eq!(estimator.config.attitude_source, navigation::AttitudeSource::Triad);
+        assert_eq!(estimator.config.sun_sensor_std, 0.02);
+        assert_eq!(estimator.config.star_sensor_std, navigation::AttitudeEstimatorConfig::default().star_sensor_std);
+        let filter = app.world.query::<&navigation::NavigationFilter>().single(&app.world);
+        assert_eq!(filter.config.doppler_std, 0.5);
+        assert_eq!(filter.config.uplink_position_std, navigation::NavigationFilterConfig::default().uplink_position_std);
     }
 }
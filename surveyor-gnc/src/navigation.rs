@@ -2,106 +2,1313 @@
 use bevy_ecs::prelude::*;
 use hifitime::prelude::*;
 use nalgebra as na;
-use crate::{sensors::{StarTrackerOutput, IMUOutput, StarSensorOutput}, clock::SystemClock};
+use surveyor_types::config::{AttitudeEstimatorParams, NavigationFilterParams};
+pub use surveyor_types::config::AttitudeSource;
+use crate::{sensors::{EphemerisOutput, StarTrackerOutput, IMUOutput, StarSensorOutput, MagnetometerOutput, SunSensorOutput, AmrOutput, RadvsOutput, CameraImage}, clock::SystemClock};
 
 
+/// Health of a sensor as seen by the sensor aggregator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SensorHealth {
+    /// Nothing valid has been received from the sensor yet
+    #[default]
+    NoData,
+    Good,
+    /// The latest valid measurement is older than the maximum age for the sensor
+    Stale,
+    /// Too many invalid measurements in a row
+    Failed,
+}
+
+/// Latest valid measurement from a sensor along with its health. The value is kept through
+/// short outages and can be used as long as `valid` is set.
 #[derive(Debug, Clone, Default)]
 pub struct Measurement<T: Default> {
+    /// Name of the sensor
+    pub sensor: String,
     pub value: T,
+    /// Time of the latest valid measurement
     pub time: Epoch,
+    /// The sensor is healthy and the value is not stale
     pub valid: bool,
+    /// A new valid measurement was received in this update. Estimators should only process
+    /// measurements that are fresh so that the same one is not used twice.
+    pub fresh: bool,
+    pub health: SensorHealth,
+    /// Number of invalid measurements received since the last valid one
+    pub consecutive_failures: usize,
 }
 
+impl<T: Default> Measurement<T> {
+    pub fn new(sensor: &str) -> Self {
+        Self {
+            sensor: sensor.to_string(),
+            ..Default::default()
+        }
+    }
+    /// Records a measurement from the sensor (`value` is None if the sensor reported it as invalid)
+    fn record(&mut self, value: Option<T>, time: Epoch) {
+        match value {
+            Some(value) => {
+                self.value = value;
+                self.time = time;
+                self.fresh = true;
+                self.consecutive_failures = 0;
+                if self.health == SensorHealth::NoData {
+                    self.health = SensorHealth::Good;
+                }
+            }
+            None => self.consecutive_failures += 1,
+        }
+    }
+    /// Updates the health at the given time
+    fn update_health(&mut self, time: Epoch, max_age: f64, max_consecutive_failures: usize) {
+        self.health = if self.consecutive_failures >= max_consecutive_failures {
+            SensorHealth::Failed
+        } else if self.health == SensorHealth::NoData {
+            SensorHealth::NoData
+        } else if (time - self.time).to_seconds() > max_age {
+            SensorHealth::Stale
+        } else {
+            SensorHealth::Good
+        };
+        self.valid = self.health == SensorHealth::Good;
+        self.fresh &= self.valid;
+    }
+}
 
-#[derive(Debug, Clone, Event)]
+/// Measurements from all the sensors, in the order in which the sensors were configured
+#[derive(Debug, Clone, Default, Event)]
 pub struct SensorData {
-    pub imus: [Measurement<IMUOutput>; 2],
-    pub star_trackers: [Measurement<StarTrackerOutput>; 2],
-    pub star_sensors: [Measurement<StarSensorOutput>; 2],
+    pub imus: Vec<Measurement<IMUOutput>>,
+    pub star_trackers: Vec<Measurement<StarTrackerOutput>>,
+    pub star_sensors: Vec<Measurement<StarSensorOutput>>,
+    pub magnetometers: Vec<Measurement<MagnetometerOutput>>,
+    pub sun_sensors: Vec<Measurement<SunSensorOutput>>,
+    pub amrs: Vec<Measurement<AmrOutput>>,
+    pub radvs: Vec<Measurement<RadvsOutput>>,
+    pub cameras: Vec<Measurement<CameraImage>>,
+}
+
+/// Names of the sensors of one type and the age after which their measurements are stale (s)
+#[derive(Debug, Clone)]
+pub struct SensorGroupConfig {
+    pub sensors: Vec<String>,
+    pub max_age: f64,
+}
+impl SensorGroupConfig {
+    pub fn new(sensors: &[&str], max_age: f64) -> Self {
+        Self {
+            sensors: sensors.iter().map(|name| name.to_string()).collect(),
+            max_age,
+        }
+    }
 }
 
-impl Default for SensorData {
+/// Sensors known to the aggregator. Measurements from sensors that are not listed are added
+/// to their group when they first arrive.
+#[derive(Debug, Clone)]
+pub struct SensorAggregatorConfig {
+    pub imus: SensorGroupConfig,
+    pub star_trackers: SensorGroupConfig,
+    pub star_sensors: SensorGroupConfig,
+    pub magnetometers: SensorGroupConfig,
+    pub sun_sensors: SensorGroupConfig,
+    pub amrs: SensorGroupConfig,
+    pub radvs: SensorGroupConfig,
+    pub cameras: SensorGroupConfig,
+    /// A sensor is marked as failed after this many invalid measurements in a row
+    pub max_consecutive_failures: usize,
+}
+impl Default for SensorAggregatorConfig {
     fn default() -> Self {
         Self {
-            imus: [Measurement::default(), Measurement::default()],
-            star_trackers: [Measurement::default(), Measurement::default()],
-            star_sensors: [Measurement::default(), Measurement::default()],
+            imus: SensorGroupConfig::new(&[], 0.5),
+            star_trackers: SensorGroupConfig::new(&[], 5.0),
+            star_sensors: SensorGroupConfig::new(&[], 2.0),
+            magnetometers: SensorGroupConfig::new(&[], 2.0),
+            sun_sensors: SensorGroupConfig::new(&[], 2.0),
+            amrs: SensorGroupConfig::new(&[], 2.0),
+            radvs: SensorGroupConfig::new(&[], 1.0),
+            // The camera only takes an image every few seconds
+            cameras: SensorGroupConfig::new(&[], 10.0),
+            max_consecutive_failures: 10,
+        }
+    }
+}
+
+/// Keeps track of the latest measurement and the health of every sensor
+#[derive(Debug, Clone, Component, Default)]
+pub struct SensorAggregator {
+    pub config: SensorAggregatorConfig,
+    data: SensorData,
+    last_time: Option<Epoch>,
+}
+
+/// Records the measurements from one type of sensor
+fn record_measurements<'a, T: Default + Clone + 'a>(
+    measurements: &mut Vec<Measurement<T>>,
+    outputs: impl Iterator<Item = &'a T>,
+    sensor: impl Fn(&T) -> &str,
+    time: impl Fn(&T) -> Epoch,
+    valid: impl Fn(&T) -> bool,
+) {
+    for output in outputs {
+        let name = sensor(output);
+        let index = match measurements.iter().position(|meas| meas.sensor == name) {
+            Some(index) => index,
+            None => {
+                measurements.push(Measurement::new(name));
+                measurements.len() - 1
+            }
+        };
+        measurements[index].record(valid(output).then(|| output.clone()), time(output));
+    }
+}
+
+impl SensorAggregator {
+    pub fn new(config: SensorAggregatorConfig) -> Self {
+        let mut aggregator = Self { config, ..Default::default() };
+        aggregator.reset();
+        aggregator
+    }
+    /// Forgets all the measurements
+    pub fn reset(&mut self) {
+        fn measurements<T: Default>(group: &SensorGroupConfig) -> Vec<Measurement<T>> {
+            group.sensors.iter().map(|name| Measurement::new(name)).collect()
+        }
+        let config = &self.config;
+        self.data = SensorData {
+            imus: measurements(&config.imus),
+            star_trackers: measurements(&config.star_trackers),
+            star_sensors: measurements(&config.star_sensors),
+            magnetometers: measurements(&config.magnetometers),
+            sun_sensors: measurements(&config.sun_sensors),
+            amrs: measurements(&config.amrs),
+            radvs: measurements(&config.radvs),
+            cameras: measurements(&config.cameras),
+        };
+        self.last_time = None;
+    }
+    /// Clears the fresh flags before the measurements for a new update are recorded
+    pub fn start_update(&mut self, time: Epoch) {
+        // Start over if the clock has gone backwards (e.g. the simulation was reset)
+        if self.last_time.is_some_and(|last_time| time < last_time) {
+            self.reset();
         }
+        self.last_time = Some(time);
+        let data = &mut self.data;
+        data.imus.iter_mut().for_each(|meas| meas.fresh = false);
+        data.star_trackers.iter_mut().for_each(|meas| meas.fresh = false);
+        data.star_sensors.iter_mut().for_each(|meas| meas.fresh = false);
+        data.magnetometers.iter_mut().for_each(|meas| meas.fresh = false);
+        data.sun_sensors.iter_mut().for_each(|meas| meas.fresh = false);
+        data.amrs.iter_mut().for_each(|meas| meas.fresh = false);
+        data.radvs.iter_mut().for_each(|meas| meas.fresh = false);
+        data.cameras.iter_mut().for_each(|meas| meas.fresh = false);
+    }
+    /// Updates the health of all the sensors and returns the measurements
+    pub fn finish_update(&mut self, time: Epoch) -> &SensorData {
+        fn update<T: Default>(measurements: &mut [Measurement<T>], group: &SensorGroupConfig, time: Epoch, max_failures: usize) {
+            for meas in measurements {
+                meas.update_health(time, group.max_age, max_failures);
+            }
+        }
+        let (config, data) = (&self.config, &mut self.data);
+        let max_failures = config.max_consecutive_failures;
+        update(&mut data.imus, &config.imus, time, max_failures);
+        update(&mut data.star_trackers, &config.star_trackers, time, max_failures);
+        update(&mut data.star_sensors, &config.star_sensors, time, max_failures);
+        update(&mut data.magnetometers, &config.magnetometers, time, max_failures);
+        update(&mut data.sun_sensors, &config.sun_sensors, time, max_failures);
+        update(&mut data.amrs, &config.amrs, time, max_failures);
+        update(&mut data.radvs, &config.radvs, time, max_failures);
+        update(&mut data.cameras, &config.cameras, time, max_failures);
+        &self.data
+    }
+    pub fn data(&self) -> &SensorData {
+        &self.data
     }
 }
 #[derive(Debug, Clone, Event)]
 pub struct AttitudeEstimatorOutput {
     pub q_i2b: na::UnitQuaternion<f64>,
+    /// Body rates corrected for the estimated gyro bias
     pub omega_b: na::Vector3<f64>,
+    pub gyro_bias_b: na::Vector3<f64>,
+    /// Covariance of the attitude error (rad, body frame) and the gyro bias error (rad/s)
+    pub covariance: na::Matrix6<f64>,
+    /// False until the estimator has been initialized with an attitude measurement
+    pub valid: bool,
 }
 impl Default for AttitudeEstimatorOutput {
     fn default() -> Self {
         Self {
             q_i2b: na::UnitQuaternion::identity(),
             omega_b: na::Vector3::zeros(),
+            gyro_bias_b: na::Vector3::zeros(),
+            covariance: na::Matrix6::identity(),
+            valid: false,
         }
     }
 }
 
-pub fn update_sensor_aggregator(mut imu_query: EventReader<IMUOutput>,
-                                mut str_query: EventReader<StarTrackerOutput>,
-                                mut star_sensor_query: EventReader<StarSensorOutput>,
-                                mut sensor_data_writer: EventWriter<SensorData>,
-                                _clock: Res<SystemClock>
-)
-{
-    // TODO: Pass through "valid" flag and timestamp from the sensor data
-    // Assume there is only a single sensor data entity
-    let mut sensor_data = SensorData::default();
-    // Iterate over all imu outputs and add them to the sensor data
-    for (imu_idx, imu_output) in imu_query.read().enumerate() {
-        let meas = Measurement {
-            value: imu_output.clone(),
-            time: imu_output.measurement_time,
-            valid: true,
+
+/// Tuning of the attitude estimator
+#[derive(Debug, Clone)]
+pub struct AttitudeEstimatorConfig {
+    pub attitude_source: AttitudeSource,
+    /// Gyro angle random walk (rad/s^0.5)
+    pub gyro_angle_random_walk: f64,
+    /// Gyro rate random walk, which drives the bias (rad/s^1.5)
+    pub gyro_rate_random_walk: f64,
+    /// 1-sigma error of the star tracker attitude about each axis (rad)
+    pub star_tracker_std: f64,
+    /// 1-sigma error of the star sensor direction (rad)
+    pub star_sensor_std: f64,
+    /// 1-sigma error of the sun sensor direction (rad)
+    pub sun_sensor_std: f64,
+    pub initial_attitude_std: f64,
+    pub initial_bias_std: f64,
+    /// Measurements with a normalized innovation squared above this are rejected
+    pub innovation_gate: f64,
+    /// The filter is restarted from the measured attitude after this many attitude measurements
+    /// in a row have been rejected (the estimate has most likely diverged)
+    pub max_rejected_attitude_updates: usize,
+    /// Direction of the star tracked by the star sensor in J2000
+    pub star_sensor_target_i: na::Vector3<f64>,
+}
+impl Default for AttitudeEstimatorConfig {
+    fn default() -> Self {
+        Self {
+            attitude_source: AttitudeSource::default(),
+            gyro_angle_random_walk: 3e-5,
+            gyro_rate_random_walk: 1e-6,
+            star_tracker_std: 1e-4,
+            star_sensor_std: 1e-3,
+            sun_sensor_std: 1e-2,
+            initial_attitude_std: 1e-2,
+            initial_bias_std: 1e-4,
+            // 99.9% of the chi-squared distribution with 3 degrees of freedom
+            innovation_gate: 16.27,
+            max_rejected_attitude_updates: 5,
+            star_sensor_target_i: canopus_direction_j2000(),
+        }
+    }
+}
+impl AttitudeEstimatorConfig {
+    /// Defaults with whatever is given in the configuration applied
+    pub fn from_params(params: &AttitudeEstimatorParams) -> Self {
+        let default = Self::default();
+        Self {
+            attitude_source: params.attitude_source.unwrap_or(default.attitude_source),
+            gyro_angle_random_walk: params.gyro_angle_random_walk.unwrap_or(default.gyro_angle_random_walk),
+            gyro_rate_random_walk: params.gyro_rate_random_walk.unwrap_or(default.gyro_rate_random_walk),
+            star_tracker_std: params.star_tracker_std.unwrap_or(default.star_tracker_std),
+            star_sensor_std: params.star_sensor_std.unwrap_or(default.star_sensor_std),
+            sun_sensor_std: params.sun_sensor_std.unwrap_or(default.sun_sensor_std),
+            initial_attitude_std: params.initial_attitude_std.unwrap_or(default.initial_attitude_std),
+            initial_bias_std: params.initial_bias_std.unwrap_or(default.initial_bias_std),
+            innovation_gate: params.innovation_gate.unwrap_or(default.innovation_gate),
+            ..default
+        }
+    }
+}
+
+/// Direction of Canopus in J2000 (the star that Surveyor used for roll reference)
+pub fn canopus_direction_j2000() -> na::Vector3<f64> {
+    let (ra, dec) = (95.98796f64.to_radians(), (-52.69566f64).to_radians());
+    na::Vector3::new(dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin())
+}
+
+/// Low-precision direction of the Sun in J2000 (from the Astronomical Almanac, good to about 0.01
+/// deg). The parallax between the Earth and the Moon (0.15 deg at most) is ignored.
+pub fn sun_direction_j2000(epoch: Epoch) -> na::Vector3<f64> {
+    let n = epoch.to_jde_utc_days() - 2451545.0;
+    let mean_longitude = 280.460 + 0.9856474 * n;
+    let mean_anomaly = (357.528 + 0.9856003 * n).to_radians();
+    // Ecliptic longitude referred to the equinox of date and then precessed back to J2000
+    let longitude = mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin()
+        - 1.397 * n / 36525.0;
+    let (longitude, obliquity) = (longitude.to_radians(), 23.439291f64.to_radians());
+    na::Vector3::new(longitude.cos(), obliquity.cos() * longitude.sin(), obliquity.sin() * longitude.sin())
+}
+
+/// Rotation rate of the Moon about its pole (rad/s)
+pub const MOON_ROTATION_RATE: f64 = 13.17635815 / 86400.0 * std::f64::consts::PI / 180.0;
+
+/// Rotation from J2000 to the Moon-fixed (mean Earth/polar axis) frame from the IAU rotation model
+/// of the Moon, keeping only the largest of the periodic terms
+pub fn moon_j2000_to_fixed(epoch: Epoch) -> na::UnitQuaternion<f64> {
+    let d = epoch.to_jde_tdb_days() - 2451545.0;
+    let t = d / 36525.0;
+    let e1 = (125.045 - 0.0529921 * d).to_radians();
+    let pole_ra = (269.9949 + 0.0031 * t - 3.8787 * e1.sin()).to_radians();
+    let pole_dec = (66.5392 + 0.0130 * t + 1.5419 * e1.cos()).to_radians();
+    let prime_meridian = (38.3213 + 13.17635815 * d - 1.4e-12 * d * d + 3.5610 * e1.sin()).rem_euclid(360.0).to_radians();
+    na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), -prime_meridian)
+        * na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), pole_dec - std::f64::consts::FRAC_PI_2)
+        * na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), -pole_ra - std::f64::consts::FRAC_PI_2)
+}
+
+/// Angular velocity of the Moon in J2000 (rad/s)
+pub fn moon_angular_velocity_j2000(epoch: Epoch) -> na::Vector3<f64> {
+    moon_j2000_to_fixed(epoch).inverse_transform_vector(&na::Vector3::z()) * MOON_ROTATION_RATE
+}
+
+/// Unit vector measured in the body frame along with its known direction in the inertial frame
+#[derive(Debug, Clone)]
+pub struct VectorObservation {
+    pub meas_b: na::Vector3<f64>,
+    pub ref_i: na::Vector3<f64>,
+    /// 1-sigma error of the measured direction (rad)
+    pub std: f64,
+}
+impl VectorObservation {
+    /// None if either of the vectors is (close to) zero
+    pub fn new(meas_b: &na::Vector3<f64>, ref_i: &na::Vector3<f64>, std: f64) -> Option<Self> {
+        Some(Self {
+            meas_b: meas_b.try_normalize(1e-9)?,
+            ref_i: ref_i.try_normalize(1e-9)?,
+            std,
+        })
+    }
+}
+
+/// Minimum angle between two vector observations for them to determine the attitude
+const MIN_VECTOR_SEPARATION: f64 = 1e-3; // rad
+
+/// Deterministic attitude from two vector observations using the TRIAD algorithm. The first
+/// observation (which should be the more accurate one) is matched exactly. None if the vectors
+/// are (nearly) parallel.
+pub fn triad(primary: &VectorObservation, secondary: &VectorObservation) -> Option<na::UnitQuaternion<f64>> {
+    let triad_frame = |v1: &na::Vector3<f64>, v2: &na::Vector3<f64>| {
+        let t2 = v1.cross(v2).try_normalize(MIN_VECTOR_SEPARATION.sin())?;
+        Some(na::Matrix3::from_columns(&[*v1, t2, v1.cross(&t2)]))
+    };
+    let frame_b = triad_frame(&primary.meas_b, &secondary.meas_b)?;
+    let frame_i = triad_frame(&primary.ref_i, &secondary.ref_i)?;
+    let r_b2i = na::Rotation3::from_matrix_unchecked(frame_i * frame_b.transpose());
+    Some(na::UnitQuaternion::from_rotation_matrix(&r_b2i))
+}
+
+/// Optimal attitude (in the sense of Wahba's problem) from two or more vector observations using
+/// Shuster's QUEST algorithm, weighting each observation by its inverse variance. None if the
+/// observations do not determine the attitude.
+pub fn quest(observations: &[VectorObservation]) -> Option<na::UnitQuaternion<f64>> {
+    attitude_covariance(observations)?;
+    // QUEST loses precision for rotations close to 180 deg, so the reference vectors are
+    // rotated by 180 deg about one of the axes if needed (method of sequential rotations)
+    let flips = [None, Some(na::Vector3::x_axis()), Some(na::Vector3::y_axis()), Some(na::Vector3::z_axis())];
+    flips.iter().find_map(|flip| {
+        let flip = flip.map_or(na::UnitQuaternion::identity(), |axis| na::UnitQuaternion::from_axis_angle(&axis, std::f64::consts::PI));
+        let rotated = observations.iter().map(|obs| VectorObservation { ref_i: flip * obs.ref_i, ..obs.clone() }).collect::<Vec<_>>();
+        quest_unrotated(&rotated).map(|q_i2b| flip.inverse() * q_i2b)
+    })
+}
+
+fn quest_unrotated(observations: &[VectorObservation]) -> Option<na::UnitQuaternion<f64>> {
+    let weights = observations.iter().map(|obs| obs.std.powi(-2)).collect::<Vec<_>>();
+    let total_weight = weights.iter().sum::<f64>();
+    let (b, z) = observations.iter().zip(&weights).fold((na::Matrix3::zeros(), na::Vector3::zeros()), |(b, z), (obs, w)| {
+        let w = w / total_weight;
+        (b + obs.meas_b * obs.ref_i.transpose() * w, z + obs.meas_b.cross(&obs.ref_i) * w)
+    });
+    let s = b + b.transpose();
+    let sigma = b.trace();
+    // Trace of the adjugate of S (sum of its principal 2x2 minors)
+    let kappa = s[(1, 1)] * s[(2, 2)] - s[(1, 2)] * s[(2, 1)] + s[(0, 0)] * s[(2, 2)] - s[(0, 2)] * s[(2, 0)]
+        + s[(0, 0)] * s[(1, 1)] - s[(0, 1)] * s[(1, 0)];
+    let delta = s.determinant();
+    let (a, b_coef) = (sigma * sigma - kappa, sigma * sigma + z.dot(&z));
+    let (c, d) = (delta + z.dot(&(s * z)), z.dot(&(s * s * z)));
+    // Newton-Raphson on the characteristic equation starting from the sum of the (normalized) weights
+    let mut lambda = 1.0_f64;
+    for _ in 0..20 {
+        let f = lambda.powi(4) - (a + b_coef) * lambda.powi(2) - c * lambda + (a * b_coef + c * sigma - d);
+        let df = 4.0 * lambda.powi(3) - 2.0 * (a + b_coef) * lambda - c;
+        let step = f / df;
+        lambda -= step;
+        if step.abs() < 1e-14 {
+            break;
+        }
+    }
+    let alpha = lambda * lambda - sigma * sigma + kappa;
+    let beta = lambda - sigma;
+    let gamma = (lambda + sigma) * alpha - delta;
+    let x = (na::Matrix3::identity() * alpha + s * beta + s * s) * z;
+    // Close to 180 deg, both parts of the quaternion vanish
+    let q = na::Quaternion::new(gamma, x.x, x.y, x.z);
+    (q.norm() > 1e-6).then(|| na::UnitQuaternion::from_quaternion(q))
+}
+
+/// Covariance of the attitude error (rad, body frame) of the optimal attitude from the given
+/// vector observations. None if they do not determine the attitude.
+pub fn attitude_covariance(observations: &[VectorObservation]) -> Option<na::Matrix3<f64>> {
+    let separated = observations.iter().enumerate().any(|(i, obs1)| observations[i + 1..].iter()
+        .any(|obs2| obs1.meas_b.angle(&obs2.meas_b).sin() > MIN_VECTOR_SEPARATION.sin()));
+    if !separated {
+        return None;
+    }
+    observations.iter()
+        .fold(na::Matrix3::zeros(), |info, obs| info + (na::Matrix3::identity() - obs.meas_b * obs.meas_b.transpose()) / obs.std.powi(2))
+        .try_inverse()
+}
+
+/// Multiplicative extended Kalman filter (MEKF) that estimates the attitude and the gyro bias
+///
+/// The attitude is propagated with the gyro rates and corrected with star tracker attitudes and
+/// star/sun vectors. The error state is the rotation vector from the estimated body frame to the
+/// true one, followed by the gyro bias error.
+#[derive(Debug, Clone, Component)]
+pub struct AttitudeEstimator {
+    pub config: AttitudeEstimatorConfig,
+    q_i2b: na::UnitQuaternion<f64>,
+    gyro_bias_b: na::Vector3<f64>,
+    covariance: na::Matrix6<f64>,
+    initialized: bool,
+    rejected_attitude_updates: usize,
+    /// Last gyro measurement, which is held through IMU outages
+    omega_meas_b: na::Vector3<f64>,
+    last_time: Option<Epoch>,
+}
+impl Default for AttitudeEstimator {
+    fn default() -> Self {
+        Self::new(AttitudeEstimatorConfig::default())
+    }
+}
+impl AttitudeEstimator {
+    pub fn new(config: AttitudeEstimatorConfig) -> Self {
+        Self {
+            config,
+            q_i2b: na::UnitQuaternion::identity(),
+            gyro_bias_b: na::Vector3::zeros(),
+            covariance: na::Matrix6::identity(),
+            initialized: false,
+            rejected_attitude_updates: 0,
+            omega_meas_b: na::Vector3::zeros(),
+            last_time: None,
+        }
+    }
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+    /// Starts the filter from the given attitude with zero gyro bias
+    pub fn initialize(&mut self, q_i2b: na::UnitQuaternion<f64>) {
+        let (attitude_var, bias_var) = (self.config.initial_attitude_std.powi(2), self.config.initial_bias_std.powi(2));
+        self.q_i2b = q_i2b;
+        self.gyro_bias_b = na::Vector3::zeros();
+        self.covariance = na::Matrix6::from_diagonal(&na::Vector6::new(
+            attitude_var, attitude_var, attitude_var, bias_var, bias_var, bias_var));
+        self.initialized = true;
+        self.rejected_attitude_updates = 0;
+    }
+    /// Propagates the state and covariance over `dt` seconds with the latest gyro measurement
+    pub fn propagate(&mut self, dt: f64) {
+        let omega_b = self.omega_meas_b - self.gyro_bias_b;
+        let rotation = na::UnitQuaternion::from_scaled_axis(omega_b * dt);
+        self.q_i2b *= rotation;
+
+        let mut phi = na::Matrix6::identity();
+        phi.fixed_view_mut::<3, 3>(0, 0).copy_from(&rotation.to_rotation_matrix().matrix().transpose());
+        phi.fixed_view_mut::<3, 3>(0, 3).copy_from(&(-na::Matrix3::identity() * dt));
+        let (sigma_v2, sigma_u2) = (self.config.gyro_angle_random_walk.powi(2), self.config.gyro_rate_random_walk.powi(2));
+        let mut q = na::Matrix6::zeros();
+        q.fixed_view_mut::<3, 3>(0, 0).fill_diagonal(sigma_v2 * dt + sigma_u2 * dt.powi(3) / 3.0);
+        q.fixed_view_mut::<3, 3>(0, 3).fill_diagonal(-sigma_u2 * dt.powi(2) / 2.0);
+        q.fixed_view_mut::<3, 3>(3, 0).fill_diagonal(-sigma_u2 * dt.powi(2) / 2.0);
+        q.fixed_view_mut::<3, 3>(3, 3).fill_diagonal(sigma_u2 * dt);
+        self.covariance = phi * self.covariance * phi.transpose() + q;
+    }
+    /// Kalman update with the residual `y`, measurement matrix `h` and noise covariance `r`.
+    /// Returns false if the measurement fails the innovation gate.
+    fn update(&mut self, y: na::Vector3<f64>, h: na::SMatrix<f64, 3, 6>, r: na::Matrix3<f64>) -> bool {
+        let s = h * self.covariance * h.transpose() + r;
+        let Some(s_inv) = s.try_inverse() else {
+            return false;
         };
-        sensor_data.imus[imu_idx] = meas;
-    }
-    // Iterate over all star tracker outputs and add them to the sensor data
-    for (st_idx, star_tracker_output) in str_query.read().enumerate() {
-        let meas = Measurement {
-            value: star_tracker_output.clone(),
-            time: star_tracker_output.measurement_time,
-            valid: true,
+        if (y.transpose() * s_inv * y)[0] > self.config.innovation_gate {
+            return false;
+        }
+        let k = self.covariance * h.transpose() * s_inv;
+        let dx = k * y;
+        self.q_i2b *= na::UnitQuaternion::from_scaled_axis(dx.fixed_rows::<3>(0).into_owned());
+        self.gyro_bias_b += dx.fixed_rows::<3>(3);
+        // Joseph form to keep the covariance symmetric and positive definite
+        let i_kh = na::Matrix6::identity() - k * h;
+        self.covariance = i_kh * self.covariance * i_kh.transpose() + k * r * k.transpose();
+        true
+    }
+    /// Update with a measured attitude
+    pub fn update_attitude(&mut self, q_meas_i2b: &na::UnitQuaternion<f64>, std: f64) -> bool {
+        self.update_attitude_with_covariance(q_meas_i2b, &(na::Matrix3::identity() * std.powi(2)))
+    }
+    /// Update with a measured attitude whose error (body frame) has the given covariance
+    pub fn update_attitude_with_covariance(&mut self, q_meas_i2b: &na::UnitQuaternion<f64>, covariance: &na::Matrix3<f64>) -> bool {
+        let y = (self.q_i2b.inverse() * q_meas_i2b).scaled_axis();
+        let mut h = na::SMatrix::<f64, 3, 6>::zeros();
+        h.fixed_view_mut::<3, 3>(0, 0).fill_with_identity();
+        self.update(y, h, *covariance)
+    }
+    /// Star sensor and sun sensor measurements with their reference directions, most accurate first
+    fn vector_observations(&self, sensor_data: &SensorData, time: Epoch) -> Vec<VectorObservation> {
+        let star_sensors = sensor_data.star_sensors.iter().filter(|ss| ss.fresh).filter_map(|ss|
+            VectorObservation::new(&ss.value.star_vec_b, &self.config.star_sensor_target_i, self.config.star_sensor_std));
+        let sun_i = sun_direction_j2000(time);
+        let sun_sensors = sensor_data.sun_sensors.iter().filter(|ss| ss.fresh).filter_map(|ss|
+            VectorObservation::new(&ss.value.sun_vec_b, &sun_i, self.config.sun_sensor_std));
+        let mut observations = star_sensors.chain(sun_sensors).collect::<Vec<_>>();
+        observations.sort_by(|a, b| a.std.total_cmp(&b.std));
+        observations
+    }
+    /// Update with a measured attitude (or initialization if the filter is not running yet).
+    /// The filter is restarted after too many measurements in a row fail the innovation gate.
+    fn process_attitude(&mut self, q_meas_i2b: &na::UnitQuaternion<f64>, covariance: &na::Matrix3<f64>, source: &str) {
+        if !self.initialized {
+            self.initialize(*q_meas_i2b);
+        } else if self.update_attitude_with_covariance(q_meas_i2b, covariance) {
+            self.rejected_attitude_updates = 0;
+        } else {
+            self.rejected_attitude_updates += 1;
+            if self.rejected_attitude_updates >= self.config.max_rejected_attitude_updates {
+                log::warn!("Attitude estimator restarted from the {} after {} rejected measurements",
+                    source, self.rejected_attitude_updates);
+                self.initialize(*q_meas_i2b);
+            } else {
+                log::warn!("Attitude estimator rejected a {} measurement", source);
+            }
+        }
+    }
+    /// Update with a unit vector measured in the body frame whose direction in the inertial
+    /// frame is known
+    pub fn update_vector(&mut self, meas_b: &na::Vector3<f64>, ref_i: &na::Vector3<f64>, std: f64) -> bool {
+        let Some(meas_b) = meas_b.try_normalize(1e-9) else {
+            return false;
         };
-        sensor_data.star_trackers[st_idx] = meas;
+        let predicted_b = self.q_i2b.inverse() * ref_i.normalize();
+        let mut h = na::SMatrix::<f64, 3, 6>::zeros();
+        h.fixed_view_mut::<3, 3>(0, 0).copy_from(&predicted_b.cross_matrix());
+        self.update(meas_b - predicted_b, h, na::Matrix3::identity() * std.powi(2))
     }
+    /// Runs one step of the filter with the sensor data available at `time`
+    pub fn step(&mut self, sensor_data: &SensorData, time: Epoch) {
+        if let Some(imu) = sensor_data.imus.iter().find(|imu| imu.valid) {
+            self.omega_meas_b = imu.value.omega_b;
+        }
+        match self.last_time {
+            // Start over if the clock has gone backwards (e.g. the simulation was reset)
+            Some(last_time) if time < last_time => self.initialized = false,
+            Some(last_time) if self.initialized => self.propagate((time - last_time).to_seconds()),
+            _ => {}
+        }
+        self.last_time = Some(time);
 
-    for (st_idx, star_sensor_output) in star_sensor_query.read().enumerate() {
-        let meas = Measurement {
-            value: star_sensor_output.clone(),
-            time: star_sensor_output.measurement_time,
-            valid: true,
+        let observations = self.vector_observations(sensor_data, time);
+        let determined_attitude = match self.config.attitude_source {
+            AttitudeSource::StarTracker => {
+                let star_tracker_covariance = na::Matrix3::identity() * self.config.star_tracker_std.powi(2);
+                for star_tracker in sensor_data.star_trackers.iter().filter(|st| st.fresh) {
+                    self.process_attitude(&star_tracker.value.q_i2b, &star_tracker_covariance, "star tracker");
+                }
+                if self.initialized {
+                    for obs in &observations {
+                        if !self.update_vector(&obs.meas_b, &obs.ref_i, obs.std) {
+                            log::warn!("Attitude estimator rejected a star/sun vector measurement");
+                        }
+                    }
+                }
+                None
+            },
+            AttitudeSource::Triad if observations.len() >= 2 => triad(&observations[0], &observations[1]),
+            AttitudeSource::Quest => quest(&observations),
+            _ => None,
         };
-        sensor_data.star_sensors[st_idx] = meas;
+        if let (Some(q_meas_i2b), Some(covariance)) = (determined_attitude, attitude_covariance(&observations)) {
+            self.process_attitude(&q_meas_i2b, &covariance, "vector attitude");
+        }
+    }
+    pub fn output(&self) -> AttitudeEstimatorOutput {
+        AttitudeEstimatorOutput {
+            q_i2b: self.q_i2b,
+            omega_b: self.omega_meas_b - self.gyro_bias_b,
+            gyro_bias_b: self.gyro_bias_b,
+            covariance: self.covariance,
+            valid: self.initialized,
+        }
+    }
+}
+/// Position and velocity of the spacecraft relative to the Moon, published for guidance
+#[derive(Debug, Clone, Event)]
+pub struct NavigationState {
+    pub pos_i: na::Vector3<f64>,
+    pub vel_i: na::Vector3<f64>,
+    /// Covariance of the position (m) and velocity (m/s) errors
+    pub covariance: na::Matrix6<f64>,
+    /// Height above the mean radius of the Moon
+    pub altitude: f64,
+    pub time: Epoch,
+    /// False until the filter has been initialized with an uplinked state vector
+    pub valid: bool,
+}
+impl Default for NavigationState {
+    fn default() -> Self {
+        Self {
+            pos_i: na::Vector3::zeros(),
+            vel_i: na::Vector3::zeros(),
+            covariance: na::Matrix6::identity(),
+            altitude: 0.0,
+            time: Epoch::default(),
+            valid: false,
+        }
     }
+}
+
+/// Tuning of the navigation filter
+#[derive(Debug, Clone)]
+pub struct NavigationFilterConfig {
+    /// Gravitational parameter (m^3/s^2) and mean radius (m) of the Moon
+    pub mu: f64,
+    pub body_radius: f64,
+    /// Accelerometer noise and unmodelled accelerations such as third-body gravity (m/s^1.5)
+    pub accel_noise_density: f64,
+    /// 1-sigma errors of the uplinked position (m) and velocity (m/s)
+    pub uplink_position_std: f64,
+    pub uplink_velocity_std: f64,
+    /// 1-sigma error of the radar slant range: a constant part (m) and a part proportional to the range
+    pub altimeter_std: f64,
+    pub altimeter_std_per_range: f64,
+    /// 1-sigma error of the Doppler velocity along each axis (m/s)
+    pub doppler_std: f64,
+}
+impl Default for NavigationFilterConfig {
+    fn default() -> Self {
+        Self {
+            mu: 4.9028695e12,
+            body_radius: 1737400.0,
+            accel_noise_density: 2e-3,
+            uplink_position_std: 100.0,
+            uplink_velocity_std: 0.1,
+            altimeter_std: 1.0,
+            altimeter_std_per_range: 5e-3,
+            doppler_std: 0.2,
+        }
+    }
+}
+impl NavigationFilterConfig {
+    /// Defaults with whatever is given in the configuration applied
+    pub fn from_params(params: &NavigationFilterParams) -> Self {
+        let default = Self::default();
+        Self {
+            accel_noise_density: params.accel_noise_density.unwrap_or(default.accel_noise_density),
+            uplink_position_std: params.uplink_position_std.unwrap_or(default.uplink_position_std),
+            uplink_velocity_std: params.uplink_velocity_std.unwrap_or(default.uplink_velocity_std),
+            altimeter_std: params.altimeter_std.unwrap_or(default.altimeter_std),
+            altimeter_std_per_range: params.altimeter_std_per_range.unwrap_or(default.altimeter_std_per_range),
+            doppler_std: params.doppler_std.unwrap_or(default.doppler_std),
+            ..default
+        }
+    }
+}
+
+/// 99.9% points of the chi-squared distribution with 1 to 6 degrees of freedom, used to gate
+/// the normalized innovation squared
+const CHI_SQUARED_GATE: [f64; 6] = [10.83, 13.82, 16.27, 18.47, 20.52, 22.46];
+
+/// Kalman filter for the position and velocity of the spacecraft in the Moon-centered inertial
+/// frame. The state is propagated with a point-mass lunar gravity model plus the specific force
+/// measured by the accelerometers (rotated with the estimated attitude). It is updated with the
+/// RADVS slant range (against a spherical Moon) and Doppler velocity and with state vectors
+/// uplinked from the ground, the first of which initializes the filter. The rotation of the Moon
+/// is ignored for the Doppler velocity, as it is in the RADVS itself.
+#[derive(Debug, Clone, Component)]
+pub struct NavigationFilter {
+    pub config: NavigationFilterConfig,
+    pos_i: na::Vector3<f64>,
+    vel_i: na::Vector3<f64>,
+    covariance: na::Matrix6<f64>,
+    initialized: bool,
+    /// Last accelerometer measurement, which is held through IMU outages
+    acc_meas_b: na::Vector3<f64>,
+    last_time: Option<Epoch>,
+}
+impl Default for NavigationFilter {
+    fn default() -> Self {
+        Self::new(NavigationFilterConfig::default())
+    }
+}
+impl NavigationFilter {
+    pub fn new(config: NavigationFilterConfig) -> Self {
+        Self {
+            config,
+            pos_i: na::Vector3::zeros(),
+            vel_i: na::Vector3::zeros(),
+            covariance: na::Matrix6::identity(),
+            initialized: false,
+            acc_meas_b: na::Vector3::zeros(),
+            last_time: None,
+        }
+    }
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+    /// Starts the filter from an uplinked state vector
+    pub fn initialize(&mut self, pos_i: na::Vector3<f64>, vel_i: na::Vector3<f64>) {
+        self.pos_i = pos_i;
+        self.vel_i = vel_i;
+        self.covariance = self.uplink_covariance();
+        self.initialized = true;
+    }
+    fn uplink_covariance(&self) -> na::Matrix6<f64> {
+        let (pos_var, vel_var) = (self.config.uplink_position_std.powi(2), self.config.uplink_velocity_std.powi(2));
+        na::Matrix6::from_diagonal(&na::Vector6::new(pos_var, pos_var, pos_var, vel_var, vel_var, vel_var))
+    }
+    fn gravity(&self, pos_i: &na::Vector3<f64>) -> na::Vector3<f64> {
+        -self.config.mu / pos_i.norm().powi(3) * pos_i
+    }
+    /// Propagates the state and covariance over `dt` seconds with the given non-gravitational
+    /// acceleration in the inertial frame
+    pub fn propagate(&mut self, dt: f64, acc_i: &na::Vector3<f64>) {
+        let acc0 = self.gravity(&self.pos_i) + acc_i;
+        let pos1 = self.pos_i + self.vel_i * dt + acc0 * (0.5 * dt * dt);
+        let acc1 = self.gravity(&pos1) + acc_i;
+        // Gravity gradient at the start of the step
+        let r = self.pos_i.norm();
+        let r_hat = self.pos_i / r;
+        let gradient = self.config.mu / r.powi(3) * (3.0 * r_hat * r_hat.transpose() - na::Matrix3::identity());
+        self.pos_i = pos1;
+        self.vel_i += (acc0 + acc1) * (0.5 * dt);
+
+        let mut f = na::Matrix6::zeros();
+        f.fixed_view_mut::<3, 3>(0, 3).fill_with_identity();
+        f.fixed_view_mut::<3, 3>(3, 0).copy_from(&gradient);
+        let phi = na::Matrix6::identity() + f * dt + f * f * (0.5 * dt * dt);
+        let q_a = self.config.accel_noise_density.powi(2);
+        let mut q = na::Matrix6::zeros();
+        q.fixed_view_mut::<3, 3>(0, 0).fill_diagonal(q_a * dt.powi(3) / 3.0);
+        q.fixed_view_mut::<3, 3>(0, 3).fill_diagonal(q_a * dt.powi(2) / 2.0);
+        q.fixed_view_mut::<3, 3>(3, 0).fill_diagonal(q_a * dt.powi(2) / 2.0);
+        q.fixed_view_mut::<3, 3>(3, 3).fill_diagonal(q_a * dt);
+        self.covariance = phi * self.covariance * phi.transpose() + q;
+    }
+    /// Kalman update with the residual `y`, measurement matrix `h` and noise covariance `r`.
+    /// Returns false if the measurement fails the innovation gate.
+    fn update<const D: usize>(&mut self, y: na::SVector<f64, D>, h: na::SMatrix<f64, D, 6>, r: na::SMatrix<f64, D, D>) -> bool {
+        let s = h * self.covariance * h.transpose() + r;
+        let Some(s_inv) = s.try_inverse() else {
+            return false;
+        };
+        if (y.transpose() * s_inv * y)[0] > CHI_SQUARED_GATE[D - 1] {
+            return false;
+        }
+        let k = self.covariance * h.transpose() * s_inv;
+        let dx = k * y;
+        self.pos_i += dx.fixed_rows::<3>(0);
+        self.vel_i += dx.fixed_rows::<3>(3);
+        // Joseph form to keep the covariance symmetric and positive definite
+        let i_kh = na::Matrix6::identity() - k * h;
+        self.covariance = i_kh * self.covariance * i_kh.transpose() + k * r * k.transpose();
+        true
+    }
+    /// Update with a slant range measured along `beam_i` to a spherical Moon. Returns false if
+    /// the measurement is rejected or the beam is not expected to hit the surface.
+    pub fn update_slant_range(&mut self, slant_range: f64, beam_i: &na::Vector3<f64>) -> bool {
+        let Some(u) = beam_i.try_normalize(1e-9) else {
+            return false;
+        };
+        let r_dot_u = self.pos_i.dot(&u);
+        let discriminant = r_dot_u * r_dot_u - self.pos_i.norm_squared() + self.config.body_radius.powi(2);
+        if discriminant <= 0.0 || r_dot_u >= 0.0 {
+            return false;
+        }
+        let predicted = -r_dot_u - discriminant.sqrt();
+        let d_range_d_pos = -u - (u * r_dot_u - self.pos_i) / discriminant.sqrt();
+        let mut h = na::SMatrix::<f64, 1, 6>::zeros();
+        h.fixed_view_mut::<1, 3>(0, 0).copy_from(&d_range_d_pos.transpose());
+        let std = self.config.altimeter_std + self.config.altimeter_std_per_range * slant_range;
+        self.update(na::Vector1::new(slant_range - predicted), h, na::Matrix1::new(std * std))
+    }
+    /// Update with a measured velocity relative to the surface
+    pub fn update_velocity(&mut self, vel_meas_i: &na::Vector3<f64>) -> bool {
+        let mut h = na::SMatrix::<f64, 3, 6>::zeros();
+        h.fixed_view_mut::<3, 3>(0, 3).fill_with_identity();
+        let r = na::Matrix3::identity() * self.config.doppler_std.powi(2);
+        self.update(vel_meas_i - self.vel_i, h, r)
+    }
+    /// Update with an uplinked state vector. The filter is restarted from it if it is rejected,
+    /// since the ground solution is trusted over the onboard one.
+    pub fn update_state_vector(&mut self, pos_i: &na::Vector3<f64>, vel_i: &na::Vector3<f64>) {
+        if !self.initialized {
+            self.initialize(*pos_i, *vel_i);
+            return;
+        }
+        let y = na::Vector6::new(pos_i.x, pos_i.y, pos_i.z, vel_i.x, vel_i.y, vel_i.z)
+            - na::Vector6::new(self.pos_i.x, self.pos_i.y, self.pos_i.z, self.vel_i.x, self.vel_i.y, self.vel_i.z);
+        if !self.update(y, na::Matrix6::identity(), self.uplink_covariance()) {
+            log::warn!("Navigation filter restarted from an uplinked state vector that failed the innovation gate");
+            self.initialize(*pos_i, *vel_i);
+        }
+    }
+    /// Runs the filter up to `time` and processes the new measurements
+    pub fn step(&mut self, sensor_data: &SensorData, attitude: &AttitudeEstimatorOutput,
+                uplinks: &[EphemerisOutput], time: Epoch) {
+        if let Some(imu) = sensor_data.imus.iter().find(|imu| imu.valid) {
+            self.acc_meas_b = imu.value.acc_b;
+        }
+        let acc_i = if attitude.valid { attitude.q_i2b * self.acc_meas_b } else { na::Vector3::zeros() };
+        match self.last_time {
+            // Start over if the clock has gone backwards (e.g. the simulation was reset)
+            Some(last_time) if time < last_time => self.initialized = false,
+            Some(last_time) if self.initialized => self.propagate((time - last_time).to_seconds(), &acc_i),
+            _ => {}
+        }
+        self.last_time = Some(time);
+
+        for uplink in uplinks {
+            // Bring the state vector up to the current time (it is at most a few steps old)
+            let dt = (time - uplink.time).to_seconds();
+            let acc = self.gravity(&uplink.pos_i) + acc_i;
+            self.update_state_vector(&(uplink.pos_i + uplink.vel_i * dt + acc * (0.5 * dt * dt)), &(uplink.vel_i + acc * dt));
+        }
+        if !self.initialized || !attitude.valid {
+            return;
+        }
+        let Some(radvs) = sensor_data.radvs.iter().find(|radvs| radvs.fresh).map(|radvs| &radvs.value) else {
+            return;
+        };
+        if radvs.range_valid && !self.update_slant_range(radvs.slant_range, &(attitude.q_i2b * radvs.range_beam_b)) {
+            log::warn!("Navigation filter rejected a radar altimeter measurement");
+        }
+        if radvs.velocity_valid && !self.update_velocity(&(attitude.q_i2b * radvs.vel_b)) {
+            log::warn!("Navigation filter rejected a Doppler velocity measurement");
+        }
+    }
+    pub fn output(&self) -> NavigationState {
+        NavigationState {
+            pos_i: self.pos_i,
+            vel_i: self.vel_i,
+            covariance: self.covariance,
+            altitude: self.pos_i.norm() - self.config.body_radius,
+            time: self.last_time.unwrap_or_default(),
+            valid: self.initialized,
+        }
+    }
+}
 
-    // TODO: Mark measurements as invalid if they are too old
-    sensor_data_writer.send(sensor_data);
+/// Collects the outputs of all the sensors and publishes them with their health
+pub fn update_sensor_aggregator(mut imu_query: EventReader<IMUOutput>,
+                                mut str_query: EventReader<StarTrackerOutput>,
+                                mut star_sensor_query: EventReader<StarSensorOutput>,
+                                mut magnetometer_query: EventReader<MagnetometerOutput>,
+                                mut sun_sensor_query: EventReader<SunSensorOutput>,
+                                mut amr_query: EventReader<AmrOutput>,
+                                mut radvs_query: EventReader<RadvsOutput>,
+                                mut camera_query: EventReader<CameraImage>,
+                                mut sensor_data_writer: EventWriter<SensorData>,
+                                mut query: Query<&mut SensorAggregator>,
+                                clock: Res<SystemClock>
+)
+{
+    // Assume there is only a single sensor aggregator
+    let Ok(mut aggregator) = query.get_single_mut() else {
+        return;
+    };
+    aggregator.start_update(clock.time);
+    let data = &mut aggregator.data;
+    record_measurements(&mut data.imus, imu_query.read(), |imu| &imu.sensor, |imu| imu.measurement_time, |_| true);
+    record_measurements(&mut data.star_trackers, str_query.read(), |st| &st.sensor, |st| st.measurement_time, |st| st.valid);
+    record_measurements(&mut data.star_sensors, star_sensor_query.read(), |ss| &ss.sensor, |ss| ss.measurement_time, |ss| ss.valid);
+    record_measurements(&mut data.magnetometers, magnetometer_query.read(), |mag| &mag.sensor, |mag| mag.measurement_time, |mag| mag.valid);
+    record_measurements(&mut data.sun_sensors, sun_sensor_query.read(), |ss| &ss.sensor, |ss| ss.measurement_time, |ss| ss.valid);
+    record_measurements(&mut data.amrs, amr_query.read(), |amr| &amr.sensor, |amr| amr.measurement_time, |amr| amr.valid);
+    record_measurements(&mut data.radvs, radvs_query.read(), |radvs| &radvs.sensor, |radvs| radvs.measurement_time,
+        |radvs| radvs.velocity_valid || radvs.range_valid);
+    record_measurements(&mut data.cameras, camera_query.read(), |image| &image.sensor, |image| image.measurement_time, |_| true);
+    sensor_data_writer.send(aggregator.finish_update(clock.time).clone());
 }
 
-/// A simple attitude estimator that uses the data from the IMU and Star Tracker directly
-pub fn update_simple_attitude_estimator(mut sensor_data_reader: EventReader<SensorData>,
-                                        mut attitude_estimate_writer: EventWriter<AttitudeEstimatorOutput>) {
-    let mut attitude_estimator_output = AttitudeEstimatorOutput::default();
+/// Attitude estimator (MEKF) that runs whenever new sensor data is available
+pub fn update_attitude_estimator(mut sensor_data_reader: EventReader<SensorData>,
+                                 mut attitude_estimate_writer: EventWriter<AttitudeEstimatorOutput>,
+                                 mut query: Query<&mut AttitudeEstimator>,
+                                 clock: Res<SystemClock>) {
+    let Ok(mut estimator) = query.get_single_mut() else {
+        return;
+    };
     if let Some(sensor_data) = sensor_data_reader.read().last()
     {
-        // Find first IMU with valid data
-        if let Some(imu) = sensor_data.imus.iter().filter(|x| x.valid).next()
-        {
-            attitude_estimator_output.omega_b = imu.value.omega_b;
+        estimator.step(sensor_data, clock.time);
+    }
+    attitude_estimate_writer.send(estimator.output());
+}
+
+/// Navigation filter that runs after the attitude estimator
+pub fn update_navigation_filter(mut sensor_data_reader: EventReader<SensorData>,
+                                mut attitude_reader: EventReader<AttitudeEstimatorOutput>,
+                                mut uplink_reader: EventReader<EphemerisOutput>,
+                                mut navigation_state_writer: EventWriter<NavigationState>,
+                                mut query: Query<&mut NavigationFilter>,
+                                clock: Res<SystemClock>) {
+    let Ok(mut filter) = query.get_single_mut() else {
+        return;
+    };
+    let uplinks = uplink_reader.read().cloned().collect::<Vec<_>>();
+    let attitude = attitude_reader.read().last().cloned();
+    if let (Some(sensor_data), Some(attitude)) = (sensor_data_reader.read().last(), attitude) {
+        filter.step(sensor_data, &attitude, &uplinks, clock.time);
+    }
+    navigation_state_writer.send(filter.output());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::{IMUOutput, RadvsOutput, StarSensorOutput, StarTrackerOutput, SunSensorOutput};
+
+    /// Measurement that has just been received from a healthy sensor
+    fn fresh<T: Default>(value: T) -> Measurement<T> {
+        Measurement { value, valid: true, fresh: true, health: SensorHealth::Good, ..Default::default() }
+    }
+
+    fn sensor_data(omega_meas_b: na::Vector3<f64>, q_meas_i2b: Option<na::UnitQuaternion<f64>>) -> SensorData {
+        let mut sensor_data = SensorData::default();
+        sensor_data.imus = vec![fresh(IMUOutput { omega_b: omega_meas_b, ..Default::default() })];
+        if let Some(q_i2b) = q_meas_i2b {
+            sensor_data.star_trackers = vec![fresh(StarTrackerOutput { q_i2b, valid: true, ..Default::default() })];
         }
+        sensor_data
+    }
 
-        if let Some(star_tracker) = sensor_data.star_trackers.iter().filter(|x| x.valid).next()
-        {
-            attitude_estimator_output.q_i2b = star_tracker.value.q_i2b;
+    /// Runs the estimator on a spacecraft spinning at a constant rate with a biased gyro. Star
+    /// tracker updates come at 1 Hz when `star_tracker_available` is true.
+    fn run(estimator: &mut AttitudeEstimator, start: f64, duration: f64, star_tracker_available: bool) -> na::UnitQuaternion<f64> {
+        let omega_b = na::Vector3::new(0.01, -0.02, 0.005);
+        let bias_b = na::Vector3::new(5e-5, -8e-5, 2e-5);
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_true = |t: f64| na::UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3) * na::UnitQuaternion::from_scaled_axis(omega_b * t);
+        let steps = (duration * 10.0).round() as usize;
+        for step in 0..=steps {
+            let t = start + step as f64 * 0.1;
+            let q_meas = (star_tracker_available && step % 10 == 0).then(|| q_true(t));
+            estimator.step(&sensor_data(omega_b + bias_b, q_meas), epoch + Duration::from_seconds(t));
         }
-        // screen_print!("Omega_b: {:.2?}", attitude_estimator_output.omega_b);
+        q_true(start + duration)
+    }
+
+    #[test]
+    fn test_sun_direction() {
+        // March equinox of 2000 (07:35 UTC)
+        let sun_i = sun_direction_j2000(Epoch::from_gregorian_utc(2000, 3, 20, 7, 35, 0, 0));
+        assert!(sun_i.angle(&na::Vector3::x()).to_degrees() < 0.05);
+        // June solstice of 2024 (20:51 UTC)
+        let sun_i = sun_direction_j2000(Epoch::from_gregorian_utc(2024, 6, 20, 20, 51, 0, 0));
+        assert!((sun_i.z.asin().to_degrees() - 23.44).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_moon_orientation() {
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_i2f = moon_j2000_to_fixed(epoch);
+        // The pole of the Moon is about 1.5 deg from the pole of the ecliptic
+        let pole_i = q_i2f.inverse_transform_vector(&na::Vector3::z());
+        assert!((pole_i.z.asin().to_degrees() - 66.5).abs() < 2.0);
+        assert!((pole_i.y.atan2(pole_i.x).to_degrees().rem_euclid(360.0) - 270.0).abs() < 5.0);
+        // A point fixed to the surface moves with the angular velocity of the Moon
+        let site_f = na::Vector3::new(1737400.0, 0.0, 0.0);
+        let dt = 10.0;
+        let site_vel_i = (moon_j2000_to_fixed(epoch + Duration::from_seconds(dt)).inverse_transform_vector(&site_f)
+            - q_i2f.inverse_transform_vector(&site_f)) / dt;
+        let expected_vel_i = moon_angular_velocity_j2000(epoch).cross(&q_i2f.inverse_transform_vector(&site_f));
+        assert!((expected_vel_i.norm() - 4.62).abs() < 0.01, "{}", expected_vel_i.norm());
+        assert!((site_vel_i - expected_vel_i).norm() < 1e-3 * expected_vel_i.norm(), "{} {}", site_vel_i, expected_vel_i);
+    }
+
+    #[test]
+    fn test_mekf_bias_estimation_and_outage() {
+        let mut estimator = AttitudeEstimator::default();
+        assert!(!estimator.output().valid);
+        let q_true = run(&mut estimator, 0.0, 300.0, true);
+        let output = estimator.output();
+        assert!(output.valid);
+        assert!((output.gyro_bias_b - na::Vector3::new(5e-5, -8e-5, 2e-5)).norm() < 2e-6, "{:?}", output.gyro_bias_b);
+        assert!(output.q_i2b.angle_to(&q_true) < 1e-4);
+        let converged_std = output.covariance[(0, 0)].sqrt();
+
+        // The attitude is propagated with the gyros through a star tracker outage
+        let q_true = run(&mut estimator, 300.1, 60.0, false);
+        let output = estimator.output();
+        assert!(output.q_i2b.angle_to(&q_true) < 1e-3);
+        assert!(output.covariance[(0, 0)].sqrt() > 2.0 * converged_std);
+
+        // A measurement that is far off is rejected
+        let q_wrong = q_true * na::UnitQuaternion::from_euler_angles(0.2, 0.0, 0.0);
+        assert!(!estimator.update_attitude(&q_wrong, 1e-4));
+        assert!(estimator.output().q_i2b.angle_to(&q_true) < 1e-3);
+    }
+
+    #[test]
+    fn test_mekf_recovers_from_divergence() {
+        // Confidently wrong initial attitude: the star tracker measurements fail the innovation
+        // gate until the filter is restarted
+        let mut estimator = AttitudeEstimator::default();
+        estimator.initialize(na::UnitQuaternion::from_euler_angles(0.5, 0.0, 0.0));
+        let q_true = run(&mut estimator, 0.0, 10.0, true);
+        assert!(estimator.output().q_i2b.angle_to(&q_true) < 1e-3);
+    }
+
+    #[test]
+    fn test_mekf_vector_update() {
+        let mut estimator = AttitudeEstimator::default();
+        estimator.initialize(na::UnitQuaternion::identity());
+        // A single vector only observes the attitude about the axes perpendicular to it
+        let sun_i = na::Vector3::x();
+        assert!(estimator.update_vector(&sun_i, &sun_i, 1e-3));
+        let covariance = estimator.output().covariance;
+        assert!(covariance[(1, 1)] < 1e-5 && covariance[(2, 2)] < 1e-5);
+        assert!((covariance[(0, 0)] - 1e-4).abs() < 1e-9);
+
+        // An error about Z shows up in the measured direction of the X axis
+        let q_true = na::UnitQuaternion::from_scaled_axis(na::Vector3::z() * 1e-3);
+        let mut estimator = AttitudeEstimator::default();
+        estimator.initialize(na::UnitQuaternion::identity());
+        estimator.update_vector(&(q_true.inverse() * sun_i), &sun_i, 1e-4);
+        assert!((estimator.output().q_i2b.scaled_axis().z - 1e-3).abs() < 1e-5);
+    }
+
+    /// Exact observations of the given inertial directions by a spacecraft with attitude `q_i2b`
+    fn observations(q_i2b: &na::UnitQuaternion<f64>, refs_i: &[(na::Vector3<f64>, f64)]) -> Vec<VectorObservation> {
+        refs_i.iter().filter_map(|(ref_i, std)| VectorObservation::new(&(q_i2b.inverse() * ref_i), ref_i, *std)).collect()
+    }
+
+    #[test]
+    fn test_triad_and_quest() {
+        let q_i2b = na::UnitQuaternion::from_euler_angles(0.3, -1.2, 2.5);
+        let refs_i = [
+            (na::Vector3::new(0.2, -0.9, 0.4), 1e-4),
+            (na::Vector3::new(1.0, 0.3, -0.2), 1e-2),
+            (na::Vector3::new(-0.1, 0.2, 1.0), 1e-3),
+        ];
+        let obs = observations(&q_i2b, &refs_i);
+        assert!(triad(&obs[0], &obs[1]).unwrap().angle_to(&q_i2b) < 1e-9);
+        assert!(quest(&obs[..2]).unwrap().angle_to(&q_i2b) < 1e-6);
+        assert!(quest(&obs).unwrap().angle_to(&q_i2b) < 1e-6);
+
+        // TRIAD matches the primary vector exactly, QUEST weights the vectors by their accuracy
+        let mut noisy = obs.clone();
+        noisy[1].meas_b = (na::UnitQuaternion::from_scaled_axis(na::Vector3::new(0.0, 0.0, 1e-2)) * noisy[1].meas_b).normalize();
+        let q_triad = triad(&noisy[0], &noisy[1]).unwrap();
+        assert!((q_triad.inverse() * noisy[0].ref_i - noisy[0].meas_b).norm() < 1e-9);
+        assert!(quest(&noisy).unwrap().angle_to(&q_i2b) < 2e-3);
+
+        // The attitude error covariance follows the accuracy of the vectors
+        let covariance = attitude_covariance(&obs).unwrap();
+        assert!(covariance.trace().sqrt() < 2e-3);
+    }
+
+    #[test]
+    fn test_quest_180_deg() {
+        // Rotations of 180 deg about each axis are handled by rotating the reference frame
+        let refs_i = [(na::Vector3::new(0.3, 0.4, 0.5), 1e-3), (na::Vector3::new(-0.5, 0.1, 0.2), 1e-3)];
+        for axis in [na::Vector3::x_axis(), na::Vector3::y_axis(), na::Vector3::z_axis()] {
+            let q_i2b = na::UnitQuaternion::from_axis_angle(&axis, std::f64::consts::PI);
+            let q_quest = quest(&observations(&q_i2b, &refs_i)).unwrap();
+            assert!(q_quest.angle_to(&q_i2b) < 1e-6, "{:?}", q_quest);
+        }
+    }
+
+    #[test]
+    fn test_collinear_vectors() {
+        let q_i2b = na::UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3);
+        let v = na::Vector3::new(0.3, 0.4, 0.5);
+        let obs = observations(&q_i2b, &[(v, 1e-3), (-v * 2.0, 1e-3)]);
+        assert!(triad(&obs[0], &obs[1]).is_none());
+        assert!(quest(&obs).is_none());
+        assert!(quest(&obs[..1]).is_none());
+        assert!(attitude_covariance(&obs).is_none());
+    }
+
+    #[test]
+    fn test_mekf_with_vector_attitude_source() {
+        // No star tracker: the attitude comes from the star sensor (Canopus) and the sun sensor
+        let omega_b = na::Vector3::new(0.002, -0.001, 0.003);
+        let bias_b = na::Vector3::new(5e-5, -8e-5, 2e-5);
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let q_true = |t: f64| na::UnitQuaternion::from_euler_angles(0.1, 0.2, 0.3) * na::UnitQuaternion::from_scaled_axis(omega_b * t);
+        for source in [AttitudeSource::Triad, AttitudeSource::Quest] {
+            let config = AttitudeEstimatorConfig { attitude_source: source, ..Default::default() };
+            let canopus_i = config.star_sensor_target_i;
+            let mut estimator = AttitudeEstimator::new(config);
+            for step in 0..=3000 {
+                let t = step as f64 * 0.1;
+                let time = epoch + Duration::from_seconds(t);
+                let mut data = sensor_data(omega_b + bias_b, None);
+                // A star tracker measurement (wrong on purpose) is ignored
+                data.star_trackers = vec![fresh(StarTrackerOutput { q_i2b: na::UnitQuaternion::identity(), valid: true, ..Default::default() })];
+                if step % 10 == 0 {
+                    let q_b2i = q_true(t).inverse();
+                    data.star_sensors = vec![fresh(StarSensorOutput { star_vec_b: q_b2i * canopus_i, valid: true, ..Default::default() })];
+                    data.sun_sensors = vec![fresh(SunSensorOutput { sun_vec_b: q_b2i * sun_direction_j2000(time), valid: true, ..Default::default() })];
+                }
+                estimator.step(&data, time);
+            }
+            let output = estimator.output();
+            assert!(output.valid);
+            assert!(output.q_i2b.angle_to(&q_true(300.0)) < 1e-3, "{:?}", source);
+            assert!((output.gyro_bias_b - bias_b).norm() < 2e-5, "{:?}: {:?}", source, output.gyro_bias_b);
+        }
+    }
+
+    #[test]
+    fn test_navigation_filter_orbit_propagation() {
+        // Circular orbit 100 km above the Moon: the state after 30 min matches the analytical one
+        let mut filter = NavigationFilter::default();
+        let radius = filter.config.body_radius + 100e3;
+        let speed = (filter.config.mu / radius).sqrt();
+        filter.initialize(na::Vector3::new(radius, 0.0, 0.0), na::Vector3::new(0.0, speed, 0.0));
+        for _ in 0..18000 {
+            filter.propagate(0.1, &na::Vector3::zeros());
+        }
+        let angle = speed / radius * 1800.0;
+        let output = filter.output();
+        assert!((output.pos_i - na::Vector3::new(angle.cos(), angle.sin(), 0.0) * radius).norm() < 1.0, "{:?}", output.pos_i);
+        assert!((output.altitude - 100e3).abs() < 1.0);
+        // The covariance grows without measurements
+        assert!(output.covariance[(0, 0)] > 100f64.powi(2));
+    }
+
+    #[test]
+    fn test_navigation_filter_radar_updates() {
+        // Vertical descent over the north pole at a constant rate with the engines cancelling gravity
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let mut filter = NavigationFilter::default();
+        let pos0 = na::Vector3::new(0.0, 0.0, filter.config.body_radius + 10e3);
+        let vel = na::Vector3::new(0.0, 0.0, -50.0);
+        let pos_true = |t: f64| pos0 + vel * t;
+        let attitude = AttitudeEstimatorOutput { valid: true, ..Default::default() };
+        let uplink = EphemerisOutput { pos_i: pos0 + na::Vector3::new(50.0, -80.0, 150.0), vel_i: vel + na::Vector3::new(0.1, 0.0, -0.2), time: epoch };
+        for step in 0..=600 {
+            let t = step as f64 * 0.1;
+            let pos_i = pos_true(t);
+            let mut data = SensorData::default();
+            data.imus = vec![fresh(IMUOutput { acc_b: filter.config.mu / pos_i.norm().powi(3) * pos_i, ..Default::default() })];
+            data.radvs = vec![fresh(RadvsOutput {
+                vel_b: vel,
+                slant_range: pos_i.z - filter.config.body_radius,
+                range_beam_b: -na::Vector3::z(),
+                velocity_valid: true,
+                range_valid: true,
+                ..Default::default()
+            })];
+            let uplinks = if step == 0 { vec![uplink.clone()] } else { vec![] };
+            filter.step(&data, &attitude, &uplinks, epoch + Duration::from_seconds(t));
+        }
+        let output = filter.output();
+        assert!(output.valid);
+        // The altitude and the velocity are observed, the horizontal position is not
+        let pos_error = output.pos_i - pos_true(60.0);
+        assert!(pos_error.z.abs() < 2.0, "{:?}", pos_error);
+        assert!((output.vel_i - vel).norm() < 0.05, "{:?}", output.vel_i);
+        assert!(output.covariance[(2, 2)].sqrt() < 2.0);
+        assert!(output.covariance[(0, 0)].sqrt() > 50.0);
+    }
+
+    #[test]
+    fn test_navigation_filter_uplink() {
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let mut filter = NavigationFilter::default();
+        let attitude = AttitudeEstimatorOutput::default();
+        filter.step(&SensorData::default(), &attitude, &[], epoch);
+        assert!(!filter.output().valid);
+
+        // The first state vector initializes the filter
+        let pos_i = na::Vector3::new(1837400.0, 0.0, 0.0);
+        let vel_i = na::Vector3::new(0.0, 1633.0, 0.0);
+        filter.step(&SensorData::default(), &attitude, &[EphemerisOutput { pos_i, vel_i, time: epoch }], epoch);
+        assert!(filter.output().valid);
+        assert_eq!(filter.output().pos_i, pos_i);
+
+        // One that is far off restarts it, others are blended in
+        let time = epoch + Duration::from_seconds(1.0);
+        let far_off = EphemerisOutput { pos_i: pos_i + na::Vector3::new(10e3, 0.0, 0.0), vel_i, time };
+        filter.step(&SensorData::default(), &attitude, std::slice::from_ref(&far_off), time);
+        assert_eq!(filter.output().pos_i, far_off.pos_i);
+        assert_eq!(filter.output().covariance, filter.uplink_covariance());
+        let time = time + Duration::from_seconds(1.0);
+        filter.step(&SensorData::default(), &attitude, &[], time);
+        let output = filter.output();
+        let consistent = EphemerisOutput { pos_i: output.pos_i + na::Vector3::new(20.0, 0.0, 0.0), vel_i: output.vel_i, time };
+        filter.step(&SensorData::default(), &attitude, &[consistent], time);
+        assert!(filter.output().covariance[(0, 0)] < output.covariance[(0, 0)]);
+    }
+
+    #[test]
+    fn test_sensor_aggregator() {
+        let mut app = bevy_app::App::new();
+        app.add_event::<IMUOutput>()
+            .add_event::<StarTrackerOutput>()
+            .add_event::<StarSensorOutput>()
+            .add_event::<MagnetometerOutput>()
+            .add_event::<SunSensorOutput>()
+            .add_event::<AmrOutput>()
+            .add_event::<RadvsOutput>()
+            .add_event::<CameraImage>()
+            .add_event::<SensorData>()
+            .insert_resource(SystemClock::default())
+            .add_systems(bevy_app::Update, update_sensor_aggregator);
+        let mut config = SensorAggregatorConfig { max_consecutive_failures: 3, ..Default::default() };
+        config.imus = SensorGroupConfig::new(&["A", "B"], 0.5);
+        config.star_trackers.sensors = vec!["ST".to_string()];
+        app.world.spawn(SensorAggregator::new(config));
+
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let mut run = |t: f64, imus: &[&str], star_tracker_valid: Option<bool>| {
+            let time = epoch + Duration::from_seconds(t);
+            app.world.resource_mut::<SystemClock>().time = time;
+            for imu in imus {
+                app.world.send_event(IMUOutput { sensor: imu.to_string(), measurement_time: time, ..Default::default() });
+            }
+            if let Some(valid) = star_tracker_valid {
+                app.world.send_event(StarTrackerOutput { sensor: "ST".to_string(), measurement_time: time, valid, ..Default::default() });
+            }
+            app.update();
+            let events = app.world.resource::<Events<SensorData>>();
+            events.get_reader().read(events).last().unwrap().clone()
+        };
+
+        // The sensors keep their configured order whatever order the measurements arrive in
+        let data = run(0.0, &["B"], Some(true));
+        assert_eq!(data.imus.iter().map(|imu| imu.sensor.as_str()).collect::<Vec<_>>(), ["A", "B"]);
+        assert_eq!(data.imus[0].health, SensorHealth::NoData);
+        assert!(!data.imus[0].valid);
+        assert!(data.imus[1].valid && data.imus[1].fresh);
+        assert!(data.star_trackers[0].fresh);
+
+        // The last measurement is kept (but not fresh) until it becomes stale
+        let data = run(0.3, &[], None);
+        assert!(data.imus[1].valid && !data.imus[1].fresh);
+        assert_eq!(data.imus[1].time, epoch);
+        let data = run(0.6, &["C"], None);
+        assert_eq!(data.imus[1].health, SensorHealth::Stale);
+        assert!(!data.imus[1].valid);
+        // Sensors that are not configured are added when they first report
+        assert_eq!(data.imus[2].sensor, "C");
+        assert!(data.imus[2].fresh);
+
+        // Invalid measurements in a row mark the sensor as failed until it recovers
+        for (step, t) in [0.7, 0.8, 0.9].into_iter().enumerate() {
+            let data = run(t, &[], Some(false));
+            assert_eq!(data.star_trackers[0].consecutive_failures, step + 1);
+            assert!(!data.star_trackers[0].fresh);
+        }
+        let data = run(1.0, &[], None);
+        assert_eq!(data.star_trackers[0].health, SensorHealth::Failed);
+        let data = run(1.1, &[], Some(true));
+        assert_eq!(data.star_trackers[0].health, SensorHealth::Good);
+        assert!(data.star_trackers[0].fresh);
+
+        // Everything is forgotten when the clock goes backwards
+        let data = run(0.0, &[], None);
+        assert_eq!(data.imus.len(), 2);
+        assert!(data.imus.iter().all(|imu| imu.health == SensorHealth::NoData));
+        assert_eq!(data.star_trackers[0].health, SensorHealth::NoData);
     }
-    attitude_estimate_writer.send(attitude_estimator_output);
 }
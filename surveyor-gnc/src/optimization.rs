@@ -0,0 +1,417 @@
+//! Small dense solvers for the optimization problems in the flight software
+
+use nalgebra as na;
+
+/// Pivots smaller than this are treated as zero by the simplex method
+const SIMPLEX_TOLERANCE: f64 = 1e-9;
+const MAX_SIMPLEX_ITERATIONS: usize = 10_000;
+/// Duality gap at which the barrier method stops, relative to the size of the objective
+const BARRIER_TOLERANCE: f64 = 1e-6;
+const BARRIER_GROWTH: f64 = 20.0;
+const MAX_NEWTON_ITERATIONS: usize = 50;
+
+/// Simplex tableau with the objective (reduced costs) in the last row and the right-hand side in
+/// the last column
+struct Tableau {
+    table: na::DMatrix<f64>,
+    /// Index of the basic variable of each constraint row
+    basis: Vec<usize>,
+}
+
+impl Tableau {
+    fn num_rows(&self) -> usize {
+        self.basis.len()
+    }
+    fn rhs(&self, row: usize) -> f64 {
+        self.table[(row, self.table.ncols() - 1)]
+    }
+    fn pivot(&mut self, row: usize, col: usize) {
+        let pivot_row = self.table.row(row) / self.table[(row, col)];
+        self.table.set_row(row, &pivot_row);
+        for i in 0..self.table.nrows() {
+            let factor = self.table[(i, col)];
+            if i != row && factor != 0.0 {
+                let new_row = self.table.row(i) - &pivot_row * factor;
+                self.table.set_row(i, &new_row);
+            }
+        }
+        self.basis[row] = col;
+    }
+    /// Pivots until none of the first `num_cols` columns can improve the objective. Bland's rule
+    /// is used to avoid cycling.
+    fn optimize(&mut self, num_cols: usize) -> Result<(), String> {
+        let obj_row = self.num_rows();
+        for _ in 0..MAX_SIMPLEX_ITERATIONS {
+            let Some(col) = (0..num_cols).find(|&j| self.table[(obj_row, j)] < -SIMPLEX_TOLERANCE) else {
+                return Ok(());
+            };
+            let row = (0..self.num_rows())
+                .filter(|&i| self.table[(i, col)] > SIMPLEX_TOLERANCE)
+                .min_by(|&i, &k| {
+                    let ratio_i = self.rhs(i) / self.table[(i, col)];
+                    let ratio_k = self.rhs(k) / self.table[(k, col)];
+                    ratio_i.total_cmp(&ratio_k).then(self.basis[i].cmp(&self.basis[k]))
+                })
+                .ok_or_else(|| "Linear program is unbounded".to_string())?;
+            self.pivot(row, col);
+        }
+        Err("Simplex method did not converge".to_string())
+    }
+}
+
+/// Solves the linear program
+///
+/// minimize `c^T x` subject to `A x = b` and `0 <= x <= upper`
+///
+/// using the two-phase simplex method. Entries of `upper` may be infinite.
+pub fn solve_lp(c: &na::DVector<f64>, a: &na::DMatrix<f64>, b: &na::DVector<f64>, upper: &[f64]) -> Result<na::DVector<f64>, String> {
+    let num_vars = c.len();
+    assert_eq!(a.ncols(), num_vars, "Constraint matrix does not match the number of variables");
+    assert_eq!(upper.len(), num_vars, "Bounds do not match the number of variables");
+    // Finite upper bounds become constraints with a slack variable
+    let bounded = (0..num_vars).filter(|&i| upper[i].is_finite()).collect::<Vec<_>>();
+    let num_rows = a.nrows() + bounded.len();
+    let num_cols = num_vars + bounded.len();
+    // One artificial variable per constraint gives the starting basis of phase 1
+    let mut table = na::DMatrix::zeros(num_rows + 1, num_cols + num_rows + 1);
+    table.view_mut((0, 0), (a.nrows(), num_vars)).copy_from(a);
+    table.view_mut((0, num_cols + num_rows), (a.nrows(), 1)).copy_from(b);
+    for (k, &i) in bounded.iter().enumerate() {
+        table[(a.nrows() + k, i)] = 1.0;
+        table[(a.nrows() + k, num_vars + k)] = 1.0;
+        table[(a.nrows() + k, num_cols + num_rows)] = upper[i];
+    }
+    for row in 0..num_rows {
+        if table[(row, num_cols + num_rows)] < 0.0 {
+            let flipped = -table.row(row);
+            table.set_row(row, &flipped);
+        }
+        table[(row, num_cols + row)] = 1.0;
+    }
+    // Phase 1 minimizes the sum of the artificial variables
+    for col in (0..num_cols).chain(std::iter::once(num_cols + num_rows)) {
+        table[(num_rows, col)] = -table.view((0, col), (num_rows, 1)).sum();
+    }
+    let mut tableau = Tableau { table, basis: (num_cols..num_cols + num_rows).collect() };
+    tableau.optimize(num_cols + num_rows)?;
+    if -tableau.rhs(num_rows) > 1e-7 * (1.0 + b.amax()) {
+        return Err("Linear program is infeasible".to_string());
+    }
+    // Drive any artificial variables that are left in the basis (at zero) out of it. Rows where
+    // this is not possible are redundant.
+    for row in 0..num_rows {
+        if tableau.basis[row] >= num_cols {
+            if let Some(col) = (0..num_cols).find(|&j| tableau.table[(row, j)].abs() > SIMPLEX_TOLERANCE) {
+                tableau.pivot(row, col);
+            }
+        }
+    }
+    // Phase 2 with the actual objective
+    tableau.table.row_mut(num_rows).fill(0.0);
+    tableau.table.view_mut((num_rows, 0), (1, num_vars)).copy_from(&c.transpose());
+    for row in 0..num_rows {
+        let cost = tableau.table[(num_rows, tableau.basis[row])];
+        if cost != 0.0 {
+            let new_row = tableau.table.row(num_rows) - tableau.table.row(row) * cost;
+            tableau.table.set_row(num_rows, &new_row);
+        }
+    }
+    tableau.optimize(num_cols)?;
+
+    let mut x = na::DVector::zeros(num_vars);
+    for row in 0..num_rows {
+        if tableau.basis[row] < num_vars {
+            x[tableau.basis[row]] = tableau.rhs(row);
+        }
+    }
+    Ok(x)
+}
+
+/// Second-order cone constraint `||A x + b|| <= c^T x + d`. A linear inequality `c^T x + d >= 0`
+/// is a cone with no rows in `A`.
+#[derive(Debug, Clone)]
+pub struct SecondOrderCone {
+    pub a: na::DMatrix<f64>,
+    pub b: na::DVector<f64>,
+    pub c: na::DVector<f64>,
+    pub d: f64,
+}
+
+impl SecondOrderCone {
+    pub fn new(a: na::DMatrix<f64>, b: na::DVector<f64>, c: na::DVector<f64>, d: f64) -> Self {
+        assert_eq!(a.nrows(), b.len(), "Cone offset does not match the cone matrix");
+        assert_eq!(a.ncols(), c.len(), "Cone matrix does not match the number of variables");
+        Self { a, b, c, d }
+    }
+    pub fn linear(c: na::DVector<f64>, d: f64) -> Self {
+        Self::new(na::DMatrix::zeros(0, c.len()), na::DVector::zeros(0), c, d)
+    }
+    /// Vector and bound of the cone at `x`
+    fn evaluate(&self, x: &na::DVector<f64>) -> (na::DVector<f64>, f64) {
+        (&self.a * x + &self.b, self.c.dot(x) + self.d)
+    }
+    /// Amount by which `x` is inside the cone (negative if it is outside)
+    fn margin(&self, x: &na::DVector<f64>) -> f64 {
+        let (u, t) = self.evaluate(x);
+        t - u.norm()
+    }
+}
+
+/// Cones stacked into single matrices, so that the barrier is evaluated with a few large matrix
+/// products
+struct ConeStack {
+    a: na::DMatrix<f64>,
+    b: na::DVector<f64>,
+    c: na::DMatrix<f64>,
+    d: na::DVector<f64>,
+    /// First row in `a` and number of rows of each cone
+    rows: Vec<(usize, usize)>,
+}
+
+impl ConeStack {
+    fn new(cones: &[SecondOrderCone], num_vars: usize) -> Self {
+        let num_rows = cones.iter().map(|cone| cone.a.nrows()).sum();
+        let mut stack = Self {
+            a: na::DMatrix::zeros(num_rows, num_vars),
+            b: na::DVector::zeros(num_rows),
+            c: na::DMatrix::zeros(cones.len(), num_vars),
+            d: na::DVector::zeros(cones.len()),
+            rows: Vec::with_capacity(cones.len()),
+        };
+        let mut start = 0;
+        for (i, cone) in cones.iter().enumerate() {
+            let len = cone.a.nrows();
+            stack.a.view_mut((start, 0), (len, num_vars)).copy_from(&cone.a);
+            stack.b.rows_mut(start, len).copy_from(&cone.b);
+            stack.c.set_row(i, &cone.c.transpose());
+            stack.d[i] = cone.d;
+            stack.rows.push((start, len));
+            start += len;
+        }
+        stack
+    }
+    fn num_cones(&self) -> usize {
+        self.rows.len()
+    }
+    /// Vectors `u`, bounds `t` and slacks `t^2 - |u|^2` of the cones, or None outside the interior
+    /// of any cone
+    fn evaluate(&self, x: &na::DVector<f64>) -> Option<(na::DVector<f64>, na::DVector<f64>, na::DVector<f64>)> {
+        let u = &self.a * x + &self.b;
+        let t = &self.c * x + &self.d;
+        let slack = na::DVector::from_fn(self.num_cones(), |i, _| {
+            let (start, len) = self.rows[i];
+            t[i] * t[i] - u.rows(start, len).norm_squared()
+        });
+        (t.iter().all(|&t| t > 0.0) && slack.iter().all(|&slack| slack > 0.0)).then_some((u, t, slack))
+    }
+    /// Sum of the logarithmic barriers `-ln(t^2 - |u|^2)` of the cones
+    fn barrier_value(&self, x: &na::DVector<f64>) -> Option<f64> {
+        let (_, _, slack) = self.evaluate(x)?;
+        Some(-slack.iter().map(|slack| slack.ln()).sum::<f64>())
+    }
+    /// Sum of the barriers with its gradient and Hessian
+    fn barrier(&self, x: &na::DVector<f64>) -> Option<(f64, na::DVector<f64>, na::DMatrix<f64>)> {
+        let (u, t, slack) = self.evaluate(x)?;
+        // Gradient of each slack, 2 t c - 2 A^T u, as a row
+        let mut u_blocks = na::DMatrix::zeros(self.num_cones(), self.a.nrows());
+        for (i, &(start, len)) in self.rows.iter().enumerate() {
+            u_blocks.view_mut((i, start), (1, len)).copy_from(&u.rows(start, len).transpose());
+        }
+        let mut slack_gradients = &u_blocks * &self.a * -2.0;
+        let mut scaled_c = self.c.clone();
+        let mut scaled_a = self.a.clone();
+        for (i, &(start, len)) in self.rows.iter().enumerate() {
+            let mut gradient = slack_gradients.row_mut(i);
+            gradient += self.c.row(i) * (2.0 * t[i]);
+            scaled_c.row_mut(i).scale_mut(-2.0 / slack[i]);
+            scaled_a.rows_mut(start, len).scale_mut(2.0 / slack[i]);
+        }
+        let mut scaled_gradients = slack_gradients.clone();
+        for i in 0..self.num_cones() {
+            scaled_gradients.row_mut(i).scale_mut(1.0 / (slack[i] * slack[i]));
+        }
+        let value = -slack.iter().map(|slack| slack.ln()).sum::<f64>();
+        let gradient = -(slack_gradients.transpose() * slack.map(|slack| 1.0 / slack));
+        let hessian = slack_gradients.transpose() * scaled_gradients + self.c.transpose() * scaled_c + self.a.transpose() * scaled_a;
+        Some((value, gradient, hessian))
+    }
+}
+
+/// Objective of the barrier problem `weight * c^T x + sum of cone barriers`, with its gradient and
+/// Hessian, or None outside the interior of the cones
+fn barrier_objective(c: &na::DVector<f64>, cones: &ConeStack, weight: f64, x: &na::DVector<f64>)
+    -> Option<(f64, na::DVector<f64>, na::DMatrix<f64>)> {
+    let (value, gradient, hessian) = cones.barrier(x)?;
+    Some((value + weight * c.dot(x), gradient + c * weight, hessian))
+}
+
+fn barrier_value(c: &na::DVector<f64>, cones: &ConeStack, weight: f64, x: &na::DVector<f64>) -> Option<f64> {
+    Some(cones.barrier_value(x)? + weight * c.dot(x))
+}
+
+/// Minimizes the barrier problem subject to `A x = b` with Newton's method from a point inside the
+/// cones. Stops early at a point for which `done` returns true.
+fn center(c: &na::DVector<f64>, a: &na::DMatrix<f64>, b: &na::DVector<f64>, cones: &ConeStack,
+          weight: f64, mut x: na::DVector<f64>, done: &impl Fn(&na::DVector<f64>) -> bool) -> Result<na::DVector<f64>, String> {
+    let n = x.len();
+    let m = a.nrows();
+    for _ in 0..MAX_NEWTON_ITERATIONS {
+        if done(&x) {
+            return Ok(x);
+        }
+        let (value, gradient, hessian) = barrier_objective(c, cones, weight, &x)
+            .ok_or_else(|| "Barrier method left the interior of the cones".to_string())?;
+        let mut kkt = na::DMatrix::zeros(n + m, n + m);
+        kkt.view_mut((0, 0), (n, n)).copy_from(&hessian);
+        kkt.view_mut((n, 0), (m, n)).copy_from(a);
+        kkt.view_mut((0, n), (n, m)).copy_from(&a.transpose());
+        let mut rhs = na::DVector::zeros(n + m);
+        rhs.rows_mut(0, n).copy_from(&-&gradient);
+        rhs.rows_mut(n, m).copy_from(&(b - a * &x));
+        let step = kkt.lu().solve(&rhs).ok_or_else(|| "Singular Newton system in the barrier method".to_string())?;
+        let dx = step.rows(0, n).into_owned();
+        let decrement = dx.dot(&(&hessian * &dx));
+        if decrement / 2.0 < 1e-8 {
+            return Ok(x);
+        }
+        // Backtracking line search that stays inside the cones
+        let slope = gradient.dot(&dx);
+        let mut step_size = 1.0;
+        loop {
+            let candidate = &x + &dx * step_size;
+            if let Some(candidate_value) = barrier_value(c, cones, weight, &candidate) {
+                if candidate_value <= value + 0.01 * step_size * slope {
+                    x = candidate;
+                    break;
+                }
+            }
+            step_size *= 0.5;
+            if step_size < 1e-12 {
+                return Ok(x);
+            }
+        }
+    }
+    Ok(x)
+}
+
+/// Barrier method from a point inside the cones. Stops early at a point for which `done` returns
+/// true.
+fn barrier_method(c: &na::DVector<f64>, a: &na::DMatrix<f64>, b: &na::DVector<f64>, cones: &ConeStack,
+                  mut x: na::DVector<f64>, done: impl Fn(&na::DVector<f64>) -> bool) -> Result<na::DVector<f64>, String> {
+    // Each cone barrier contributes 2 to the duality gap
+    let gap_per_weight = 2.0 * cones.num_cones() as f64;
+    let mut weight = 1.0;
+    loop {
+        x = center(c, a, b, cones, weight, x, &done)?;
+        if done(&x) || gap_per_weight / weight < BARRIER_TOLERANCE * (1.0 + c.dot(&x).abs()) {
+            return Ok(x);
+        }
+        weight *= BARRIER_GROWTH;
+    }
+}
+
+/// Solves the second-order cone program
+///
+/// minimize `c^T x` subject to `A x = b` and `||A_i x + b_i|| <= c_i^T x + d_i` for each cone
+///
+/// using a barrier (interior-point) method. A point strictly inside all the cones is found first
+/// by minimizing the amount by which the cones are violated (phase 1).
+pub fn solve_socp(c: &na::DVector<f64>, a: &na::DMatrix<f64>, b: &na::DVector<f64>, cones: &[SecondOrderCone]) -> Result<na::DVector<f64>, String> {
+    let num_vars = c.len();
+    assert_eq!(a.ncols(), num_vars, "Constraint matrix does not match the number of variables");
+    // Least-squares solution of the equality constraints
+    let x = if a.nrows() == 0 {
+        na::DVector::zeros(num_vars)
+    } else {
+        let x = a.clone().svd(true, true).solve(b, 1e-12)?;
+        if (a * &x - b).amax() > 1e-6 * (1.0 + b.amax()) {
+            return Err("Equality constraints are inconsistent".to_string());
+        }
+        x
+    };
+    let min_margin = cones.iter().map(|cone| cone.margin(&x)).fold(f64::INFINITY, f64::min);
+    let x = if min_margin > 0.0 {
+        x
+    } else {
+        // Phase 1: the cones are widened by an extra variable `s` that is minimized until it is
+        // negative. It is bounded below, and all the variables are kept inside a large ball, so
+        // that the barrier problem has a solution.
+        let phase1_c = na::DVector::from_fn(num_vars + 1, |i, _| if i == num_vars { 1.0 } else { 0.0 });
+        let phase1_x = x.insert_row(num_vars, 1.0 - min_margin);
+        let radius = 1e6 * (1.0 + phase1_x.norm());
+        let phase1_cones = cones.iter().map(|cone| {
+            let a = cone.a.clone().insert_column(num_vars, 0.0);
+            let c = cone.c.clone().insert_row(num_vars, 1.0);
+            SecondOrderCone::new(a, cone.b.clone(), c, cone.d)
+        }).chain([
+            SecondOrderCone::linear(phase1_c.clone(), 1.0),
+            SecondOrderCone::new(na::DMatrix::identity(num_vars + 1, num_vars + 1), na::DVector::zeros(num_vars + 1),
+                                 na::DVector::zeros(num_vars + 1), radius),
+        ]).collect::<Vec<_>>();
+        let phase1_a = a.clone().insert_column(num_vars, 0.0);
+        let phase1_cones = ConeStack::new(&phase1_cones, num_vars + 1);
+        let phase1_x = barrier_method(&phase1_c, &phase1_a, b, &phase1_cones, phase1_x, |x| x[num_vars] < 0.0)?;
+        if phase1_x[num_vars] >= 0.0 {
+            return Err("Second-order cone program is infeasible".to_string());
+        }
+        phase1_x.rows(0, num_vars).into_owned()
+    };
+    barrier_method(c, a, b, &ConeStack::new(cones, num_vars), x, |_| false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve_lp() {
+        // maximize x + y subject to x + 2y <= 4 and 3x + y <= 6 (with slack variables)
+        let c = na::DVector::from_column_slice(&[-1.0, -1.0, 0.0, 0.0]);
+        let a = na::DMatrix::from_row_slice(2, 4, &[1.0, 2.0, 1.0, 0.0, 3.0, 1.0, 0.0, 1.0]);
+        let b = na::DVector::from_column_slice(&[4.0, 6.0]);
+        let x = solve_lp(&c, &a, &b, &[f64::INFINITY; 4]).unwrap();
+        assert!((x[0] - 1.6).abs() < 1e-9 && (x[1] - 1.2).abs() < 1e-9);
+
+        // The same problem with an upper bound on y
+        let x = solve_lp(&c, &a, &b, &[f64::INFINITY, 1.0, f64::INFINITY, f64::INFINITY]).unwrap();
+        assert!((x[0] - 5.0 / 3.0).abs() < 1e-9 && (x[1] - 1.0).abs() < 1e-9);
+
+        // Negative right-hand sides and redundant constraints
+        let a = na::DMatrix::from_row_slice(2, 2, &[-1.0, -1.0, -2.0, -2.0]);
+        let b = na::DVector::from_column_slice(&[-1.0, -2.0]);
+        let x = solve_lp(&na::DVector::from_column_slice(&[1.0, 2.0]), &a, &b, &[f64::INFINITY; 2]).unwrap();
+        assert!((x[0] - 1.0).abs() < 1e-9 && x[1].abs() < 1e-9);
+
+        // Infeasible and unbounded problems
+        let a = na::DMatrix::from_row_slice(1, 2, &[1.0, 1.0]);
+        assert!(solve_lp(&na::DVector::zeros(2), &a, &na::DVector::from_element(1, -1.0), &[f64::INFINITY; 2]).is_err());
+        assert!(solve_lp(&na::DVector::from_column_slice(&[0.0, -1.0]), &na::DMatrix::zeros(0, 2), &na::DVector::zeros(0), &[1.0, f64::INFINITY]).is_err());
+    }
+    #[test]
+    fn test_solve_socp() {
+        // Point of the unit disc furthest along (-1, -1)
+        let c = na::DVector::from_column_slice(&[1.0, 1.0]);
+        let disc = SecondOrderCone::new(na::DMatrix::identity(2, 2), na::DVector::zeros(2), na::DVector::zeros(2), 1.0);
+        let x = solve_socp(&c, &na::DMatrix::zeros(0, 2), &na::DVector::zeros(0), &[disc]).unwrap();
+        assert!((x - na::DVector::from_element(2, -0.5f64.sqrt())).amax() < 1e-6);
+
+        // Closest point to (1, 2) on the line x + y = 0, starting outside the cone
+        let c = na::DVector::from_column_slice(&[0.0, 0.0, 1.0]);
+        let distance = SecondOrderCone::new(na::DMatrix::from_row_slice(2, 3, &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0]),
+                                            na::DVector::from_column_slice(&[-1.0, -2.0]), c.clone(), 0.0);
+        let a = na::DMatrix::from_row_slice(1, 3, &[1.0, 1.0, 0.0]);
+        let x = solve_socp(&c, &a, &na::DVector::zeros(1), std::slice::from_ref(&distance)).unwrap();
+        assert!((x[0] + 0.5).abs() < 1e-6 && (x[1] - 0.5).abs() < 1e-6);
+        assert!((x[2] - 4.5f64.sqrt()).abs() < 1e-6);
+
+        // The same problem with the linear constraint x >= 0
+        let x_positive = SecondOrderCone::linear(na::DVector::from_column_slice(&[1.0, 0.0, 0.0]), 0.0);
+        let x = solve_socp(&c, &a, &na::DVector::zeros(1), &[distance.clone(), x_positive]).unwrap();
+        assert!(x[0].abs() < 1e-5 && x[1].abs() < 1e-5);
+
+        // Infeasible cone
+        let x_negative = SecondOrderCone::linear(na::DVector::from_column_slice(&[-1.0, 0.0, 0.0]), -1.0);
+        let x_positive = SecondOrderCone::linear(na::DVector::from_column_slice(&[1.0, 0.0, 0.0]), 0.0);
+        assert!(solve_socp(&c, &a, &na::DVector::zeros(1), &[distance, x_positive, x_negative]).is_err());
+    }
+}
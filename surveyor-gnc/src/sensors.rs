@@ -1,11 +1,12 @@
 
+use std::sync::Arc;
+
+use bevy::core::Name;
 use bevy_ecs::prelude::*;
 use nalgebra as na;
 
 use surveyor_types::config::GeometryConfig;
 
-use crate::clock::SystemClock;
-
 #[derive(Debug, Clone, Component)]
 pub struct StarTracker;
 
@@ -14,12 +15,18 @@ pub struct StarTracker;
 pub struct StarTrackerInput {
     pub sensor_id: usize,
     pub q_i2cf: na::UnitQuaternion<f64>,
+    /// Time at which the attitude was measured (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+    /// False if the star tracker could not produce an attitude solution
+    pub valid: bool,
 }
 impl Default for StarTrackerInput {
     fn default() -> Self {
         Self {
             sensor_id: 0,
             q_i2cf: na::UnitQuaternion::identity(),
+            measurement_time: hifitime::Epoch::default(),
+            valid: false,
         }
     }
 }
@@ -27,6 +34,8 @@ impl Default for StarTrackerInput {
 #[derive(Debug, Clone, Event)]
 pub struct StarTrackerOutput
 {
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
     pub q_i2b: na::UnitQuaternion<f64>,
     pub measurement_time: hifitime::Epoch,
     pub valid: bool,
@@ -34,6 +43,7 @@ pub struct StarTrackerOutput
 impl Default for StarTrackerOutput {
     fn default() -> Self {
         Self {
+            sensor: String::new(),
             q_i2b: na::UnitQuaternion::identity(),
             measurement_time: hifitime::Epoch::default(),
             valid: false,
@@ -50,14 +60,17 @@ pub struct IMUInput
 {
     pub sensor_id: usize,
     pub omega_cf: na::Vector3<f64>,
-    pub acc_cf: na::Vector3<f64>
+    pub acc_cf: na::Vector3<f64>,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
 }
 impl IMUInput {
-    pub fn new(sensor_id: usize, omega_cf: na::Vector3<f64>, acc_cf: na::Vector3<f64>) -> Self {
+    pub fn new(sensor_id: usize, omega_cf: na::Vector3<f64>, acc_cf: na::Vector3<f64>, measurement_time: hifitime::Epoch) -> Self {
         Self {
             sensor_id,
             omega_cf,
             acc_cf,
+            measurement_time,
         }
     }
 }
@@ -67,15 +80,16 @@ impl Default for IMUInput {
             sensor_id: 0,
             omega_cf: na::Vector3::zeros(),
             acc_cf: na::Vector3::zeros(),
+            measurement_time: hifitime::Epoch::default(),
         }
     }
 }
 
-// TODO:  Add fields to show the time of the measurement being ingested
-// and if it is stale
 #[derive(Debug, Clone, Event)]
 pub struct IMUOutput
 {
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
     pub omega_b: na::Vector3<f64>,
     pub acc_b: na::Vector3<f64>,
     pub measurement_time: hifitime::Epoch,
@@ -83,6 +97,7 @@ pub struct IMUOutput
 impl Default for IMUOutput {
     fn default() -> Self {
         Self {
+            sensor: String::new(),
             omega_b: na::Vector3::zeros(),
             acc_b: na::Vector3::zeros(),
             measurement_time: hifitime::Epoch::default(),
@@ -90,8 +105,8 @@ impl Default for IMUOutput {
     }
 }
 
-/// This is set by an ephemeris estimator, radar sensor or other source
-/// For a start, this component could be set directly from the simulation
+/// State vector uplinked from the ground (the simulation sends the true state plus noise),
+/// which is used by the navigation filter
 #[derive(Debug, Clone, Component, Event)]
 pub struct EphemerisOutput
 {
@@ -118,12 +133,27 @@ pub struct StarSensorInput
 {
     pub sensor_id: usize,
     pub star_vec_cf: na::Vector3<f64>,
+    /// Angle of the star from the boresight along the sensor X-axis in radians
+    pub roll_error: f64,
+    /// Angle of the star from the boresight along the sensor Y-axis in radians
+    pub cone_error: f64,
+    /// Brightness of the star relative to Canopus
+    pub intensity: f64,
+    /// False if the sensor is not locked on to a star
+    pub valid: bool,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
 }
 impl Default for StarSensorInput {
     fn default() -> Self {
         Self {
             sensor_id: 0,
             star_vec_cf: na::Vector3::zeros(),
+            roll_error: 0.0,
+            cone_error: 0.0,
+            intensity: 0.0,
+            valid: false,
+            measurement_time: hifitime::Epoch::default(),
         }
     }
 }
@@ -131,95 +161,584 @@ impl Default for StarSensorInput {
 #[derive(Debug, Clone, Event)]
 pub struct StarSensorOutput
 {
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
     pub star_vec_b: na::Vector3<f64>,
+    pub roll_error: f64,
+    pub cone_error: f64,
+    pub intensity: f64,
     pub measurement_time: hifitime::Epoch,
     pub valid: bool,
 }
 impl Default for StarSensorOutput {
     fn default() -> Self {
         Self {
+            sensor: String::new(),
             star_vec_b: na::Vector3::zeros(),
+            roll_error: 0.0,
+            cone_error: 0.0,
+            intensity: 0.0,
+            measurement_time: hifitime::Epoch::default(),
+            valid: false,
+        }
+    }
+}
+
+/// Magnetometer component, input and output
+#[derive(Debug, Clone, Component)]
+pub struct Magnetometer;
+
+#[derive(Debug, Clone, Event)]
+pub struct MagnetometerInput
+{
+    pub sensor_id: usize,
+    /// Magnetic field in component frame in Tesla
+    pub field_cf: na::Vector3<f64>,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+impl Default for MagnetometerInput {
+    fn default() -> Self {
+        Self {
+            sensor_id: 0,
+            field_cf: na::Vector3::zeros(),
+            measurement_time: hifitime::Epoch::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct MagnetometerOutput
+{
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
+    /// Magnetic field in body frame in Tesla
+    pub field_b: na::Vector3<f64>,
+    pub measurement_time: hifitime::Epoch,
+    pub valid: bool,
+}
+impl Default for MagnetometerOutput {
+    fn default() -> Self {
+        Self {
+            sensor: String::new(),
+            field_b: na::Vector3::zeros(),
             measurement_time: hifitime::Epoch::default(),
             valid: false,
         }
     }
 }
 
+/// Sun sensor components, inputs and output
+/// A coarse sun sensor is a single photocell. The coarse sun sensors are combined into a
+/// single sun vector, while each fine sun sensor produces its own.
+#[derive(Debug, Clone, Component)]
+pub struct CoarseSunSensor;
+
+#[derive(Debug, Clone, Component)]
+pub struct FineSunSensor;
+
+/// Minimum output of a coarse sun sensor for it to be considered lit by the Sun
+pub const COARSE_SUN_SENSOR_THRESHOLD: f64 = 0.1;
+/// Name of the sun vector combined from all the coarse sun sensors
+pub const COARSE_SUN_SENSOR_NAME: &str = "CSS";
+
+#[derive(Debug, Clone, Default, Event)]
+pub struct CoarseSunSensorInput
+{
+    pub sensor_id: usize,
+    /// Output current normalized to 1.0 with the Sun on the boresight
+    pub current: f64,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct FineSunSensorInput
+{
+    pub sensor_id: usize,
+    pub sun_vec_cf: na::Vector3<f64>,
+    /// Angle of the Sun from the boresight along the sensor X-axis in radians
+    pub alpha: f64,
+    /// Angle of the Sun from the boresight along the sensor Y-axis in radians
+    pub beta: f64,
+    /// False if the Sun is not in the field of view
+    pub valid: bool,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+impl Default for FineSunSensorInput {
+    fn default() -> Self {
+        Self {
+            sensor_id: 0,
+            sun_vec_cf: na::Vector3::zeros(),
+            alpha: 0.0,
+            beta: 0.0,
+            valid: false,
+            measurement_time: hifitime::Epoch::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct SunSensorOutput
+{
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
+    /// Unit vector to the Sun in the body frame
+    pub sun_vec_b: na::Vector3<f64>,
+    pub measurement_time: hifitime::Epoch,
+    pub valid: bool,
+}
+impl Default for SunSensorOutput {
+    fn default() -> Self {
+        Self {
+            sensor: String::new(),
+            sun_vec_b: na::Vector3::zeros(),
+            measurement_time: hifitime::Epoch::default(),
+            valid: false,
+        }
+    }
+}
+
+/// Altitude marking radar component, input and output
+#[derive(Debug, Clone, Component)]
+pub struct Amr;
+
+#[derive(Debug, Clone, Default, Event)]
+pub struct AmrInput
+{
+    pub sensor_id: usize,
+    /// Slant range to the surface along the beam in metres
+    pub slant_range: f64,
+    /// False if the surface is out of range
+    pub valid: bool,
+    /// True when the radar generates its mark
+    pub mark: bool,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct AmrOutput
+{
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
+    pub slant_range: f64,
+    pub measurement_time: hifitime::Epoch,
+    pub valid: bool,
+}
+impl Default for AmrOutput {
+    fn default() -> Self {
+        Self {
+            sensor: String::new(),
+            slant_range: 0.0,
+            measurement_time: hifitime::Epoch::default(),
+            valid: false,
+        }
+    }
+}
+
+/// Sent once when the altitude marking radar generates its mark. This is what starts the
+/// retro sequence.
+#[derive(Debug, Clone, Event)]
+pub struct AmrMark
+{
+    pub sensor_id: usize,
+    pub time: hifitime::Epoch,
+}
+
+/// Name of the output combined from all the RADVS beams
+pub const RADVS_NAME: &str = "RADVS";
+
+/// Radar altimeter and Doppler velocity sensor (RADVS) components, inputs and output
+/// The Doppler beams are combined into a single body-frame velocity
+#[derive(Debug, Clone, Component)]
+pub struct RadvsDopplerBeam;
+
+#[derive(Debug, Clone, Component)]
+pub struct RadvsRangeBeam;
+
+#[derive(Debug, Clone, Default, Event)]
+pub struct RadvsDopplerInput
+{
+    pub sensor_id: usize,
+    /// Velocity relative to the surface along the beam in m/s
+    pub velocity: f64,
+    pub locked: bool,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+
+#[derive(Debug, Clone, Default, Event)]
+pub struct RadvsRangeInput
+{
+    pub sensor_id: usize,
+    /// Slant range along the beam in metres
+    pub slant_range: f64,
+    pub locked: bool,
+    /// Time at which the sensor was sampled (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+
+#[derive(Debug, Clone, Event)]
+pub struct RadvsOutput
+{
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
+    /// Velocity relative to the surface in the body frame in m/s
+    pub vel_b: na::Vector3<f64>,
+    /// Slant range along the range beam in metres
+    pub slant_range: f64,
+    /// Direction of the range beam in the body frame
+    pub range_beam_b: na::Vector3<f64>,
+    pub measurement_time: hifitime::Epoch,
+    /// True if enough Doppler beams are locked to solve for the velocity
+    pub velocity_valid: bool,
+    /// True if the range beam is locked
+    pub range_valid: bool,
+}
+impl Default for RadvsOutput {
+    fn default() -> Self {
+        Self {
+            sensor: String::new(),
+            vel_b: na::Vector3::zeros(),
+            slant_range: 0.0,
+            range_beam_b: na::Vector3::zeros(),
+            measurement_time: hifitime::Epoch::default(),
+            velocity_valid: false,
+            range_valid: false,
+        }
+    }
+}
+
+/// Camera component, input and output
+#[derive(Debug, Clone, Component)]
+pub struct Camera {
+    /// Focal length in pixels (pinhole model with the principal point at the image center)
+    pub focal_length_px: f64,
+}
+
+#[derive(Debug, Clone, Default, Event)]
+pub struct CameraInput
+{
+    pub sensor_id: usize,
+    pub width: usize,
+    pub height: usize,
+    /// 8-bit grayscale pixels, row by row
+    pub pixels: Arc<Vec<u8>>,
+    /// Time at which the image was taken (before any output latency)
+    pub measurement_time: hifitime::Epoch,
+}
+
+/// Image along with what is needed to project the pixels into the body frame
+#[derive(Debug, Clone, Event)]
+pub struct CameraImage
+{
+    pub sensor_id: usize,
+    /// Name of the sensor that produced the measurement
+    pub sensor: String,
+    pub width: usize,
+    pub height: usize,
+    pub focal_length_px: f64,
+    pub q_cf2b: na::UnitQuaternion<f64>,
+    pub pixels: Arc<Vec<u8>>,
+    pub measurement_time: hifitime::Epoch,
+}
+impl Default for CameraImage {
+    fn default() -> Self {
+        Self {
+            sensor_id: 0,
+            sensor: String::new(),
+            width: 0,
+            height: 0,
+            focal_length_px: 1.0,
+            q_cf2b: na::UnitQuaternion::identity(),
+            pixels: Arc::new(Vec::new()),
+            measurement_time: hifitime::Epoch::default(),
+        }
+    }
+}
 
 /// System to update the IMU output
 /// Generalize this later to apply to any sensor with a vector input in component frame
 pub fn update_imu(
     mut imu_input: EventReader<IMUInput>,
-    mut query: Query<(&IMU, &GeometryConfig)>,
+    mut query: Query<(&Name, &IMU, &GeometryConfig)>,
     mut output: EventWriter<IMUOutput>,
-    clock: Res<SystemClock>,
 ) {
     // Rotate the angular velocity from the CF frame to the body frame and
     // assign it to the IMU output
 
     // Get IMU and geometry by sensor id
-    imu_input.read().last().map(|imu_input|
+    for imu_input in imu_input.read()
     {
-        if let Some((_, geometry)) = query.iter_mut().nth(imu_input.sensor_id)
+        if let Some((name, _, geometry)) = query.iter_mut().nth(imu_input.sensor_id)
         {
             let imu_output = IMUOutput{
+                sensor: name.to_string(),
                 omega_b: geometry.q_cf2b * imu_input.omega_cf,
                 acc_b: geometry.q_cf2b * imu_input.acc_cf,
-                measurement_time: clock.time,
+                measurement_time: imu_input.measurement_time,
             };
             output.send(imu_output);
         }else{
             log::error!("IMU sensor id {} not found", imu_input.sensor_id);
         }
-    });
+    }
 }
 
 /// System to update the star tracker component
 pub fn update_star_tracker(
     mut star_tracker_input: EventReader<StarTrackerInput>,
-    mut query: Query<(&StarTracker, &GeometryConfig)>,
+    mut query: Query<(&Name, &StarTracker, &GeometryConfig)>,
     mut output: EventWriter<StarTrackerOutput>,
-    clock: Res<SystemClock>,
 ) {
-    star_tracker_input.read().last().map(| star_tracker_input|{
+    for star_tracker_input in star_tracker_input.read() {
         // Get star tracker and geometry by sensor id
-        if let Some((_, geometry,
+        if let Some((name, _, geometry,
                 )) = query.iter_mut().nth(star_tracker_input.sensor_id)
         {
+            // v_i = q_i2cf * v_cf = q_i2cf * q_cf2b^-1 * v_b
             let star_tracker_output = StarTrackerOutput{
-                q_i2b: geometry.q_cf2b * star_tracker_input.q_i2cf,
-                measurement_time: clock.time,
-                valid: true,
+                sensor: name.to_string(),
+                q_i2b: star_tracker_input.q_i2cf * geometry.q_cf2b.inverse(),
+                measurement_time: star_tracker_input.measurement_time,
+                valid: star_tracker_input.valid,
             };
             output.send(star_tracker_output);
         }else{
             log::error!("Star tracker sensor id {} not found", star_tracker_input.sensor_id);
         }
-    });
+    }
 }
 
 /// System to update the Star Sensor component
 pub fn update_star_sensor(
     mut star_sensor_input: EventReader<StarSensorInput>,
-    mut query: Query<(&StarSensor, &GeometryConfig)>,
+    mut query: Query<(&Name, &StarSensor, &GeometryConfig)>,
     mut output: EventWriter<StarSensorOutput>,
-    clock: Res<SystemClock>,
 ) {
-    star_sensor_input.read().last().map(| star_sensor_input|{
+    for star_sensor_input in star_sensor_input.read() {
         // Get star sensor and geometry by sensor id
-        if let Some((_, geometry,
+        if let Some((name, _, geometry,
                 )) = query.iter_mut().nth(star_sensor_input.sensor_id)
         {
             let star_sensor_output = StarSensorOutput{
+                sensor: name.to_string(),
                 star_vec_b: geometry.q_cf2b * star_sensor_input.star_vec_cf,
-                measurement_time: clock.time,
-                valid: true,
+                roll_error: star_sensor_input.roll_error,
+                cone_error: star_sensor_input.cone_error,
+                intensity: star_sensor_input.intensity,
+                measurement_time: star_sensor_input.measurement_time,
+                valid: star_sensor_input.valid,
             };
             output.send(star_sensor_output);
         }else{
             log::error!("Star sensor sensor id {} not found", star_sensor_input.sensor_id);
         }
-    });
+    }
+}
+
+/// System to update the magnetometer component
+pub fn update_magnetometer(
+    mut magnetometer_input: EventReader<MagnetometerInput>,
+    mut query: Query<(&Name, &Magnetometer, &GeometryConfig)>,
+    mut output: EventWriter<MagnetometerOutput>,
+) {
+    for magnetometer_input in magnetometer_input.read() {
+        // Get magnetometer and geometry by sensor id
+        if let Some((name, _, geometry)) = query.iter_mut().nth(magnetometer_input.sensor_id)
+        {
+            output.send(MagnetometerOutput{
+                sensor: name.to_string(),
+                field_b: geometry.q_cf2b * magnetometer_input.field_cf,
+                measurement_time: magnetometer_input.measurement_time,
+                valid: true,
+            });
+        }else{
+            log::error!("Magnetometer sensor id {} not found", magnetometer_input.sensor_id);
+        }
+    }
+}
+
+/// System to update the sun sensor components
+/// Each fine sun sensor produces an output, followed by one for the coarse sun sensors
+pub fn update_sun_sensors(
+    mut coarse_input: EventReader<CoarseSunSensorInput>,
+    mut fine_input: EventReader<FineSunSensorInput>,
+    mut coarse_query: Query<(&CoarseSunSensor, &GeometryConfig)>,
+    mut fine_query: Query<(&Name, &FineSunSensor, &GeometryConfig)>,
+    mut output: EventWriter<SunSensorOutput>,
+) {
+    for fine_input in fine_input.read() {
+        // Get sun sensor and geometry by sensor id
+        if let Some((name, _, geometry)) = fine_query.iter_mut().nth(fine_input.sensor_id)
+        {
+            output.send(SunSensorOutput{
+                sensor: name.to_string(),
+                sun_vec_b: geometry.q_cf2b * fine_input.sun_vec_cf,
+                measurement_time: fine_input.measurement_time,
+                valid: fine_input.valid,
+            });
+        }else{
+            log::error!("Fine sun sensor id {} not found", fine_input.sensor_id);
+        }
+    }
+
+    let coarse_inputs = coarse_input.read().collect::<Vec<_>>();
+    // The combined sun vector is as old as the latest of the coarse sensor samples
+    let Some(measurement_time) = coarse_inputs.iter().map(|coarse_input| coarse_input.measurement_time).max() else {
+        return;
+    };
+    // Boresights and outputs of the coarse sun sensors that are lit by the Sun
+    let lit = coarse_inputs.iter().filter_map(|coarse_input| {
+        match coarse_query.iter_mut().nth(coarse_input.sensor_id) {
+            Some((_, geometry)) => Some((geometry.q_cf2b * na::Vector3::z(), coarse_input.current)),
+            None => {
+                log::error!("Coarse sun sensor id {} not found", coarse_input.sensor_id);
+                None
+            }
+        }
+    }).filter(|(_, current)| *current >= COARSE_SUN_SENSOR_THRESHOLD).collect::<Vec<_>>();
+    if let Some(sun_vec_b) = coarse_sun_vector(&lit) {
+        output.send(SunSensorOutput{
+            sensor: COARSE_SUN_SENSOR_NAME.to_string(),
+            sun_vec_b,
+            measurement_time,
+            valid: true,
+        });
+    } else {
+        output.send(SunSensorOutput{
+            sensor: COARSE_SUN_SENSOR_NAME.to_string(),
+            measurement_time,
+            ..Default::default()
+        });
+    }
+}
+
+/// System to update the altitude marking radar component
+pub fn update_amr(
+    mut amr_input: EventReader<AmrInput>,
+    query: Query<&Name, With<Amr>>,
+    mut output: EventWriter<AmrOutput>,
+    mut mark_output: EventWriter<AmrMark>,
+) {
+    for amr_input in amr_input.read() {
+        let Some(name) = query.iter().nth(amr_input.sensor_id) else {
+            log::error!("AMR sensor id {} not found", amr_input.sensor_id);
+            continue;
+        };
+        output.send(AmrOutput{
+            sensor: name.to_string(),
+            slant_range: amr_input.slant_range,
+            measurement_time: amr_input.measurement_time,
+            valid: amr_input.valid,
+        });
+        if amr_input.mark {
+            log::info!("AMR mark at {}", amr_input.measurement_time);
+            mark_output.send(AmrMark{
+                sensor_id: amr_input.sensor_id,
+                time: amr_input.measurement_time,
+            });
+        }
+    }
+}
+
+/// System to update the RADVS component
+pub fn update_radvs(
+    mut doppler_input: EventReader<RadvsDopplerInput>,
+    mut range_input: EventReader<RadvsRangeInput>,
+    mut doppler_query: Query<(&RadvsDopplerBeam, &GeometryConfig)>,
+    mut range_query: Query<(&RadvsRangeBeam, &GeometryConfig)>,
+    mut output: EventWriter<RadvsOutput>,
+) {
+    let doppler_inputs = doppler_input.read().collect::<Vec<_>>();
+    let range_inputs = range_input.read().collect::<Vec<_>>();
+    // The combined output is as old as the latest of the beam samples
+    let Some(measurement_time) = doppler_inputs.iter().map(|input| input.measurement_time)
+        .chain(range_inputs.iter().map(|input| input.measurement_time)).max() else {
+        return;
+    };
+    // Beam directions and velocities of the locked Doppler beams
+    let locked = doppler_inputs.iter().filter(|input| input.locked).filter_map(|input| {
+        match doppler_query.iter_mut().nth(input.sensor_id) {
+            Some((_, geometry)) => Some((geometry.q_cf2b * na::Vector3::z(), input.velocity)),
+            None => {
+                log::error!("RADVS Doppler beam id {} not found", input.sensor_id);
+                None
+            }
+        }
+    }).collect::<Vec<_>>();
+    let vel_b = doppler_velocity(&locked);
+
+    let mut radvs_output = RadvsOutput {
+        sensor: RADVS_NAME.to_string(),
+        vel_b: vel_b.unwrap_or_else(na::Vector3::zeros),
+        measurement_time,
+        velocity_valid: vel_b.is_some(),
+        ..Default::default()
+    };
+    // Use the first locked range beam
+    for input in range_inputs.iter().filter(|input| input.locked) {
+        if let Some((_, geometry)) = range_query.iter_mut().nth(input.sensor_id) {
+            radvs_output.slant_range = input.slant_range;
+            radvs_output.range_beam_b = geometry.q_cf2b * na::Vector3::z();
+            radvs_output.range_valid = true;
+            break;
+        } else {
+            log::error!("RADVS range beam id {} not found", input.sensor_id);
+        }
+    }
+    output.send(radvs_output);
+}
+
+/// System to update the camera components
+pub fn update_camera(
+    mut camera_input: EventReader<CameraInput>,
+    query: Query<(&Name, &Camera, &GeometryConfig)>,
+    mut output: EventWriter<CameraImage>,
+) {
+    for camera_input in camera_input.read() {
+        if let Some((name, camera, geometry)) = query.iter().nth(camera_input.sensor_id)
+        {
+            output.send(CameraImage{
+                sensor_id: camera_input.sensor_id,
+                sensor: name.to_string(),
+                width: camera_input.width,
+                height: camera_input.height,
+                focal_length_px: camera.focal_length_px,
+                q_cf2b: geometry.q_cf2b,
+                pixels: camera_input.pixels.clone(),
+                measurement_time: camera_input.measurement_time,
+            });
+        }else{
+            log::error!("Camera id {} not found", camera_input.sensor_id);
+        }
+    }
+}
+
+/// Solves for the velocity from the beam directions and the velocities measured along them
+/// (least-squares if there are more than three beams). None if the beams do not span all three axes.
+pub fn doppler_velocity(beams: &[(na::Vector3<f64>, f64)]) -> Option<na::Vector3<f64>> {
+    let normal_matrix = beams.iter().fold(na::Matrix3::zeros(), |acc, (d, _)| acc + d * d.transpose());
+    let rhs = beams.iter().fold(na::Vector3::zeros(), |acc, (d, velocity)| acc + d * *velocity);
+    if normal_matrix.determinant().abs() < 1e-6 {
+        return None;
+    }
+    normal_matrix.try_inverse().map(|inv| inv * rhs)
+}
+
+/// Estimates the sun vector from the boresights and outputs of the lit coarse sun sensors.
+/// Uses a least-squares fit of the cosine law when the boresights span all three axes and a
+/// weighted sum of the boresights otherwise (exact for sensors on orthogonal axes).
+pub fn coarse_sun_vector(lit: &[(na::Vector3<f64>, f64)]) -> Option<na::Vector3<f64>> {
+    let normal_matrix = lit.iter().fold(na::Matrix3::zeros(), |acc, (n, _)| acc + n * n.transpose());
+    let weighted_sum = lit.iter().fold(na::Vector3::zeros(), |acc, (n, current)| acc + n * *current);
+    let sun_vec_b = match normal_matrix.try_inverse() {
+        Some(inv) if normal_matrix.determinant().abs() > 1e-6 => inv * weighted_sum,
+        _ => weighted_sum,
+    };
+    sun_vec_b.try_normalize(1e-9)
 }
 
 #[cfg(test)]
@@ -251,10 +770,12 @@ mod tests
 
         // Set some dummy inputs to the sensor
         let omega_b = nalgebra::Vector3::new(0.1, 0.2, 0.3);
+        let measurement_time = hifitime::Epoch::from_gregorian_utc_at_midnight(2020, 1, 1);
         let imu_input = crate::sensors::IMUInput {
             sensor_id: 0,
             acc_cf: nalgebra::Vector3::zeros(),
             omega_cf: omega_b,
+            measurement_time,
         };
         // Send events
         app.world.send_event(imu_input);
@@ -265,6 +786,8 @@ mod tests
         let mut reader = evt.get_reader();
         let imu_output = reader.read(&evt).next().unwrap();
         assert_eq!(imu_output.omega_b, omega_b);
+        // The output keeps the time the sensor was sampled at
+        assert_eq!(imu_output.measurement_time, measurement_time);
     }
     #[test]
     fn test_star_tracker()
@@ -277,6 +800,8 @@ mod tests
         let st_input = crate::sensors::StarTrackerInput {
             sensor_id: 0,
             q_i2cf: q_i2cf,
+            measurement_time: hifitime::Epoch::from_gregorian_utc_at_midnight(2020, 1, 1),
+            valid: true,
         };
         app.world.send_event(st_input);
 
@@ -287,5 +812,66 @@ mod tests
         let mut reader = evt.get_reader();
         let st_output = reader.read(&evt).next().unwrap();
         assert_eq!(st_output.q_i2b, q_i2cf);
+        assert_eq!(st_output.measurement_time, hifitime::Epoch::from_gregorian_utc_at_midnight(2020, 1, 1));
+        assert!(st_output.valid);
+    }
+    #[test]
+    fn test_amr_mark()
+    {
+        let mut app = App::new();
+        app.add_event::<AmrInput>()
+            .add_event::<AmrOutput>()
+            .add_event::<AmrMark>()
+            .add_systems(Update, update_amr);
+        app.world.spawn((Name::new("AMR"), Amr));
+        app.update();
+
+        app.world.send_event(AmrInput { sensor_id: 0, slant_range: 100100.0, valid: true, mark: false, ..Default::default() });
+        app.update();
+        assert!(app.world.resource::<Events<AmrMark>>().is_empty());
+        let measurement_time = hifitime::Epoch::from_gregorian_utc_at_midnight(2020, 1, 1);
+        app.world.send_event(AmrInput { sensor_id: 0, slant_range: 99900.0, valid: true, mark: true, measurement_time });
+        app.update();
+        let evt = app.world.resource::<Events<AmrMark>>();
+        let marks = evt.get_reader().read(evt).cloned().collect::<Vec<_>>();
+        assert_eq!(marks.len(), 1);
+        // The mark is dated when the radar was sampled
+        assert_eq!(marks[0].time, measurement_time);
+        let evt = app.world.resource::<Events<AmrOutput>>();
+        assert_eq!(evt.get_reader().read(evt).last().unwrap().slant_range, 99900.0);
+    }
+    #[test]
+    fn test_doppler_velocity()
+    {
+        // Three beams tilted 25 deg from -Z and spaced 120 deg apart
+        let tilt = 25f64.to_radians();
+        let beams = [0.0f64, 120.0, 240.0].map(|az| {
+            let az = az.to_radians();
+            nalgebra::Vector3::new(tilt.sin() * az.cos(), tilt.sin() * az.sin(), -tilt.cos())
+        });
+        let vel_b = nalgebra::Vector3::new(3.0, -2.0, -50.0);
+        let measurements = beams.iter().map(|d| (*d, d.dot(&vel_b))).collect::<Vec<_>>();
+        let estimate = doppler_velocity(&measurements).unwrap();
+        assert!((estimate - vel_b).norm() < 1e-9);
+        // Two beams are not enough
+        assert!(doppler_velocity(&measurements[..2]).is_none());
+    }
+    #[test]
+    fn test_coarse_sun_vector()
+    {
+        let sun_vec_b = nalgebra::Vector3::new(0.3, -0.5, 0.8).normalize();
+        // Sensors on all six faces of a cube, only the ones facing the Sun are lit
+        let faces = [nalgebra::Vector3::x(), -nalgebra::Vector3::x(), nalgebra::Vector3::y(),
+                     -nalgebra::Vector3::y(), nalgebra::Vector3::z(), -nalgebra::Vector3::z()];
+        let lit = faces.iter().map(|n| (*n, n.dot(&sun_vec_b)))
+            .filter(|(_, current)| *current >= COARSE_SUN_SENSOR_THRESHOLD).collect::<Vec<_>>();
+        assert_eq!(lit.len(), 3);
+        let estimate = coarse_sun_vector(&lit).unwrap();
+        assert!((estimate - sun_vec_b).norm() < 1e-9);
+
+        // Sun along a single face
+        let estimate = coarse_sun_vector(&[(-nalgebra::Vector3::y(), 1.0)]).unwrap();
+        assert!((estimate + nalgebra::Vector3::y()).norm() < 1e-9);
+        assert!(coarse_sun_vector(&[]).is_none());
     }
 }
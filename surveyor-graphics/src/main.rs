@@ -40,7 +40,7 @@ pub fn main() {
         .add_plugins(OverlayPlugin{ font_size: 32.0, ..Default::default() })
         .add_plugins(SurveyorGraphicsPlugin)
         .add_plugins(surveyor_physics::SurveyorPhysicsPlugin)
-        .add_plugins(surveyor_gnc::SurveyorGNC::new())
+        .add_plugins(surveyor_gnc::SurveyorGNC::from_config(&surveyor_types::config::load_config()).expect("Invalid FSW configuration"))
         .add_systems(Startup, start_sim)
         .add_systems(Update, show_sim_time)
         .run();
@@ -56,7 +56,7 @@ fn main() {
         .add_plugins(SurveyorGraphicsPlugin)
         // .add_plugins(bevy_inspector_egui::quick::WorldInspectorPlugin::new())
         .add_plugins(surveyor_physics::SurveyorPhysicsPlugin)
-        .add_plugins(surveyor_gnc::SurveyorGNC::new())
+        .add_plugins(surveyor_gnc::SurveyorGNC::from_config(&surveyor_types::config::load_config()).expect("Invalid FSW configuration"))
         .add_systems(Startup, start_sim)
         .add_systems(Update, show_sim_time)
         // .add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default())
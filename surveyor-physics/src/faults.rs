@@ -0,0 +1,446 @@
+//! Injection of sensor and actuator faults at scheduled times
+//!
+//! Faults come from the `FaultSchedule` in the configuration or from `InjectFaultEvent`s sent at
+//! runtime. They target a sensor or actuator by the name of its subsystem and its own name.
+//! Actuator faults are passed on to the actuator models, while sensor faults are applied to the
+//! measurements at the interface to FSW (see `interfaces`), so that the truth-side models stay
+//! unaware of them. Every fault that is injected or cleared is logged and recorded in the
+//! `FaultLog`.
+
+use bevy::prelude::*;
+use surveyor_types::config::{noise_seed, FaultConfig, FaultScheduleConfig, FaultType};
+
+use crate::{models::noise::GaussianNoise, subsystems::Subsystem, SimulationTime};
+
+/// Injects a fault at runtime. Faults with a start time in the past are injected immediately.
+#[derive(Debug, Clone, Event)]
+pub struct InjectFaultEvent(pub FaultConfig);
+
+/// Faults of the actuator models
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum ActuatorFault {
+    FailedOff,
+    FailedOn,
+    /// Fraction of the commanded thrust that is lost
+    ThrustLoss(f64),
+    /// Deflection that the gimbal is stuck at (None to hold the current deflection)
+    TvcStuck(Option<f64>),
+}
+
+impl ActuatorFault {
+    /// None for sensor faults
+    pub fn from_config(config: &FaultConfig) -> Option<Self> {
+        match config.fault_type {
+            FaultType::FailedOff => Some(Self::FailedOff),
+            FaultType::FailedOn => Some(Self::FailedOn),
+            FaultType::ThrustLoss => Some(Self::ThrustLoss(config.value.unwrap_or(1.0).clamp(0.0, 1.0))),
+            FaultType::TvcStuck => Some(Self::TvcStuck(config.value)),
+            FaultType::StuckAt | FaultType::Dropout | FaultType::BiasStep | FaultType::NoiseIncrease => None,
+        }
+    }
+    /// Output (thrust, torque or dipole) produced by a faulty actuator
+    pub fn apply_to_command(fault: Option<&Self>, command: f64, max_output: f64) -> f64 {
+        match fault {
+            Some(Self::FailedOff) => 0.0,
+            Some(Self::FailedOn) => max_output,
+            Some(Self::ThrustLoss(loss)) => command * (1.0 - loss),
+            Some(Self::TvcStuck(_)) | None => command,
+        }
+    }
+    /// Whether the fault acts on the gimbal rather than on the output, so that the two can be
+    /// active at the same time
+    fn is_gimbal_fault(&self) -> bool {
+        matches!(self, Self::TvcStuck(_))
+    }
+}
+
+/// A sensor measurement that faults can be applied to
+pub (crate) trait FaultableMeasurement {
+    /// Measured values that are affected by stuck-at, bias and noise faults
+    fn values_mut(&mut self) -> Vec<&mut f64>;
+    /// Restores any constraints between the values (e.g. unit norm) after a fault was applied
+    fn normalize(&mut self) {}
+}
+
+#[derive(Debug)]
+struct SensorFault {
+    /// Index of the fault in the `FaultSchedule`
+    id: usize,
+    component: usize,
+    config: FaultConfig,
+    noise: GaussianNoise,
+    /// Values that a stuck sensor keeps returning
+    stuck_values: Option<Vec<f64>>,
+}
+
+#[derive(Debug)]
+struct ActiveActuatorFault {
+    /// Index of the fault in the `FaultSchedule`
+    id: usize,
+    component: usize,
+    fault: ActuatorFault,
+}
+
+/// Names of the sensors or actuators of a subsystem and the faults that are active, in the order
+/// they were injected. Attached to every subsystem entity.
+#[derive(Debug, Component)]
+pub (crate) struct SubsystemFaults {
+    names: Vec<String>,
+    sensor_faults: Vec<SensorFault>,
+    actuator_faults: Vec<ActiveActuatorFault>,
+}
+
+impl SubsystemFaults {
+    pub fn new(names: Vec<String>) -> Self {
+        Self { names, sensor_faults: Vec::new(), actuator_faults: Vec::new() }
+    }
+    fn component_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+    fn add_sensor_fault(&mut self, id: usize, component: usize, config: &FaultConfig) -> Result<(), String> {
+        let noise_std = match config.fault_type {
+            FaultType::NoiseIncrease => config.value.unwrap_or(0.0),
+            _ => 0.0,
+        };
+        if !(noise_std.is_finite() && noise_std >= 0.0) {
+            return Err(format!("noise standard deviation {} must be finite and non-negative", noise_std));
+        }
+        // The id is part of the seed so that noise faults on the same sensor are independent
+        let seed = noise_seed(config.seed, &format!("{}/{}/{}", config.subsystem, config.target, id));
+        self.sensor_faults.push(SensorFault {
+            id,
+            component,
+            config: config.clone(),
+            noise: GaussianNoise::new(noise_std, seed),
+            stuck_values: None,
+        });
+        Ok(())
+    }
+    fn remove_sensor_fault(&mut self, id: usize) {
+        self.sensor_faults.retain(|fault| fault.id != id);
+    }
+    fn add_actuator_fault(&mut self, id: usize, component: usize, fault: ActuatorFault) {
+        self.actuator_faults.push(ActiveActuatorFault { id, component, fault });
+    }
+    /// Removes an actuator fault. Returns the last injected of the faults that are still active on
+    /// the same part of the actuator (the gimbal or the output), which takes over.
+    fn remove_actuator_fault(&mut self, id: usize) -> Option<ActuatorFault> {
+        let index = self.actuator_faults.iter().position(|active| active.id == id)?;
+        let removed = self.actuator_faults.remove(index);
+        self.actuator_faults.iter().rev()
+            .find(|active| active.component == removed.component
+                && active.fault.is_gimbal_fault() == removed.fault.is_gimbal_fault())
+            .map(|active| active.fault)
+    }
+    /// Applies the active faults to a measurement of the sensor at `component`. Returns false if
+    /// the measurement has dropped out.
+    pub fn apply<T: FaultableMeasurement>(&mut self, component: usize, measurement: &mut T) -> bool {
+        let mut values = measurement.values_mut();
+        for fault in self.sensor_faults.iter_mut().filter(|fault| fault.component == component) {
+            match fault.config.fault_type {
+                FaultType::Dropout => return false,
+                FaultType::StuckAt => {
+                    let stuck_values = fault.stuck_values.get_or_insert_with(|| match fault.config.value {
+                        Some(value) => vec![value; values.len()],
+                        None => values.iter().map(|v| **v).collect(),
+                    });
+                    values.iter_mut().zip(stuck_values.iter()).for_each(|(v, stuck)| **v = *stuck);
+                }
+                FaultType::BiasStep => {
+                    values.iter_mut().for_each(|v| **v += fault.config.value.unwrap_or(0.0));
+                }
+                FaultType::NoiseIncrease => {
+                    values.iter_mut().for_each(|v| **v += fault.noise.sample());
+                }
+                _ => {}
+            }
+        }
+        measurement.normalize();
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FaultStatus {
+    Pending,
+    Active,
+    /// Cleared at its end time
+    Done,
+    /// Could not be injected, which is not retried even if the simulation is reset
+    Rejected,
+}
+
+/// Faults to be injected, in the order they were added. Faults are identified by their index.
+#[derive(Debug, Resource, Default)]
+pub struct FaultSchedule {
+    faults: Vec<(FaultConfig, FaultStatus)>,
+    last_update_time: f64,
+}
+
+/// Changes to the active faults at an update of the schedule (with the id of the fault)
+#[derive(Debug, PartialEq)]
+enum FaultTransition {
+    Inject(usize, FaultConfig),
+    Clear(usize, FaultConfig),
+}
+
+impl FaultSchedule {
+    pub fn from_config(config: &FaultScheduleConfig) -> Self {
+        let mut schedule = Self::default();
+        config.faults.iter().for_each(|fault| schedule.add(fault.clone()));
+        schedule
+    }
+    pub fn add(&mut self, fault: FaultConfig) {
+        self.faults.push((fault, FaultStatus::Pending));
+    }
+    /// Faults to inject and clear at time `t`. Everything is cleared if the simulation was reset.
+    fn update(&mut self, t: f64) -> Vec<FaultTransition> {
+        let mut transitions = Vec::new();
+        if t < self.last_update_time {
+            for (id, (fault, status)) in self.faults.iter_mut().enumerate() {
+                if *status == FaultStatus::Active {
+                    transitions.push(FaultTransition::Clear(id, fault.clone()));
+                }
+                if *status != FaultStatus::Rejected {
+                    *status = FaultStatus::Pending;
+                }
+            }
+        }
+        self.last_update_time = t;
+        for (id, (fault, status)) in self.faults.iter_mut().enumerate() {
+            if *status == FaultStatus::Pending && t >= fault.start {
+                *status = FaultStatus::Active;
+                transitions.push(FaultTransition::Inject(id, fault.clone()));
+            }
+            if *status == FaultStatus::Active && fault.end.map_or(false, |end| t >= end) {
+                *status = FaultStatus::Done;
+                transitions.push(FaultTransition::Clear(id, fault.clone()));
+            }
+        }
+        transitions
+    }
+    /// Stops trying to inject a fault that does not match any sensor or actuator
+    fn reject(&mut self, id: usize) {
+        if let Some((_, status)) = self.faults.get_mut(id) {
+            *status = FaultStatus::Rejected;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FaultLogEntry {
+    /// Simulation time in seconds
+    pub time: f64,
+    pub fault: FaultConfig,
+    /// True when the fault was injected and false when it was cleared
+    pub injected: bool,
+}
+
+/// Record of all the faults that were injected and cleared during the simulation
+#[derive(Debug, Resource, Default)]
+pub struct FaultLog {
+    pub entries: Vec<FaultLogEntry>,
+}
+
+/// Injects and clears faults according to the schedule. Runs before the spacecraft model is
+/// stepped so that actuator faults act over the whole step.
+pub (crate) fn update_faults(
+    mut schedule: ResMut<FaultSchedule>,
+    mut fault_log: ResMut<FaultLog>,
+    mut inject_fault_events: EventReader<InjectFaultEvent>,
+    q_sim_time: Query<&SimulationTime>,
+    mut q_subsystems: Query<(&Name, &mut Subsystem, &mut SubsystemFaults)>,
+) {
+    for event in inject_fault_events.read() {
+        schedule.add(event.0.clone());
+    }
+    let t = q_sim_time.single().get_monotonic_time();
+    for transition in schedule.update(t) {
+        let (id, fault, injected) = match &transition {
+            FaultTransition::Inject(id, fault) => (*id, fault, true),
+            FaultTransition::Clear(id, fault) => (*id, fault, false),
+        };
+        let target = q_subsystems.iter_mut()
+            .find(|(name, _, _)| name.as_str() == fault.subsystem)
+            .and_then(|(_, subsystem, faults)| faults.component_index(&fault.target)
+                .map(|component| (subsystem, faults, component)));
+        let Some((mut subsystem, mut faults, component)) = target else {
+            log::error!("Cannot inject {:?} fault: no sensor or actuator named '{}' in subsystem '{}'",
+                fault.fault_type, fault.target, fault.subsystem);
+            schedule.reject(id);
+            continue;
+        };
+        let result = match (ActuatorFault::from_config(fault), injected) {
+            (Some(actuator_fault), true) => subsystem.set_actuator_fault(component, actuator_fault, true)
+                .map(|()| faults.add_actuator_fault(id, component, actuator_fault)),
+            (Some(actuator_fault), false) => match faults.remove_actuator_fault(id) {
+                // An overlapping fault that is still active is applied again
+                Some(remaining) => subsystem.set_actuator_fault(component, remaining, true),
+                None => subsystem.set_actuator_fault(component, actuator_fault, false),
+            },
+            (None, _) if !subsystem.is_sensor() => Err("sensor faults cannot be applied to actuators".to_string()),
+            (None, true) => faults.add_sensor_fault(id, component, fault),
+            (None, false) => {
+                faults.remove_sensor_fault(id);
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => {
+                if injected {
+                    log::warn!("t = {:.3} s: Injected {:?} fault into {}/{} (value: {:?})",
+                        t, fault.fault_type, fault.subsystem, fault.target, fault.value);
+                } else {
+                    log::info!("t = {:.3} s: Cleared {:?} fault in {}/{}", t, fault.fault_type, fault.subsystem, fault.target);
+                }
+                fault_log.entries.push(FaultLogEntry { time: t, fault: fault.clone(), injected });
+            }
+            Err(e) => {
+                log::error!("Cannot inject {:?} fault into {}/{}: {}", fault.fault_type, fault.subsystem, fault.target, e);
+                schedule.reject(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+
+    struct Measurement(Vec<f64>);
+    impl FaultableMeasurement for Measurement {
+        fn values_mut(&mut self) -> Vec<&mut f64> {
+            self.0.iter_mut().collect()
+        }
+    }
+
+    fn fault(xml: &str) -> FaultConfig {
+        FaultConfig::from_str(xml).unwrap()
+    }
+
+    #[test]
+    fn test_fault_schedule() {
+        let bias = fault(r#"<Fault subsystem="Imu" target="A" type="BiasStep" start="1.0" end="2.0" value="0.1"/>"#);
+        let dropout = fault(r#"<Fault subsystem="Amr" target="AMR" type="Dropout" start="1.5"/>"#);
+        let mut schedule = FaultSchedule::from_config(&FaultScheduleConfig { faults: vec![bias.clone(), dropout.clone()] });
+        assert!(schedule.update(0.5).is_empty());
+        assert_eq!(schedule.update(1.0), vec![FaultTransition::Inject(0, bias.clone())]);
+        assert_eq!(schedule.update(1.5), vec![FaultTransition::Inject(1, dropout.clone())]);
+        assert_eq!(schedule.update(2.0), vec![FaultTransition::Clear(0, bias.clone())]);
+        assert!(schedule.update(10.0).is_empty());
+        // Active faults are cleared when the simulation is reset
+        assert_eq!(schedule.update(0.0), vec![FaultTransition::Clear(1, dropout.clone())]);
+        assert_eq!(schedule.update(1.0), vec![FaultTransition::Inject(0, bias.clone())]);
+
+        // Rejected faults are not injected again, but identical faults are
+        schedule.add(dropout.clone());
+        schedule.reject(1);
+        assert_eq!(schedule.update(1.5), vec![FaultTransition::Inject(2, dropout.clone())]);
+        // Not even after a reset
+        assert_eq!(schedule.update(0.0), vec![FaultTransition::Clear(0, bias.clone()), FaultTransition::Clear(2, dropout.clone())]);
+        assert_eq!(schedule.update(1.5), vec![FaultTransition::Inject(0, bias), FaultTransition::Inject(2, dropout)]);
+    }
+
+    #[test]
+    fn test_sensor_faults() {
+        let mut faults = SubsystemFaults::new(vec!["A".to_string(), "B".to_string()]);
+        let bias = fault(r#"<Fault subsystem="Imu" target="A" type="BiasStep" value="0.5"/>"#);
+        faults.add_sensor_fault(0, 0, &bias).unwrap();
+        faults.add_sensor_fault(1, 0, &bias).unwrap();
+        let mut measurement = Measurement(vec![1.0, 2.0]);
+        assert!(faults.apply(0, &mut measurement));
+        assert_eq!(measurement.0, vec![2.0, 3.0]);
+        let mut measurement = Measurement(vec![1.0, 2.0]);
+        assert!(faults.apply(1, &mut measurement));
+        assert_eq!(measurement.0, vec![1.0, 2.0]);
+
+        // Only one of the identical faults is removed
+        faults.remove_sensor_fault(0);
+        let mut measurement = Measurement(vec![1.0, 2.0]);
+        faults.apply(0, &mut measurement);
+        assert_eq!(measurement.0, vec![1.5, 2.5]);
+        faults.remove_sensor_fault(1);
+
+        // Stuck at the first output after the fault
+        faults.add_sensor_fault(2, 1, &fault(r#"<Fault subsystem="Imu" target="B" type="StuckAt"/>"#)).unwrap();
+        faults.apply(1, &mut Measurement(vec![3.0, 4.0]));
+        let mut measurement = Measurement(vec![5.0, 6.0]);
+        faults.apply(1, &mut measurement);
+        assert_eq!(measurement.0, vec![3.0, 4.0]);
+
+        faults.add_sensor_fault(3, 0, &fault(r#"<Fault subsystem="Imu" target="A" type="Dropout"/>"#)).unwrap();
+        assert!(!faults.apply(0, &mut Measurement(vec![1.0, 2.0])));
+        faults.remove_sensor_fault(3);
+
+        // Two noise faults on the same sensor draw different noise
+        let noise = fault(r#"<Fault subsystem="Imu" target="B" type="NoiseIncrease" value="1.0"/>"#);
+        let mut faults = SubsystemFaults::new(vec!["A".to_string(), "B".to_string()]);
+        faults.add_sensor_fault(4, 1, &noise).unwrap();
+        faults.add_sensor_fault(5, 1, &noise).unwrap();
+        assert_ne!(faults.sensor_faults[0].noise.sample(), faults.sensor_faults[1].noise.sample());
+        // Negative noise is rejected
+        let negative_noise = fault(r#"<Fault subsystem="Imu" target="B" type="NoiseIncrease" value="-1.0"/>"#);
+        assert!(faults.add_sensor_fault(6, 1, &negative_noise).is_err());
+        assert_eq!(faults.sensor_faults.len(), 2);
+    }
+
+    #[test]
+    fn test_actuator_faults() {
+        let thrust_loss = fault(r#"<Fault subsystem="Propulsion" target="A" type="ThrustLoss" value="0.25"/>"#);
+        let fault = ActuatorFault::from_config(&thrust_loss);
+        assert_eq!(fault, Some(ActuatorFault::ThrustLoss(0.25)));
+        assert_eq!(ActuatorFault::apply_to_command(fault.as_ref(), 200.0, 400.0), 150.0);
+        assert_eq!(ActuatorFault::apply_to_command(Some(&ActuatorFault::FailedOn), 0.0, 400.0), 400.0);
+        assert_eq!(ActuatorFault::apply_to_command(Some(&ActuatorFault::FailedOff), 200.0, 400.0), 0.0);
+    }
+
+    /// A thruster that fails off while it is losing thrust keeps losing thrust once it is back on
+    #[test]
+    fn test_overlapping_actuator_faults() {
+        use crate::subsystems::rcs::{RcsCommands, RcsSubsystem};
+        use surveyor_types::config::RcsSubsystemConfig;
+        let failed_off = fault(r#"<Fault subsystem="Rcs" target="X" type="FailedOff" start="10.0" end="20.0"/>"#);
+        let thrust_loss = fault(r#"<Fault subsystem="Rcs" target="X" type="ThrustLoss" start="15.0" end="30.0" value="0.5"/>"#);
+        let mut app = App::new();
+        app.add_event::<InjectFaultEvent>()
+            .init_resource::<FaultLog>()
+            .insert_resource(FaultSchedule::from_config(&FaultScheduleConfig { faults: vec![failed_off, thrust_loss] }))
+            .add_systems(Update, update_faults);
+        let start_time = hifitime::Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let sim_time = app.world.spawn(SimulationTime::new(start_time)).id();
+        let config = RcsSubsystemConfig::from_str(r#"
+            <RcsSubsystem>
+                <thruster name="X">
+                    <min_thrust>0.0</min_thrust>
+                    <max_thrust>1.0</max_thrust>
+                    <geometry>
+                        <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                        <cf_offset_com_b>[0.0, 1.0, 0.0]</cf_offset_com_b>
+                    </geometry>
+                </thruster>
+            </RcsSubsystem>"#).unwrap();
+        let rcs = app.world.spawn((
+            Name::new("Rcs"),
+            Subsystem::Rcs(RcsSubsystem::from_config(&config)),
+            SubsystemFaults::new(vec!["X".to_string()]),
+        )).id();
+        let mut thrust_at = |t: f64| {
+            app.world.get_mut::<SimulationTime>(sim_time).unwrap().time = start_time + hifitime::Duration::from_seconds(t);
+            app.update();
+            let mut subsystem = app.world.get_mut::<Subsystem>(rcs).unwrap();
+            let Subsystem::Rcs(rcs) = &mut *subsystem else {
+                panic!("Expected the RCS subsystem");
+            };
+            rcs.handle_commands(&RcsCommands { duty_cycles: vec![1.0], on_times: None });
+            rcs.thrusters[0].thrust
+        };
+        assert_eq!(thrust_at(5.0), 1.0);
+        assert_eq!(thrust_at(12.0), 0.0);
+        // The last injected fault acts, and is still active after the first one is cleared
+        assert_eq!(thrust_at(17.0), 0.5);
+        assert_eq!(thrust_at(22.0), 0.5);
+        assert_eq!(thrust_at(31.0), 1.0);
+        assert_eq!(app.world.resource::<FaultLog>().entries.len(), 4);
+    }
+}
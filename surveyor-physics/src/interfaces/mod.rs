@@ -8,13 +8,17 @@
 //     fn set_input(&mut self, input: &SpacecraftDiscreteState);
 // }
 
+use std::collections::VecDeque;
+
 use bevy_ecs::prelude::*;
+use hifitime::Epoch;
 use surveyor_gnc::sensors::{IMUInput, StarTrackerInput};
 use surveyor_gnc::clock::TimeTickEvent;
+use surveyor_types::config::{RadvsBeamType, SensorTimingConfig, SunSensorType};
 use bevy_enum_filter::prelude::*;
 
 // ! === subsystem_filters is auto-generated by bevy_enum_filters === ! //
-use crate::{subsystems::{rcs::RcsCommands, Subsystem, subsystem_filters}, SimulationTime};
+use crate::{faults::{FaultableMeasurement, SubsystemFaults}, simulation::SimulationParams, spacecraft::SensorSampleEvent, subsystems::{magnetorquer::MagnetorquerCommands, propulsion::EngineCommands, rcs::RcsCommands, reaction_wheel::ReactionWheelCommands, Subsystem, subsystem_filters}, SimulationTime};
 
 pub fn time_event_generator(
     mut time_tick_events: EventWriter<surveyor_gnc::clock::TimeTickEvent>,
@@ -36,74 +40,454 @@ pub enum SensorEvent {
     StarTracker(surveyor_gnc::sensors::StarTrackerInput),
 }
 
+/// Tolerance used when comparing simulation times
+const TIME_EPSILON: f64 = 1e-9;
+
+/// Decides when the sensors of a subsystem are sampled. Attached to every sensor subsystem entity.
+///
+/// Samples are taken at `phase + k / sample_rate_hz` (rounded to the simulation step), or at
+/// every GNC update if no sample rate is configured.
+#[derive(Debug, Component)]
+pub (crate) struct SensorSampler {
+    timing: SensorTimingConfig,
+    next_sample_time: f64,
+    /// Simulation time of the latest sample event
+    time: f64,
+    /// Time of the latest sample if it has not been buffered yet
+    new_sample: Option<f64>,
+    /// Epoch of the latest sample, which the measurements are stamped with
+    sample_epoch: Epoch,
+}
+
+impl SensorSampler {
+    pub fn from_config(timing: &SensorTimingConfig) -> Self {
+        Self {
+            timing: timing.clone(),
+            next_sample_time: timing.phase,
+            time: f64::NEG_INFINITY,
+            new_sample: None,
+            sample_epoch: Epoch::default(),
+        }
+    }
+    /// Whether the sensors are due to be sampled at time `t`. `default_period` is used if the
+    /// subsystem does not have its own sample rate.
+    pub fn is_sample_due(&mut self, t: f64, default_period: f64) -> bool {
+        if t < self.time {
+            // The simulation has been reset
+            self.next_sample_time = self.timing.phase;
+            self.new_sample = None;
+        }
+        self.time = t;
+        if t < self.next_sample_time - TIME_EPSILON {
+            return false;
+        }
+        // Samples that fall between simulation steps are taken at the next step
+        let period = self.timing.sample_rate_hz.map_or(default_period, |rate| 1.0 / rate);
+        let num_periods = ((t - self.timing.phase) / period + TIME_EPSILON).floor() + 1.0;
+        self.next_sample_time = self.timing.phase + num_periods * period;
+        self.new_sample = Some(t);
+        true
+    }
+    /// Time of the latest sample (only returned once)
+    pub fn take_sample(&mut self) -> Option<f64> {
+        self.new_sample.take()
+    }
+    /// Records the epoch of the sample that was just taken
+    pub fn set_sample_epoch(&mut self, epoch: Epoch) {
+        self.sample_epoch = epoch;
+    }
+    pub fn sample_epoch(&self) -> Epoch {
+        self.sample_epoch
+    }
+    /// Simulation time of the latest sample event
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+    pub fn latency(&self) -> f64 {
+        self.timing.latency
+    }
+}
+
+/// Time-stamped measurements waiting to be delivered to FSW
+#[derive(Debug)]
+pub (crate) struct MeasurementBuffer<T> {
+    /// Measurements in the order they were sampled, along with the sample and delivery times
+    pending: VecDeque<(f64, f64, T)>,
+}
+
+impl<T> Default for MeasurementBuffer<T> {
+    fn default() -> Self {
+        Self { pending: VecDeque::new() }
+    }
+}
+
+impl<T> MeasurementBuffer<T> {
+    pub fn push(&mut self, measurement_time: f64, latency: f64, measurement: T) {
+        if self.pending.back().map_or(false, |(t, _, _)| measurement_time < *t) {
+            // The simulation has been reset, so the older measurements will never be delivered
+            self.pending.clear();
+        }
+        self.pending.push_back((measurement_time, measurement_time + latency, measurement));
+    }
+    /// Removes and returns the measurements that have reached FSW by time `t`
+    pub fn release(&mut self, t: f64) -> Vec<T> {
+        let num_ready = self.pending.iter()
+            .take_while(|(_, delivery_time, _)| *delivery_time <= t + TIME_EPSILON)
+            .count();
+        self.pending.drain(..num_ready).map(|(_, _, measurement)| measurement).collect()
+    }
+}
+
+/// Updates the sensor subsystems that are due to be sampled at this step of the simulation
+pub (crate) fn sample_sensors_from_event(
+    mut sensor_sample_event: EventReader<SensorSampleEvent>,
+    mut q_sensors: Query<(&mut Subsystem, &mut SensorSampler)>,
+    sim_params: Res<SimulationParams>)
+{
+    let gnc_period = sim_params.get_gnc_update_period_secs();
+    for sample in sensor_sample_event.read() {
+        for (mut subsystem, mut sampler) in q_sensors.iter_mut() {
+            if sampler.is_sample_due(sample.0.time, gnc_period) {
+                sampler.set_sample_epoch(sample.1.epoch);
+                subsystem.update_discrete(sample.0.time, &sample.0, &sample.1);
+            }
+        }
+    }
+}
+
+/// Buffers the outputs of a newly sampled subsystem (along with the index of the sensor they came
+/// from) after applying any faults, and sends the measurements that have reached FSW
+fn deliver_measurements<T: Event + FaultableMeasurement>(
+    sampler: &mut SensorSampler,
+    faults: &mut SubsystemFaults,
+    buffer: &mut MeasurementBuffer<T>,
+    events: &mut EventWriter<T>,
+    sample: impl FnOnce() -> Vec<(usize, T)>,
+) {
+    if let Some(measurement_time) = sampler.take_sample() {
+        for (idx, mut measurement) in sample() {
+            if faults.apply(idx, &mut measurement) {
+                buffer.push(measurement_time, sampler.latency(), measurement);
+            }
+        }
+    }
+    for measurement in buffer.release(sampler.time()) {
+        events.send(measurement);
+    }
+}
+
 /// Conversion from truth-side data to GNC-side events
 pub (crate) fn imu_event_generator(
-    mut q_imu: Query<&mut Subsystem, With<Enum![Subsystem::Imu]>>,
+    mut q_imu: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::Imu]>>,
+    mut buffer: Local<MeasurementBuffer<IMUInput>>,
     mut imu_input_events: EventWriter<surveyor_gnc::sensors::IMUInput>)
 {
     // The Enum filter does not work on the very first update
-    if let Some(mut subsystem) = q_imu.iter_mut().next()
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_imu.iter_mut().next()
     {
         let imu_subsystem = subsystem.as_imu_mut().unwrap();
-        for (idx, sensor) in imu_subsystem.imus.iter().enumerate() {
-            let imu_data = sensor.get_model_output();
-            let imu_input = IMUInput::new(idx, imu_data.omega_cf, imu_data.accel_cf);
-            imu_input_events.send(imu_input);
-        }
+        let measurement_time = sampler.sample_epoch();
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut imu_input_events, || {
+            imu_subsystem.imus.iter().enumerate().map(|(idx, sensor)| {
+                let imu_data = sensor.get_model_output();
+                (idx, IMUInput::new(idx, imu_data.omega_cf, imu_data.accel_cf, measurement_time))
+            }).collect()
+        });
     }
 }
 
 pub (crate) fn star_tracker_event_generator(
-    mut q_st: Query<&mut Subsystem, With<Enum![Subsystem::StarTracker]>>,
+    mut q_st: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::StarTracker]>>,
+    mut buffer: Local<MeasurementBuffer<StarTrackerInput>>,
     mut st_input_events: EventWriter<surveyor_gnc::sensors::StarTrackerInput>)
 {
     // The Enum filter does not work on the very first update
-    if let Some(mut subsystem) = q_st.iter_mut().next()
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_st.iter_mut().next()
     {
         let st_subsystem = subsystem.as_star_tracker_mut().unwrap();
-        for (idx, sensor) in st_subsystem.star_trackers.iter().enumerate() {
-            let st_data = sensor.get_model_output();
-            let st_input = StarTrackerInput{
-                q_i2cf: st_data.q_i2cf.0,
-                sensor_id: idx,
-            };
-            st_input_events.send(st_input);
-        }
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut st_input_events, || {
+            st_subsystem.star_trackers.iter().enumerate().map(|(idx, sensor)| {
+                let st_data = sensor.get_model_output();
+                (idx, StarTrackerInput{
+                    q_i2cf: st_data.q_i2cf.0,
+                    sensor_id: idx,
+                    measurement_time: st_data.measurement_time,
+                    valid: st_data.valid,
+                })
+            }).collect()
+        });
     }
 }
 
 pub (crate) fn star_sensor_event_generator(
-    mut q_st: Query<&mut Subsystem, With<Enum![Subsystem::StarSensor]>>,
+    mut q_st: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::StarSensor]>>,
+    mut buffer: Local<MeasurementBuffer<surveyor_gnc::sensors::StarSensorInput>>,
     mut st_input_events: EventWriter<surveyor_gnc::sensors::StarSensorInput>)
 {
     // The Enum filter does not work on the very first update
-    if let Some(mut subsystem) = q_st.iter_mut().next()
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_st.iter_mut().next()
     {
         let st_subsystem = subsystem.as_star_sensor_mut().unwrap();
-        for (idx, sensor) in st_subsystem.star_sensors.iter().enumerate() {
-            let st_data = sensor.get_model_output();
-            let st_input = surveyor_gnc::sensors::StarSensorInput{
-                star_vec_cf: st_data.star_vec_cf,
-                sensor_id: idx,
-            };
-            st_input_events.send(st_input);
+        let measurement_time = sampler.sample_epoch();
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut st_input_events, || {
+            st_subsystem.star_sensors.iter().enumerate().map(|(idx, sensor)| {
+                let st_data = sensor.get_model_output();
+                (idx, surveyor_gnc::sensors::StarSensorInput{
+                    star_vec_cf: st_data.star_vec_cf,
+                    roll_error: st_data.roll_error,
+                    cone_error: st_data.cone_error,
+                    intensity: st_data.intensity,
+                    valid: st_data.valid,
+                    sensor_id: idx,
+                    measurement_time,
+                })
+            }).collect()
+        });
+    }
+}
+
+pub (crate) fn reaction_wheel_event_generator(
+    mut q_rw: Query<&mut Subsystem, With<Enum![Subsystem::ReactionWheel]>>,
+    mut rw_telemetry_events: EventWriter<surveyor_gnc::control::ReactionWheelTelemetry>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some(mut subsystem) = q_rw.iter_mut().next()
+    {
+        let rw_subsystem = subsystem.as_reaction_wheel_mut().unwrap();
+        for (idx, wheel) in rw_subsystem.wheels.iter().enumerate() {
+            let rw_data = wheel.get_model_output();
+            rw_telemetry_events.send(surveyor_gnc::control::ReactionWheelTelemetry {
+                wheel_id: idx,
+                speed: rw_data.speed,
+            });
+        }
+    }
+}
+
+pub (crate) fn engine_event_generator(
+    mut q_engine: Query<&mut Subsystem, With<Enum![Subsystem::Propulsion]>>,
+    mut engine_telemetry_events: EventWriter<surveyor_gnc::control::EngineTelemetry>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some(mut subsystem) = q_engine.iter_mut().next()
+    {
+        let engine_subsystem = subsystem.as_propulsion_mut().unwrap();
+        let command_accepted = engine_subsystem.last_command_accepted();
+        for (idx, status) in engine_subsystem.engine_status().into_iter().enumerate() {
+            engine_telemetry_events.send(surveyor_gnc::control::EngineTelemetry {
+                engine_id: idx,
+                ignited: status.ignited,
+                commanded_thrust: status.commanded_thrust,
+                tvc_angle: status.tvc_deflection,
+                command_accepted,
+            });
+        }
+    }
+}
+
+pub (crate) fn magnetometer_event_generator(
+    mut q_mag: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::Magnetometer]>>,
+    mut buffer: Local<MeasurementBuffer<surveyor_gnc::sensors::MagnetometerInput>>,
+    mut mag_input_events: EventWriter<surveyor_gnc::sensors::MagnetometerInput>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_mag.iter_mut().next()
+    {
+        let mag_subsystem = subsystem.as_magnetometer_mut().unwrap();
+        let measurement_time = sampler.sample_epoch();
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut mag_input_events, || {
+            mag_subsystem.magnetometers.iter().enumerate().map(|(idx, sensor)| {
+                (idx, surveyor_gnc::sensors::MagnetometerInput {
+                    sensor_id: idx,
+                    field_cf: sensor.get_model_output().field_cf,
+                    measurement_time,
+                })
+            }).collect()
+        });
+    }
+}
+
+/// Sun sensor measurements for FSW, which has separate events for coarse and fine sensors
+#[derive(Debug, Clone)]
+pub (crate) enum SunSensorMeasurement {
+    Coarse(surveyor_gnc::sensors::CoarseSunSensorInput),
+    Fine(surveyor_gnc::sensors::FineSunSensorInput),
+}
+
+pub (crate) fn sun_sensor_event_generator(
+    mut q_ss: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::SunSensor]>>,
+    mut buffer: Local<MeasurementBuffer<SunSensorMeasurement>>,
+    mut coarse_input_events: EventWriter<surveyor_gnc::sensors::CoarseSunSensorInput>,
+    mut fine_input_events: EventWriter<surveyor_gnc::sensors::FineSunSensorInput>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_ss.iter_mut().next()
+    {
+        let ss_subsystem = subsystem.as_sun_sensor_mut().unwrap();
+        let sample_epoch = sampler.sample_epoch();
+        if let Some(measurement_time) = sampler.take_sample() {
+            // Coarse and fine sensors are numbered separately on the FSW side
+            let (mut coarse_idx, mut fine_idx) = (0, 0);
+            for (idx, sensor) in ss_subsystem.sun_sensors.iter().enumerate() {
+                let ss_data = sensor.get_model_output();
+                let mut measurement = match ss_data.sensor_type {
+                    SunSensorType::Coarse => {
+                        coarse_idx += 1;
+                        SunSensorMeasurement::Coarse(surveyor_gnc::sensors::CoarseSunSensorInput {
+                            sensor_id: coarse_idx - 1,
+                            current: ss_data.intensity,
+                            measurement_time: sample_epoch,
+                        })
+                    }
+                    SunSensorType::Fine => {
+                        fine_idx += 1;
+                        SunSensorMeasurement::Fine(surveyor_gnc::sensors::FineSunSensorInput {
+                            sensor_id: fine_idx - 1,
+                            sun_vec_cf: ss_data.sun_vec_cf,
+                            alpha: ss_data.alpha,
+                            beta: ss_data.beta,
+                            valid: ss_data.valid,
+                            measurement_time: sample_epoch,
+                        })
+                    }
+                };
+                if faults.apply(idx, &mut measurement) {
+                    buffer.push(measurement_time, sampler.latency(), measurement);
+                }
+            }
+        }
+        for measurement in buffer.release(sampler.time()) {
+            match measurement {
+                SunSensorMeasurement::Coarse(input) => { coarse_input_events.send(input); }
+                SunSensorMeasurement::Fine(input) => { fine_input_events.send(input); }
+            }
         }
     }
 }
 
+pub (crate) fn amr_event_generator(
+    mut q_amr: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::Amr]>>,
+    mut buffer: Local<MeasurementBuffer<surveyor_gnc::sensors::AmrInput>>,
+    mut amr_input_events: EventWriter<surveyor_gnc::sensors::AmrInput>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_amr.iter_mut().next()
+    {
+        let amr_subsystem = subsystem.as_amr_mut().unwrap();
+        let measurement_time = sampler.sample_epoch();
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut amr_input_events, || {
+            amr_subsystem.amrs.iter().enumerate().map(|(idx, sensor)| {
+                let amr_data = sensor.get_model_output();
+                (idx, surveyor_gnc::sensors::AmrInput {
+                    sensor_id: idx,
+                    slant_range: amr_data.slant_range,
+                    valid: amr_data.valid,
+                    mark: amr_data.mark,
+                    measurement_time,
+                })
+            }).collect()
+        });
+    }
+}
+
+/// RADVS measurements for FSW, which has separate events for Doppler and range beams
+#[derive(Debug, Clone)]
+pub (crate) enum RadvsMeasurement {
+    Doppler(surveyor_gnc::sensors::RadvsDopplerInput),
+    Range(surveyor_gnc::sensors::RadvsRangeInput),
+}
+
+pub (crate) fn radvs_event_generator(
+    mut q_radvs: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::Radvs]>>,
+    mut buffer: Local<MeasurementBuffer<RadvsMeasurement>>,
+    mut doppler_input_events: EventWriter<surveyor_gnc::sensors::RadvsDopplerInput>,
+    mut range_input_events: EventWriter<surveyor_gnc::sensors::RadvsRangeInput>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_radvs.iter_mut().next()
+    {
+        let radvs_subsystem = subsystem.as_radvs_mut().unwrap();
+        let sample_epoch = sampler.sample_epoch();
+        if let Some(measurement_time) = sampler.take_sample() {
+            // Doppler and range beams are numbered separately on the FSW side
+            let (mut doppler_idx, mut range_idx) = (0, 0);
+            for (idx, beam) in radvs_subsystem.beams.iter().enumerate() {
+                let beam_data = beam.get_model_output();
+                let mut measurement = match beam_data.beam_type {
+                    RadvsBeamType::Doppler => {
+                        doppler_idx += 1;
+                        RadvsMeasurement::Doppler(surveyor_gnc::sensors::RadvsDopplerInput {
+                            sensor_id: doppler_idx - 1,
+                            velocity: beam_data.value,
+                            locked: beam_data.locked,
+                            measurement_time: sample_epoch,
+                        })
+                    }
+                    RadvsBeamType::Range => {
+                        range_idx += 1;
+                        RadvsMeasurement::Range(surveyor_gnc::sensors::RadvsRangeInput {
+                            sensor_id: range_idx - 1,
+                            slant_range: beam_data.value,
+                            locked: beam_data.locked,
+                            measurement_time: sample_epoch,
+                        })
+                    }
+                };
+                if faults.apply(idx, &mut measurement) {
+                    buffer.push(measurement_time, sampler.latency(), measurement);
+                }
+            }
+        }
+        for measurement in buffer.release(sampler.time()) {
+            match measurement {
+                RadvsMeasurement::Doppler(input) => { doppler_input_events.send(input); }
+                RadvsMeasurement::Range(input) => { range_input_events.send(input); }
+            }
+        }
+    }
+}
+
+pub (crate) fn camera_event_generator(
+    mut q_camera: Query<(&mut Subsystem, &mut SensorSampler, &mut SubsystemFaults), With<Enum![Subsystem::Camera]>>,
+    mut buffer: Local<MeasurementBuffer<surveyor_gnc::sensors::CameraInput>>,
+    mut camera_input_events: EventWriter<surveyor_gnc::sensors::CameraInput>)
+{
+    // The Enum filter does not work on the very first update
+    if let Some((mut subsystem, mut sampler, mut faults)) = q_camera.iter_mut().next()
+    {
+        let camera_subsystem = subsystem.as_camera_mut().unwrap();
+        let measurement_time = sampler.sample_epoch();
+        deliver_measurements(&mut sampler, &mut faults, &mut buffer, &mut camera_input_events, || {
+            camera_subsystem.cameras.iter().enumerate().filter_map(|(idx, camera)| {
+                // Nothing to send until the first image has been taken
+                let image = camera.get_model_output().image?;
+                Some((idx, surveyor_gnc::sensors::CameraInput {
+                    sensor_id: idx,
+                    width: image.width,
+                    height: image.height,
+                    pixels: image.pixels.clone(),
+                    measurement_time,
+                }))
+            }).collect()
+        });
+    }
+}
 
 /// Receive actuator events from the GNC system and send them to the simulation
 /// We convert it into a truth-side type before passing it through
 #[derive(Debug, Clone, Event)]
 pub (crate) enum ActuatorEvent {
     RCS(RcsCommands),
-    // TVC(surveyor_gnc::control::TVCControllerOutput),
+    ReactionWheel(ReactionWheelCommands),
+    Magnetorquer(MagnetorquerCommands),
+    Engine(EngineCommands),
 }
 
 impl From<&surveyor_gnc::control::RCSControllerOutput> for RcsCommands {
     fn from(output: &surveyor_gnc::control::RCSControllerOutput) -> Self {
         RcsCommands {
             duty_cycles: output.duty_cycles.clone(),
+            on_times: output.on_times.clone(),
         }
     }
 }
@@ -122,3 +506,236 @@ pub (crate) fn rcs_event_receiver(
         }
     }
 }
+
+impl From<&surveyor_gnc::control::ReactionWheelControllerOutput> for ReactionWheelCommands {
+    fn from(output: &surveyor_gnc::control::ReactionWheelControllerOutput) -> Self {
+        ReactionWheelCommands {
+            torques: output.torques.clone(),
+        }
+    }
+}
+
+pub (crate) fn reaction_wheel_event_receiver(
+    mut rw_commands: EventReader<surveyor_gnc::control::ReactionWheelControllerOutput>,
+    mut q_rw: Query<&mut Subsystem, With<Enum!(Subsystem::ReactionWheel)>>,
+) {
+    if let Some(mut subsystem) = q_rw.iter_mut().next()
+    {
+        // If there are multiple events, only process the last one
+        if let Some(event) = rw_commands.read().last() {
+            let rw_subsystem = subsystem.as_reaction_wheel_mut().unwrap();
+            rw_subsystem.handle_commands(&ReactionWheelCommands::from(event));
+        }
+    }
+}
+
+impl From<&surveyor_gnc::control::MagnetorquerControllerOutput> for MagnetorquerCommands {
+    fn from(output: &surveyor_gnc::control::MagnetorquerControllerOutput) -> Self {
+        MagnetorquerCommands {
+            dipoles: output.dipoles.clone(),
+        }
+    }
+}
+
+pub (crate) fn magnetorquer_event_receiver(
+    mut mtq_commands: EventReader<surveyor_gnc::control::MagnetorquerControllerOutput>,
+    mut q_mtq: Query<&mut Subsystem, With<Enum!(Subsystem::Magnetorquer)>>,
+) {
+    if let Some(mut subsystem) = q_mtq.iter_mut().next()
+    {
+        // If there are multiple events, only process the last one
+        if let Some(event) = mtq_commands.read().last() {
+            let mtq_subsystem = subsystem.as_magnetorquer_mut().unwrap();
+            mtq_subsystem.handle_commands(&MagnetorquerCommands::from(event));
+        }
+    }
+}
+
+impl From<&surveyor_gnc::control::VernierEngineControllerOutput> for EngineCommands {
+    fn from(output: &surveyor_gnc::control::VernierEngineControllerOutput) -> Self {
+        EngineCommands {
+            ignition: output.ignition,
+            vernier_thrusts: output.thrust_levels.clone(),
+            tvc_deflections: output.tvc_angles.clone(),
+        }
+    }
+}
+
+pub (crate) fn engine_event_receiver(
+    mut engine_commands: EventReader<surveyor_gnc::control::VernierEngineControllerOutput>,
+    mut q_engine: Query<&mut Subsystem, With<Enum!(Subsystem::Propulsion)>>,
+) {
+    if let Some(mut subsystem) = q_engine.iter_mut().next()
+    {
+        // If there are multiple events, only process the last one
+        if let Some(event) = engine_commands.read().last() {
+            let engine_subsystem = subsystem.as_propulsion_mut().unwrap();
+            if let Err(err) = engine_subsystem.handle_commands(&EngineCommands::from(event)) {
+                log::error!("Rejected vernier engine command: {}", err);
+            }
+        }
+    }
+}
+
+// Values of the FSW inputs that are affected by sensor faults
+
+impl FaultableMeasurement for IMUInput {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        self.omega_cf.iter_mut().chain(self.acc_cf.iter_mut()).collect()
+    }
+}
+
+impl FaultableMeasurement for StarTrackerInput {
+    /// Vector part of the quaternion (biases and noise are about half the rotation angle)
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        self.q_i2cf.as_mut_unchecked().coords.iter_mut().take(3).collect()
+    }
+    fn normalize(&mut self) {
+        self.q_i2cf.renormalize();
+    }
+}
+
+impl FaultableMeasurement for surveyor_gnc::sensors::StarSensorInput {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        vec![&mut self.roll_error, &mut self.cone_error]
+    }
+}
+
+impl FaultableMeasurement for surveyor_gnc::sensors::MagnetometerInput {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        self.field_cf.iter_mut().collect()
+    }
+}
+
+impl FaultableMeasurement for SunSensorMeasurement {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        match self {
+            SunSensorMeasurement::Coarse(input) => vec![&mut input.current],
+            SunSensorMeasurement::Fine(input) => vec![&mut input.alpha, &mut input.beta],
+        }
+    }
+    /// The Sun vector of a fine sensor follows its angles
+    fn normalize(&mut self) {
+        if let SunSensorMeasurement::Fine(input) = self {
+            input.sun_vec_cf = nalgebra::Vector3::new(input.alpha.tan(), input.beta.tan(), 1.0).normalize();
+        }
+    }
+}
+
+impl FaultableMeasurement for surveyor_gnc::sensors::AmrInput {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        vec![&mut self.slant_range]
+    }
+}
+
+impl FaultableMeasurement for RadvsMeasurement {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        match self {
+            RadvsMeasurement::Doppler(input) => vec![&mut input.velocity],
+            RadvsMeasurement::Range(input) => vec![&mut input.slant_range],
+        }
+    }
+}
+
+/// Only dropouts apply to images
+impl FaultableMeasurement for surveyor_gnc::sensors::CameraInput {
+    fn values_mut(&mut self) -> Vec<&mut f64> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_times(timing: SensorTimingConfig, sim_dt: f64, gnc_period: f64) -> Vec<f64> {
+        let mut sampler = SensorSampler::from_config(&timing);
+        (0..20).map(|i| i as f64 * sim_dt)
+            .filter(|t| sampler.is_sample_due(*t, gnc_period))
+            .collect()
+    }
+
+    #[test]
+    fn test_sensor_sampler() {
+        // Samples at the GNC rate by default
+        assert_eq!(sample_times(SensorTimingConfig::default(), 0.05, 0.1).len(), 10);
+        // 4 Hz starting at 0.1 s (every 5 steps of 0.05 s)
+        let timing = SensorTimingConfig { sample_rate_hz: Some(4.0), phase: 0.1, latency: 0.0 };
+        let times = sample_times(timing, 0.05, 0.1);
+        assert_eq!(times.len(), 4);
+        assert!((times[0] - 0.1).abs() < 1e-9 && (times[3] - 0.85).abs() < 1e-9);
+        // Faster than the simulation can sample: every step
+        let timing = SensorTimingConfig { sample_rate_hz: Some(100.0), phase: 0.0, latency: 0.0 };
+        assert_eq!(sample_times(timing, 0.05, 0.1).len(), 20);
+
+        // Starts over after a reset
+        let mut sampler = SensorSampler::from_config(&SensorTimingConfig::default());
+        assert!(sampler.is_sample_due(0.0, 0.1));
+        assert!(sampler.is_sample_due(0.1, 0.1));
+        assert!(sampler.is_sample_due(0.0, 0.1));
+        assert_eq!(sampler.take_sample(), Some(0.0));
+        assert_eq!(sampler.take_sample(), None);
+    }
+
+    #[test]
+    fn test_measurement_buffer() {
+        let mut buffer = MeasurementBuffer::default();
+        buffer.push(0.0, 0.25, 'a');
+        buffer.push(0.1, 0.25, 'b');
+        assert!(buffer.release(0.2).is_empty());
+        assert_eq!(buffer.release(0.25), vec!['a']);
+        assert_eq!(buffer.release(0.4), vec!['b']);
+
+        // Measurements from before a reset are dropped
+        buffer.push(1.0, 0.25, 'c');
+        buffer.push(0.0, 0.25, 'd');
+        assert_eq!(buffer.release(2.0), vec!['d']);
+    }
+
+    #[test]
+    fn test_latency_keeps_sample_epoch() {
+        use std::str::FromStr;
+        use bevy::prelude::App;
+        use bevy::app::Update;
+        use surveyor_types::config::{ImuSubsystemConfig, SubsystemConfig};
+
+        let mut app = App::new();
+        app.add_event::<IMUInput>()
+            .add_enum_filter::<Subsystem>()
+            .add_systems(Update, imu_event_generator);
+        let config = ImuSubsystemConfig::from_str(r#"
+            <ImuSubsystem>
+                <Imu name="A">
+                    <geometry>
+                        <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                        <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                    </geometry>
+                </Imu>
+            </ImuSubsystem>"#).unwrap();
+        let timing = SensorTimingConfig { sample_rate_hz: None, phase: 0.0, latency: 0.25 };
+        let imu = app.world.spawn((
+            Subsystem::from_config(&SubsystemConfig::Imu(config)),
+            SensorSampler::from_config(&timing),
+            SubsystemFaults::new(vec!["A".to_string()]),
+        )).id();
+        // The Enum filter does not work on the very first update
+        app.update();
+
+        let start_time = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let epoch_at = |step: usize| start_time + hifitime::Duration::from_seconds(step as f64 * 0.1);
+        let mut reader = app.world.resource::<Events<IMUInput>>().get_reader();
+        let mut delivered = vec![];
+        for step in 0..=5 {
+            {
+                let mut sampler = app.world.get_mut::<SensorSampler>(imu).unwrap();
+                assert!(sampler.is_sample_due(step as f64 * 0.1, 0.1));
+                sampler.set_sample_epoch(epoch_at(step));
+            }
+            app.update();
+            let events = app.world.resource::<Events<IMUInput>>();
+            delivered.extend(reader.read(events).map(|input| (step, input.measurement_time)));
+        }
+        // Each measurement arrives 0.25 s late (at the next step) but is stamped with its sample epoch
+        assert_eq!(delivered, vec![(3, epoch_at(0)), (4, epoch_at(1)), (5, epoch_at(2))]);
+    }
+}
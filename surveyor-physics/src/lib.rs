@@ -3,6 +3,12 @@ pub mod integrators;
 pub mod simulation;
 pub mod spacecraft;
 pub mod universe;
+pub mod magnetic_field;
+pub mod terrain;
+pub mod faults;
+pub mod tracking;
+#[cfg(test)]
+mod test_utils;
 // #[cfg(target_arch = "wasm32")]
 // pub mod visualization;
 
@@ -10,7 +16,7 @@ pub mod models;
 pub mod subsystems;
 
 use hifitime::Epoch;
-use surveyor_types::config::Config;
+use surveyor_types::config::load_config;
 pub use surveyor_types::math;
 pub mod interfaces;
 
@@ -25,7 +31,10 @@ use hard_xml::XmlRead;
 use simulation::*;
 use spacecraft::{
     build_spacecraft_entity, do_discrete_update_from_event, DiscreteUpdateEvent, InitialState,
+    SensorSampleEvent,
 };
+use faults::{FaultLog, FaultSchedule, InjectFaultEvent};
+use tracking::TrackingNetwork;
 use subsystems::Subsystem;
 use universe::{Ephemerides, Universe};
 
@@ -80,9 +89,11 @@ pub fn build_sim_ecs(
     let initial_state: InitialState =
         InitialState::from_str(include_str!("../initial_state.xml")).unwrap();
     // Create new spacecraft with engine subsystem
-    let spacecraft_config_xml = include_str!("../simulation.xml");
-
-    let config = Config::from_str(spacecraft_config_xml).unwrap();
+    let config = load_config();
+    commands.insert_resource(FaultSchedule::from_config(&config.faults.clone().unwrap_or_default()));
+    if let Some(tracking_config) = &config.tracking {
+        commands.insert_resource(TrackingNetwork::from_config(tracking_config));
+    }
 
     // Create new bevy ECS entity for spacecraft
     build_spacecraft_entity(&mut commands, &config, &initial_state);
@@ -105,6 +116,11 @@ impl Plugin for SurveyorPhysicsPlugin {
             .init_asset_loader::<crate::universe::AlmanacLoader>()
             .add_enum_filter::<Subsystem>()
             .add_event::<DiscreteUpdateEvent>()
+            .add_event::<SensorSampleEvent>()
+            .add_event::<InjectFaultEvent>()
+            .init_resource::<FaultSchedule>()
+            .init_resource::<FaultLog>()
+            .init_resource::<TrackingNetwork>()
             .init_state::<SimulationState>()
             .add_event::<SetSimulationRate>()
             // Run simulation when we are in the `Running` state
@@ -119,8 +135,11 @@ impl Plugin for SurveyorPhysicsPlugin {
                 Update,
                 (
                     crate::universe::update_universe,
+                    crate::faults::update_faults,
                     spacecraft::step_spacecraft_model,
                     do_discrete_update_from_event,
+                    crate::interfaces::sample_sensors_from_event,
+                    crate::tracking::update_tracking,
                     update_simulation_state_and_time,
                 )
                     .chain()
@@ -135,15 +154,27 @@ impl Plugin for SurveyorPhysicsPlugin {
                     crate::interfaces::imu_event_generator,
                     crate::interfaces::star_tracker_event_generator,
                     crate::interfaces::star_sensor_event_generator,
+                    crate::interfaces::reaction_wheel_event_generator,
+                    crate::interfaces::engine_event_generator,
+                    crate::interfaces::magnetometer_event_generator,
+                    crate::interfaces::sun_sensor_event_generator,
+                    crate::interfaces::amr_event_generator,
+                    crate::interfaces::radvs_event_generator,
+                    crate::interfaces::camera_event_generator,
                     crate::interfaces::rcs_event_receiver,
+                    crate::interfaces::engine_event_receiver,
+                    crate::interfaces::reaction_wheel_event_receiver,
+                    crate::interfaces::magnetorquer_event_receiver,
                 )
                     .chain()
-                    .after(do_discrete_update_from_event),
+                    .after(crate::interfaces::sample_sensors_from_event),
             )
             // Add a Timer that keeps track of time since the simulation started
             // Run `initialize_simulation` when we enter the `Running` state
             .add_systems(OnEnter(SimulationState::Running), initialize_simulation)
             // Reset the simulation to the initial state when we enter the `Resetting` state
-            .add_systems(OnEnter(SimulationState::Resetting), reset_simulation);
+            .add_systems(OnEnter(SimulationState::Resetting), reset_simulation)
+            // Write out the tracking data once the simulation is over
+            .add_systems(OnEnter(SimulationState::Finished), crate::tracking::export_tracking_data);
     }
 }
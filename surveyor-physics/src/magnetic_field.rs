@@ -0,0 +1,207 @@
+//! Model of the geomagnetic field
+//!
+//! The field is computed from the spherical harmonic expansion of the International Geomagnetic
+//! Reference Field (IGRF-13, epoch 2020.0, main field only) truncated at a configurable degree.
+//! A degree of one gives the tilted dipole. The Earth-fixed frame is obtained from J2000 by a
+//! rotation through the Greenwich mean sidereal time (precession, nutation and polar motion are
+//! ignored).
+
+use hifitime::Epoch;
+use nalgebra::{Matrix3, Vector3};
+use surveyor_types::config::{MagneticFieldConfig, MagneticFieldModelType};
+
+/// Reference radius of the IGRF in meters
+pub const IGRF_REFERENCE_RADIUS: f64 = 6371.2e3;
+
+/// Highest degree of the coefficients included below
+pub const IGRF_MAX_DEGREE: usize = 8;
+
+/// IGRF-13 main field Gauss coefficients for epoch 2020.0 in nT as (n, m, g, h)
+const IGRF13_2020: [(usize, usize, f64, f64); 44] = [
+    (1, 0, -29404.8, 0.0),
+    (1, 1, -1450.9, 4652.5),
+    (2, 0, -2499.6, 0.0),
+    (2, 1, 2982.0, -2991.6),
+    (2, 2, 1677.0, -734.6),
+    (3, 0, 1363.2, 0.0),
+    (3, 1, -2381.2, -82.1),
+    (3, 2, 1236.2, 241.9),
+    (3, 3, 525.7, -543.4),
+    (4, 0, 903.0, 0.0),
+    (4, 1, 809.5, 281.9),
+    (4, 2, 86.3, -158.4),
+    (4, 3, -309.4, 199.7),
+    (4, 4, 48.0, -349.7),
+    (5, 0, -234.3, 0.0),
+    (5, 1, 363.2, 47.7),
+    (5, 2, 187.8, 208.3),
+    (5, 3, -140.7, -121.2),
+    (5, 4, -151.2, 32.3),
+    (5, 5, 13.5, 98.9),
+    (6, 0, 66.0, 0.0),
+    (6, 1, 65.5, -19.1),
+    (6, 2, 72.9, 25.1),
+    (6, 3, -121.5, 52.8),
+    (6, 4, -36.2, -64.5),
+    (6, 5, 13.5, 8.9),
+    (6, 6, -64.7, 68.1),
+    (7, 0, 80.6, 0.0),
+    (7, 1, -76.7, -51.5),
+    (7, 2, -8.2, -16.9),
+    (7, 3, 56.5, 2.2),
+    (7, 4, 15.8, 23.5),
+    (7, 5, 6.4, -2.2),
+    (7, 6, -7.2, -27.2),
+    (7, 7, 9.8, -1.8),
+    (8, 0, 23.7, 0.0),
+    (8, 1, 9.7, 8.4),
+    (8, 2, -17.6, -15.3),
+    (8, 3, -0.5, 12.8),
+    (8, 4, -21.1, -11.7),
+    (8, 5, 15.3, 14.9),
+    (8, 6, 13.7, 3.6),
+    (8, 7, -16.5, -6.9),
+    (8, 8, -0.3, 2.8),
+];
+
+const N: usize = IGRF_MAX_DEGREE + 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagneticFieldModel {
+    /// Degree at which the expansion is truncated
+    pub degree: usize,
+}
+
+impl MagneticFieldModel {
+    pub fn new(degree: usize) -> Self {
+        Self {
+            degree: degree.clamp(1, IGRF_MAX_DEGREE),
+        }
+    }
+    pub fn from_config(config: &MagneticFieldConfig) -> Result<Self, String> {
+        match (config.model, config.degree) {
+            (MagneticFieldModelType::Dipole, _) => Ok(Self::new(1)),
+            (MagneticFieldModelType::Igrf, None) => Ok(Self::new(IGRF_MAX_DEGREE)),
+            (MagneticFieldModelType::Igrf, Some(degree)) if (1..=IGRF_MAX_DEGREE).contains(&degree) => Ok(Self::new(degree)),
+            (MagneticFieldModelType::Igrf, Some(degree)) => {
+                Err(format!("IGRF degree {} is not between 1 and {}", degree, IGRF_MAX_DEGREE))
+            }
+        }
+    }
+
+    /// Magnetic field in Tesla at a position given in the Earth-fixed frame in meters
+    pub fn field_ecef(&self, pos_ecef: &Vector3<f64>) -> Vector3<f64> {
+        let r = pos_ecef.norm();
+        if r < 1.0 {
+            return Vector3::zeros();
+        }
+        let cos_theta = pos_ecef.z / r;
+        // Avoid the singularity in the east component at the poles
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt().max(1e-12);
+        let phi = pos_ecef.y.atan2(pos_ecef.x);
+
+        let (p, dp) = schmidt_legendre(self.degree, cos_theta, sin_theta);
+
+        let (mut b_r, mut b_theta, mut b_phi) = (0.0, 0.0, 0.0);
+        for &(n, m, g, h) in IGRF13_2020.iter().filter(|(n, ..)| *n <= self.degree) {
+            let ratio = (IGRF_REFERENCE_RADIUS / r).powi(n as i32 + 2);
+            let (sin_mphi, cos_mphi) = (m as f64 * phi).sin_cos();
+            let gh = g * cos_mphi + h * sin_mphi;
+            b_r += (n as f64 + 1.0) * ratio * gh * p[n][m];
+            b_theta -= ratio * gh * dp[n][m];
+            b_phi += ratio * m as f64 * (g * sin_mphi - h * cos_mphi) * p[n][m] / sin_theta;
+        }
+
+        // Convert from the local spherical frame to the Earth-fixed frame
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let r_hat = Vector3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+        let theta_hat = Vector3::new(cos_theta * cos_phi, cos_theta * sin_phi, -sin_theta);
+        let phi_hat = Vector3::new(-sin_phi, cos_phi, 0.0);
+        (b_r * r_hat + b_theta * theta_hat + b_phi * phi_hat) * 1e-9
+    }
+
+    /// Magnetic field in Tesla in the J2000 frame given the position relative to the Earth in J2000
+    pub fn field_j2000(&self, pos_j2000: &Vector3<f64>, epoch: Epoch) -> Vector3<f64> {
+        let r_j2000_to_ecef = j2000_to_ecef(epoch);
+        r_j2000_to_ecef.transpose() * self.field_ecef(&(r_j2000_to_ecef * pos_j2000))
+    }
+}
+
+/// Rotation from J2000 to the Earth-fixed frame using the Greenwich mean sidereal time
+pub fn j2000_to_ecef(epoch: Epoch) -> Matrix3<f64> {
+    let days_since_j2000 = epoch.to_jde_utc_days() - 2451545.0;
+    let gmst = (280.46061837 + 360.98564736629 * days_since_j2000).rem_euclid(360.0).to_radians();
+    let (s, c) = gmst.sin_cos();
+    Matrix3::new(
+        c, s, 0.0,
+        -s, c, 0.0,
+        0.0, 0.0, 1.0,
+    )
+}
+
+/// Schmidt semi-normalized associated Legendre functions and their derivatives w.r.t. colatitude
+fn schmidt_legendre(degree: usize, cos_theta: f64, sin_theta: f64) -> ([[f64; N]; N], [[f64; N]; N]) {
+    let mut p = [[0.0; N]; N];
+    let mut dp = [[0.0; N]; N];
+    p[0][0] = 1.0;
+    for n in 1..=degree {
+        for m in 0..=n {
+            if n == m {
+                let k = if n == 1 { 1.0 } else { ((2 * n - 1) as f64 / (2 * n) as f64).sqrt() };
+                p[n][n] = k * sin_theta * p[n - 1][n - 1];
+                dp[n][n] = k * (cos_theta * p[n - 1][n - 1] + sin_theta * dp[n - 1][n - 1]);
+            } else {
+                let (p_2, dp_2) = if n >= 2 { (p[n - 2][m], dp[n - 2][m]) } else { (0.0, 0.0) };
+                let k1 = (2 * n - 1) as f64;
+                let k2 = (((n - 1) * (n - 1)) as f64 - (m * m) as f64).max(0.0).sqrt();
+                let k3 = ((n * n - m * m) as f64).sqrt();
+                p[n][m] = (k1 * cos_theta * p[n - 1][m] - k2 * p_2) / k3;
+                dp[n][m] = (k1 * (cos_theta * dp[n - 1][m] - sin_theta * p[n - 1][m]) - k2 * dp_2) / k3;
+            }
+        }
+    }
+    (p, dp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    #[test]
+    fn test_dipole_field() {
+        let model = MagneticFieldModel::new(1);
+        // Field at the north pole of the dipole points down with twice the equatorial strength
+        let b = model.field_ecef(&Vector3::new(0.0, 0.0, IGRF_REFERENCE_RADIUS));
+        assert_abs_diff_eq!(b.z, 2.0 * -29404.8e-9, epsilon = 1e-6 * 29404.8e-9);
+
+        // Field at the equator points north
+        let b = model.field_ecef(&Vector3::new(0.0, IGRF_REFERENCE_RADIUS, 0.0));
+        assert_abs_diff_eq!(b.z, 29404.8e-9, epsilon = 1e-6 * 29404.8e-9);
+
+        // Falls off with the cube of the distance
+        let b2 = model.field_ecef(&Vector3::new(0.0, 2.0 * IGRF_REFERENCE_RADIUS, 0.0));
+        assert_abs_diff_eq!(b.norm() / b2.norm(), 8.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_igrf_field_strength() {
+        // Total field on the surface ranges between about 22 and 67 uT
+        let model = MagneticFieldModel::new(IGRF_MAX_DEGREE);
+        for (lat, lon) in [(0.0f64, 0.0f64), (45.0, 90.0), (-30.0, -60.0), (80.0, 180.0)] {
+            let (lat, lon) = (lat.to_radians(), lon.to_radians());
+            let pos = IGRF_REFERENCE_RADIUS * Vector3::new(lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin());
+            let b = model.field_ecef(&pos).norm();
+            assert!(b > 20e-6 && b < 70e-6, "Unexpected field strength {}", b);
+        }
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = |degree| MagneticFieldConfig { model: MagneticFieldModelType::Igrf, degree };
+        assert_eq!(MagneticFieldModel::from_config(&config(None)).unwrap().degree, IGRF_MAX_DEGREE);
+        assert_eq!(MagneticFieldModel::from_config(&config(Some(3))).unwrap().degree, 3);
+        assert!(MagneticFieldModel::from_config(&config(Some(0))).is_err());
+        assert!(MagneticFieldModel::from_config(&config(Some(IGRF_MAX_DEGREE + 1))).is_err());
+    }
+}
@@ -32,7 +32,7 @@ pub fn main() {
     body.append_child(&div)
         .unwrap();
 
-    let gnc = surveyor_gnc::SurveyorGNC::new();
+    let gnc = surveyor_gnc::SurveyorGNC::from_config(&surveyor_types::config::load_config()).expect("Invalid FSW configuration");
     App::new()
         .add_plugins(surveyor_physics::SurveyorPhysicsPlugin)
         .add_plugins(gnc)
@@ -0,0 +1,161 @@
+//! Truth-side model of the altitude marking radar (AMR)
+//!
+//! Surveyor's AMR measured the slant range to the lunar surface along its beam and generated a
+//! single "mark" at about 100 km that started the retro sequence. The beam is the Z-axis of the
+//! component frame. The mark is generated once after the slant range first drops below the mark
+//! range, delayed by a fixed delay and a random timing error. The crossing time is interpolated
+//! between updates so that the mark time does not depend on the simulation rate.
+
+use nalgebra::Vector3;
+use surveyor_types::config::{noise_seed, AmrConfig};
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+use super::noise::GaussianNoise;
+
+#[derive(Debug)]
+pub (crate) struct AltitudeMarkingRadar {
+    config: AmrConfig,
+    range_noise: GaussianNoise,
+    timing_noise: GaussianNoise,
+
+    /// Measured slant range in metres (None if the surface is out of range)
+    slant_range: Option<f64>,
+    /// True slant range and simulation time at the last update
+    last_range: Option<f64>,
+    last_update_time: f64,
+    /// Simulation time at which the mark is generated (once the mark range has been crossed)
+    mark_time: Option<f64>,
+    marked: bool,
+    /// Whether the mark was generated during the last update
+    new_mark: bool,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct AmrOutput {
+    pub slant_range: f64,
+    /// False if the surface is beyond the maximum range of the radar
+    pub valid: bool,
+    /// True only for the update in which the mark was generated
+    pub mark: bool,
+}
+
+impl AltitudeMarkingRadar {
+    pub fn from_config(config: &AmrConfig) -> Self {
+        let seed = noise_seed(config.seed, &config.name);
+        Self {
+            config: config.clone(),
+            range_noise: GaussianNoise::new(config.range_noise_std, seed),
+            timing_noise: GaussianNoise::new(config.mark_timing_std, seed.wrapping_add(1)),
+            slant_range: None,
+            last_range: None,
+            last_update_time: f64::NEG_INFINITY,
+            mark_time: None,
+            marked: false,
+            new_mark: false,
+        }
+    }
+    pub fn get_model_output(&self) -> AmrOutput {
+        AmrOutput {
+            slant_range: self.slant_range.unwrap_or(0.0),
+            valid: self.slant_range.is_some(),
+            mark: self.new_mark,
+        }
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let t = discrete_state.time;
+        if t < self.last_update_time {
+            // The simulation has been reset
+            self.last_range = None;
+            self.mark_time = None;
+            self.marked = false;
+        }
+
+        let q_i2b = discrete_state.q_i2b().0;
+        let antenna_pos_i = discrete_state.pos().0 + q_i2b * self.config.geometry.cf_offset_com_b.0;
+        let beam_i = q_i2b * (*self.config.geometry.q_cf2b * Vector3::z());
+        let range = observation.surface_range(&antenna_pos_i, &beam_i)
+            .filter(|range| *range <= self.config.max_range);
+        self.slant_range = range.map(|range| range + self.range_noise.sample());
+
+        if self.mark_time.is_none() {
+            if let Some(range) = range.filter(|range| *range <= self.config.mark_range) {
+                // Interpolate the time at which the mark range was crossed
+                let crossing_time = match self.last_range {
+                    Some(last_range) if last_range > range =>
+                        self.last_update_time + (t - self.last_update_time) * (last_range - self.config.mark_range) / (last_range - range),
+                    _ => t,
+                };
+                let delay = (self.config.mark_delay + self.timing_noise.sample()).max(0.0);
+                self.mark_time = Some(crossing_time.min(t) + delay);
+            }
+        }
+        self.new_mark = false;
+        if let Some(mark_time) = self.mark_time {
+            if !self.marked && t >= mark_time - 1e-9 {
+                self.marked = true;
+                self.new_mark = true;
+            }
+        }
+        self.last_range = range;
+        self.last_update_time = t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+    use hifitime::Epoch;
+    use crate::test_utils::{create_observation, create_state, moon, MOON_RADIUS};
+
+    fn create_amr(extra: &str) -> AltitudeMarkingRadar {
+        let config = AmrConfig::from_str(&format!(r#"
+            <Amr name="AMR">
+                <geometry>
+                    <!-- Points the beam along -Z (body frame) -->
+                    <q_cf2b>[0.0, 1.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <mark_range>100000.0</mark_range>
+                <max_range>150000.0</max_range>
+                {}
+            </Amr>"#, extra)).unwrap();
+        AltitudeMarkingRadar::from_config(&config)
+    }
+
+    /// Spacecraft directly above the north pole with -Z (body) pointing down
+    fn state(time: f64, altitude: f64) -> SpacecraftDiscreteState {
+        let pos = Vector3::new(0.0, 0.0, MOON_RADIUS + altitude);
+        create_state(time, pos, Vector3::zeros(), nalgebra::UnitQuaternion::identity(), Vector3::zeros())
+    }
+
+    fn observation() -> Observation {
+        create_observation(Epoch::default(), [moon(Vector3::zeros())])
+    }
+
+    #[test]
+    fn test_slant_range() {
+        let mut amr = create_amr("");
+        amr.update_discrete(0.0, &state(0.0, 200000.0), &observation());
+        assert!(!amr.get_model_output().valid);
+        amr.update_discrete(0.0, &state(1.0, 120000.0), &observation());
+        let output = amr.get_model_output();
+        assert!(output.valid && !output.mark);
+        assert_abs_diff_eq!(output.slant_range, 120000.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_mark() {
+        let mut amr = create_amr("<mark_delay>1.2</mark_delay>");
+        // Descending at 1 km/s, crossing 100 km at t = 2.5 s
+        let marks = (0..8).map(|i| {
+            let t = i as f64;
+            amr.update_discrete(0.0, &state(t, 102500.0 - 1000.0 * t), &observation());
+            amr.get_model_output().mark
+        }).collect::<Vec<_>>();
+        // Mark due at 3.7 s, generated at the first update after that and only once
+        assert_eq!(marks, vec![false, false, false, false, true, false, false, false]);
+    }
+}
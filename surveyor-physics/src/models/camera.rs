@@ -0,0 +1,242 @@
+//! Truth-side model of a camera that renders grayscale images of the lunar surface on the CPU
+//!
+//! Surveyor carried a TV camera and modern landers use terrain-relative navigation, so the images
+//! are meant for both. The camera is a pinhole looking along the Z-axis of its component frame.
+//! A ray is cast through the center of every pixel to the terrain model (see `terrain`) and the
+//! surface is shaded with the Sun as the only light source, using either a Lambertian or a Hapke
+//! reflectance model. Points that the Sun cannot see (including cast shadows over a DEM) are
+//! black, as is the sky. The reflectance (I/F) is scaled so that `saturation` maps to 255.
+
+use std::{fs, io, sync::Arc};
+
+use nalgebra::Vector3;
+use surveyor_types::{config::{noise_seed, CameraConfig, ShadingModel}, CelestialBodyType};
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+use super::noise::GaussianNoise;
+
+/// Normal albedo of the lunar surface
+const DEFAULT_LAMBERT_ALBEDO: f64 = 0.12;
+/// Hapke parameters for the average lunar surface (single-scattering albedo, width and amplitude
+/// of the opposition surge and asymmetry of the Henyey-Greenstein phase function)
+const DEFAULT_HAPKE_ALBEDO: f64 = 0.25;
+const HAPKE_OPPOSITION_WIDTH: f64 = 0.06;
+const HAPKE_OPPOSITION_AMPLITUDE: f64 = 1.0;
+const HAPKE_ASYMMETRY: f64 = -0.25;
+/// Reflectance that saturates the pixels by default
+const DEFAULT_SATURATION: f64 = 0.25;
+
+/// 8-bit grayscale image stored row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrayscaleImage {
+    pub width: usize,
+    pub height: usize,
+    /// Shared with the camera inputs of the flight software without copying
+    pub pixels: Arc<Vec<u8>>,
+}
+
+impl GrayscaleImage {
+    pub fn pixel(&self, col: usize, row: usize) -> u8 {
+        self.pixels[row * self.width + col]
+    }
+    /// Binary portable graymap (PGM), which most image viewers can open
+    pub fn to_pgm(&self) -> Vec<u8> {
+        let mut pgm = format!("P5\n{} {}\n255\n", self.width, self.height).into_bytes();
+        pgm.extend_from_slice(&self.pixels);
+        pgm
+    }
+}
+
+/// Lambertian reflectance (I/F) for the cosine of the incidence angle
+fn lambert_reflectance(albedo: f64, mu0: f64) -> f64 {
+    albedo * mu0
+}
+
+/// Hapke reflectance (I/F) for the cosines of the incidence and emission angles and the phase
+/// angle (Hapke 1981, without macroscopic roughness)
+fn hapke_reflectance(albedo: f64, mu0: f64, mu: f64, phase: f64) -> f64 {
+    let h = |x: f64| (1.0 + 2.0 * x) / (1.0 + 2.0 * x * (1.0 - albedo).sqrt());
+    let opposition = HAPKE_OPPOSITION_AMPLITUDE / (1.0 + (0.5 * phase).tan() / HAPKE_OPPOSITION_WIDTH);
+    let xi = HAPKE_ASYMMETRY;
+    let phase_function = (1.0 - xi * xi) / (1.0 + 2.0 * xi * phase.cos() + xi * xi).powf(1.5);
+    albedo / 4.0 * mu0 / (mu0 + mu) * ((1.0 + opposition) * phase_function + h(mu0) * h(mu) - 1.0)
+}
+
+#[derive(Debug)]
+pub (crate) struct Camera {
+    config: CameraConfig,
+    noise: GaussianNoise,
+    image: Option<Arc<GrayscaleImage>>,
+    /// Number of images taken so far
+    frame: usize,
+    /// Stops trying to save images after the first failure
+    save_failed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct CameraOutput {
+    /// Latest image (None until the first image is taken)
+    pub image: Option<Arc<GrayscaleImage>>,
+}
+
+impl Camera {
+    pub fn from_config(config: &CameraConfig) -> Self {
+        Self {
+            config: config.clone(),
+            noise: GaussianNoise::new(config.noise_std, noise_seed(config.seed, &config.name)),
+            image: None,
+            frame: 0,
+            save_failed: false,
+        }
+    }
+    pub fn get_model_output(&self) -> CameraOutput {
+        CameraOutput {
+            image: self.image.clone(),
+        }
+    }
+    /// Unit vector along the ray through the center of a pixel in the component frame
+    fn pixel_ray_cf(&self, col: usize, row: usize) -> Vector3<f64> {
+        let f = self.config.focal_length_px();
+        let x = (col as f64 + 0.5 - 0.5 * self.config.width as f64) / f;
+        let y = (row as f64 + 0.5 - 0.5 * self.config.height as f64) / f;
+        Vector3::new(x, y, 1.0).normalize()
+    }
+    /// Reflectance (I/F) of the surface seen along `ray_i` from `camera_pos_i`
+    fn reflectance(&self, camera_pos_i: &Vector3<f64>, ray_i: &Vector3<f64>, sun_pos_i: &Vector3<f64>, observation: &Observation) -> f64 {
+        let Some(range) = observation.surface_range(camera_pos_i, ray_i) else {
+            return 0.0;
+        };
+        let point_i = camera_pos_i + ray_i * range;
+        let Some(normal_i) = observation.surface_normal(&point_i) else {
+            return 0.0;
+        };
+        let sun_dir_i = (sun_pos_i - point_i).normalize();
+        let mu0 = normal_i.dot(&sun_dir_i);
+        let mu = -normal_i.dot(ray_i);
+        if mu0 <= 0.0 || mu <= 0.0 {
+            return 0.0;
+        }
+        // Cast shadows (start slightly above the surface so that the ray does not hit the point itself)
+        if observation.surface_range(&(point_i + normal_i), &sun_dir_i).is_some() {
+            return 0.0;
+        }
+        match self.config.shading {
+            ShadingModel::Lambert => lambert_reflectance(self.config.albedo.unwrap_or(DEFAULT_LAMBERT_ALBEDO), mu0),
+            ShadingModel::Hapke => {
+                let phase = sun_dir_i.angle(&-ray_i);
+                hapke_reflectance(self.config.albedo.unwrap_or(DEFAULT_HAPKE_ALBEDO), mu0, mu, phase)
+            }
+        }
+    }
+    pub fn render(&mut self, discrete_state: &SpacecraftDiscreteState, observation: &Observation) -> GrayscaleImage {
+        let (width, height) = (self.config.width, self.config.height);
+        let mut pixels = vec![0u8; width * height];
+        if let Some(sun) = observation.get_body(CelestialBodyType::Sun) {
+            let q_i2b = discrete_state.q_i2b().0;
+            let q_i2cf = q_i2b * self.config.geometry.q_cf2b.0;
+            let camera_pos_i = discrete_state.pos().0 + q_i2b * self.config.geometry.cf_offset_com_b.0;
+            let scale = 255.0 / self.config.saturation.unwrap_or(DEFAULT_SATURATION);
+            for row in 0..height {
+                for col in 0..width {
+                    let ray_i = q_i2cf * self.pixel_ray_cf(col, row);
+                    let reflectance = self.reflectance(&camera_pos_i, &ray_i, &sun.position.0, observation);
+                    pixels[row * width + col] = (reflectance * scale + self.noise.sample()).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+        GrayscaleImage { width, height, pixels: Arc::new(pixels) }
+    }
+    fn save(&self, image: &GrayscaleImage, output_dir: &str) -> io::Result<()> {
+        fs::create_dir_all(output_dir)?;
+        let path = format!("{}/{}_{:05}.pgm", output_dir, self.config.name, self.frame);
+        fs::write(path, image.to_pgm())
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let image = self.render(discrete_state, observation);
+        if let (Some(output_dir), false) = (self.config.output_dir.as_ref(), self.save_failed) {
+            if let Err(e) = self.save(&image, output_dir) {
+                log::error!("Could not save image from camera {} to '{}': {}", self.config.name, output_dir, e);
+                self.save_failed = true;
+            }
+        }
+        self.image = Some(Arc::new(image));
+        self.frame += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+    use hifitime::Epoch;
+    use crate::test_utils::{create_observation, create_state, moon, sun, MOON_RADIUS};
+
+    fn create_camera(shading: &str) -> Camera {
+        let config = CameraConfig::from_str(&format!(r#"
+            <Camera name="TV" shading="{}">
+                <geometry>
+                    <!-- Looks along -Z (body frame) -->
+                    <q_cf2b>[0.0, 1.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <fov_deg>30.0</fov_deg>
+                <width>16</width>
+                <height>12</height>
+            </Camera>"#, shading)).unwrap();
+        Camera::from_config(&config)
+    }
+
+    /// Moon at the origin and the Sun in the given direction
+    fn observation(sun_dir: Vector3<f64>) -> Observation {
+        create_observation(Epoch::default(), [moon(Vector3::zeros()), sun(sun_dir)])
+    }
+
+    /// Spacecraft above the north pole. The camera looks down unless the spacecraft is flipped.
+    fn state(altitude: f64, flipped: bool) -> SpacecraftDiscreteState {
+        let pos = Vector3::new(0.0, 0.0, MOON_RADIUS + altitude);
+        let q_i2b = if flipped {
+            nalgebra::UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI)
+        } else {
+            nalgebra::UnitQuaternion::identity()
+        };
+        create_state(0.0, pos, Vector3::zeros(), q_i2b, Vector3::zeros())
+    }
+
+    #[test]
+    fn test_lambert_image() {
+        let mut camera = create_camera("Lambert");
+        // Sun 60 deg from the vertical
+        let sun_dir = Vector3::new(60f64.to_radians().sin(), 0.0, 60f64.to_radians().cos());
+        let image = camera.render(&state(1000.0, false), &observation(sun_dir));
+        assert_eq!(image.pixels.len(), 16 * 12);
+        // Nearly flat surface: I/F = 0.12 * cos(60 deg) = 0.06, which is 0.24 of full scale
+        let expected = (0.06 / DEFAULT_SATURATION * 255.0).round() as u8;
+        assert!(image.pixels.iter().all(|p| p.abs_diff(expected) <= 1));
+
+        // The sky is black and so is the night side
+        assert!(camera.render(&state(1000.0, true), &observation(sun_dir)).pixels.iter().all(|p| *p == 0));
+        assert!(camera.render(&state(1000.0, false), &observation(-sun_dir)).pixels.iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn test_hapke_reflectance() {
+        // Opposition surge: brighter looking straight down the sunlight than at 30 deg phase
+        let mu = 30f64.to_radians().cos();
+        assert!(hapke_reflectance(0.25, mu, mu, 0.0) > 1.5 * hapke_reflectance(0.25, 1.0, mu, 30f64.to_radians()));
+        // Close to the observed normal albedo of the Moon at zero phase
+        assert_abs_diff_eq!(hapke_reflectance(0.25, 1.0, 1.0, 0.0), 0.12, epsilon = 0.04);
+
+        let mut camera = create_camera("Hapke");
+        let image = camera.render(&state(1000.0, false), &observation(Vector3::z()));
+        // Sun directly behind the camera: brightest at the center of the image
+        assert!(image.pixel(8, 6) > image.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_pgm() {
+        let image = GrayscaleImage { width: 2, height: 1, pixels: Arc::new(vec![0, 255]) };
+        assert_eq!(image.to_pgm(), b"P5\n2 1\n255\n\x00\xff".to_vec());
+    }
+}
@@ -1,20 +1,114 @@
 //! The IMU model on the simulation side
-use surveyor_types::config::ImuConfig;
-use nalgebra::Vector3;
+//!
+//! The gyro and the accelerometer share the same error model:
+//! ```text
+//! y = Q(sat((I + S + M) * x + b + n))
+//! ```
+//! where `S` is the scale-factor error, `M` the misalignment, `b` the sum of the turn-on bias,
+//! the bias instability (first-order Gauss-Markov) and the random walk, and `n` the white noise.
+use surveyor_types::config::{noise_seed, ImuConfig, InertialSensorErrorParams};
+use nalgebra::{Matrix3, Vector3};
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, StandardNormal};
 
 use crate::spacecraft::SpacecraftDiscreteState;
 
-#[derive(Debug, Default)]
+/// Error model for one triad of inertial sensors (gyros or accelerometers)
+#[derive(Debug, Clone)]
+pub (crate) struct InertialSensorErrorModel {
+    params: InertialSensorErrorParams,
+    rng: StdRng,
+    /// Combined scale-factor and misalignment matrix
+    scale_misalignment: Matrix3<f64>,
+    /// Constant turn-on bias
+    turn_on_bias: Vector3<f64>,
+    /// Gauss-Markov part of the bias
+    bias_instability: Vector3<f64>,
+    /// Random walk part of the bias
+    bias_random_walk: Vector3<f64>,
+}
+
+impl InertialSensorErrorModel {
+    pub fn new(params: InertialSensorErrorParams, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut randn = |std: f64| {
+            let x: f64 = StandardNormal.sample(&mut rng);
+            std * x
+        };
+
+        let scale_factor = Matrix3::from_diagonal(&Vector3::from_fn(|_, _| randn(params.scale_factor_std)));
+        // Small-angle misalignment of each sensitive axis towards the other two
+        let misalignment = Matrix3::from_fn(|i, j| if i == j { 0.0 } else { randn(params.misalignment_std) });
+        let turn_on_bias = Vector3::from_fn(|_, _| randn(params.bias_std));
+        Self {
+            scale_misalignment: Matrix3::identity() + scale_factor + misalignment,
+            turn_on_bias,
+            bias_instability: Vector3::zeros(),
+            bias_random_walk: Vector3::zeros(),
+            params,
+            rng,
+        }
+    }
+    /// Current total bias
+    pub fn bias(&self) -> Vector3<f64> {
+        self.turn_on_bias + self.bias_instability + self.bias_random_walk
+    }
+    fn randn3(&mut self) -> Vector3<f64> {
+        let rng = &mut self.rng;
+        Vector3::from_fn(|_, _| StandardNormal.sample(rng))
+    }
+    /// Propagates the bias over `dt` seconds and returns the measurement of `truth`
+    pub fn measure(&mut self, truth: &Vector3<f64>, dt: f64) -> Vector3<f64> {
+        if dt > 0.0 {
+            if self.params.bias_correlation_time > 0.0 {
+                let phi = (-dt / self.params.bias_correlation_time).exp();
+                let w = self.randn3();
+                self.bias_instability = phi * self.bias_instability
+                    + self.params.bias_instability * (1.0 - phi * phi).sqrt() * w;
+            }
+            let w = self.randn3();
+            self.bias_random_walk += self.params.random_walk * dt.sqrt() * w;
+        }
+        let white_noise = if dt > 0.0 {
+            self.params.noise_density / dt.sqrt() * self.randn3()
+        } else {
+            Vector3::zeros()
+        };
+        let mut measurement = self.scale_misalignment * truth + self.bias() + white_noise;
+        if let Some(saturation) = self.params.saturation {
+            measurement = measurement.map(|x| x.clamp(-saturation, saturation));
+        }
+        if let Some(lsb) = self.params.quantization.filter(|lsb| *lsb > 0.0) {
+            measurement = measurement.map(|x| (x / lsb).round() * lsb);
+        }
+        measurement
+    }
+}
+
+#[derive(Debug)]
 pub (crate) struct IMUSensor{
     q_cf2b: nalgebra::UnitQuaternion<f64>,
+    /// Location of the IMU relative to the center-of-mass in body frame
+    offset_b: Vector3<f64>,
+    gyro_errors: InertialSensorErrorModel,
+    accel_errors: InertialSensorErrorModel,
+    /// Time of the previous measurement
+    last_update_time: Option<f64>,
     pub omega_cf: Vector3<f64>,
     pub accel_cf: Vector3<f64>,
 }
 
 impl IMUSensor {
     pub fn from_config(config: &ImuConfig) -> Self {
+        let gyro_params = config.gyro.as_ref().map(InertialSensorErrorParams::from).unwrap_or_default();
+        let accel_params = config.accelerometer.as_ref().map(InertialSensorErrorParams::from).unwrap_or_default();
+        let seed = noise_seed(config.seed, &config.name);
         Self{
             q_cf2b: *config.geometry.q_cf2b,
+            offset_b: *config.geometry.cf_offset_com_b,
+            gyro_errors: InertialSensorErrorModel::new(gyro_params, seed),
+            accel_errors: InertialSensorErrorModel::new(accel_params, seed.wrapping_add(1)),
+            last_update_time: None,
             omega_cf: Vector3::zeros(),
             accel_cf: Vector3::zeros(),
         }
@@ -26,11 +120,25 @@ impl IMUSensor {
         }
     }
 
+    /// Specific force (excluding gravity) sensed at the IMU location in body frame
+    /// including the tangential and centripetal terms due to the lever arm from the center-of-mass
+    pub fn specific_force_at_imu_b(&self, discrete_state: &SpacecraftDiscreteState) -> Vector3<f64> {
+        let omega_b = discrete_state.omega_b().0;
+        let r = self.offset_b;
+        discrete_state.specific_force_b + discrete_state.omega_dot_b.cross(&r) + omega_b.cross(&omega_b.cross(&r))
+    }
+
     pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState) {
+        // Time since the last sample (used to scale the noise and propagate the biases)
+        let dt = self.last_update_time.map_or(0.0, |t| discrete_state.time - t);
+        self.last_update_time = Some(discrete_state.time);
+
         // Store omega and accel from discrete state
         let omega_b = discrete_state.omega_b();
-        self.omega_cf = self.q_cf2b.inverse_transform_vector(&omega_b);
-        // self.accel_cf = ...
+        let omega_cf = self.q_cf2b.inverse_transform_vector(&omega_b);
+        self.omega_cf = self.gyro_errors.measure(&omega_cf, dt);
+        let accel_cf = self.q_cf2b.inverse_transform_vector(&self.specific_force_at_imu_b(discrete_state));
+        self.accel_cf = self.accel_errors.measure(&accel_cf, dt);
     }
 }
 
@@ -39,3 +147,102 @@ pub (crate) struct IMUSensorOutput {
     pub omega_cf: Vector3<f64>,
     pub accel_cf: Vector3<f64>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+
+    #[test]
+    fn test_ideal_sensor() {
+        let mut model = InertialSensorErrorModel::new(InertialSensorErrorParams::default(), 0);
+        let truth = Vector3::new(0.1, -0.2, 0.3);
+        assert_abs_diff_eq!(model.measure(&truth, 0.1), truth, epsilon = 1e-15);
+    }
+
+    #[test]
+    fn test_reproducible_noise() {
+        let params = InertialSensorErrorParams {
+            noise_density: 1e-3,
+            bias_std: 1e-4,
+            bias_instability: 1e-5,
+            bias_correlation_time: 100.0,
+            random_walk: 1e-6,
+            scale_factor_std: 1e-3,
+            misalignment_std: 1e-3,
+            ..Default::default()
+        };
+        let truth = Vector3::new(0.1, -0.2, 0.3);
+        let mut model_a = InertialSensorErrorModel::new(params.clone(), 42);
+        let mut model_b = InertialSensorErrorModel::new(params.clone(), 42);
+        let mut model_c = InertialSensorErrorModel::new(params, 43);
+        for _ in 0..10 {
+            let a = model_a.measure(&truth, 0.1);
+            assert_eq!(a, model_b.measure(&truth, 0.1));
+            assert_ne!(a, model_c.measure(&truth, 0.1));
+        }
+    }
+
+    #[test]
+    fn test_white_noise_level() {
+        // Standard deviation of the samples is the noise density divided by sqrt(dt)
+        let params = InertialSensorErrorParams { noise_density: 1e-3, ..Default::default() };
+        let mut model = InertialSensorErrorModel::new(params, 7);
+        let n = 20000;
+        let sum_sq = (0..n).map(|_| model.measure(&Vector3::zeros(), 0.01).x.powi(2)).sum::<f64>();
+        assert_abs_diff_eq!((sum_sq / n as f64).sqrt(), 1e-2, epsilon = 5e-4);
+    }
+
+    #[test]
+    fn test_lever_arm_specific_force() {
+        let config = ImuConfig::from_str(r#"
+            <Imu name="A">
+                <geometry>
+                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[1.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+            </Imu>"#).unwrap();
+        let mut imu = IMUSensor::from_config(&config);
+        // Spinning about Z at 0.5 rad/s while spinning up at 0.1 rad/s^2 and thrusting along Z
+        let mut state = nalgebra::SVector::<f64, 13>::zeros();
+        state[6] = 1.0;
+        state[12] = 0.5;
+        let discrete_state = SpacecraftDiscreteState::new(0.0, &state)
+            .with_accelerations(Vector3::new(0.0, 0.0, 2.0), Vector3::new(0.0, 0.0, 0.1));
+        imu.update_discrete(0.0, &discrete_state);
+        // Centripetal acceleration points towards the spin axis and tangential acceleration along +Y
+        assert_abs_diff_eq!(imu.accel_cf, Vector3::new(-0.25, 0.1, 2.0), epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_seed_from_name() {
+        // IMUs without a configured seed see different noise
+        let imu_config = |name: &str| ImuConfig::from_str(&format!(r#"
+            <Imu name="{}">
+                <geometry>
+                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <gyro>
+                    <bias_std>1e-4</bias_std>
+                </gyro>
+            </Imu>"#, name)).unwrap();
+        let imu_a = IMUSensor::from_config(&imu_config("A"));
+        let imu_b = IMUSensor::from_config(&imu_config("B"));
+        assert_ne!(imu_a.gyro_errors.bias(), imu_b.gyro_errors.bias());
+        assert_eq!(imu_a.gyro_errors.bias(), IMUSensor::from_config(&imu_config("A")).gyro_errors.bias());
+    }
+
+    #[test]
+    fn test_saturation_and_quantization() {
+        let params = InertialSensorErrorParams {
+            saturation: Some(1.0),
+            quantization: Some(0.25),
+            ..Default::default()
+        };
+        let mut model = InertialSensorErrorModel::new(params, 0);
+        let measurement = model.measure(&Vector3::new(2.0, -0.3, 0.1), 0.1);
+        assert_eq!(measurement, Vector3::new(1.0, -0.25, 0.0));
+    }
+}
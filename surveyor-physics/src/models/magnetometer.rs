@@ -0,0 +1,41 @@
+//! Truth-side model of a three-axis magnetometer
+
+use nalgebra::Vector3;
+use surveyor_types::config::{noise_seed, MagnetometerConfig};
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+use super::noise::GaussianNoise;
+
+#[derive(Debug)]
+pub (crate) struct Magnetometer {
+    q_cf2b: nalgebra::UnitQuaternion<f64>,
+    noise: GaussianNoise,
+    /// Measured magnetic field in component frame in Tesla
+    pub field_cf: Vector3<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct MagnetometerOutput {
+    pub field_cf: Vector3<f64>,
+}
+
+impl Magnetometer {
+    pub fn from_config(config: &MagnetometerConfig) -> Self {
+        Self {
+            q_cf2b: *config.geometry.q_cf2b,
+            noise: GaussianNoise::new(config.noise_std, noise_seed(config.seed, &config.name)),
+            field_cf: Vector3::zeros(),
+        }
+    }
+    pub fn get_model_output(&self) -> MagnetometerOutput {
+        MagnetometerOutput {
+            field_cf: self.field_cf,
+        }
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let field_i = observation.magnetic_field_i(&discrete_state.pos());
+        let field_b = discrete_state.q_i2b().inverse_transform_vector(&field_i);
+        self.field_cf = self.q_cf2b.inverse_transform_vector(&field_b) + self.noise.sample_vector3();
+    }
+}
@@ -0,0 +1,88 @@
+//! Truth-side model of a magnetorquer rod
+//!
+//! The rod generates a magnetic dipole along the Z-axis of its component frame. The torque on
+//! the spacecraft is the cross product of the dipole with the local magnetic field, which is
+//! sampled at every discrete update.
+
+use nalgebra::Vector3;
+use surveyor_types::config::MagnetorquerConfig;
+
+use crate::{faults::ActuatorFault, spacecraft::{OrbitalDynamicsInputs, SpacecraftDiscreteState}, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct Magnetorquer {
+    pub config: MagnetorquerConfig,
+    /// Dipole axis in the body frame
+    axis_b: Vector3<f64>,
+    /// Commanded dipole moment in A-m^2
+    pub dipole_cmd: f64,
+    /// Magnetic field at the spacecraft in the body frame in Tesla
+    field_b: Vector3<f64>,
+    fault: Option<ActuatorFault>,
+}
+
+impl Magnetorquer {
+    pub fn from_config(config: &MagnetorquerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            axis_b: config.geometry.q_cf2b.transform_vector(&Vector3::z()),
+            dipole_cmd: 0.0,
+            field_b: Vector3::zeros(),
+            fault: None,
+        }
+    }
+    pub fn handle_commands(&mut self, dipole_cmd: f64) {
+        self.dipole_cmd = dipole_cmd.clamp(-self.config.max_dipole, self.config.max_dipole);
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let field_i = observation.magnetic_field_i(&discrete_state.pos());
+        self.field_b = discrete_state.q_i2b().inverse_transform_vector(&field_i);
+    }
+    /// Dipole moment generated by the rod after accounting for faults in A-m^2
+    pub fn dipole(&self) -> f64 {
+        ActuatorFault::apply_to_command(self.fault.as_ref(), self.dipole_cmd, self.config.max_dipole)
+    }
+    pub fn set_fault(&mut self, fault: Option<ActuatorFault>) -> Result<(), String> {
+        if let Some(ActuatorFault::TvcStuck(_)) = fault {
+            return Err("Magnetorquers are not gimbaled".to_string());
+        }
+        self.fault = fault;
+        Ok(())
+    }
+    pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
+        outputs.total_torque_b += (self.dipole() * self.axis_b).cross(&self.field_b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+
+    #[test]
+    fn test_magnetorquer_torque() {
+        let config = MagnetorquerConfig::from_str(r#"
+            <Magnetorquer name="X">
+                <geometry>
+                    <!-- Dipole along +X (body frame) -->
+                    <q_cf2b>[0.7071068, 0.0, 0.7071068, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <max_dipole>10.0</max_dipole>
+            </Magnetorquer>"#).unwrap();
+        let mut torquer = Magnetorquer::from_config(&config);
+        torquer.field_b = Vector3::new(0.0, 30e-6, 0.0);
+        // Dipole command gets clamped to the rod limit
+        torquer.handle_commands(20.0);
+        let mut outputs = OrbitalDynamicsInputs::default();
+        torquer.update_dynamics(&mut outputs);
+        assert_abs_diff_eq!(outputs.total_torque_b, Vector3::new(0.0, 0.0, 3e-4), epsilon = 1e-9);
+
+        // A failed rod generates no dipole
+        torquer.set_fault(Some(ActuatorFault::FailedOff)).unwrap();
+        let mut outputs = OrbitalDynamicsInputs::default();
+        torquer.update_dynamics(&mut outputs);
+        assert_eq!(outputs.total_torque_b, Vector3::zeros());
+    }
+}
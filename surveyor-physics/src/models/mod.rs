@@ -3,6 +3,15 @@ pub mod tvc;
 pub mod rcs;
 pub mod surveyor_engines;
 pub mod star_sensor;
+pub mod star_catalog;
+pub mod reaction_wheel;
+pub mod noise;
+pub mod magnetometer;
+pub mod magnetorquer;
+pub mod sun_sensor;
+pub mod amr;
+pub mod radvs;
+pub mod camera;
 
 // Trait for a model of an actuator (e.g. a servo)
 pub trait ActuatorModel<'a> {
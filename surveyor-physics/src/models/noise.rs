@@ -0,0 +1,30 @@
+//! Random noise sources shared by the sensor models
+//!
+//! Each noise source owns its own seeded generator so that runs are reproducible and adding a
+//! sensor does not change the noise seen by the others.
+
+use nalgebra::Vector3;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+/// Zero-mean white Gaussian noise
+#[derive(Debug, Clone)]
+pub (crate) struct GaussianNoise {
+    rng: StdRng,
+    dist: Normal<f64>,
+}
+
+impl GaussianNoise {
+    pub fn new(std_dev: f64, seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            dist: Normal::new(0.0, std_dev).expect("Noise standard deviation must be finite and non-negative"),
+        }
+    }
+    pub fn sample(&mut self) -> f64 {
+        self.dist.sample(&mut self.rng)
+    }
+    pub fn sample_vector3(&mut self) -> Vector3<f64> {
+        Vector3::new(self.sample(), self.sample(), self.sample())
+    }
+}
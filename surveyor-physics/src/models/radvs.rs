@@ -0,0 +1,186 @@
+//! Truth-side model of the radar altimeter and Doppler velocity sensor (RADVS)
+//!
+//! Surveyor's terminal descent relied on three Doppler velocity beams and a range beam. Each beam
+//! is along the Z-axis of its component frame. A Doppler beam measures the velocity of the
+//! antenna relative to the lunar surface along the beam (positive when moving towards the
+//! surface) and a range beam measures the slant range along the beam.
+//!
+//! A beam acquires lock once the surface has been within its lock range for the lock delay and
+//! loses it as soon as the surface moves beyond its unlock range. The noise grows with the slant
+//! range so that the measurements get more accurate as the lander descends.
+
+use nalgebra::Vector3;
+use surveyor_types::{config::{noise_seed, RadvsBeamConfig, RadvsBeamType}, CelestialBodyType};
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+use super::noise::GaussianNoise;
+
+#[derive(Debug)]
+pub (crate) struct RadvsBeam {
+    config: RadvsBeamConfig,
+    /// Unit-variance noise that is scaled by the range-dependent standard deviation
+    noise: GaussianNoise,
+
+    /// Measured slant range (m) or velocity along the beam (m/s)
+    value: f64,
+    locked: bool,
+    /// Simulation time at which the return was first seen (while not locked)
+    acquisition_time: Option<f64>,
+    last_update_time: f64,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct RadvsBeamOutput {
+    pub beam_type: RadvsBeamType,
+    /// Measured slant range (m) or velocity along the beam (m/s)
+    pub value: f64,
+    pub locked: bool,
+}
+
+impl RadvsBeam {
+    pub fn from_config(config: &RadvsBeamConfig) -> Self {
+        Self {
+            config: config.clone(),
+            noise: GaussianNoise::new(1.0, noise_seed(config.seed, &config.name)),
+            value: 0.0,
+            locked: false,
+            acquisition_time: None,
+            last_update_time: f64::NEG_INFINITY,
+        }
+    }
+    pub fn get_model_output(&self) -> RadvsBeamOutput {
+        RadvsBeamOutput {
+            beam_type: self.config.beam_type,
+            value: self.value,
+            locked: self.locked,
+        }
+    }
+    /// Standard deviation of the noise at the given slant range
+    fn noise_std(&self, range: f64) -> f64 {
+        self.config.noise_std + self.config.noise_per_range * range
+    }
+    fn update_lock(&mut self, t: f64, range: Option<f64>) {
+        let max_range = match self.locked {
+            true => self.config.unlock_range.unwrap_or(self.config.lock_range),
+            false => self.config.lock_range,
+        };
+        if range.map_or(false, |range| range <= max_range) {
+            let acquisition_time = *self.acquisition_time.get_or_insert(t);
+            self.locked = self.locked || t - acquisition_time >= self.config.lock_delay - 1e-9;
+        } else {
+            self.locked = false;
+            self.acquisition_time = None;
+        }
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let t = discrete_state.time;
+        if t < self.last_update_time {
+            // The simulation has been reset
+            self.locked = false;
+            self.acquisition_time = None;
+        }
+        self.last_update_time = t;
+
+        let q_i2b = discrete_state.q_i2b().0;
+        let offset_b = self.config.geometry.cf_offset_com_b.0;
+        let antenna_pos_i = discrete_state.pos().0 + q_i2b * offset_b;
+        let beam_i = q_i2b * (*self.config.geometry.q_cf2b * Vector3::z());
+        let range = observation.surface_range(&antenna_pos_i, &beam_i);
+        self.update_lock(t, range);
+
+        match (self.locked, range) {
+            (true, Some(range)) => {
+                let noise = self.noise_std(range) * self.noise.sample();
+                self.value = match self.config.beam_type {
+                    RadvsBeamType::Range => range + noise,
+                    RadvsBeamType::Doppler => {
+                        // Velocity of the antenna relative to the surface (rotation of the Moon is ignored)
+                        let moon_vel_i = observation.get_body(CelestialBodyType::Moon)
+                            .map_or(Vector3::zeros(), |moon| moon.velocity.0);
+                        let antenna_vel_i = discrete_state.vel().0 + q_i2b * discrete_state.omega_b().cross(&offset_b);
+                        (antenna_vel_i - moon_vel_i).dot(&beam_i) + noise
+                    }
+                };
+            }
+            _ => self.value = 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+    use hifitime::Epoch;
+    use crate::test_utils::{create_observation, create_state, moon, MOON_RADIUS};
+
+    fn create_beam(beam_type: &str, q_cf2b: &str, extra: &str) -> RadvsBeam {
+        let config = RadvsBeamConfig::from_str(&format!(r#"
+            <Beam name="A" type="{}">
+                <geometry>
+                    <q_cf2b>{}</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <lock_range>15000.0</lock_range>
+                <unlock_range>16000.0</unlock_range>
+                {}
+            </Beam>"#, beam_type, q_cf2b, extra)).unwrap();
+        RadvsBeam::from_config(&config)
+    }
+
+    /// Spacecraft above the north pole with -Z (body) pointing down
+    fn state(time: f64, altitude: f64, vel: Vector3<f64>) -> SpacecraftDiscreteState {
+        let pos = Vector3::new(0.0, 0.0, MOON_RADIUS + altitude);
+        create_state(time, pos, vel, nalgebra::UnitQuaternion::identity(), Vector3::zeros())
+    }
+
+    fn observation() -> Observation {
+        create_observation(Epoch::default(), [moon(Vector3::zeros())])
+    }
+
+    #[test]
+    fn test_range_beam_lock() {
+        let mut beam = create_beam("Range", "[0.0, 1.0, 0.0, 0.0]", "<lock_delay>1.0</lock_delay>");
+        let observation = observation();
+        let locked = [(0.0, 20000.0), (1.0, 14000.0), (2.0, 14000.0), (3.0, 15500.0), (4.0, 16500.0), (5.0, 15500.0)]
+            .iter().map(|(t, altitude)| {
+                beam.update_discrete(0.0, &state(*t, *altitude, Vector3::zeros()), &observation);
+                beam.get_model_output().locked
+            }).collect::<Vec<_>>();
+        // Locks after the delay, holds it until the unlock range and needs the lock range to reacquire
+        assert_eq!(locked, vec![false, false, true, true, false, false]);
+
+        beam.update_discrete(0.0, &state(6.0, 1000.0, Vector3::zeros()), &observation);
+        beam.update_discrete(0.0, &state(7.0, 1000.0, Vector3::zeros()), &observation);
+        assert_abs_diff_eq!(beam.get_model_output().value, 1000.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_doppler_beam() {
+        // Beam tilted 25 deg from -Z (body) towards +X
+        let mut beam = create_beam("Doppler", "[0.2164396, 0.0, 0.976296, 0.0]", "");
+        let vel = Vector3::new(10.0, 0.0, -100.0);
+        beam.update_discrete(0.0, &state(0.0, 1000.0, vel), &observation());
+        let output = beam.get_model_output();
+        assert!(output.locked);
+        let beam_dir = Vector3::new(25f64.to_radians().sin(), 0.0, -25f64.to_radians().cos());
+        assert_abs_diff_eq!(output.value, vel.dot(&beam_dir), epsilon = 1e-4);
+    }
+
+    #[test]
+    fn test_altitude_dependent_noise() {
+        let noise_spread = |altitude: f64| {
+            let mut beam = create_beam("Range", "[0.0, 1.0, 0.0, 0.0]", "<noise_std>1.0</noise_std><noise_per_range>0.01</noise_per_range>");
+            let errors = (0..200).map(|i| {
+                beam.update_discrete(0.0, &state(i as f64, altitude, Vector3::zeros()), &observation());
+                beam.get_model_output().value - altitude
+            }).collect::<Vec<_>>();
+            (errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64).sqrt()
+        };
+        // 1-sigma of 101 m at 10 km and 2 m at 100 m
+        assert!((noise_spread(10000.0) - 101.0).abs() < 20.0);
+        assert!((noise_spread(100.0) - 2.0).abs() < 0.5);
+    }
+}
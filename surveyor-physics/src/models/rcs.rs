@@ -1,10 +1,17 @@
-use crate::spacecraft::OrbitalDynamicsInputs;
+use crate::{faults::ActuatorFault, spacecraft::OrbitalDynamicsInputs};
 use surveyor_types::config::ThrusterConfig;
 
 #[derive(Debug)]
 pub (crate) struct RcsThruster {
     pub config: ThrusterConfig,
     pub thrust: f64,
+    /// Thrust requested by the last command
+    commanded_thrust: f64,
+    /// Remaining on-time of the current pulse (None if the thruster is throttled continuously)
+    pulse_remaining: Option<f64>,
+    /// Length of the latest simulation step. Pulses that end within a step are averaged over it.
+    step_dt: f64,
+    fault: Option<ActuatorFault>,
 }
 
 impl RcsThruster {
@@ -12,6 +19,10 @@ impl RcsThruster {
         Self {
             config: config.clone(),
             thrust: 0.0,
+            commanded_thrust: 0.0,
+            pulse_remaining: None,
+            step_dt: 0.0,
+            fault: None,
         }
     }
     pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
@@ -28,6 +39,75 @@ impl RcsThruster {
     }
     pub fn handle_commands(&mut self, duty_cycle: f64) {
         // Compute the thrust based on the duty cycle
-        self.thrust = self.config.max_thrust * duty_cycle;
+        self.pulse_remaining = None;
+        self.commanded_thrust = self.config.max_thrust * duty_cycle;
+        self.thrust = ActuatorFault::apply_to_command(self.fault.as_ref(), self.commanded_thrust, self.config.max_thrust);
+    }
+    /// Fires the thruster at full thrust for the given time (s)
+    pub fn fire_pulse(&mut self, on_time: f64) {
+        self.pulse_remaining = Some(on_time.max(0.0));
+        self.update_pulse();
+    }
+    /// Thrust for the next simulation step from the remaining on-time of the current pulse
+    fn update_pulse(&mut self) {
+        if let Some(remaining) = self.pulse_remaining {
+            let on_fraction = if self.step_dt > 0.0 {
+                (remaining / self.step_dt).min(1.0)
+            } else if remaining > 0.0 { 1.0 } else { 0.0 };
+            self.commanded_thrust = self.config.max_thrust * on_fraction;
+            self.thrust = ActuatorFault::apply_to_command(self.fault.as_ref(), self.commanded_thrust, self.config.max_thrust);
+        }
+    }
+    pub fn update_continuous(&mut self, dt: f64) {
+        self.step_dt = dt;
+        if let Some(remaining) = self.pulse_remaining.as_mut() {
+            *remaining = (*remaining - dt).max(0.0);
+        }
+        self.update_pulse();
+    }
+    pub fn set_fault(&mut self, fault: Option<ActuatorFault>) -> Result<(), String> {
+        if let Some(ActuatorFault::TvcStuck(_)) = fault {
+            return Err("RCS thrusters are not gimbaled".to_string());
+        }
+        self.fault = fault;
+        self.thrust = ActuatorFault::apply_to_command(self.fault.as_ref(), self.commanded_thrust, self.config.max_thrust);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+
+    #[test]
+    fn test_pulse() {
+        let config = ThrusterConfig::from_str(r#"
+            <thruster name="RCS">
+                <min_thrust>0.0</min_thrust>
+                <max_thrust>0.25</max_thrust>
+                <geometry>
+                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 1.0, 0.0]</cf_offset_com_b>
+                </geometry>
+            </thruster>"#).unwrap();
+        let mut thruster = RcsThruster::from_config(&config);
+        let dt = 0.05;
+        thruster.update_continuous(dt);
+        // A 70 ms pulse is on for a full step and then for 40% of the next one
+        thruster.fire_pulse(0.07);
+        let mut impulse = 0.0;
+        for _ in 0..4 {
+            impulse += thruster.thrust * dt;
+            thruster.update_continuous(dt);
+        }
+        assert!((impulse - 0.25 * 0.07).abs() < 1e-12);
+        assert_eq!(thruster.thrust, 0.0);
+
+        // Duty cycle commands end the pulse
+        thruster.fire_pulse(1.0);
+        thruster.handle_commands(0.5);
+        thruster.update_continuous(dt);
+        assert_eq!(thruster.thrust, 0.125);
     }
 }
@@ -0,0 +1,151 @@
+//! Truth-side model of a reaction wheel
+//!
+//! The wheel spins about the Z-axis of its component frame. The motor torque accelerates the
+//! rotor and an equal and opposite torque is applied to the spacecraft. The angular momentum
+//! stored in the rotor is fed back into the rigid-body dynamics for the gyroscopic coupling.
+
+use nalgebra::Vector3;
+use surveyor_types::config::ReactionWheelConfig;
+
+use crate::{faults::ActuatorFault, spacecraft::OrbitalDynamicsInputs};
+
+#[derive(Debug)]
+pub (crate) struct ReactionWheel {
+    pub config: ReactionWheelConfig,
+    /// Spin axis of the wheel in the body frame
+    axis_b: Vector3<f64>,
+    /// Wheel speed in rad/s
+    pub speed: f64,
+    /// Motor torque commanded by the flight software in N-m
+    torque_cmd: f64,
+    fault: Option<ActuatorFault>,
+}
+
+pub (crate) struct ReactionWheelOutput {
+    /// Wheel speed in rad/s (as measured by the wheel tachometer)
+    pub speed: f64,
+}
+
+impl ReactionWheel {
+    pub fn from_config(config: &ReactionWheelConfig) -> Self {
+        Self {
+            config: config.clone(),
+            axis_b: config.geometry.q_cf2b.transform_vector(&Vector3::z()),
+            speed: config.initial_speed,
+            torque_cmd: 0.0,
+            fault: None,
+        }
+    }
+    pub fn handle_commands(&mut self, torque_cmd: f64) {
+        self.torque_cmd = torque_cmd.clamp(-self.config.max_torque, self.config.max_torque);
+    }
+    pub fn get_model_output(&self) -> ReactionWheelOutput {
+        ReactionWheelOutput {
+            speed: self.speed,
+        }
+    }
+    /// Angular momentum stored in the rotor in N-m-s
+    pub fn momentum(&self) -> f64 {
+        self.config.inertia * self.speed
+    }
+    /// Torque applied by the motor after accounting for faults and the speed limit
+    pub fn motor_torque(&self) -> f64 {
+        let torque = ActuatorFault::apply_to_command(self.fault.as_ref(), self.torque_cmd, self.config.max_torque);
+        // The motor cannot spin the wheel up any further once it has hit the speed limit
+        if self.speed.abs() >= self.config.max_speed && torque * self.speed > 0.0 {
+            0.0
+        } else {
+            torque
+        }
+    }
+    pub fn set_fault(&mut self, fault: Option<ActuatorFault>) -> Result<(), String> {
+        if let Some(ActuatorFault::TvcStuck(_)) = fault {
+            return Err("Reaction wheels are not gimbaled".to_string());
+        }
+        self.fault = fault;
+        Ok(())
+    }
+    /// Friction torque opposing the wheel motion
+    pub fn friction_torque(&self) -> f64 {
+        let coulomb = if self.speed != 0.0 { self.config.coulomb_friction * self.speed.signum() } else { 0.0 };
+        self.config.viscous_friction * self.speed + coulomb
+    }
+    /// Net torque acting on the rotor about its spin axis
+    pub fn net_torque(&self) -> f64 {
+        self.motor_torque() - self.friction_torque()
+    }
+    pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
+        // Whatever accelerates the rotor acts on the spacecraft in the opposite direction
+        outputs.total_torque_b -= self.net_torque() * self.axis_b;
+        outputs.internal_momentum_b += self.momentum() * self.axis_b;
+    }
+    pub fn update_continuous(&mut self, dt: f64) {
+        let speed = self.speed + self.net_torque() / self.config.inertia * dt;
+        // Friction cannot reverse the direction of the wheel
+        self.speed = if self.motor_torque() == 0.0 && speed * self.speed < 0.0 { 0.0 } else { speed };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+
+    fn create_test_wheel() -> ReactionWheel {
+        let config = ReactionWheelConfig::from_str(r#"
+            <ReactionWheel name="X">
+                <geometry>
+                    <!-- Spin axis along +X (body frame) -->
+                    <q_cf2b>[0.7071068, 0.0, 0.7071068, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <inertia>0.1</inertia>
+                <max_speed>100.0</max_speed>
+                <max_torque>0.5</max_torque>
+            </ReactionWheel>"#).unwrap();
+        ReactionWheel::from_config(&config)
+    }
+
+    #[test]
+    fn test_reaction_torque() {
+        let mut wheel = create_test_wheel();
+        // Torque command gets clamped to the motor limit
+        wheel.handle_commands(1.0);
+        let mut outputs = OrbitalDynamicsInputs::default();
+        wheel.update_dynamics(&mut outputs);
+        assert_abs_diff_eq!(outputs.total_torque_b, Vector3::new(-0.5, 0.0, 0.0), epsilon = 1e-6);
+
+        wheel.update_continuous(1.0);
+        assert_abs_diff_eq!(wheel.speed, 5.0, epsilon = 1e-9);
+        let mut outputs = OrbitalDynamicsInputs::default();
+        wheel.update_dynamics(&mut outputs);
+        assert_abs_diff_eq!(outputs.internal_momentum_b, Vector3::new(0.5, 0.0, 0.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_speed_limit() {
+        let mut wheel = create_test_wheel();
+        wheel.speed = 100.0;
+        wheel.handle_commands(0.5);
+        assert_eq!(wheel.motor_torque(), 0.0);
+        // Can still be commanded to slow down
+        wheel.handle_commands(-0.5);
+        assert_eq!(wheel.motor_torque(), -0.5);
+    }
+
+    #[test]
+    fn test_wheel_faults() {
+        let mut wheel = create_test_wheel();
+        wheel.handle_commands(-0.4);
+        wheel.set_fault(Some(ActuatorFault::ThrustLoss(0.5))).unwrap();
+        assert_abs_diff_eq!(wheel.motor_torque(), -0.2, epsilon = 1e-12);
+        wheel.set_fault(Some(ActuatorFault::FailedOn)).unwrap();
+        assert_eq!(wheel.motor_torque(), 0.5);
+        wheel.set_fault(Some(ActuatorFault::FailedOff)).unwrap();
+        assert_eq!(wheel.motor_torque(), 0.0);
+        assert!(wheel.set_fault(Some(ActuatorFault::TvcStuck(None))).is_err());
+        wheel.set_fault(None).unwrap();
+        assert_eq!(wheel.motor_torque(), -0.4);
+    }
+}
@@ -0,0 +1,80 @@
+//! A small catalog of the brightest stars in the sky used by the star sensor models
+//!
+//! Positions are J2000 right ascension and declination in degrees and brightness is the visual
+//! magnitude. Proper motion and parallax are ignored.
+
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CatalogStar {
+    pub name: &'static str,
+    pub ra_deg: f64,
+    pub dec_deg: f64,
+    pub magnitude: f64,
+}
+
+impl CatalogStar {
+    /// Unit vector to the star in the inertial frame
+    pub fn unit_vector_i(&self) -> Vector3<f64> {
+        radec_to_unit_vector(self.ra_deg, self.dec_deg)
+    }
+    /// Brightness of the star relative to Canopus
+    pub fn intensity(&self) -> f64 {
+        10f64.powf(-0.4 * (self.magnitude - CANOPUS_MAGNITUDE))
+    }
+}
+
+pub fn radec_to_unit_vector(ra_deg: f64, dec_deg: f64) -> Vector3<f64> {
+    let (ra, dec) = (ra_deg.to_radians(), dec_deg.to_radians());
+    Vector3::new(dec.cos() * ra.cos(), dec.cos() * ra.sin(), dec.sin())
+}
+
+pub const CANOPUS_MAGNITUDE: f64 = -0.74;
+
+pub const BRIGHT_STARS: [CatalogStar; 45] = [
+    CatalogStar { name: "Sirius", ra_deg: 101.2872, dec_deg: -16.7161, magnitude: -1.46 },
+    CatalogStar { name: "Canopus", ra_deg: 95.9880, dec_deg: -52.6957, magnitude: CANOPUS_MAGNITUDE },
+    CatalogStar { name: "Rigil Kentaurus", ra_deg: 219.9021, dec_deg: -60.8340, magnitude: -0.27 },
+    CatalogStar { name: "Arcturus", ra_deg: 213.9153, dec_deg: 19.1824, magnitude: -0.05 },
+    CatalogStar { name: "Vega", ra_deg: 279.2347, dec_deg: 38.7837, magnitude: 0.03 },
+    CatalogStar { name: "Capella", ra_deg: 79.1723, dec_deg: 45.9980, magnitude: 0.08 },
+    CatalogStar { name: "Rigel", ra_deg: 78.6345, dec_deg: -8.2016, magnitude: 0.13 },
+    CatalogStar { name: "Procyon", ra_deg: 114.8255, dec_deg: 5.2250, magnitude: 0.34 },
+    CatalogStar { name: "Achernar", ra_deg: 24.4285, dec_deg: -57.2368, magnitude: 0.46 },
+    CatalogStar { name: "Betelgeuse", ra_deg: 88.7929, dec_deg: 7.4071, magnitude: 0.50 },
+    CatalogStar { name: "Hadar", ra_deg: 210.9559, dec_deg: -60.3730, magnitude: 0.61 },
+    CatalogStar { name: "Altair", ra_deg: 297.6958, dec_deg: 8.8683, magnitude: 0.76 },
+    CatalogStar { name: "Acrux", ra_deg: 186.6496, dec_deg: -63.0991, magnitude: 0.76 },
+    CatalogStar { name: "Aldebaran", ra_deg: 68.9802, dec_deg: 16.5093, magnitude: 0.86 },
+    CatalogStar { name: "Antares", ra_deg: 247.3519, dec_deg: -26.4320, magnitude: 0.96 },
+    CatalogStar { name: "Spica", ra_deg: 201.2983, dec_deg: -11.1613, magnitude: 0.97 },
+    CatalogStar { name: "Pollux", ra_deg: 116.3290, dec_deg: 28.0262, magnitude: 1.14 },
+    CatalogStar { name: "Fomalhaut", ra_deg: 344.4127, dec_deg: -29.6222, magnitude: 1.16 },
+    CatalogStar { name: "Deneb", ra_deg: 310.3580, dec_deg: 45.2803, magnitude: 1.25 },
+    CatalogStar { name: "Mimosa", ra_deg: 191.9303, dec_deg: -59.6888, magnitude: 1.25 },
+    CatalogStar { name: "Regulus", ra_deg: 152.0930, dec_deg: 11.9672, magnitude: 1.35 },
+    CatalogStar { name: "Adhara", ra_deg: 104.6565, dec_deg: -28.9721, magnitude: 1.50 },
+    CatalogStar { name: "Castor", ra_deg: 113.6494, dec_deg: 31.8883, magnitude: 1.58 },
+    CatalogStar { name: "Shaula", ra_deg: 263.4022, dec_deg: -37.1038, magnitude: 1.62 },
+    CatalogStar { name: "Gacrux", ra_deg: 187.7915, dec_deg: -57.1132, magnitude: 1.63 },
+    CatalogStar { name: "Bellatrix", ra_deg: 81.2828, dec_deg: 6.3497, magnitude: 1.64 },
+    CatalogStar { name: "Elnath", ra_deg: 81.5730, dec_deg: 28.6075, magnitude: 1.65 },
+    CatalogStar { name: "Miaplacidus", ra_deg: 138.2999, dec_deg: -69.7172, magnitude: 1.67 },
+    CatalogStar { name: "Alnilam", ra_deg: 84.0534, dec_deg: -1.2019, magnitude: 1.69 },
+    CatalogStar { name: "Alnair", ra_deg: 332.0583, dec_deg: -46.9610, magnitude: 1.74 },
+    CatalogStar { name: "Alnitak", ra_deg: 85.1897, dec_deg: -1.9426, magnitude: 1.77 },
+    CatalogStar { name: "Alioth", ra_deg: 193.5073, dec_deg: 55.9598, magnitude: 1.77 },
+    CatalogStar { name: "Dubhe", ra_deg: 165.9320, dec_deg: 61.7510, magnitude: 1.79 },
+    CatalogStar { name: "Mirfak", ra_deg: 51.0807, dec_deg: 49.8612, magnitude: 1.79 },
+    CatalogStar { name: "Wezen", ra_deg: 107.0979, dec_deg: -26.3932, magnitude: 1.83 },
+    CatalogStar { name: "Regor", ra_deg: 122.3831, dec_deg: -47.3366, magnitude: 1.83 },
+    CatalogStar { name: "Kaus Australis", ra_deg: 276.0430, dec_deg: -34.3846, magnitude: 1.85 },
+    CatalogStar { name: "Avior", ra_deg: 125.6285, dec_deg: -59.5095, magnitude: 1.86 },
+    CatalogStar { name: "Alkaid", ra_deg: 206.8852, dec_deg: 49.3133, magnitude: 1.86 },
+    CatalogStar { name: "Sargas", ra_deg: 264.3297, dec_deg: -42.9978, magnitude: 1.86 },
+    CatalogStar { name: "Menkalinan", ra_deg: 89.8822, dec_deg: 44.9474, magnitude: 1.90 },
+    CatalogStar { name: "Atria", ra_deg: 252.1662, dec_deg: -69.0277, magnitude: 1.91 },
+    CatalogStar { name: "Alhena", ra_deg: 99.4280, dec_deg: 16.3993, magnitude: 1.92 },
+    CatalogStar { name: "Peacock", ra_deg: 306.4119, dec_deg: -56.7351, magnitude: 1.94 },
+    CatalogStar { name: "Polaris", ra_deg: 37.9546, dec_deg: 89.2641, magnitude: 1.98 },
+];
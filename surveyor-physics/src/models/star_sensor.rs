@@ -1,11 +1,21 @@
-//! Truth-side model for a sensor that tracks a single star and returns its unit vector
+//! Truth-side model for a sensor that locks on to a single star and returns its unit vector
 //! in the sensor frame (boresight is the Z-axis).
 //! For example, Canopus (which was used for the Surveyor missions)
+//!
+//! The sensor does not know which star it is looking at. It locks on to the star in the bright
+//! star catalog that is closest to the boresight and within the brightness window, and holds on
+//! to it while it remains in the field of view. If another star fits the window it can lock on
+//! to the wrong star, which can only be detected through the intensity or roll error signals.
+//!
+//! The roll error is the angle of the star from the boresight along the X-axis of the sensor
+//! frame and the cone error is the angle along the Y-axis.
 
-use nalgebra::Vector3;
+use nalgebra::{UnitQuaternion, Vector3};
 
 use crate::spacecraft::SpacecraftDiscreteState;
 
+use super::{noise::GaussianNoise, star_catalog::{radec_to_unit_vector, CatalogStar, BRIGHT_STARS}};
+
 
 #[derive(Debug)]
 pub (crate) struct StarSensor {
@@ -13,55 +23,100 @@ pub (crate) struct StarSensor {
     q_cf2b: surveyor_types::math::UnitQuaternion,
     /// Field of view of the sensor in radians
     fov: f64,
-    /// Unit vector to the star in the inertial frame
-    star_vec_i: Vector3<f64>,
+    /// Brightness window relative to Canopus
+    min_intensity: f64,
+    max_intensity: f64,
+    /// Unit vector to the intended star in the inertial frame
+    target_vec_i: Vector3<f64>,
+    noise: GaussianNoise,
 
+    /// Star that the sensor is locked on to
+    locked_star: Option<CatalogStar>,
     /// Unit vector to the star in the camera frame
     star_vec_cf: Vector3<f64>,
-    /// Whether the star is in the field of view of the sensor
-    in_fov: bool,
+    roll_error: f64,
+    cone_error: f64,
 }
 
 pub (crate) struct StarSensorOutput {
     /// Unit vector to the star in the camera frame
     pub star_vec_cf: Vector3<f64>,
-    /// Whether the star is in the field of view of the sensor
+    /// Angle of the star from the boresight along the sensor X-axis in radians
+    pub roll_error: f64,
+    /// Angle of the star from the boresight along the sensor Y-axis in radians
+    pub cone_error: f64,
+    /// Brightness of the star relative to Canopus
+    pub intensity: f64,
+    /// Whether the sensor is locked on to a star
     pub valid: bool,
+    /// Whether the sensor is locked on to a star other than the intended one (not visible to FSW)
+    pub false_lock: bool,
 }
 
 impl StarSensor {
     pub fn from_config(config: &surveyor_types::config::StarSensorConfig) -> Self {
-        let ra = config.ra_deg.to_radians();
-        let dec = config.dec_deg.to_radians();
         Self{
             q_cf2b: config.geometry.q_cf2b.clone(),
             fov: config.fov_deg.to_radians(),
-            star_vec_i: Vector3::new(
-                dec.cos() * ra.cos(),
-                dec.cos() * ra.sin(),
-                dec.sin(),
-            ),
+            min_intensity: config.min_intensity.unwrap_or(0.0),
+            max_intensity: config.max_intensity.unwrap_or(f64::INFINITY),
+            target_vec_i: radec_to_unit_vector(config.ra_deg, config.dec_deg),
+            noise: GaussianNoise::new(config.noise_std, surveyor_types::config::noise_seed(config.seed, &config.name)),
+            locked_star: None,
             star_vec_cf: Vector3::zeros(),
-            in_fov: false,
+            roll_error: 0.0,
+            cone_error: 0.0,
         }
     }
     pub fn get_model_output(&self) -> StarSensorOutput {
         StarSensorOutput {
             star_vec_cf: self.star_vec_cf,
-            valid: self.in_fov,
+            roll_error: self.roll_error,
+            cone_error: self.cone_error,
+            intensity: self.locked_star.map_or(0.0, |star| star.intensity()),
+            valid: self.locked_star.is_some(),
+            false_lock: self.locked_star.map_or(false, |star|
+                star.unit_vector_i().angle(&self.target_vec_i) > 1e-3),
         }
     }
-    pub fn is_in_fov(&self) -> bool {
-        self.star_vec_cf.z >= self.fov.cos()
+    fn is_in_fov(&self, star_vec_cf: &Vector3<f64>) -> bool {
+        star_vec_cf.z >= self.fov.cos() - 1e-12
+    }
+    fn is_in_brightness_window(&self, star: &CatalogStar) -> bool {
+        let intensity = star.intensity();
+        intensity >= self.min_intensity && intensity <= self.max_intensity
     }
     pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState) {
-        // Rotate the star vector into the body frame
-        let star_vec_b = discrete_state.q_i2b().inverse_transform_vector(&self.star_vec_i);
-        self.in_fov = self.is_in_fov();
-        if !self.in_fov {
-            return;
+        let q_i2cf = discrete_state.q_i2b().0 * self.q_cf2b.0;
+        let to_cf = |star: &CatalogStar| q_i2cf.inverse_transform_vector(&star.unit_vector_i());
+
+        // Hold on to the current star while it is in view, otherwise look for a new one
+        let locked_star = self.locked_star.filter(|star| self.is_in_fov(&to_cf(star)));
+        self.locked_star = locked_star.or_else(|| {
+            BRIGHT_STARS.iter()
+                .filter(|star| self.is_in_brightness_window(star) && self.is_in_fov(&to_cf(star)))
+                .max_by(|a, b| to_cf(a).z.total_cmp(&to_cf(b).z))
+                .copied()
+        });
+
+        match self.locked_star {
+            Some(star) => {
+                if locked_star.is_none() && self.get_model_output().false_lock {
+                    log::debug!("Star sensor locked on to {}", star.name);
+                }
+                // Small rotation about the sensor X- and Y-axes so that the noise shows up
+                // consistently in the star vector and the roll and cone errors
+                let noise = UnitQuaternion::from_scaled_axis(Vector3::new(-self.noise.sample(), self.noise.sample(), 0.0));
+                self.star_vec_cf = noise * to_cf(&star);
+                self.roll_error = self.star_vec_cf.x.atan2(self.star_vec_cf.z);
+                self.cone_error = self.star_vec_cf.y.atan2(self.star_vec_cf.z);
+            }
+            None => {
+                self.star_vec_cf = Vector3::zeros();
+                self.roll_error = 0.0;
+                self.cone_error = 0.0;
+            }
         }
-        self.star_vec_cf = self.q_cf2b.inverse_transform_vector(&star_vec_b);
     }
 }
 
@@ -69,37 +124,84 @@ impl StarSensor {
 mod tests {
     use super::*;
     use approx::assert_abs_diff_eq;
-    use nalgebra::{UnitQuaternion, SVector};
+    use nalgebra::SVector;
 
-    #[test]
-    fn test_star_sensor() {
+    fn create_star_sensor(min_intensity: Option<f64>, max_intensity: Option<f64>) -> StarSensor {
         let config = surveyor_types::config::StarSensorConfig {
             name: "Canopus".to_string(),
             geometry: surveyor_types::config::GeometryParams {
-                // Z-axis is boresight, pointing out along the +X body axis
-                q_cf2b: surveyor_types::math::UnitQuaternion(UnitQuaternion::from_euler_angles(0.0, std::f64::consts::FRAC_PI_2, 0.0)),
+                q_cf2b: surveyor_types::math::UnitQuaternion(UnitQuaternion::identity()),
                 cf_offset_com_b: surveyor_types::math::Vector3(nalgebra::Vector3::zeros()),
             },
-            ra_deg: 0.0,
-            dec_deg: 0.0,
-            fov_deg: 0.0,
+            ra_deg: 95.9880,
+            dec_deg: -52.6957,
+            fov_deg: 5.0,
+            min_intensity,
+            max_intensity,
+            noise_std: 0.0,
+            seed: Some(0),
         };
-        let mut star_sensor = StarSensor::from_config(&config);
+        StarSensor::from_config(&config)
+    }
 
-        // Define state with identity quaternion
+    /// State with the boresight (body Z-axis) pointed at the given unit vector
+    fn point_at(star_vec_i: &Vector3<f64>) -> SpacecraftDiscreteState {
+        let q_i2b = UnitQuaternion::rotation_between(&Vector3::z(), star_vec_i).unwrap();
         let mut state: SVector<f64, 13> = SVector::zeros();
-        state[6] = 1.0;
-        let discrete_state = SpacecraftDiscreteState::new(0.0, &state);
-        star_sensor.update_discrete(0.0, &discrete_state);
+        state[6] = q_i2b.w;
+        state[7] = q_i2b.i;
+        state[8] = q_i2b.j;
+        state[9] = q_i2b.k;
+        SpacecraftDiscreteState::new(0.0, &state)
+    }
+
+    #[test]
+    fn test_star_sensor() {
+        let mut star_sensor = create_star_sensor(Some(0.5), Some(1.5));
+        let canopus = BRIGHT_STARS.iter().find(|star| star.name == "Canopus").unwrap();
+        star_sensor.update_discrete(0.0, &point_at(&canopus.unit_vector_i()));
         let output = star_sensor.get_model_output();
         assert_eq!(output.valid, true);
-        assert_abs_diff_eq!(output.star_vec_cf, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(output.false_lock, false);
+        assert_abs_diff_eq!(output.star_vec_cf, Vector3::new(0.0, 0.0, 1.0), epsilon = 1e-9);
+        assert_abs_diff_eq!(output.roll_error, 0.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(output.intensity, 1.0, epsilon = 1e-9);
 
-        // Now use an attitude where the star is not in the field of view
-        state[6] = 0.0;
-        state[8] = 1.0;
-        star_sensor.update_discrete(0.0, &SpacecraftDiscreteState::new(0.0, &state));
+        // Now use an attitude where there is no bright star in the field of view
+        star_sensor.update_discrete(0.0, &point_at(&-Vector3::x()));
         let output = star_sensor.get_model_output();
         assert_eq!(output.valid, false);
     }
+
+    #[test]
+    fn test_star_sensor_noise() {
+        let mut star_sensor = create_star_sensor(Some(0.5), Some(1.5));
+        star_sensor.noise = GaussianNoise::new(1e-3, 0);
+        let canopus = BRIGHT_STARS.iter().find(|star| star.name == "Canopus").unwrap();
+        star_sensor.update_discrete(0.0, &point_at(&canopus.unit_vector_i()));
+        let output = star_sensor.get_model_output();
+        // The noise perturbs the star vector and the errors agree with it
+        assert_abs_diff_eq!(output.star_vec_cf.norm(), 1.0, epsilon = 1e-12);
+        assert!(output.star_vec_cf.angle(&Vector3::z()) > 0.0);
+        assert!(output.star_vec_cf.angle(&Vector3::z()) < 1e-2);
+        assert_abs_diff_eq!(output.roll_error, output.star_vec_cf.x.atan2(output.star_vec_cf.z), epsilon = 1e-12);
+        assert_abs_diff_eq!(output.cone_error, output.star_vec_cf.y.atan2(output.star_vec_cf.z), epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_false_lock() {
+        let sirius = BRIGHT_STARS.iter().find(|star| star.name == "Sirius").unwrap();
+        // Sirius is too bright to pass the brightness window
+        let mut star_sensor = create_star_sensor(Some(0.5), Some(1.5));
+        star_sensor.update_discrete(0.0, &point_at(&sirius.unit_vector_i()));
+        assert_eq!(star_sensor.get_model_output().valid, false);
+
+        // Without a brightness window, the sensor locks on to the wrong star
+        let mut star_sensor = create_star_sensor(None, None);
+        star_sensor.update_discrete(0.0, &point_at(&sirius.unit_vector_i()));
+        let output = star_sensor.get_model_output();
+        assert_eq!(output.valid, true);
+        assert_eq!(output.false_lock, true);
+        assert_abs_diff_eq!(output.intensity, 10f64.powf(0.4 * 0.72), epsilon = 1e-9);
+    }
 }
@@ -0,0 +1,291 @@
+//! Truth-side models of coarse and fine sun sensors
+//!
+//! The boresight of both sensors is the Z-axis of the component frame. A coarse sun sensor is a
+//! photocell whose output current follows the cosine of the angle between the boresight and the
+//! Sun. Its output is normalized so that it reads 1.0 with the full Sun on the boresight. A fine
+//! sun sensor measures the two angles of the Sun from the boresight within its field of view.
+//!
+//! Sunlight reflected by the Moon and the Earth (albedo) is seen as a much dimmer light source in
+//! the direction of the body. It adds to the coarse sensor output and pulls the fine sensor
+//! measurement towards the body. The Sun is partially or fully hidden when the Moon or Earth
+//! passes in front of it.
+
+use nalgebra::Vector3;
+use surveyor_types::{config::{noise_seed, SunSensorConfig, SunSensorType}, CelestialBodyType};
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+use super::noise::GaussianNoise;
+
+/// Bond albedo of the bodies that reflect sunlight on to the sensors
+const ALBEDO: [(CelestialBodyType, f64); 2] = [
+    (CelestialBodyType::Moon, 0.12),
+    (CelestialBodyType::Earth, 0.30),
+];
+/// Fraction of full sunlight needed by the fine sun sensor to produce a measurement
+const FINE_SENSOR_MIN_ILLUMINATION: f64 = 0.5;
+
+/// Light source as seen from the spacecraft
+#[derive(Debug, Clone, Copy)]
+struct LightSource {
+    /// Unit vector from the spacecraft to the source in the inertial frame
+    dir_i: Vector3<f64>,
+    /// Irradiance relative to full sunlight
+    intensity: f64,
+}
+
+#[derive(Debug)]
+pub (crate) struct SunSensor {
+    pub sensor_type: SunSensorType,
+    q_cf2b: nalgebra::UnitQuaternion<f64>,
+    /// Field of view of the sensor in radians (half-angle)
+    fov: f64,
+    noise: GaussianNoise,
+
+    /// Normalized output current of the sensor (coarse) or total light in the field of view (fine)
+    intensity: f64,
+    /// Unit vector to the Sun in the component frame (fine)
+    sun_vec_cf: Vector3<f64>,
+    alpha: f64,
+    beta: f64,
+    valid: bool,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct SunSensorOutput {
+    pub sensor_type: SunSensorType,
+    /// Normalized output current of the sensor (coarse) or total light in the field of view (fine)
+    pub intensity: f64,
+    /// Measured unit vector to the Sun in the component frame (fine)
+    pub sun_vec_cf: Vector3<f64>,
+    /// Angle of the Sun from the boresight along the sensor X-axis in radians (fine)
+    pub alpha: f64,
+    /// Angle of the Sun from the boresight along the sensor Y-axis in radians (fine)
+    pub beta: f64,
+    /// Whether the Sun is in the field of view and bright enough to be measured (fine)
+    pub valid: bool,
+}
+
+impl SunSensor {
+    pub fn from_config(config: &SunSensorConfig) -> Self {
+        Self {
+            sensor_type: config.sensor_type,
+            q_cf2b: *config.geometry.q_cf2b,
+            fov: config.fov_deg.to_radians(),
+            noise: GaussianNoise::new(config.noise_std, noise_seed(config.seed, &config.name)),
+            intensity: 0.0,
+            sun_vec_cf: Vector3::zeros(),
+            alpha: 0.0,
+            beta: 0.0,
+            valid: false,
+        }
+    }
+    pub fn get_model_output(&self) -> SunSensorOutput {
+        SunSensorOutput {
+            sensor_type: self.sensor_type,
+            intensity: self.intensity,
+            sun_vec_cf: self.sun_vec_cf,
+            alpha: self.alpha,
+            beta: self.beta,
+            valid: self.valid,
+        }
+    }
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let q_i2cf = discrete_state.q_i2b().0 * self.q_cf2b;
+        let sources = light_sources(&discrete_state.pos(), observation);
+        let to_cf_in_fov = |source: &LightSource| {
+            let dir_cf = q_i2cf.inverse_transform_vector(&source.dir_i);
+            (dir_cf.z > 0.0 && dir_cf.z >= self.fov.cos() - 1e-12).then_some(dir_cf)
+        };
+        // Direction and (cosine-weighted) brightness of every source in the field of view
+        let in_fov = sources.iter().filter_map(|source|
+            to_cf_in_fov(source).map(|dir_cf| (dir_cf, source.intensity * dir_cf.z))
+        ).collect::<Vec<_>>();
+        let sun_visible = sources.first().map_or(false, |sun|
+            sun.intensity >= FINE_SENSOR_MIN_ILLUMINATION && to_cf_in_fov(sun).is_some());
+
+        let total = in_fov.iter().map(|(_, weight)| weight).sum::<f64>();
+        match self.sensor_type {
+            SunSensorType::Coarse => {
+                self.intensity = (total + self.noise.sample()).max(0.0);
+                self.valid = true;
+            }
+            SunSensorType::Fine => {
+                self.intensity = total;
+                self.valid = sun_visible;
+                if self.valid {
+                    // The sensor sees the centroid of all the light in its field of view
+                    let apparent = in_fov.iter().map(|(dir_cf, weight)| dir_cf * *weight).sum::<Vector3<f64>>();
+                    self.alpha = apparent.x.atan2(apparent.z) + self.noise.sample();
+                    self.beta = apparent.y.atan2(apparent.z) + self.noise.sample();
+                    self.sun_vec_cf = Vector3::new(self.alpha.tan(), self.beta.tan(), 1.0).normalize();
+                } else {
+                    self.alpha = 0.0;
+                    self.beta = 0.0;
+                    self.sun_vec_cf = Vector3::zeros();
+                }
+            }
+        }
+    }
+}
+
+/// Returns the Sun (always first, dimmed by any eclipse) followed by the sunlight reflected by
+/// the Moon and the Earth. Empty if there is no Sun in the simulation.
+fn light_sources(pos_i: &Vector3<f64>, observation: &Observation) -> Vec<LightSource> {
+    let Some(sun) = observation.get_body(CelestialBodyType::Sun) else {
+        return Vec::new();
+    };
+    let sun_pos = sun.position.0;
+    let mut sources = vec![LightSource {
+        dir_i: (sun_pos - pos_i).normalize(),
+        intensity: sun_visible_fraction(pos_i, observation),
+    }];
+    for (body_type, albedo) in ALBEDO.iter() {
+        if let Some(body) = observation.get_body(*body_type) {
+            let body_to_sc = pos_i - body.position.0;
+            let distance = body_to_sc.norm();
+            if distance <= 0.0 {
+                continue;
+            }
+            // Reflected light from a Lambertian sphere at the given phase angle
+            // (body is treated as a point source)
+            let phase = (sun_pos - body.position.0).angle(&body_to_sc);
+            let phase_function = (phase.sin() + (std::f64::consts::PI - phase) * phase.cos()) / std::f64::consts::PI;
+            let ratio = (body.radius / distance).min(1.0);
+            sources.push(LightSource {
+                dir_i: -body_to_sc / distance,
+                intensity: albedo * 2.0 / 3.0 * ratio * ratio * phase_function,
+            });
+        }
+    }
+    sources
+}
+
+/// Fraction of the solar disc that is not hidden behind the Moon or the Earth
+pub (crate) fn sun_visible_fraction(pos_i: &Vector3<f64>, observation: &Observation) -> f64 {
+    let Some(sun) = observation.get_body(CelestialBodyType::Sun) else {
+        return 0.0;
+    };
+    let sun_rel = sun.position.0 - pos_i;
+    let sun_distance = sun_rel.norm();
+    let sun_radius = (sun.radius / sun_distance).min(1.0).asin();
+    [CelestialBodyType::Moon, CelestialBodyType::Earth].iter()
+        .filter_map(|body_type| observation.get_body(*body_type))
+        .fold(1.0, |fraction, body| {
+            let body_rel = body.position.0 - pos_i;
+            let body_distance = body_rel.norm();
+            if body_distance >= sun_distance {
+                return fraction;
+            }
+            let body_radius = (body.radius / body_distance).min(1.0).asin();
+            let overlap = disc_overlap_area(sun_radius, body_radius, sun_rel.angle(&body_rel));
+            fraction * (1.0 - overlap / (std::f64::consts::PI * sun_radius * sun_radius)).clamp(0.0, 1.0)
+        })
+}
+
+/// Area of overlap of two discs with radii `r1` and `r2` whose centers are `d` apart
+fn disc_overlap_area(r1: f64, r2: f64, d: f64) -> f64 {
+    if d >= r1 + r2 {
+        0.0
+    } else if d <= (r1 - r2).abs() {
+        std::f64::consts::PI * r1.min(r2).powi(2)
+    } else {
+        let a1 = ((d * d + r1 * r1 - r2 * r2) / (2.0 * d * r1)).clamp(-1.0, 1.0).acos();
+        let a2 = ((d * d + r2 * r2 - r1 * r1) / (2.0 * d * r2)).clamp(-1.0, 1.0).acos();
+        let k = ((-d + r1 + r2) * (d + r1 - r2) * (d - r1 + r2) * (d + r1 + r2)).max(0.0).sqrt();
+        r1 * r1 * a1 + r2 * r2 * a2 - 0.5 * k
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+    use hifitime::Epoch;
+    use crate::test_utils::{create_observation, create_state, moon, sun, AU, MOON_RADIUS, SUN_RADIUS};
+
+    fn create_sun_sensor(sensor_type: &str, fov_deg: f64) -> SunSensor {
+        let config = SunSensorConfig::from_str(&format!(r#"
+            <SunSensor name="A" type="{}">
+                <geometry>
+                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                <fov_deg>{}</fov_deg>
+            </SunSensor>"#, sensor_type, fov_deg)).unwrap();
+        SunSensor::from_config(&config)
+    }
+
+    /// Sun in the given direction and the Moon (if any) at the given position
+    fn sun_moon_observation(sun_dir: Vector3<f64>, moon_pos: Option<Vector3<f64>>) -> Observation {
+        create_observation(Epoch::default(), [sun(sun_dir)].into_iter().chain(moon_pos.map(moon)))
+    }
+
+    /// Spacecraft at the origin with the body frame aligned with the inertial frame
+    fn state() -> SpacecraftDiscreteState {
+        create_state(0.0, Vector3::zeros(), Vector3::zeros(), nalgebra::UnitQuaternion::identity(), Vector3::zeros())
+    }
+
+    #[test]
+    fn test_coarse_sun_sensor() {
+        let mut css = create_sun_sensor("Coarse", 80.0);
+        css.update_discrete(0.0, &state(), &sun_moon_observation(Vector3::new(3f64.sqrt(), 0.0, 1.0), None));
+        assert_abs_diff_eq!(css.get_model_output().intensity, 0.5, epsilon = 1e-6);
+
+        // Sun outside the field of view
+        css.update_discrete(0.0, &state(), &sun_moon_observation(Vector3::new(1.0, 0.0, 0.1), None));
+        assert_abs_diff_eq!(css.get_model_output().intensity, 0.0);
+    }
+
+    #[test]
+    fn test_fine_sun_sensor() {
+        let mut fss = create_sun_sensor("Fine", 30.0);
+        let sun_dir = Vector3::new(0.1, -0.2, 1.0);
+        fss.update_discrete(0.0, &state(), &sun_moon_observation(sun_dir, None));
+        let output = fss.get_model_output();
+        assert!(output.valid);
+        assert_abs_diff_eq!(output.alpha, 0.1f64.atan(), epsilon = 1e-9);
+        assert_abs_diff_eq!(output.beta, (-0.2f64).atan(), epsilon = 1e-9);
+        assert_abs_diff_eq!(output.sun_vec_cf, sun_dir.normalize(), epsilon = 1e-9);
+
+        fss.update_discrete(0.0, &state(), &sun_moon_observation(Vector3::x(), None));
+        assert!(!fss.get_model_output().valid);
+    }
+
+    #[test]
+    fn test_eclipse() {
+        // Spacecraft behind the Moon as seen from the Sun
+        let observation = sun_moon_observation(Vector3::z(), Some(Vector3::new(0.0, 0.0, 2.0 * MOON_RADIUS)));
+        assert_abs_diff_eq!(sun_visible_fraction(&Vector3::zeros(), &observation), 0.0);
+        let mut fss = create_sun_sensor("Fine", 30.0);
+        fss.update_discrete(0.0, &state(), &observation);
+        assert!(!fss.get_model_output().valid);
+
+        // Edge of a much larger (nearby) Moon passing through the center of the Sun
+        let moon_radius = 100.0 * (SUN_RADIUS / AU).asin();
+        let moon_distance = MOON_RADIUS / moon_radius.sin();
+        let moon_dir = Vector3::new(moon_radius.sin(), 0.0, moon_radius.cos());
+        let observation = sun_moon_observation(Vector3::z(), Some(moon_dir * moon_distance));
+        assert_abs_diff_eq!(sun_visible_fraction(&Vector3::zeros(), &observation), 0.5, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn test_albedo() {
+        // Coarse sensor looking down at the fully lit Moon with the Sun behind it
+        let moon_pos = Vector3::new(0.0, 0.0, 2.0 * MOON_RADIUS);
+        let observation = sun_moon_observation(-Vector3::z(), Some(moon_pos));
+        let mut css = create_sun_sensor("Coarse", 80.0);
+        css.update_discrete(0.0, &state(), &observation);
+        assert_abs_diff_eq!(css.get_model_output().intensity, 0.12 * 2.0 / 3.0 * 0.25, epsilon = 1e-6);
+
+        // The half-lit Moon in the field of view pulls the fine sensor away from the Sun
+        let moon_pos = Vector3::new(0.0, -1.0, 1.0).normalize() * 2.0 * MOON_RADIUS;
+        let observation = sun_moon_observation(Vector3::new(0.0, 1.0, 1.0), Some(moon_pos));
+        let mut fss = create_sun_sensor("Fine", 60.0);
+        fss.update_discrete(0.0, &state(), &observation);
+        let output = fss.get_model_output();
+        assert!(output.valid);
+        assert!(output.beta < std::f64::consts::FRAC_PI_4 - 1e-3);
+    }
+}
@@ -1,10 +1,11 @@
-use crate::spacecraft::OrbitalDynamicsInputs;
+use crate::{faults::ActuatorFault, spacecraft::OrbitalDynamicsInputs};
 use surveyor_types::config::ThrusterConfig;
 
 use super::ActuatorModel;
 
 /// Engine types specific to the Surveyor mission
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum VerierRocketCommand {
     Ignite,
     Throttle(f64),
@@ -18,8 +19,13 @@ pub enum VerierRocketCommand {
 #[derive(Debug)]
 pub struct VernierRocket {
     config: ThrusterConfig,
+    /// Whether the engine has been ignited (and not shut down since)
+    ignited: bool,
     /// Current thrust level
     thrust: f64,
+    /// Thrust requested by the last command (after clamping)
+    commanded_thrust: f64,
+    fault: Option<ActuatorFault>,
     /// Thrust vector in the body frame
     thrust_b: nalgebra::Vector3<f64>,
     /// Torque vector in the body frame
@@ -30,7 +36,10 @@ impl VernierRocket {
     pub fn new(config: &ThrusterConfig) -> Self {
         Self {
             config: config.clone(),
+            ignited: false,
             thrust: 0.0,
+            commanded_thrust: 0.0,
+            fault: None,
             thrust_b: nalgebra::Vector3::<f64>::zeros(),
             torque_b: nalgebra::Vector3::<f64>::zeros(),
         }
@@ -40,9 +49,46 @@ impl VernierRocket {
     }
     pub fn handle_commands(&mut self, thrust_value: &f64) {
         // Clamp thrust value to min/max
-        self.thrust = thrust_value
+        self.commanded_thrust = thrust_value
             .max(self.config.min_thrust)
             .min(self.config.max_thrust);
+        self.update_thrust();
+    }
+    /// Ignites, throttles or shuts down the engine. The engine can only be throttled while it is
+    /// running.
+    pub fn handle_command(&mut self, command: &VerierRocketCommand) -> Result<(), String> {
+        match command {
+            VerierRocketCommand::Ignite => {
+                self.ignited = true;
+                self.handle_commands(&self.commanded_thrust.clone());
+            }
+            VerierRocketCommand::Throttle(thrust) if !thrust.is_finite() => {
+                return Err(format!("invalid thrust command {} for engine {}", thrust, self.config.name));
+            }
+            VerierRocketCommand::Throttle(_) if !self.ignited => {
+                return Err(format!("engine {} cannot be throttled before it is ignited", self.config.name));
+            }
+            VerierRocketCommand::Throttle(thrust) => self.handle_commands(thrust),
+            VerierRocketCommand::Shutdown => {
+                self.ignited = false;
+                self.update_thrust();
+            }
+        }
+        Ok(())
+    }
+    pub fn is_ignited(&self) -> bool {
+        self.ignited
+    }
+    /// Thrust requested by the last command (zero while the engine is shut down)
+    pub fn commanded_thrust(&self) -> f64 {
+        if self.ignited { self.commanded_thrust } else { 0.0 }
+    }
+    pub (crate) fn set_fault(&mut self, fault: Option<ActuatorFault>) {
+        self.fault = fault;
+        self.update_thrust();
+    }
+    fn update_thrust(&mut self) {
+        self.thrust = ActuatorFault::apply_to_command(self.fault.as_ref(), self.commanded_thrust(), self.config.max_thrust);
     }
     pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
         // Return a "DynamicOutput" struct that then gets added to the OrbitalDynamicsInputs in simulation.rs
@@ -65,7 +111,7 @@ impl<'a> ActuatorModel<'a> for VernierRocket {
 
     // This will be triggered by the FSW with the desired deflection angle
     fn handle_commands(&'a mut self, command: &Self::Command) {
-        self.thrust = *command;
+        VernierRocket::handle_commands(self, command);
     }
     fn update_continuous(&'a mut self, _dt: f64, inputs: &Self::ContinuousInputs) {
         // Thrust is aligned with "z" axis of the component frame, we rotate it to the spacecraft frame
@@ -102,6 +148,7 @@ mod tests {
 
     fn create_test_engine() -> VernierRocket {
         let config = ThrusterConfig {
+            name: "A".to_string(),
             min_thrust: 0.0,
             max_thrust: 100.0,
             geometry: GeometryParams::from_str(
@@ -117,6 +164,7 @@ mod tests {
     }
     fn create_test_engine_aligned_with_x() -> VernierRocket {
         let config = ThrusterConfig {
+            name: "A".to_string(),
             min_thrust: 0.0,
             max_thrust: 100.0,
             geometry: GeometryParams::from_str(
@@ -148,6 +196,7 @@ mod tests {
     fn test_min_thrust() {
         let mut engine = create_test_engine();
         let inputs = VernierRocketContinuousInputs::default();
+        engine.handle_command(&VerierRocketCommand::Ignite).unwrap();
         engine.handle_commands(&10.0);
         engine.update_continuous(0.0, &inputs);
         assert_abs_diff_eq!(
@@ -159,6 +208,7 @@ mod tests {
 
         let mut engine = create_test_engine_aligned_with_x();
         let inputs = VernierRocketContinuousInputs::default();
+        engine.handle_command(&VerierRocketCommand::Ignite).unwrap();
         engine.handle_commands(&10.0);
         engine.update_continuous(0.0, &inputs);
         assert_abs_diff_eq!(
@@ -175,6 +225,7 @@ mod tests {
         let inputs = VernierRocketContinuousInputs {
             q_tvc2nozzle: Some(nalgebra::UnitQuaternion::from_euler_angles(0.0, 0.0, 0.0)),
         };
+        engine.handle_command(&VerierRocketCommand::Ignite).unwrap();
         engine.handle_commands(&10.0);
         engine.update_continuous(0.0, &inputs);
         assert_eq!(engine.thrust_b, Vector3::new(0.0, 0.0, 10.0));
@@ -198,4 +249,28 @@ mod tests {
         );
         assert_abs_diff_eq!(engine.thrust_b, expected_thrust_b);
     }
+    #[test]
+    fn test_ignition() {
+        let mut engine = create_test_engine();
+        let inputs = VernierRocketContinuousInputs::default();
+        // Throttle commands are rejected until the engine is ignited
+        assert!(engine.handle_command(&VerierRocketCommand::Throttle(10.0)).is_err());
+        engine.update_continuous(0.0, &inputs);
+        assert_eq!(engine.thrust_b, Vector3::zeros());
+
+        engine.handle_command(&VerierRocketCommand::Ignite).unwrap();
+        engine.handle_command(&VerierRocketCommand::Throttle(20.0)).unwrap();
+        assert!(engine.handle_command(&VerierRocketCommand::Throttle(f64::NAN)).is_err());
+        engine.update_continuous(0.0, &inputs);
+        assert_abs_diff_eq!(engine.thrust_b, Vector3::new(0.0, 0.0, 20.0), epsilon = 1e-10);
+        assert_eq!(engine.commanded_thrust(), 20.0);
+
+        engine.handle_command(&VerierRocketCommand::Shutdown).unwrap();
+        engine.update_continuous(0.0, &inputs);
+        assert_eq!(engine.thrust_b, Vector3::zeros());
+        assert!(!engine.is_ignited());
+        // The engine restarts at the last commanded thrust
+        engine.handle_command(&VerierRocketCommand::Ignite).unwrap();
+        assert_eq!(engine.commanded_thrust(), 20.0);
+    }
 }
@@ -9,6 +9,8 @@ pub struct TVC {
     pub config: TVCConfig,
     // Current angle of deflection of the X servo
     deflection: f64,
+    // Commands are ignored while the servo is stuck
+    stuck: bool,
     // Outputs
     outputs: TVCContinuousOutputs,
 }
@@ -18,9 +20,22 @@ impl TVC {
         Self {
             config: config.clone(),
             deflection: 0.0f64.to_radians(),
+            stuck: false,
             outputs: TVCContinuousOutputs::default(),
         }
     }
+    /// Current deflection of the servo in radians
+    pub fn deflection(&self) -> f64 {
+        self.deflection
+    }
+    /// Sticks the servo at the given deflection (or the current one if None). Frees the servo
+    /// at the current deflection if `stuck` is false.
+    pub fn set_stuck(&mut self, stuck: bool, deflection: Option<f64>) {
+        self.stuck = stuck;
+        if let Some(deflection) = deflection.filter(|_| stuck) {
+            self.deflection = deflection;
+        }
+    }
 }
 #[derive(Debug, Default)]
 pub struct TVCContinuousOutputs {
@@ -37,7 +52,9 @@ impl<'a> ActuatorModel<'a> for TVC {
 
     // This will be triggered by the FSW with the desired deflection angle
     fn handle_commands(&'a mut self, command: &Self::Command) {
-        self.deflection = *command;
+        if !self.stuck {
+            self.deflection = *command;
+        }
     }
     fn update_continuous(&'a mut self, _dt: f64, _inputs: &Self::ContinuousInputs) {
         // Compute the rotation from the TVC nominal direction to the nozzle direction
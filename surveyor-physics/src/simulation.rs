@@ -61,6 +61,10 @@ impl SimulationParams {
     pub fn get_update_period_secs(&self) -> f64 {
         self.dt / self.config.time_acceleration
     }
+    /// Simulation time between GNC updates in seconds
+    pub fn get_gnc_update_period_secs(&self) -> f64 {
+        self.dt * self.num_steps_per_gnc_update as f64
+    }
     pub fn set_multiplier(&mut self, multiplier: f64) {
         self.config.time_acceleration = multiplier;
     }
@@ -33,6 +33,8 @@ impl ContinuousSystemState {
 
 
 
+use crate::faults::SubsystemFaults;
+use crate::interfaces::SensorSampler;
 use crate::subsystems::Subsystem;
 use crate::universe::{Universe, Observation};
 use crate::{
@@ -77,11 +79,22 @@ pub struct OrbitalDynamics {
     pub prev_state: SVector<f64, 13>,
     pub start_time: Epoch,
     pub time: Duration,
+    /// Non-gravitational acceleration of the center-of-mass in body frame at the current state
+    pub specific_force_b: SVector<f64, 3>,
+    /// Angular acceleration in body frame at the current state
+    pub omega_dot_b: SVector<f64, 3>,
 }
 impl OrbitalDynamics {
     pub fn new(t: f64, state: SVector<f64, 13>) -> Self {
         let start_time = hifitime::UNIX_REF_EPOCH;
-        Self { state, start_time, prev_state: SVector::<f64, 13>::zeros(), time: Duration::from_seconds(t) }
+        Self {
+            state,
+            start_time,
+            prev_state: SVector::<f64, 13>::zeros(),
+            time: Duration::from_seconds(t),
+            specific_force_b: SVector::<f64, 3>::zeros(),
+            omega_dot_b: SVector::<f64, 3>::zeros(),
+        }
     }
     pub fn from_initial_state(initial_state: &InitialState) -> Self {
 
@@ -106,6 +119,8 @@ impl OrbitalDynamics {
             prev_state: SVector::<f64, 13>::zeros(),
             time: Duration::from_seconds(0.0),
             start_time: sim_time,
+            specific_force_b: SVector::<f64, 3>::zeros(),
+            omega_dot_b: SVector::<f64, 3>::zeros(),
         }
     }
     pub fn get_t(&self) -> f64 {
@@ -140,12 +155,16 @@ impl OrbitalDynamics {
         let mut dx = new_state.fixed_rows_mut::<3>(0);
         dx.copy_from(&v);
 
+        // Quaternion is in the order [w, x, y, z]
+        let q = self.state.fixed_rows::<4>(6);
+
+        // Forces from the actuators act in the body frame while gravity acts in the inertial frame
+        let q_i2b = na::UnitQuaternion::from_quaternion(na::Quaternion::new(q[0], q[1], q[2], q[3]));
+        let total_force_i = orbital_dynamics_inputs.gravity_force_i + q_i2b * orbital_dynamics_inputs.total_force_b;
         let mut dv = new_state.fixed_rows_mut::<3>(3);
-        dv.copy_from(&(orbital_dynamics_inputs.total_force_b / sc.mass));
+        dv.copy_from(&(total_force_i / sc.mass));
 
         // Rotational dynamics
-        // Quaternion is in the order [w, x, y, z]
-        let q = self.state.fixed_rows::<4>(6);
         let w = self.state.fixed_rows::<3>(10);
 
         let mut q_dot = new_state.fixed_rows_mut::<4>(6);
@@ -154,7 +173,8 @@ impl OrbitalDynamics {
         q_dot[2] = 0.5 * (q[0] * w[1] - q[1] * w[2] + q[3] * w[0]);
         q_dot[3] = 0.5 * (q[0] * w[2] + q[1] * w[1] - q[2] * w[0]);
 
-        let h = sc.inertia * w;
+        // Include momentum stored in internal actuators (e.g. reaction wheels) in the gyroscopic term
+        let h = sc.inertia * w + orbital_dynamics_inputs.internal_momentum_b;
         let dwdt = sc.inertia_inv * (orbital_dynamics_inputs.total_torque_b - w.cross(&h));
 
         let mut w_dot = new_state.fixed_rows_mut::<3>(10);
@@ -170,15 +190,22 @@ impl OrbitalDynamics {
 // This includes the total force and torque acting on the spacecraft center-of-mass
 #[derive(Component, Debug, Clone)]
 pub struct OrbitalDynamicsInputs {
+    /// Gravitational force in the inertial frame
+    pub gravity_force_i: SVector<f64, 3>,
+    /// Non-gravitational force (thrust, contact forces etc.) in the body frame
     pub total_force_b: SVector<f64, 3>,
     pub total_torque_b: SVector<f64, 3>,
+    /// Angular momentum stored inside the spacecraft (e.g. in reaction wheels)
+    pub internal_momentum_b: SVector<f64, 3>,
 }
 
 impl Default for OrbitalDynamicsInputs {
     fn default() -> Self {
         Self {
+            gravity_force_i: SVector::<f64, 3>::zeros(),
             total_force_b: SVector::<f64, 3>::zeros(),
             total_torque_b: SVector::<f64, 3>::zeros(),
+            internal_momentum_b: SVector::<f64, 3>::zeros(),
         }
     }
 }
@@ -235,17 +262,29 @@ impl<'a> DynamicSystem<'a> for OrbitalDynamics {
 }
 
 /// Struct used to pass spacecraft state information to subsystems without copying
+#[derive(Clone)]
 pub struct SpacecraftDiscreteState {
     pub time: f64,
     pub state: SVector<f64, 13>,
+    /// Non-gravitational acceleration of the center-of-mass in body frame
+    pub specific_force_b: na::Vector3<f64>,
+    /// Angular acceleration in body frame
+    pub omega_dot_b: na::Vector3<f64>,
 }
 impl SpacecraftDiscreteState {
     pub fn new(time: f64, state: &SVector<f64, 13>) -> Self {
         Self {
             time,
             state: state.clone(),
+            specific_force_b: na::Vector3::zeros(),
+            omega_dot_b: na::Vector3::zeros(),
         }
     }
+    pub fn with_accelerations(mut self, specific_force_b: na::Vector3<f64>, omega_dot_b: na::Vector3<f64>) -> Self {
+        self.specific_force_b = specific_force_b;
+        self.omega_dot_b = omega_dot_b;
+        self
+    }
     pub fn pos(&self) -> Vector3 {
         Vector3::from_column_slice(&self.state.as_slice()[0..3])
     }
@@ -268,6 +307,10 @@ impl SpacecraftDiscreteState {
 #[derive(Event)]
 pub struct DiscreteUpdateEvent(pub SpacecraftDiscreteState, pub Observation);
 
+/// Sent at every simulation step so that each sensor subsystem can be sampled at its own rate
+#[derive(Event)]
+pub struct SensorSampleEvent(pub SpacecraftDiscreteState, pub Observation);
+
 
 // Have a startup system that initializes all the continuous systems
 // Queries for the size of all continuous systems and creates a new strate vector component that
@@ -288,30 +331,43 @@ pub fn build_spacecraft_entity(commands: &mut Commands, config: &Config, initial
     // commands.entity(spacecraft_ent).push_children(&[orbital_dynamics]);
     commands.entity(spacecraft_ent).with_children(|parent| {
         config.spacecraft.subsystems.iter().for_each(|subsystem_config| {
-            parent.spawn((Subsystem::from_config(subsystem_config), Name::new(subsystem_config.to_string())));
+            let mut subsystem = parent.spawn((
+                Subsystem::from_config(subsystem_config),
+                Name::new(subsystem_config.to_string()),
+                SubsystemFaults::new(subsystem_config.component_names()),
+            ));
+            if let Some(timing) = subsystem_config.sensor_timing() {
+                subsystem.insert(SensorSampler::from_config(&timing));
+            }
         });
     });
 }
 
-/// Computes the derivatives of the spacecraft state from the current state and inputs
-/// and all the subsystems
-fn dydt(_t: f64, state: &[f64], universe: &Universe, subsystems: &[&Subsystem], orb: &mut OrbitalDynamics, sc_props: &SpacecraftProperties) -> DVector<f64>
+/// Collects the forces and torques acting on the spacecraft from gravity and all the subsystems
+fn compute_dynamics_inputs(universe: &Universe, subsystems: &[&Subsystem], orb: &OrbitalDynamics, sc_props: &SpacecraftProperties) -> OrbitalDynamicsInputs
 {
-    // None of the subsystems have internal continuous states for now
-    let mut d_state = DVector::zeros(13);
-
-    // First call update_dynamics on all subsystems
     let mut orbital_dynamics_input = OrbitalDynamicsInputs::default();
     {
         let r = SVectorView::from_slice(orb.get_position());
         // Apply all gravity model forces to external force
-        orbital_dynamics_input.total_torque_b.fill(0.0);
-        orbital_dynamics_input.total_force_b = universe.compute_force(&r, &sc_props);
+        orbital_dynamics_input.gravity_force_i = universe.compute_force(&r, &sc_props);
     }
 
     for subsystem in subsystems.iter() {
         subsystem.update_dynamics(&mut orbital_dynamics_input);
     }
+    orbital_dynamics_input
+}
+
+/// Computes the derivatives of the spacecraft state from the current state and inputs
+/// and all the subsystems
+fn dydt(_t: f64, state: &[f64], universe: &Universe, subsystems: &[&Subsystem], orb: &mut OrbitalDynamics, sc_props: &SpacecraftProperties) -> DVector<f64>
+{
+    // Subsystem states (e.g. wheel speeds) are stepped separately using `update_continuous`
+    let mut d_state = DVector::zeros(13);
+
+    // First call update_dynamics on all subsystems
+    let orbital_dynamics_input = compute_dynamics_inputs(universe, subsystems, orb, sc_props);
 
     let deriv_inputs = (sc_props, &orbital_dynamics_input);
     orb.get_derivatives(0.0, &state, d_state.as_mut_slice(), &deriv_inputs);
@@ -324,10 +380,11 @@ use crate::simulation::{SimulationParams, SimulationResults, SimClock};
 pub (crate) fn step_spacecraft_model<'a>(
     mut q_universe: Query<&mut Universe>,
     mut q_spacecrafts: Query<(&mut SpacecraftModel, &SimulationTime, &SpacecraftProperties, &mut OrbitalDynamics, &mut SimulationResults, &Children)>,
-    q_subsystems: Query<&mut Subsystem>,
+    mut q_subsystems: Query<&mut Subsystem>,
     q_sim_clock: Query<&SimClock>,
     sim_params: Res<SimulationParams>,
-    mut discrete_update_event: EventWriter<DiscreteUpdateEvent>)
+    mut discrete_update_event: EventWriter<DiscreteUpdateEvent>,
+    mut sensor_sample_event: EventWriter<SensorSampleEvent>)
 {
     let dt = sim_params.dt;
 
@@ -337,18 +394,32 @@ pub (crate) fn step_spacecraft_model<'a>(
     for (_, t, sc_props, mut orb, mut results, children) in q_spacecrafts.iter_mut() {
         let t = t.get_monotonic_time();
 
-        // Iterate over all subsystems
-        let subsystems = children.iter().map(|child| q_subsystems.get(*child).unwrap()).collect::<Vec<_>>();
+        {
+            // Iterate over all subsystems
+            let subsystems = children.iter().map(|child| q_subsystems.get(*child).unwrap()).collect::<Vec<_>>();
+
+            // Call RK4 over all subsystems and orbital dynamics
+            let state = DVector::from_column_slice(orb.get_state());
+            let k1 = dydt(t, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
+            let k2 = dydt(t+dt/2.0, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
+            let k3 = dydt(t+dt/2.0, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
+            let k4 = dydt(t+dt, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
+            let new_state = state + (k1 + 2.0 * k2 + 2.0 * k3 + k4) * dt / 6.0;
+            orb.set_state(t + dt, new_state.as_slice());
+
+            // Accelerations at the new state are needed by the accelerometers
+            let orbital_dynamics_input = compute_dynamics_inputs(&universe, &subsystems, &orb, &sc_props);
+            let d_state = orb.dynamics(t + dt, (sc_props, &orbital_dynamics_input));
+            orb.specific_force_b = orbital_dynamics_input.total_force_b / sc_props.mass;
+            orb.omega_dot_b = d_state.fixed_rows::<3>(10).into();
+        }
 
-        // TODO: Also call update_continuous on all subsystems each with its own state
-        // Call RK4 over all subsystems and orbital dynamics
-        let state = DVector::from_column_slice(orb.get_state());
-        let k1 = dydt(t, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
-        let k2 = dydt(t+dt/2.0, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
-        let k3 = dydt(t+dt/2.0, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
-        let k4 = dydt(t+dt, state.as_slice(), &universe, &subsystems, &mut orb, &sc_props);
-        let new_state = state + (k1 + 2.0 * k2 + 2.0 * k3 + k4) * dt / 6.0;
-        orb.set_state(t + dt, new_state.as_slice());
+        // Step the states internal to each subsystem (e.g. wheel speeds) over the same timestep
+        for child in children.iter() {
+            if let Ok(mut subsystem) = q_subsystems.get_mut(*child) {
+                subsystem.update_continuous(dt);
+            }
+        }
 
         // TODO: Move to a separate logging system. Convert the "new state" into an event
         results
@@ -356,14 +427,18 @@ pub (crate) fn step_spacecraft_model<'a>(
             .push(orb.clone());
     }
 
+    // Sensors decide for themselves whether they are due to be sampled at this step.
     // If we have a discrete update, send it as an event
     let sim_clock = q_sim_clock.single();
     let observation = universe.observe();
-    if sim_clock.num_steps % sim_params.num_steps_per_gnc_update == 0 {
-        for (_, t, _, orb, _, _) in q_spacecrafts.iter_mut() {
-            let t = t.get_monotonic_time();
-            let spacecraft_discrete_state =
-                SpacecraftDiscreteState::new(t, &orb.state);
+    let is_gnc_update = sim_clock.num_steps % sim_params.num_steps_per_gnc_update == 0;
+    for (_, t, _, orb, _, _) in q_spacecrafts.iter_mut() {
+        let t = t.get_monotonic_time();
+        let spacecraft_discrete_state =
+            SpacecraftDiscreteState::new(t, &orb.state)
+                .with_accelerations(orb.specific_force_b, orb.omega_dot_b);
+        sensor_sample_event.send(SensorSampleEvent(spacecraft_discrete_state.clone(), observation.clone()));
+        if is_gnc_update {
             // Send event
             discrete_update_event.send(DiscreteUpdateEvent(spacecraft_discrete_state, observation.clone()));
         }
@@ -372,8 +447,9 @@ pub (crate) fn step_spacecraft_model<'a>(
 
 // System that updates the discrete state of all subsystems
 // Will be called only when a discrete update event is received (which is every num_steps_per_gnc_update)
+// Sensors are sampled separately (see `interfaces::sample_sensors_from_event`)
 pub (crate) fn do_discrete_update_from_event(mut discrete_update_event: EventReader<DiscreteUpdateEvent>,
-    mut q_subsystems: Query<&mut Subsystem>)
+    mut q_subsystems: Query<&mut Subsystem, Without<SensorSampler>>)
 {
     for discrete_update in discrete_update_event.read() {
         for subsystem in q_subsystems.iter_mut() {
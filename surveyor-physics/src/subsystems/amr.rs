@@ -0,0 +1,25 @@
+use surveyor_types::config::AmrSubsystemConfig;
+use crate::{models::amr::AltitudeMarkingRadar, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct AmrSubsystem {
+    pub amrs: Vec<AltitudeMarkingRadar>,
+}
+
+impl AmrSubsystem {
+    pub fn from_config(config: &AmrSubsystemConfig) -> Self {
+        let mut amrs = Vec::new();
+        for amr_config in &config.sensors {
+            amrs.push(AltitudeMarkingRadar::from_config(amr_config));
+        }
+        Self { amrs }
+    }
+    pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for amr in self.amrs.iter_mut() {
+            amr.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
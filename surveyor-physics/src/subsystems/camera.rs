@@ -0,0 +1,25 @@
+use surveyor_types::config::CameraSubsystemConfig;
+use crate::{models::camera::Camera, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct CameraSubsystem {
+    pub cameras: Vec<Camera>,
+}
+
+impl CameraSubsystem {
+    pub fn from_config(config: &CameraSubsystemConfig) -> Self {
+        let mut cameras = Vec::new();
+        for camera_config in &config.cameras {
+            cameras.push(Camera::from_config(camera_config));
+        }
+        Self { cameras }
+    }
+    pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for camera in self.cameras.iter_mut() {
+            camera.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
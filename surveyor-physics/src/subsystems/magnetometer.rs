@@ -0,0 +1,25 @@
+use surveyor_types::config::MagnetometerSubsystemConfig;
+use crate::{models::magnetometer::Magnetometer, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct MagnetometerSubsystem {
+    pub magnetometers: Vec<Magnetometer>,
+}
+
+impl MagnetometerSubsystem {
+    pub fn from_config(config: &MagnetometerSubsystemConfig) -> Self {
+        let mut magnetometers = Vec::new();
+        for magnetometer_config in &config.sensors {
+            magnetometers.push(Magnetometer::from_config(magnetometer_config));
+        }
+        Self { magnetometers }
+    }
+    pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for magnetometer in self.magnetometers.iter_mut() {
+            magnetometer.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
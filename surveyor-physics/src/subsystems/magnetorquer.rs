@@ -0,0 +1,45 @@
+use surveyor_types::config::MagnetorquerSubsystemConfig;
+use crate::{faults::ActuatorFault, models::magnetorquer::Magnetorquer, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct MagnetorquerSubsystem {
+    pub torquers: Vec<Magnetorquer>,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct MagnetorquerCommands {
+    /// Dipole moment for each magnetorquer in A-m^2
+    pub dipoles: Vec<f64>,
+}
+
+impl MagnetorquerSubsystem {
+    pub fn from_config(config: &MagnetorquerSubsystemConfig) -> Self {
+        let mut torquers = Vec::new();
+        for torquer_config in &config.torquers {
+            torquers.push(Magnetorquer::from_config(torquer_config));
+        }
+        Self { torquers }
+    }
+    pub fn handle_commands(&mut self, commands: &MagnetorquerCommands) {
+        for (torquer, dipole) in self.torquers.iter_mut().zip(commands.dipoles.iter()) {
+            torquer.handle_commands(*dipole);
+        }
+    }
+    /// Injects (or clears) a fault in the magnetorquer at the given index
+    pub fn set_fault(&mut self, torquer: usize, fault: ActuatorFault, active: bool) -> Result<(), String> {
+        self.torquers.get_mut(torquer)
+            .ok_or_else(|| format!("No magnetorquer at index {}", torquer))?
+            .set_fault(Some(fault).filter(|_| active))
+    }
+    pub fn update_dynamics(&self, outputs: &mut super::OrbitalDynamicsInputs) {
+        for torquer in &self.torquers {
+            torquer.update_dynamics(outputs);
+        }
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for torquer in self.torquers.iter_mut() {
+            torquer.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
@@ -2,6 +2,7 @@ use surveyor_types::config::SubsystemConfig;
 use enum_as_inner::EnumAsInner;
 
 use crate::{
+    faults::ActuatorFault,
     integrators::DynamicSystem,
     spacecraft::{OrbitalDynamicsInputs, SpacecraftDiscreteState}, universe::Observation
 };
@@ -12,6 +13,13 @@ pub mod rcs;
 pub mod imu;
 pub mod star_tracker;
 pub mod star_sensor;
+pub mod reaction_wheel;
+pub mod magnetometer;
+pub mod magnetorquer;
+pub mod sun_sensor;
+pub mod amr;
+pub mod radvs;
+pub mod camera;
 
 #[derive(Debug, EnumAsInner, Component, EnumFilter)]
 pub (crate) enum Subsystem {
@@ -20,6 +28,13 @@ pub (crate) enum Subsystem {
     Imu(imu::IMUSubsystem),
     StarTracker(star_tracker::StarTrackerSubsystem),
     StarSensor(star_sensor::StarSensorSubsystem),
+    ReactionWheel(reaction_wheel::ReactionWheelSubsystem),
+    Magnetometer(magnetometer::MagnetometerSubsystem),
+    Magnetorquer(magnetorquer::MagnetorquerSubsystem),
+    SunSensor(sun_sensor::SunSensorSubsystem),
+    Amr(amr::AmrSubsystem),
+    Radvs(radvs::RadvsSubsystem),
+    Camera(camera::CameraSubsystem),
 }
 
 impl Subsystem {
@@ -40,9 +55,45 @@ impl Subsystem {
             SubsystemConfig::StarSensor(star_sensor_subsystem_config) => {
                 Subsystem::StarSensor(star_sensor::StarSensorSubsystem::from_config(star_sensor_subsystem_config))
             },
+            SubsystemConfig::ReactionWheel(reaction_wheel_subsystem_config) => {
+                Subsystem::ReactionWheel(reaction_wheel::ReactionWheelSubsystem::from_config(reaction_wheel_subsystem_config))
+            },
+            SubsystemConfig::Magnetometer(magnetometer_subsystem_config) => {
+                Subsystem::Magnetometer(magnetometer::MagnetometerSubsystem::from_config(magnetometer_subsystem_config))
+            },
+            SubsystemConfig::Magnetorquer(magnetorquer_subsystem_config) => {
+                Subsystem::Magnetorquer(magnetorquer::MagnetorquerSubsystem::from_config(magnetorquer_subsystem_config))
+            },
+            SubsystemConfig::SunSensor(sun_sensor_subsystem_config) => {
+                Subsystem::SunSensor(sun_sensor::SunSensorSubsystem::from_config(sun_sensor_subsystem_config))
+            },
+            SubsystemConfig::Amr(amr_subsystem_config) => {
+                Subsystem::Amr(amr::AmrSubsystem::from_config(amr_subsystem_config))
+            },
+            SubsystemConfig::Radvs(radvs_subsystem_config) => {
+                Subsystem::Radvs(radvs::RadvsSubsystem::from_config(radvs_subsystem_config))
+            },
+            SubsystemConfig::Camera(camera_subsystem_config) => {
+                Subsystem::Camera(camera::CameraSubsystem::from_config(camera_subsystem_config))
+            },
             // _ => panic!("Invalid subsystem config"),
         }
     }
+    /// Whether the subsystem is made up of sensors (as opposed to actuators)
+    pub fn is_sensor(&self) -> bool {
+        matches!(self, Subsystem::Imu(_) | Subsystem::StarTracker(_) | Subsystem::StarSensor(_) | Subsystem::Magnetometer(_)
+            | Subsystem::SunSensor(_) | Subsystem::Amr(_) | Subsystem::Radvs(_) | Subsystem::Camera(_))
+    }
+    /// Injects (or clears) a fault in the actuator at the given index
+    pub fn set_actuator_fault(&mut self, actuator: usize, fault: ActuatorFault, active: bool) -> Result<(), String> {
+        match self {
+            Subsystem::Propulsion(engine_subsystem) => engine_subsystem.set_fault(actuator, fault, active),
+            Subsystem::Rcs(rcs_subsystem) => rcs_subsystem.set_fault(actuator, fault, active),
+            Subsystem::ReactionWheel(reaction_wheel_subsystem) => reaction_wheel_subsystem.set_fault(actuator, fault, active),
+            Subsystem::Magnetorquer(magnetorquer_subsystem) => magnetorquer_subsystem.set_fault(actuator, fault, active),
+            _ => Err("actuator faults cannot be applied to sensors".to_string()),
+        }
+    }
     // Represents a collection of models that make up a subsystem
     pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
         match self {
@@ -57,11 +108,32 @@ impl Subsystem {
                 imu_subsystem.update_discrete(dt, discrete_state);
             }
             Subsystem::StarTracker(star_tracker_subsystem) => {
-                star_tracker_subsystem.update_discrete(dt, discrete_state);
+                star_tracker_subsystem.update_discrete(dt, discrete_state, observation);
             }
             Subsystem::StarSensor(star_sensor_subsystem) => {
                 star_sensor_subsystem.update_discrete(dt, discrete_state);
             }
+            Subsystem::ReactionWheel(reaction_wheel_subsystem) => {
+                reaction_wheel_subsystem.update_discrete(dt, discrete_state);
+            }
+            Subsystem::Magnetometer(magnetometer_subsystem) => {
+                magnetometer_subsystem.update_discrete(dt, discrete_state, observation);
+            }
+            Subsystem::Magnetorquer(magnetorquer_subsystem) => {
+                magnetorquer_subsystem.update_discrete(dt, discrete_state, observation);
+            }
+            Subsystem::SunSensor(sun_sensor_subsystem) => {
+                sun_sensor_subsystem.update_discrete(dt, discrete_state, observation);
+            }
+            Subsystem::Amr(amr_subsystem) => {
+                amr_subsystem.update_discrete(dt, discrete_state, observation);
+            }
+            Subsystem::Radvs(radvs_subsystem) => {
+                radvs_subsystem.update_discrete(dt, discrete_state, observation);
+            }
+            Subsystem::Camera(camera_subsystem) => {
+                camera_subsystem.update_discrete(dt, discrete_state, observation);
+            }
         }
     }
     pub fn update_continuous(&mut self, dt: f64) {
@@ -79,6 +151,27 @@ impl Subsystem {
             Subsystem::StarSensor(star_sensor_subsystem) => {
                 star_sensor_subsystem.update_continuous(dt);
             }
+            Subsystem::ReactionWheel(reaction_wheel_subsystem) => {
+                reaction_wheel_subsystem.update_continuous(dt);
+            }
+            Subsystem::Magnetometer(magnetometer_subsystem) => {
+                magnetometer_subsystem.update_continuous(dt);
+            }
+            Subsystem::Magnetorquer(magnetorquer_subsystem) => {
+                magnetorquer_subsystem.update_continuous(dt);
+            }
+            Subsystem::SunSensor(sun_sensor_subsystem) => {
+                sun_sensor_subsystem.update_continuous(dt);
+            }
+            Subsystem::Amr(amr_subsystem) => {
+                amr_subsystem.update_continuous(dt);
+            }
+            Subsystem::Radvs(radvs_subsystem) => {
+                radvs_subsystem.update_continuous(dt);
+            }
+            Subsystem::Camera(camera_subsystem) => {
+                camera_subsystem.update_continuous(dt);
+            }
         }
     }
     pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
@@ -96,6 +189,27 @@ impl Subsystem {
             Subsystem::StarSensor(star_sensor_subsystem) => {
                 star_sensor_subsystem.update_dynamics(outputs);
             }
+            Subsystem::ReactionWheel(reaction_wheel_subsystem) => {
+                reaction_wheel_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::Magnetometer(magnetometer_subsystem) => {
+                magnetometer_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::Magnetorquer(magnetorquer_subsystem) => {
+                magnetorquer_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::SunSensor(sun_sensor_subsystem) => {
+                sun_sensor_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::Amr(amr_subsystem) => {
+                amr_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::Radvs(radvs_subsystem) => {
+                radvs_subsystem.update_dynamics(outputs);
+            }
+            Subsystem::Camera(camera_subsystem) => {
+                camera_subsystem.update_dynamics(outputs);
+            }
         }
     }
 }
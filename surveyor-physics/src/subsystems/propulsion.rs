@@ -1,11 +1,12 @@
 use bevy_ecs::prelude::*;
 
 use surveyor_types::config::EngineSubsystemConfig;
+use crate::faults::ActuatorFault;
 use crate::spacecraft::SpacecraftDiscreteState;
 use crate::{
     integrators::DynamicSystem,
     models::{
-        surveyor_engines::{VernierRocket, VernierRocketContinuousInputs},
+        surveyor_engines::{VernierRocket, VernierRocketContinuousInputs, VerierRocketCommand},
         ActuatorModel, tvc::TVC,
     },
     spacecraft::OrbitalDynamicsInputs,
@@ -17,6 +18,8 @@ pub struct SurveyorPropulsion {
     vernier_b: VernierRocket,
     vernier_c: VernierRocket,
     tvc_a: TVC,
+    /// Whether the last set of engine commands passed validation
+    last_command_accepted: bool,
 }
 
 impl SurveyorPropulsion {
@@ -31,15 +34,27 @@ impl SurveyorPropulsion {
             vernier_b,
             vernier_c,
             tvc_a,
+            last_command_accepted: true,
         }
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct EngineCommands {
-    // This command sets the throttle for the vernier thrusters
-    pub vernier_thrust_a: f64,
-    pub vernier_thrust_b: f64,
-    pub vernier_thrust_c: f64,
+    /// Ignites the engines if they are not running, or shuts them down if false
+    pub ignition: bool,
+    // This command sets the throttle for the vernier thrusters (a..c)
+    pub vernier_thrusts: Vec<f64>,
+    /// TVC deflection of each vernier engine in radians. Only vernier engine A has a TVC.
+    pub tvc_deflections: Vec<f64>,
+}
+
+/// Status of a vernier engine that is echoed back to FSW
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineStatus {
+    pub ignited: bool,
+    pub commanded_thrust: f64,
+    pub tvc_deflection: f64,
 }
 
 impl SurveyorPropulsion {
@@ -61,10 +76,62 @@ impl SurveyorPropulsion {
         self.vernier_b.update_continuous(dt, &vernier_inputs);
         self.vernier_c.update_continuous(dt, &vernier_inputs);
     }
-    pub fn handle_commands(&mut self, commands: &EngineCommands) {
-        self.vernier_a.handle_commands(&commands.vernier_thrust_a);
-        self.vernier_b.handle_commands(&commands.vernier_thrust_b);
-        self.vernier_c.handle_commands(&commands.vernier_thrust_c);
+    /// Applies the engine commands if they are valid. Invalid commands are rejected as a whole
+    /// and the engines keep their current settings.
+    pub fn handle_commands(&mut self, commands: &EngineCommands) -> Result<(), String> {
+        let result = self.validate_commands(commands);
+        self.last_command_accepted = result.is_ok();
+        result?;
+        let ignition = if commands.ignition { VerierRocketCommand::Ignite } else { VerierRocketCommand::Shutdown };
+        for (engine, thrust) in [&mut self.vernier_a, &mut self.vernier_b, &mut self.vernier_c].into_iter().zip(&commands.vernier_thrusts) {
+            engine.handle_command(&ignition)?;
+            if commands.ignition {
+                engine.handle_command(&VerierRocketCommand::Throttle(*thrust))?;
+            }
+        }
+        self.tvc_a.handle_commands(&commands.tvc_deflections[0]);
+        Ok(())
+    }
+    fn validate_commands(&self, commands: &EngineCommands) -> Result<(), String> {
+        if commands.vernier_thrusts.len() != 3 || commands.tvc_deflections.len() != 3 {
+            return Err(format!("Expected commands for 3 vernier engines, got {} thrusts and {} TVC deflections",
+                commands.vernier_thrusts.len(), commands.tvc_deflections.len()));
+        }
+        if let Some(thrust) = commands.vernier_thrusts.iter().find(|thrust| !thrust.is_finite() || **thrust < 0.0) {
+            return Err(format!("Invalid vernier thrust command {}", thrust));
+        }
+        if commands.tvc_deflections[1..].iter().any(|deflection| *deflection != 0.0) {
+            return Err("Only vernier engine A has a TVC".to_string());
+        }
+        let deflection = commands.tvc_deflections[0];
+        if !deflection.is_finite() || deflection.abs() > self.tvc_a.config.max_deflection.to_radians() {
+            return Err(format!("TVC deflection of {} deg exceeds the limit of {} deg", deflection.to_degrees(), self.tvc_a.config.max_deflection));
+        }
+        Ok(())
+    }
+    pub fn last_command_accepted(&self) -> bool {
+        self.last_command_accepted
+    }
+    /// Status of each vernier engine (a..c)
+    pub fn engine_status(&self) -> Vec<EngineStatus> {
+        [(&self.vernier_a, Some(&self.tvc_a)), (&self.vernier_b, None), (&self.vernier_c, None)].iter()
+            .map(|(engine, tvc)| EngineStatus {
+                ignited: engine.is_ignited(),
+                commanded_thrust: engine.commanded_thrust(),
+                tvc_deflection: tvc.map_or(0.0, |tvc| tvc.deflection()),
+            }).collect()
+    }
+    /// Injects (or clears) a fault in the engine at the given index (0..2 -> a..c)
+    pub (crate) fn set_fault(&mut self, engine: usize, fault: ActuatorFault, active: bool) -> Result<(), String> {
+        match (engine, fault) {
+            (0, ActuatorFault::TvcStuck(deflection)) => self.tvc_a.set_stuck(active, deflection),
+            (_, ActuatorFault::TvcStuck(_)) => return Err("Only vernier engine A has a TVC".to_string()),
+            (0, _) => self.vernier_a.set_fault(Some(fault).filter(|_| active)),
+            (1, _) => self.vernier_b.set_fault(Some(fault).filter(|_| active)),
+            (2, _) => self.vernier_c.set_fault(Some(fault).filter(|_| active)),
+            _ => return Err(format!("No vernier engine at index {}", engine)),
+        }
+        Ok(())
     }
     pub fn update_dynamics(&self, outputs: &mut OrbitalDynamicsInputs) {
         self.vernier_a.update_dynamics(outputs);
@@ -98,30 +165,64 @@ impl<'a> DynamicSystem<'a> for SurveyorPropulsion {
     ) {
     }
 }
-// // TODO: Add tests for engine subsystem
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use approx::assert_relative_eq;
-
-//     #[test]
-//     fn test_engine_subsystem() {
-//         let mut engine = EngineSubsystem::new();
-//         let commands = EngineCommands {
-//             vernier_thrust_a: 1.0,
-//             vernier_thrust_b: 2.0,
-//             vernier_thrust_c: 3.0,
-//         };
-//         let mut outputs = OrbitalDynamicsInputs::new();
-//         engine.handle_commands(&commands);
-//         engine.update_dynamics(1.0, &mut outputs);
-
-//         // Test that the computed thrusts are correct in output
-//         let expected_thrust = nalgebra::Vector3::new(0.0, 0.0, 6.0);
-//         assert_relative_eq!(outputs.total_force_b, expected_thrust, epsilon = 1e-6);
-
-//         // Test that the computed torques are correct
-//         let expected_torque = nalgebra::Vector3::new(0.0, 0.0, 0.0);
-//         assert_relative_eq!(outputs.total_torque_b, expected_torque, epsilon = 1e-6);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+
+    fn create_propulsion() -> SurveyorPropulsion {
+        let thrusters = ["A", "B", "C"].iter().map(|name| {
+            let tvc = if *name == "A" {
+                "<tvc><axis_cf>[1.0, 0.0, 0.0]</axis_cf><max_deflection>5.0</max_deflection></tvc>"
+            } else { "" };
+            format!(r#"
+                <thruster name="Vernier{}">
+                    <geometry>
+                        <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                        <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                    </geometry>
+                    <min_thrust>130.0</min_thrust>
+                    <max_thrust>460.0</max_thrust>
+                    {}
+                </thruster>"#, name, tvc)
+        }).collect::<String>();
+        let config = EngineSubsystemConfig::from_str(&format!("<EngineSubsystem>{}</EngineSubsystem>", thrusters)).unwrap();
+        SurveyorPropulsion::from_config(&config)
+    }
+
+    fn commands(ignition: bool, thrusts: [f64; 3], tvc_deflection: f64) -> EngineCommands {
+        EngineCommands {
+            ignition,
+            vernier_thrusts: thrusts.to_vec(),
+            tvc_deflections: vec![tvc_deflection, 0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn test_engine_commands() {
+        let mut propulsion = create_propulsion();
+        assert!(propulsion.engine_status().iter().all(|status| !status.ignited));
+
+        propulsion.handle_commands(&commands(true, [200.0, 300.0, 500.0], 0.05)).unwrap();
+        let status = propulsion.engine_status();
+        assert!(status.iter().all(|status| status.ignited));
+        // Thrust is limited to the range of the engine
+        assert_eq!(status.iter().map(|status| status.commanded_thrust).collect::<Vec<_>>(), vec![200.0, 300.0, 460.0]);
+        assert_eq!(status[0].tvc_deflection, 0.05);
+
+        // Invalid commands are rejected and the engines keep their settings
+        for invalid in [commands(true, [f64::NAN, 300.0, 300.0], 0.0), commands(true, [300.0; 3], 0.2),
+                        commands(true, [-1.0, 300.0, 300.0], 0.0)] {
+            assert!(propulsion.handle_commands(&invalid).is_err());
+            assert!(!propulsion.last_command_accepted());
+        }
+        let mut no_tvc = commands(true, [300.0; 3], 0.0);
+        no_tvc.tvc_deflections[1] = 0.01;
+        assert!(propulsion.handle_commands(&no_tvc).is_err());
+        assert_eq!(propulsion.engine_status(), status);
+
+        propulsion.handle_commands(&commands(false, [0.0; 3], 0.0)).unwrap();
+        assert!(propulsion.last_command_accepted());
+        assert!(propulsion.engine_status().iter().all(|status| !status.ignited && status.commanded_thrust == 0.0));
+    }
+}
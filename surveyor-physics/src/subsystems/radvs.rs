@@ -0,0 +1,25 @@
+use surveyor_types::config::RadvsSubsystemConfig;
+use crate::{models::radvs::RadvsBeam, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct RadvsSubsystem {
+    pub beams: Vec<RadvsBeam>,
+}
+
+impl RadvsSubsystem {
+    pub fn from_config(config: &RadvsSubsystemConfig) -> Self {
+        let mut beams = Vec::new();
+        for beam_config in &config.beams {
+            beams.push(RadvsBeam::from_config(beam_config));
+        }
+        Self { beams }
+    }
+    pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for beam in self.beams.iter_mut() {
+            beam.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
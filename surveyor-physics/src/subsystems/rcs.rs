@@ -1,5 +1,5 @@
 use surveyor_types::config::RcsSubsystemConfig;
-use crate::{models::rcs::RcsThruster, spacecraft::SpacecraftDiscreteState};
+use crate::{faults::ActuatorFault, models::rcs::RcsThruster, spacecraft::SpacecraftDiscreteState};
 
 #[derive(Debug)]
 pub (crate) struct RcsSubsystem {
@@ -18,14 +18,29 @@ impl RcsSubsystem {
 #[derive(Debug, Clone)]
 pub (crate) struct RcsCommands {
     pub duty_cycles: Vec<f64>,
+    /// Pulse on-time (s) of each thruster. The thrusters fire at full thrust for this long instead
+    /// of following the duty cycles.
+    pub on_times: Option<Vec<f64>>,
 }
 impl RcsSubsystem {
     pub fn handle_commands(&mut self, commands: &RcsCommands) {
+        if let Some(on_times) = &commands.on_times {
+            for (thruster, on_time) in self.thrusters.iter_mut().zip(on_times.iter()) {
+                thruster.fire_pulse(*on_time);
+            }
+            return;
+        }
         // Iterate over thrusters and call their handle_commands method
         for (thruster, duty_cycle) in self.thrusters.iter_mut().zip(commands.duty_cycles.iter()) {
             thruster.handle_commands(*duty_cycle);
         }
     }
+    /// Injects (or clears) a fault in the thruster at the given index
+    pub fn set_fault(&mut self, thruster: usize, fault: ActuatorFault, active: bool) -> Result<(), String> {
+        self.thrusters.get_mut(thruster)
+            .ok_or_else(|| format!("No RCS thruster at index {}", thruster))?
+            .set_fault(Some(fault).filter(|_| active))
+    }
     pub fn update_dynamics(&self, outputs: &mut super::OrbitalDynamicsInputs) {
         // Iterate over thrusters and call their update_dynamics method
         for thruster in &self.thrusters {
@@ -33,5 +48,9 @@ impl RcsSubsystem {
         }
     }
     pub fn update_discrete(&mut self, _dt: f64, _discrete_state: &SpacecraftDiscreteState) {}
-    pub fn update_continuous(&mut self, _dt: f64) {}
+    pub fn update_continuous(&mut self, dt: f64) {
+        for thruster in &mut self.thrusters {
+            thruster.update_continuous(dt);
+        }
+    }
 }
@@ -0,0 +1,45 @@
+use surveyor_types::config::ReactionWheelSubsystemConfig;
+use crate::{faults::ActuatorFault, models::reaction_wheel::ReactionWheel, spacecraft::SpacecraftDiscreteState};
+
+#[derive(Debug)]
+pub (crate) struct ReactionWheelSubsystem {
+    pub wheels: Vec<ReactionWheel>,
+}
+
+#[derive(Debug, Clone)]
+pub (crate) struct ReactionWheelCommands {
+    /// Motor torque for each wheel in N-m
+    pub torques: Vec<f64>,
+}
+
+impl ReactionWheelSubsystem {
+    pub fn from_config(config: &ReactionWheelSubsystemConfig) -> Self {
+        let mut wheels = Vec::new();
+        for wheel_config in &config.wheels {
+            wheels.push(ReactionWheel::from_config(wheel_config));
+        }
+        Self { wheels }
+    }
+    pub fn handle_commands(&mut self, commands: &ReactionWheelCommands) {
+        for (wheel, torque) in self.wheels.iter_mut().zip(commands.torques.iter()) {
+            wheel.handle_commands(*torque);
+        }
+    }
+    /// Injects (or clears) a fault in the reaction wheel at the given index
+    pub fn set_fault(&mut self, wheel: usize, fault: ActuatorFault, active: bool) -> Result<(), String> {
+        self.wheels.get_mut(wheel)
+            .ok_or_else(|| format!("No reaction wheel at index {}", wheel))?
+            .set_fault(Some(fault).filter(|_| active))
+    }
+    pub fn update_dynamics(&self, outputs: &mut super::OrbitalDynamicsInputs) {
+        for wheel in &self.wheels {
+            wheel.update_dynamics(outputs);
+        }
+    }
+    pub fn update_discrete(&mut self, _dt: f64, _discrete_state: &SpacecraftDiscreteState) {}
+    pub fn update_continuous(&mut self, dt: f64) {
+        for wheel in self.wheels.iter_mut() {
+            wheel.update_continuous(dt);
+        }
+    }
+}
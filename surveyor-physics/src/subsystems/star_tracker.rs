@@ -1,22 +1,72 @@
 //! Star Tracker Simulation
+//!
+//! The boresight of the star tracker is the Z-axis of the component frame. The attitude
+//! solution is corrupted by noise that is typically larger about the boresight than across it.
+//! No solution is produced when the Sun, Moon or Earth is too close to the boresight or when the
+//! spacecraft is rotating too fast for the stars to be tracked.
 
-use surveyor_types::{config::{StarTrackerConfig, StarTrackerSubsystemConfig}, math::UnitQuaternion};
+use hifitime::Epoch;
+use nalgebra::Vector3;
+use surveyor_types::{config::{noise_seed, StarTrackerConfig, StarTrackerSubsystemConfig}, math::UnitQuaternion, CelestialBodyType};
 
-use crate::spacecraft::SpacecraftDiscreteState;
+use crate::{models::noise::GaussianNoise, spacecraft::SpacecraftDiscreteState, universe::Observation};
 
 #[derive(Debug)]
 pub (crate) struct StarTrackerSensor {
     pub config: StarTrackerConfig,
     pub q_i2cf: UnitQuaternion,
+    pub measurement_time: Epoch,
+    pub valid: bool,
+    cross_boresight_noise: GaussianNoise,
+    about_boresight_noise: GaussianNoise,
 }
 
 impl StarTrackerSensor {
     pub fn from_config(config: &StarTrackerConfig) -> Self {
+        let seed = noise_seed(config.seed, &config.name);
         Self {
             config: config.clone(),
             q_i2cf: UnitQuaternion(nalgebra::UnitQuaternion::<f64>::identity()),
+            measurement_time: Epoch::default(),
+            valid: false,
+            cross_boresight_noise: GaussianNoise::new(config.cross_boresight_noise, seed),
+            about_boresight_noise: GaussianNoise::new(config.about_boresight_noise, seed.wrapping_add(1)),
         }
     }
+
+    /// Checks whether any of the bright bodies is within its exclusion angle from the boresight
+    fn is_blinded(&self, q_i2cf: &nalgebra::UnitQuaternion<f64>, pos_i: &Vector3<f64>, observation: &Observation) -> bool {
+        let boresight_i = q_i2cf * Vector3::z();
+        [
+            (CelestialBodyType::Sun, self.config.sun_exclusion_deg),
+            (CelestialBodyType::Moon, self.config.moon_exclusion_deg),
+            (CelestialBodyType::Earth, self.config.earth_exclusion_deg),
+        ].iter().filter(|(_, exclusion_deg)| *exclusion_deg > 0.0).any(|(body_type, exclusion_deg)| {
+            observation.get_body(*body_type).map_or(false, |body| {
+                let rel_pos = body.position.0 - pos_i;
+                let distance = rel_pos.norm();
+                // Exclusion angle is measured from the limb of the body
+                let angular_radius = (body.radius / distance).min(1.0).asin();
+                let angle = boresight_i.angle(&rel_pos);
+                angle - angular_radius < exclusion_deg.to_radians()
+            })
+        })
+    }
+
+    pub fn update_discrete(&mut self, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        let q_i2cf = discrete_state.q_i2b().0 * *self.config.geometry.q_cf2b;
+        // Small rotation error about the sensor axes
+        let cross = self.cross_boresight_noise.sample_vector3();
+        let about = self.about_boresight_noise.sample();
+        let q_err = nalgebra::UnitQuaternion::from_scaled_axis(Vector3::new(cross.x, cross.y, about));
+
+        let too_fast = self.config.max_rate_deg_s.map_or(false, |max_rate|
+            discrete_state.omega_b().norm() > max_rate.to_radians());
+        let blinded = self.is_blinded(&q_i2cf, &discrete_state.pos(), observation);
+        self.q_i2cf = UnitQuaternion(q_i2cf * q_err);
+        self.measurement_time = observation.epoch;
+        self.valid = !too_fast && !blinded;
+    }
 }
 
 #[derive(Debug)]
@@ -25,6 +75,8 @@ pub (crate) struct StarTrackerSubsystem {
 }
 pub (crate) struct StarTrackerOutput {
     pub q_i2cf: UnitQuaternion,
+    pub measurement_time: Epoch,
+    pub valid: bool,
 }
 
 
@@ -39,11 +91,9 @@ impl StarTrackerSubsystem {
     pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
 
     }
-    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState) {
-        // Store omega and accel from discrete state
+    pub fn update_discrete(&mut self, _dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
         for st in &mut self.star_trackers.iter_mut() {
-            let q_i2b = discrete_state.q_i2b();
-            st.q_i2cf = UnitQuaternion(q_i2b.0 * st.config.geometry.q_cf2b.inverse());
+            st.update_discrete(discrete_state, observation);
         }
     }
     pub fn update_continuous(&mut self, _dt: f64) {}
@@ -54,6 +104,47 @@ impl StarTrackerSensor {
     pub fn get_model_output(&self) -> StarTrackerOutput {
         StarTrackerOutput {
             q_i2cf: self.q_i2cf.clone(),
+            measurement_time: self.measurement_time,
+            valid: self.valid,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hard_xml::XmlRead;
+    use crate::test_utils::{create_observation, create_state, sun};
+
+    fn create_star_tracker(extra: &str) -> StarTrackerSensor {
+        let config = StarTrackerConfig::from_str(&format!(r#"
+            <StarTracker name="A">
+                <geometry>
+                    <q_cf2b>[1.0, 0.0, 0.0, 0.0]</q_cf2b>
+                    <cf_offset_com_b>[0.0, 0.0, 0.0]</cf_offset_com_b>
+                </geometry>
+                {}
+            </StarTracker>"#, extra)).unwrap();
+        StarTrackerSensor::from_config(&config)
+    }
+
+    /// Spacecraft at the origin rotating about the body X-axis
+    fn state(time: f64, rate: f64) -> SpacecraftDiscreteState {
+        create_state(time, Vector3::zeros(), Vector3::zeros(), nalgebra::UnitQuaternion::identity(), Vector3::new(rate, 0.0, 0.0))
+    }
+
+    fn observation(sun_dir: Vector3<f64>) -> Observation {
+        create_observation(Epoch::default(), [sun(sun_dir)])
+    }
+
+    #[test]
+    fn test_exclusion_and_rate_limit() {
+        let mut st = create_star_tracker("<sun_exclusion_deg>30.0</sun_exclusion_deg><max_rate_deg_s>2.0</max_rate_deg_s>");
+        st.update_discrete(&state(0.0, 0.0), &observation(Vector3::new(0.3, 0.0, 1.0)));
+        assert!(!st.valid);
+        st.update_discrete(&state(0.1, 0.0), &observation(Vector3::x()));
+        assert!(st.valid);
+        st.update_discrete(&state(0.2, 0.1), &observation(Vector3::x()));
+        assert!(!st.valid);
+    }
+}
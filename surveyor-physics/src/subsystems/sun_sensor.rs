@@ -0,0 +1,25 @@
+use surveyor_types::config::SunSensorSubsystemConfig;
+use crate::{models::sun_sensor::SunSensor, spacecraft::SpacecraftDiscreteState, universe::Observation};
+
+#[derive(Debug)]
+pub (crate) struct SunSensorSubsystem {
+    pub sun_sensors: Vec<SunSensor>,
+}
+
+impl SunSensorSubsystem {
+    pub fn from_config(config: &SunSensorSubsystemConfig) -> Self {
+        let mut sun_sensors = Vec::new();
+        for sun_sensor_config in &config.sensors {
+            sun_sensors.push(SunSensor::from_config(sun_sensor_config));
+        }
+        Self { sun_sensors }
+    }
+    pub fn update_dynamics(&self, _outputs: &mut super::OrbitalDynamicsInputs) {
+    }
+    pub fn update_discrete(&mut self, dt: f64, discrete_state: &SpacecraftDiscreteState, observation: &Observation) {
+        for sun_sensor in self.sun_sensors.iter_mut() {
+            sun_sensor.update_discrete(dt, discrete_state, observation);
+        }
+    }
+    pub fn update_continuous(&mut self, _dt: f64) {}
+}
@@ -0,0 +1,246 @@
+//! Lunar surface models used by the radar and camera models
+//!
+//! The surface is either a smooth sphere with the radius of the Moon or a digital elevation model
+//! (DEM) that gives the height above that sphere on a latitude/longitude grid. The DEM is fixed
+//! in the Moon-centered inertial frame (the rotation of the Moon is ignored, which amounts to
+//! about 0.5 deg over an hour of simulation).
+//!
+//! DEM files are plain text with one row of heights (in metres) per line, separated by commas or
+//! whitespace. Rows go from the north pole to the south pole (both included) and columns go
+//! eastwards from -180 deg longitude, wrapping around at +180 deg. Lines starting with '#' are
+//! ignored.
+
+use std::{fmt::Debug, str::FromStr, sync::Arc};
+
+use nalgebra::Vector3;
+use surveyor_types::config::{TerrainConfig, TerrainModelType};
+
+pub struct DigitalElevationModel {
+    heights: Vec<f64>,
+    n_lat: usize,
+    n_lon: usize,
+    min_height: f64,
+    max_height: f64,
+}
+
+impl Debug for DigitalElevationModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DigitalElevationModel({}x{})", self.n_lat, self.n_lon)
+    }
+}
+
+impl FromStr for DigitalElevationModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|v| !v.is_empty())
+                .map(|v| v.parse::<f64>().map_err(|e| format!("Invalid height '{}': {}", v, e)))
+                .collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let n_lon = rows.first().map_or(0, |row| row.len());
+        if rows.len() < 2 || n_lon == 0 {
+            return Err("DEM needs at least two rows of heights".to_string());
+        }
+        if rows.iter().any(|row| row.len() != n_lon) {
+            return Err("All rows of the DEM must have the same number of heights".to_string());
+        }
+        Ok(Self::new(rows.len(), n_lon, rows.concat()))
+    }
+}
+
+impl DigitalElevationModel {
+    /// Creates a DEM from heights in row-major order (see the module documentation for the layout)
+    pub fn new(n_lat: usize, n_lon: usize, heights: Vec<f64>) -> Self {
+        assert!(n_lat >= 2 && n_lon >= 1 && heights.len() == n_lat * n_lon, "Invalid DEM dimensions");
+        let min_height = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let max_height = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        Self { heights, n_lat, n_lon, min_height, max_height }
+    }
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read DEM '{}': {}", path, e))?
+            .parse()
+    }
+    fn at(&self, row: usize, col: usize) -> f64 {
+        self.heights[row * self.n_lon + col % self.n_lon]
+    }
+    /// Height in metres in the direction of the given unit vector from the center of the body
+    /// (bilinear interpolation)
+    pub fn height(&self, dir: &Vector3<f64>) -> f64 {
+        let lat = dir.z.clamp(-1.0, 1.0).asin();
+        let lon = dir.y.atan2(dir.x);
+        let row = (std::f64::consts::FRAC_PI_2 - lat) / std::f64::consts::PI * (self.n_lat - 1) as f64;
+        let col = (lon + std::f64::consts::PI) / std::f64::consts::TAU * self.n_lon as f64;
+        let (row0, col0) = (row.floor().min((self.n_lat - 2) as f64), col.floor());
+        let (fr, fc) = (row - row0, col - col0);
+        let (row0, col0) = (row0 as usize, col0 as usize % self.n_lon);
+        let top = self.at(row0, col0) * (1.0 - fc) + self.at(row0, col0 + 1) * fc;
+        let bottom = self.at(row0 + 1, col0) * (1.0 - fc) + self.at(row0 + 1, col0 + 1) * fc;
+        top * (1.0 - fr) + bottom * fr
+    }
+    /// Angular spacing of the grid in radians
+    pub fn spacing(&self) -> f64 {
+        (std::f64::consts::PI / (self.n_lat - 1) as f64).min(std::f64::consts::TAU / self.n_lon as f64)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum TerrainModel {
+    #[default]
+    Sphere,
+    Dem(Arc<DigitalElevationModel>),
+}
+
+impl TerrainModel {
+    /// Falls back to a sphere if the elevation data cannot be loaded
+    pub fn from_config(config: &TerrainConfig) -> Self {
+        match (config.model, config.path.as_ref()) {
+            (TerrainModelType::Sphere, _) => Self::Sphere,
+            (TerrainModelType::Dem, Some(path)) => match DigitalElevationModel::from_file(path) {
+                Ok(dem) => Self::Dem(Arc::new(dem)),
+                Err(e) => {
+                    log::error!("{}. Using a spherical Moon instead", e);
+                    Self::Sphere
+                }
+            },
+            (TerrainModelType::Dem, None) => {
+                log::error!("No path given for the DEM. Using a spherical Moon instead");
+                Self::Sphere
+            }
+        }
+    }
+    /// Height of the terrain above the reference sphere in the direction of `pos` (relative to
+    /// the center of the body)
+    pub fn height(&self, pos: &Vector3<f64>) -> f64 {
+        match self {
+            Self::Sphere => 0.0,
+            Self::Dem(dem) => dem.height(&pos.normalize()),
+        }
+    }
+    /// Outward unit normal of the surface below `pos` (relative to the center of the body)
+    pub fn normal(&self, pos: &Vector3<f64>) -> Vector3<f64> {
+        let up = pos.normalize();
+        match self {
+            Self::Sphere => up,
+            Self::Dem(dem) => {
+                // Slopes from central differences along the local east and north directions
+                let east = Vector3::z().cross(&up).try_normalize(1e-9).unwrap_or_else(Vector3::y);
+                let north = up.cross(&east);
+                let delta = 0.5 * dem.spacing();
+                let (sin_delta, cos_delta) = delta.sin_cos();
+                let slope = |dir: &Vector3<f64>| (dem.height(&(up * cos_delta + dir * sin_delta))
+                    - dem.height(&(up * cos_delta - dir * sin_delta))) / (2.0 * delta * pos.norm());
+                (up - east * slope(&east) - north * slope(&north)).normalize()
+            }
+        }
+    }
+    /// Height of `pos` (relative to the center of the body) above the terrain directly below it
+    pub fn altitude(&self, pos: &Vector3<f64>, radius: f64) -> f64 {
+        pos.norm() - radius - self.height(pos)
+    }
+    /// Distance along the unit vector `dir` from `origin` (relative to the center of the body) to
+    /// the first point on the surface. Zero if the origin is below the surface.
+    pub fn ray_intersection(&self, origin: &Vector3<f64>, dir: &Vector3<f64>, radius: f64) -> Option<f64> {
+        match self {
+            Self::Sphere => ray_sphere_intersection(origin, dir, radius).map(|(t0, _)| t0.max(0.0)),
+            Self::Dem(dem) => {
+                if self.altitude(origin, radius) <= 0.0 {
+                    return Some(0.0);
+                }
+                // Search between the spheres bounding the highest and lowest terrain
+                let (t_enter, t_outer_exit) = ray_sphere_intersection(origin, dir, radius + dem.max_height)?;
+                let t_end = ray_sphere_intersection(origin, dir, radius + dem.min_height)
+                    .map_or(t_outer_exit, |(t0, _)| t0);
+                let step = 0.25 * dem.spacing() * radius;
+                let mut t_prev = t_enter.max(0.0);
+                while t_prev < t_end {
+                    let t = (t_prev + step).min(t_end);
+                    if self.altitude(&(origin + dir * t), radius) <= 0.0 {
+                        // Refine the crossing by bisection
+                        let (mut lo, mut hi) = (t_prev, t);
+                        for _ in 0..50 {
+                            let mid = 0.5 * (lo + hi);
+                            if self.altitude(&(origin + dir * mid), radius) <= 0.0 { hi = mid } else { lo = mid }
+                        }
+                        return Some(hi);
+                    }
+                    t_prev = t;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Distances along the ray to where it enters and exits the sphere (if it hits the sphere)
+fn ray_sphere_intersection(origin: &Vector3<f64>, dir: &Vector3<f64>, radius: f64) -> Option<(f64, f64)> {
+    let b = origin.dot(dir);
+    let c = origin.norm_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let (t0, t1) = (-b - discriminant.sqrt(), -b + discriminant.sqrt());
+    (t1 >= 0.0).then_some((t0, t1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    const RADIUS: f64 = 1737400.0;
+
+    #[test]
+    fn test_sphere_intersection() {
+        let terrain = TerrainModel::Sphere;
+        let origin = Vector3::new(0.0, 0.0, RADIUS + 1000.0);
+        assert_abs_diff_eq!(terrain.ray_intersection(&origin, &-Vector3::z(), RADIUS).unwrap(), 1000.0, epsilon = 1e-6);
+        // Slant range at 60 deg from the vertical at low altitude (nearly a flat surface)
+        let dir = Vector3::new(60f64.to_radians().sin(), 0.0, -60f64.to_radians().cos());
+        assert_abs_diff_eq!(terrain.ray_intersection(&origin, &dir, RADIUS).unwrap(), 2000.0, epsilon = 5.0);
+        assert!(terrain.ray_intersection(&origin, &Vector3::z(), RADIUS).is_none());
+        assert!(terrain.ray_intersection(&origin, &Vector3::x(), RADIUS).is_none());
+    }
+
+    #[test]
+    fn test_dem() {
+        // A 500 m plateau over the northern hemisphere
+        let dem = "# 5 x 4 grid\n500 500 500 500\n500 500 500 500\n0 0 0 0\n0 0 0 0\n0 0 0 0\n"
+            .parse::<DigitalElevationModel>().unwrap();
+        assert_abs_diff_eq!(dem.height(&Vector3::z()), 500.0);
+        assert_abs_diff_eq!(dem.height(&-Vector3::z()), 0.0);
+        // Halfway between the 45 deg row and the equator
+        let dir = Vector3::new(22.5f64.to_radians().cos(), 0.0, 22.5f64.to_radians().sin());
+        assert_abs_diff_eq!(dem.height(&dir), 250.0, epsilon = 1e-9);
+
+        let terrain = TerrainModel::Dem(Arc::new(dem));
+        let origin = Vector3::new(0.0, 0.0, RADIUS + 1000.0);
+        assert_abs_diff_eq!(terrain.altitude(&origin, RADIUS), 500.0, epsilon = 1e-9);
+        assert_abs_diff_eq!(terrain.ray_intersection(&origin, &-Vector3::z(), RADIUS).unwrap(), 500.0, epsilon = 1e-6);
+        let origin = Vector3::new(0.0, 0.0, -RADIUS - 1000.0);
+        assert_abs_diff_eq!(terrain.ray_intersection(&origin, &Vector3::z(), RADIUS).unwrap(), 1000.0, epsilon = 1e-6);
+
+        assert!("1 2\n3".parse::<DigitalElevationModel>().is_err());
+    }
+
+    #[test]
+    fn test_normal() {
+        let pos = Vector3::new(0.0, 0.0, RADIUS);
+        assert_abs_diff_eq!(TerrainModel::Sphere.normal(&pos), Vector3::z(), epsilon = 1e-12);
+
+        // 500 m drop between the 45 deg row and the equator tilts the surface towards the south
+        let dem = "500 500 500 500\n500 500 500 500\n0 0 0 0\n0 0 0 0\n0 0 0 0\n"
+            .parse::<DigitalElevationModel>().unwrap();
+        let terrain = TerrainModel::Dem(Arc::new(dem));
+        let up = Vector3::new(22.5f64.to_radians().cos(), 0.0, 22.5f64.to_radians().sin());
+        let north = Vector3::new(-22.5f64.to_radians().sin(), 0.0, 22.5f64.to_radians().cos());
+        let normal = terrain.normal(&(up * RADIUS));
+        assert_abs_diff_eq!(normal.angle(&up), (500.0 / (RADIUS * std::f64::consts::FRAC_PI_4)).atan(), epsilon = 1e-6);
+        assert!(normal.dot(&north) < 0.0);
+    }
+}
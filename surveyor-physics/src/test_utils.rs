@@ -0,0 +1,60 @@
+//! Fixtures shared by the unit tests of the sensor models
+
+use std::collections::HashMap;
+
+use hifitime::Epoch;
+use nalgebra::{SVector, UnitQuaternion, Vector3};
+use surveyor_types::CelestialBodyType;
+
+use crate::{spacecraft::SpacecraftDiscreteState, universe::{CelestialBodyObservation, Observation}};
+
+pub const AU: f64 = 1.496e11;
+pub const SUN_RADIUS: f64 = 6.957e8;
+pub const EARTH_RADIUS: f64 = 6378140.0;
+pub const MOON_RADIUS: f64 = 1737400.0;
+
+/// Body at rest at `position` with the given radius
+pub fn body(position: Vector3<f64>, radius: f64) -> CelestialBodyObservation {
+    CelestialBodyObservation {
+        position: surveyor_types::math::Vector3(position),
+        velocity: surveyor_types::math::Vector3(Vector3::zeros()),
+        radius,
+    }
+}
+
+/// Sun one astronomical unit away in the direction `sun_dir`
+pub fn sun(sun_dir: Vector3<f64>) -> (CelestialBodyType, CelestialBodyObservation) {
+    (CelestialBodyType::Sun, body(sun_dir.normalize() * AU, SUN_RADIUS))
+}
+
+pub fn earth(position: Vector3<f64>) -> (CelestialBodyType, CelestialBodyObservation) {
+    (CelestialBodyType::Earth, body(position, EARTH_RADIUS))
+}
+
+pub fn moon(position: Vector3<f64>) -> (CelestialBodyType, CelestialBodyObservation) {
+    (CelestialBodyType::Moon, body(position, MOON_RADIUS))
+}
+
+/// Observation of the given bodies at `epoch` over a spherical Moon
+pub fn create_observation(epoch: Epoch, bodies: impl IntoIterator<Item = (CelestialBodyType, CelestialBodyObservation)>) -> Observation {
+    Observation {
+        celestial_bodies: HashMap::from_iter(bodies),
+        magnetic_field: None,
+        epoch,
+        terrain: Default::default(),
+        j2000_to_itrf: None,
+    }
+}
+
+/// Spacecraft with the given position, velocity, attitude and angular rate (body frame)
+pub fn create_state(time: f64, pos: Vector3<f64>, vel: Vector3<f64>, q_i2b: UnitQuaternion<f64>, omega_b: Vector3<f64>) -> SpacecraftDiscreteState {
+    let mut state = SVector::<f64, 13>::zeros();
+    state.fixed_rows_mut::<3>(0).copy_from(&pos);
+    state.fixed_rows_mut::<3>(3).copy_from(&vel);
+    state[6] = q_i2b.w;
+    state[7] = q_i2b.i;
+    state[8] = q_i2b.j;
+    state[9] = q_i2b.k;
+    state.fixed_rows_mut::<3>(10).copy_from(&omega_b);
+    SpacecraftDiscreteState::new(time, &state)
+}
@@ -0,0 +1,453 @@
+//! Ground-station tracking measurements for orbit determination
+//!
+//! Each Earth ground station produces two-way range, two-way range-rate (Doppler) and
+//! azimuth/elevation measurements of the spacecraft at a fixed interval while the spacecraft is
+//! above its elevation mask and not hidden behind the Moon. The signal leaves the station, is
+//! turned around by the spacecraft at the current simulation time and is received back at the
+//! station, with the light time of each leg solved iteratively. Measurements are time-tagged at
+//! reception.
+//!
+//! The position and velocity of the Earth come from the ephemerides and the orientation of the
+//! Earth-fixed (ITRF93) frame from the high-precision Earth orientation kernel. Without that
+//! kernel, the Earth-fixed frame is obtained from J2000 by a rotation through the Greenwich mean
+//! sidereal time, as in the magnetic field model (precession, nutation and polar motion are
+//! ignored).
+//!
+//! The measurements can be exported as a CCSDS Tracking Data Message (TDM, KVN format).
+//!
+//! The orbit determination on the ground is not simulated. Instead, the true state of the
+//! spacecraft plus noise is uplinked as a state vector at a fixed interval, as long as the
+//! spacecraft has been tracked since the previous uplink.
+
+use std::fmt::Write;
+
+use bevy_ecs::prelude::*;
+use hifitime::Epoch;
+use nalgebra::{Matrix3, Vector3};
+use surveyor_gnc::sensors::EphemerisOutput;
+use surveyor_types::{config::{noise_seed, GroundStationConfig, StateVectorUplinkConfig, TrackingConfig}, CelestialBodyType};
+
+use crate::{
+    magnetic_field::j2000_to_ecef,
+    models::noise::GaussianNoise,
+    spacecraft::{SensorSampleEvent, SpacecraftDiscreteState},
+    universe::{CelestialBodyObservation, Observation},
+};
+
+pub const SPEED_OF_LIGHT: f64 = 299792458.0; // m/s
+/// Rotation rate of the Earth-fixed frame (rate of change of the sidereal time)
+const EARTH_ROTATION_RATE: f64 = 360.98564736629 / 180.0 * std::f64::consts::PI / 86400.0; // rad/s
+/// WGS-84 ellipsoid (used to find the local horizon of a station)
+const WGS84_A: f64 = 6378137.0;
+const WGS84_F: f64 = 1.0 / 298.257223563;
+/// Name of the spacecraft in the tracking data message
+const SPACECRAFT_NAME: &str = "SURVEYOR";
+
+/// Two-way tracking measurement from a single ground station
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackingMeasurement {
+    pub station: String,
+    /// Time at which the signal was received back at the station
+    pub epoch: Epoch,
+    /// Half of the round-trip light time multiplied by the speed of light, in metres
+    pub range: f64,
+    /// Rate of change of the range in m/s (positive when moving away from the station)
+    pub range_rate: f64,
+    /// Azimuth (clockwise from north) and elevation above the local horizon in radians
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+/// Rotation from J2000 to the Earth-fixed frame `offset` seconds after the observation. The
+/// rotation from the Earth orientation kernel is carried over the offset (at most a few seconds of
+/// light time) at the rotation rate of the Earth.
+fn j2000_to_itrf(observation: &Observation, offset: f64) -> Matrix3<f64> {
+    match observation.j2000_to_itrf {
+        Some(j2000_to_itrf) => {
+            let (s, c) = (EARTH_ROTATION_RATE * offset).sin_cos();
+            Matrix3::new(
+                c, s, 0.0,
+                -s, c, 0.0,
+                0.0, 0.0, 1.0,
+            ) * j2000_to_itrf
+        }
+        None => j2000_to_ecef(observation.epoch + hifitime::Duration::from_seconds(offset)),
+    }
+}
+
+/// Geodetic latitude and longitude of a position in the Earth-fixed frame
+fn geodetic_lat_lon(pos_ecef: &Vector3<f64>) -> (f64, f64) {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let p = pos_ecef.xy().norm();
+    let mut lat = pos_ecef.z.atan2(p * (1.0 - e2));
+    for _ in 0..5 {
+        let n = WGS84_A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+        let h = p / lat.cos() - n;
+        lat = pos_ecef.z.atan2(p * (1.0 - e2 * n / (n + h)));
+    }
+    (lat, pos_ecef.y.atan2(pos_ecef.x))
+}
+
+#[derive(Debug)]
+pub (crate) struct GroundStation {
+    config: GroundStationConfig,
+    /// Rotation from the Earth-fixed frame to the local east-north-up frame
+    ecef_to_enu: Matrix3<f64>,
+    range_noise: GaussianNoise,
+    range_rate_noise: GaussianNoise,
+    angle_noise: GaussianNoise,
+}
+
+impl GroundStation {
+    pub fn from_config(config: &GroundStationConfig) -> Self {
+        let (lat, lon) = geodetic_lat_lon(&config.position_itrf.0);
+        let (sin_lat, cos_lat) = lat.sin_cos();
+        let (sin_lon, cos_lon) = lon.sin_cos();
+        let ecef_to_enu = Matrix3::new(
+            -sin_lon, cos_lon, 0.0,
+            -sin_lat * cos_lon, -sin_lat * sin_lon, cos_lat,
+            cos_lat * cos_lon, cos_lat * sin_lon, sin_lat,
+        );
+        let seed = noise_seed(config.seed, &config.name);
+        Self {
+            config: config.clone(),
+            ecef_to_enu,
+            range_noise: GaussianNoise::new(config.range_noise_std, seed),
+            range_rate_noise: GaussianNoise::new(config.range_rate_noise_std, seed.wrapping_add(1)),
+            angle_noise: GaussianNoise::new(config.angle_noise_std.to_radians(), seed.wrapping_add(2)),
+        }
+    }
+    /// Position and velocity of the station in the inertial frame `offset` seconds after the
+    /// observation
+    fn state_i(&self, earth: &CelestialBodyObservation, observation: &Observation, offset: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let ecef_to_i = j2000_to_itrf(observation, offset).transpose();
+        let rel_pos_i = ecef_to_i * self.config.position_itrf.0;
+        let pos_i = earth.position.0 + earth.velocity.0 * offset + rel_pos_i;
+        let vel_i = earth.velocity.0 + (ecef_to_i * Vector3::z()).cross(&rel_pos_i) * EARTH_ROTATION_RATE;
+        (pos_i, vel_i)
+    }
+    /// Light time between the spacecraft and the station when the station transmits
+    /// (`direction = -1.0`) or receives (`direction = 1.0`)
+    fn light_time(&self, pos_sc_i: &Vector3<f64>, earth: &CelestialBodyObservation, observation: &Observation, direction: f64) -> f64 {
+        let mut light_time = 0.0;
+        for _ in 0..3 {
+            let (pos_i, _) = self.state_i(earth, observation, direction * light_time);
+            light_time = (pos_sc_i - pos_i).norm() / SPEED_OF_LIGHT;
+        }
+        light_time
+    }
+    /// Measurement of a spacecraft that turns the signal around at the epoch of the observation.
+    /// None if the spacecraft is not visible from the station.
+    pub fn measure(&mut self, pos_sc_i: &Vector3<f64>, vel_sc_i: &Vector3<f64>, observation: &Observation) -> Option<TrackingMeasurement> {
+        let earth = observation.get_body(CelestialBodyType::Earth)?;
+        let epoch = observation.epoch;
+        let uplink_time = self.light_time(pos_sc_i, earth, observation, -1.0);
+        let downlink_time = self.light_time(pos_sc_i, earth, observation, 1.0);
+        let (uplink_pos_i, uplink_vel_i) = self.state_i(earth, observation, -uplink_time);
+        let (downlink_pos_i, downlink_vel_i) = self.state_i(earth, observation, downlink_time);
+
+        // Visibility at reception
+        let los_i = pos_sc_i - downlink_pos_i;
+        let receive_epoch = epoch + hifitime::Duration::from_seconds(downlink_time);
+        let los_enu = self.ecef_to_enu * j2000_to_itrf(observation, downlink_time) * los_i.normalize();
+        let elevation = los_enu.z.asin();
+        if elevation < self.config.elevation_mask_deg.to_radians() {
+            return None;
+        }
+        if let Some(moon) = observation.get_body(CelestialBodyType::Moon) {
+            // Closest approach of the line of sight to the center of the Moon
+            let s = (moon.position.0 - downlink_pos_i).dot(&los_i) / los_i.norm_squared();
+            let closest_i = downlink_pos_i + los_i * s.clamp(0.0, 1.0);
+            if s < 1.0 && (closest_i - moon.position.0).norm() < moon.radius {
+                return None;
+            }
+        }
+        let azimuth = los_enu.x.atan2(los_enu.y);
+
+        let range_rate = |station_pos_i: Vector3<f64>, station_vel_i: Vector3<f64>|
+            (pos_sc_i - station_pos_i).normalize().dot(&(vel_sc_i - station_vel_i));
+        Some(TrackingMeasurement {
+            station: self.config.name.clone(),
+            epoch: receive_epoch,
+            range: 0.5 * SPEED_OF_LIGHT * (uplink_time + downlink_time) + self.range_noise.sample(),
+            range_rate: 0.5 * (range_rate(uplink_pos_i, uplink_vel_i) + range_rate(downlink_pos_i, downlink_vel_i))
+                + self.range_rate_noise.sample(),
+            azimuth: (azimuth + self.angle_noise.sample()).rem_euclid(2.0 * std::f64::consts::PI),
+            elevation: elevation + self.angle_noise.sample(),
+        })
+    }
+}
+
+/// Epoch in the format used by CCSDS messages
+fn ccsds_epoch(epoch: Epoch) -> String {
+    let (y, m, d, h, min, s, ns) = epoch.to_gregorian_utc();
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}", y, m, d, h, min, s, ns / 1000)
+}
+
+/// State vectors determined on the ground and sent up to the spacecraft
+#[derive(Debug)]
+struct StateVectorUplink {
+    interval: f64,
+    /// Simulation time of the next uplink
+    next_time: f64,
+    /// Whether the spacecraft has been tracked since the previous uplink
+    tracked: bool,
+    position_noise: GaussianNoise,
+    velocity_noise: GaussianNoise,
+}
+
+impl StateVectorUplink {
+    fn from_config(config: &StateVectorUplinkConfig) -> Self {
+        Self {
+            interval: config.interval,
+            next_time: 0.0,
+            tracked: false,
+            position_noise: GaussianNoise::new(config.position_noise_std, config.seed),
+            velocity_noise: GaussianNoise::new(config.velocity_noise_std, config.seed.wrapping_add(1)),
+        }
+    }
+}
+
+/// Ground stations along with all the measurements they have made so far
+#[derive(Debug, Resource, Default)]
+pub struct TrackingNetwork {
+    stations: Vec<GroundStation>,
+    interval: f64,
+    output: Option<String>,
+    uplink: Option<StateVectorUplink>,
+    /// Simulation time of the next set of measurements
+    next_time: f64,
+    last_update_time: f64,
+    pub measurements: Vec<TrackingMeasurement>,
+}
+
+impl TrackingNetwork {
+    pub fn from_config(config: &TrackingConfig) -> Self {
+        Self {
+            stations: config.stations.iter().map(GroundStation::from_config).collect(),
+            interval: config.interval,
+            output: config.output.clone(),
+            uplink: config.uplink.as_ref().map(StateVectorUplink::from_config),
+            ..Default::default()
+        }
+    }
+    /// Takes measurements from all the stations if they are due. Returns the position and
+    /// velocity to uplink to the spacecraft if an uplink is due.
+    pub fn update(&mut self, state: &SpacecraftDiscreteState, observation: &Observation) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let t = state.time;
+        if t < self.last_update_time {
+            // The simulation has been reset
+            self.measurements.clear();
+            self.next_time = 0.0;
+            if let Some(uplink) = self.uplink.as_mut() {
+                uplink.next_time = 0.0;
+                uplink.tracked = false;
+            }
+        }
+        self.last_update_time = t;
+        let (pos_i, vel_i) = (state.pos().0, state.vel().0);
+        if !self.stations.is_empty() && t >= self.next_time - 1e-9 {
+            while self.next_time <= t + 1e-9 {
+                self.next_time += self.interval.max(f64::EPSILON);
+            }
+            let num_measurements = self.measurements.len();
+            for station in self.stations.iter_mut() {
+                if let Some(measurement) = station.measure(&pos_i, &vel_i, observation) {
+                    self.measurements.push(measurement);
+                }
+            }
+            if let Some(uplink) = self.uplink.as_mut() {
+                uplink.tracked |= self.measurements.len() > num_measurements;
+            }
+        }
+        let uplink = self.uplink.as_mut().filter(|uplink| uplink.tracked && t >= uplink.next_time - 1e-9)?;
+        while uplink.next_time <= t + 1e-9 {
+            uplink.next_time += uplink.interval.max(f64::EPSILON);
+        }
+        uplink.tracked = false;
+        Some((pos_i + uplink.position_noise.sample_vector3(), vel_i + uplink.velocity_noise.sample_vector3()))
+    }
+    /// CCSDS Tracking Data Message (KVN) with a segment for each station
+    pub fn to_tdm(&self, creation_date: Epoch) -> String {
+        let mut tdm = String::new();
+        let _ = writeln!(tdm, "CCSDS_TDM_VERS = 2.0");
+        let _ = writeln!(tdm, "COMMENT Simulated two-way tracking data. RANGE is half of the round-trip light time times the speed of light.");
+        let _ = writeln!(tdm, "CREATION_DATE = {}", ccsds_epoch(creation_date));
+        let _ = writeln!(tdm, "ORIGINATOR = SURVEYOR-SIM");
+        for station in &self.stations {
+            let name = &station.config.name;
+            let _ = write!(tdm, "\nMETA_START\nTIME_SYSTEM = UTC\nPARTICIPANT_1 = {}\nPARTICIPANT_2 = {}\n", name, SPACECRAFT_NAME);
+            let _ = write!(tdm, "MODE = SEQUENTIAL\nPATH = 1,2,1\nTIMETAG_REF = RECEIVE\nRANGE_UNITS = km\nANGLE_TYPE = AZEL\nMETA_STOP\n");
+            let _ = writeln!(tdm, "\nDATA_START");
+            for measurement in self.measurements.iter().filter(|measurement| &measurement.station == name) {
+                let epoch = ccsds_epoch(measurement.epoch);
+                let _ = writeln!(tdm, "RANGE = {} {:.6}", epoch, measurement.range / 1000.0);
+                let _ = writeln!(tdm, "DOPPLER_INSTANTANEOUS = {} {:.9}", epoch, measurement.range_rate / 1000.0);
+                let _ = writeln!(tdm, "ANGLE_1 = {} {:.6}", epoch, measurement.azimuth.to_degrees());
+                let _ = writeln!(tdm, "ANGLE_2 = {} {:.6}", epoch, measurement.elevation.to_degrees());
+            }
+            let _ = writeln!(tdm, "DATA_STOP");
+        }
+        tdm
+    }
+}
+
+/// Takes the tracking measurements after each simulation step and sends the uplinked state
+/// vectors to the FSW
+pub (crate) fn update_tracking(
+    mut tracking: ResMut<TrackingNetwork>,
+    mut sensor_sample_event: EventReader<SensorSampleEvent>,
+    mut uplink_events: EventWriter<EphemerisOutput>,
+) {
+    for sample in sensor_sample_event.read() {
+        if let Some((pos_i, vel_i)) = tracking.update(&sample.0, &sample.1) {
+            uplink_events.send(EphemerisOutput { pos_i, vel_i, time: sample.1.epoch });
+        }
+    }
+}
+
+/// Writes the tracking data message once the simulation has finished
+pub (crate) fn export_tracking_data(tracking: Res<TrackingNetwork>) {
+    let Some(path) = tracking.output.as_ref() else {
+        return;
+    };
+    let creation_date = Epoch::now().unwrap_or_default();
+    match std::fs::write(path, tracking.to_tdm(creation_date)) {
+        Ok(()) => log::info!("Wrote {} tracking measurements to {}", tracking.measurements.len(), path),
+        Err(e) => log::error!("Could not write tracking data to '{}': {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+    use hard_xml::XmlRead;
+    use crate::test_utils::{create_observation, create_state, earth, moon};
+
+    fn create_network() -> TrackingNetwork {
+        let config = TrackingConfig::from_str(r#"
+            <Tracking interval="10.0">
+                <GroundStation name="EQ">
+                    <position_itrf>[6378137.0, 0.0, 0.0]</position_itrf>
+                    <elevation_mask_deg>10.0</elevation_mask_deg>
+                </GroundStation>
+                <GroundStation name="DSS-14">
+                    <position_itrf>[-2353621.420, -4641341.472, 3677052.318]</position_itrf>
+                    <elevation_mask_deg>6.0</elevation_mask_deg>
+                </GroundStation>
+            </Tracking>"#).unwrap();
+        TrackingNetwork::from_config(&config)
+    }
+
+    /// Earth at the origin (at rest) and optionally the Moon
+    fn observation(epoch: Epoch, moon_pos: Option<Vector3<f64>>) -> Observation {
+        create_observation(epoch, [earth(Vector3::zeros())].into_iter().chain(moon_pos.map(moon)))
+    }
+
+    fn state(time: f64, pos: Vector3<f64>, vel: Vector3<f64>) -> SpacecraftDiscreteState {
+        create_state(time, pos, vel, nalgebra::UnitQuaternion::identity(), Vector3::zeros())
+    }
+
+    #[test]
+    fn test_geodetic_lat_lon() {
+        // DSS-14 at Goldstone
+        let (lat, lon) = geodetic_lat_lon(&Vector3::new(-2353621.420, -4641341.472, 3677052.318));
+        assert_abs_diff_eq!(lat.to_degrees(), 35.4259, epsilon = 1e-3);
+        assert_abs_diff_eq!(lon.to_degrees(), -116.8895, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn test_two_way_measurements() {
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        // Spacecraft 384,400 km directly above the station on the equator, moving away at 1 km/s
+        let distance = 384400e3;
+        let up_i = j2000_to_ecef(epoch).transpose() * Vector3::x();
+        let pos = up_i * (WGS84_A + distance);
+        let mut network = create_network();
+        network.update(&state(0.0, pos, up_i * 1000.0), &observation(epoch, None));
+        assert_eq!(network.measurements.len(), 1);
+
+        let measurement = &network.measurements[0];
+        assert_eq!(measurement.station, "EQ");
+        // The station moves by about 600 m during each leg, which barely changes the range
+        assert_abs_diff_eq!(measurement.range, distance, epsilon = 2.0);
+        assert_abs_diff_eq!(measurement.range_rate, 1000.0, epsilon = 0.1);
+        assert!(measurement.elevation.to_degrees() > 89.9);
+        let light_time = (measurement.epoch - epoch).to_seconds();
+        assert_abs_diff_eq!(light_time, distance / SPEED_OF_LIGHT, epsilon = 1e-3);
+
+        // Nothing is measured until the next interval
+        network.update(&state(5.0, pos, up_i * 1000.0), &observation(epoch, None));
+        assert_eq!(network.measurements.len(), 1);
+
+        // Below the horizon of both stations
+        network.update(&state(10.0, -Vector3::z() * distance, up_i * 1000.0), &observation(epoch, None));
+        assert_eq!(network.measurements.len(), 1);
+
+        // Behind the Moon
+        network.update(&state(20.0, pos, up_i * 1000.0), &observation(epoch, Some(pos * 0.9)));
+        assert_eq!(network.measurements.len(), 1);
+
+        // Reset
+        network.update(&state(0.0, pos, up_i * 1000.0), &observation(epoch, None));
+        assert_eq!(network.measurements.len(), 1);
+    }
+
+    #[test]
+    fn test_earth_orientation_kernel() {
+        // Orientation of the Earth from the kernel, far from the one given by the sidereal time
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let j2000_to_itrf = nalgebra::Rotation3::from_euler_angles(0.001, -0.002, 1.0).into_inner();
+        let mut observation = observation(epoch, None);
+        observation.j2000_to_itrf = Some(j2000_to_itrf);
+        let up_i = j2000_to_itrf.transpose() * Vector3::x();
+        let mut network = create_network();
+        network.update(&state(0.0, up_i * (WGS84_A + 384400e3), Vector3::zeros()), &observation);
+        assert_eq!(network.measurements.len(), 1);
+        assert!(network.measurements[0].elevation.to_degrees() > 89.9);
+    }
+
+    #[test]
+    fn test_state_vector_uplink() {
+        let config = TrackingConfig::from_str(r#"
+            <Tracking interval="10.0">
+                <GroundStation name="EQ">
+                    <position_itrf>[6378137.0, 0.0, 0.0]</position_itrf>
+                </GroundStation>
+                <StateVectorUplink interval="30.0">
+                    <position_noise_std>100.0</position_noise_std>
+                    <velocity_noise_std>0.1</velocity_noise_std>
+                </StateVectorUplink>
+            </Tracking>"#).unwrap();
+        let mut network = TrackingNetwork::from_config(&config);
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let up_i = j2000_to_ecef(epoch).transpose() * Vector3::x();
+        let (pos, vel) = (up_i * 1e8, Vector3::new(0.0, 1000.0, 0.0));
+
+        let (pos_uplink, vel_uplink) = network.update(&state(0.0, pos, vel), &observation(epoch, None)).unwrap();
+        assert!((pos_uplink - pos).norm() < 1000.0 && (vel_uplink - vel).norm() < 1.0);
+        assert!(network.update(&state(20.0, pos, vel), &observation(epoch, None)).is_none());
+        assert!(network.update(&state(30.0, pos, vel), &observation(epoch, None)).is_some());
+        // Below the horizon when the next uplink is due, so it waits until the spacecraft has
+        // been tracked again
+        assert!(network.update(&state(60.0, -pos, vel), &observation(epoch, None)).is_none());
+        assert!(network.update(&state(70.0, pos, vel), &observation(epoch, None)).is_some());
+    }
+
+    #[test]
+    fn test_tdm() {
+        let epoch = Epoch::from_gregorian_utc_at_midnight(2024, 1, 1);
+        let pos = j2000_to_ecef(epoch).transpose() * Vector3::x() * 1e8;
+        let mut network = create_network();
+        network.update(&state(0.0, pos, Vector3::zeros()), &observation(epoch, None));
+        let tdm = network.to_tdm(epoch);
+        assert!(tdm.starts_with("CCSDS_TDM_VERS = 2.0\n"));
+        assert!(tdm.contains("CREATION_DATE = 2024-01-01T00:00:00.000000\n"));
+        assert_eq!(tdm.matches("META_START").count(), 2);
+        assert_eq!(tdm.matches("DATA_STOP").count(), 2);
+        assert!(tdm.contains("PARTICIPANT_1 = EQ\n"));
+        assert_eq!(tdm.lines().filter(|line| line.starts_with("RANGE = 2024-01-01T00:00:00.")).count(), 1);
+        assert_eq!(tdm.lines().filter(|line| line.starts_with("ANGLE_2 = ")).count(), 1);
+    }
+}
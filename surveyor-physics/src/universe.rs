@@ -2,18 +2,21 @@ use std::fmt::Debug;
 use std::{collections::HashMap, str::FromStr};
 use anise::almanac::Almanac;
 use anise::astro::Aberration;
+use anise::constants::frames::{EARTH_ITRF93, EARTH_J2000};
 use bevy::asset::{AssetLoader, AsyncReadExt};
 use bevy_derive::{Deref, DerefMut};
 use bevy::utils::BoxedFuture;
 use surveyor_types::CelestialBodyType;
 use surveyor_types::config::UniverseConfig;
 
-use nalgebra::{SVector, SVectorView};
+use nalgebra::{Matrix3, SVector, SVectorView};
 use bevy::prelude::*;
 use bevy::{asset::{AssetServer, io::Reader, LoadContext}, utils::thiserror::Error};
 use surveyor_types::math::Vector3;
 
 use crate::SimulationTime;
+use crate::magnetic_field::MagneticFieldModel;
+use crate::terrain::TerrainModel;
 use crate::spacecraft::SpacecraftProperties;
 
 /// Environment models
@@ -59,6 +62,7 @@ impl CelestialBodyModel {
         CelestialBodyObservation {
             position: self.position.into(),
             velocity: self.velocity.into(),
+            radius: self.radius,
         }
     }
 }
@@ -67,6 +71,16 @@ impl CelestialBodyModel {
 pub struct Universe {
     pub celestial_bodies: HashMap<CelestialBodyType, CelestialBodyModel>,
     pub ephem: Handle<Ephemerides>,
+    /// Earth magnetic field model (if enabled)
+    pub magnetic_field: Option<MagneticFieldModel>,
+    /// Time at which the body states were last updated
+    pub epoch: hifitime::Epoch,
+    /// Lunar surface model
+    pub terrain: TerrainModel,
+    /// Earth orientation kernel (if configured)
+    pub earth_orientation: Option<Handle<Ephemerides>>,
+    /// Rotation from J2000 to the ITRF93 frame at `epoch` (once the Earth orientation is loaded)
+    pub j2000_to_itrf: Option<Matrix3<f64>>,
 }
 
 impl Default for Universe {
@@ -89,11 +103,20 @@ impl Universe {
                 .into_iter()
                 .collect(),
             ephem: Default::default(),
+            magnetic_field: None,
+            epoch: hifitime::Epoch::default(),
+            terrain: TerrainModel::Sphere,
+            earth_orientation: None,
+            j2000_to_itrf: None,
         }
     }
     pub fn from_config(config: UniverseConfig, server: &Res<AssetServer>, _eph_loader: &Res<Assets<Ephemerides>>) -> Self {
         let ephemerides_path = config.ephemerides_path;
         let ephemerides_handle = server.load::<Ephemerides>(ephemerides_path);
+        let earth_orientation = config.earth_orientation_path.map(|path| server.load::<Ephemerides>(path));
+        let magnetic_field = config.magnetic_field.as_ref()
+            .map(|c| MagneticFieldModel::from_config(c).expect("Invalid magnetic field configuration"));
+        let terrain = config.terrain.as_ref().map(TerrainModel::from_config).unwrap_or_default();
 
         let celestial_bodies = config
             .celestial_bodies
@@ -113,7 +136,15 @@ impl Universe {
                 )
             })
             .collect();
-        Self { celestial_bodies, ephem: ephemerides_handle }
+        Self {
+            celestial_bodies,
+            ephem: ephemerides_handle,
+            magnetic_field,
+            epoch: hifitime::Epoch::default(),
+            terrain,
+            earth_orientation,
+            j2000_to_itrf: None,
+        }
     }
 
     pub fn compute_force(
@@ -142,10 +173,16 @@ impl Universe {
 pub struct CelestialBodyObservation {
     pub position: Vector3,
     pub velocity: Vector3,
+    pub radius: f64,
 }
 #[derive(Debug, Clone)]
 pub struct Observation{
     pub celestial_bodies: HashMap<CelestialBodyType, CelestialBodyObservation>,
+    pub magnetic_field: Option<MagneticFieldModel>,
+    pub epoch: hifitime::Epoch,
+    pub terrain: TerrainModel,
+    /// Rotation from J2000 to the ITRF93 frame from the Earth orientation kernel
+    pub j2000_to_itrf: Option<Matrix3<f64>>,
 }
 impl Observation{
     pub fn new(universe: & Universe) -> Self {
@@ -153,6 +190,28 @@ impl Observation{
             celestial_bodies: universe.celestial_bodies.iter().map(|(body_type, body_model)|
             (*body_type, body_model.observe())
         ).collect(),
+            magnetic_field: universe.magnetic_field,
+            epoch: universe.epoch,
+            terrain: universe.terrain.clone(),
+            j2000_to_itrf: universe.j2000_to_itrf,
+        }
+    }
+    /// Distance from `origin_i` along the unit vector `dir_i` (both in the inertial frame) to the
+    /// lunar surface. None if the ray misses the Moon or the Moon is not part of the universe
+    pub fn surface_range(&self, origin_i: &nalgebra::Vector3<f64>, dir_i: &nalgebra::Vector3<f64>) -> Option<f64> {
+        self.get_body(CelestialBodyType::Moon).and_then(|moon|
+            self.terrain.ray_intersection(&(origin_i - moon.position.0), dir_i, moon.radius))
+    }
+    /// Outward unit normal of the lunar surface below `pos_i` (in the inertial frame)
+    pub fn surface_normal(&self, pos_i: &nalgebra::Vector3<f64>) -> Option<nalgebra::Vector3<f64>> {
+        self.get_body(CelestialBodyType::Moon).map(|moon| self.terrain.normal(&(pos_i - moon.position.0)))
+    }
+    /// Earth magnetic field in Tesla at the given position in the inertial frame
+    /// Returns zero if there is no magnetic field model or the Earth is not part of the universe
+    pub fn magnetic_field_i(&self, pos_i: &nalgebra::Vector3<f64>) -> nalgebra::Vector3<f64> {
+        match (self.magnetic_field, self.get_body(CelestialBodyType::Earth)) {
+            (Some(model), Some(earth)) => model.field_j2000(&(pos_i - earth.position.0), self.epoch),
+            _ => nalgebra::Vector3::zeros(),
         }
     }
     pub fn get_body(&self, body_type: CelestialBodyType) -> Option<&CelestialBodyObservation> {
@@ -212,7 +271,7 @@ impl AssetLoader for AlmanacLoader {
     }
 
     fn extensions(&self) -> &[&str] {
-        &["bsp"]
+        &["bsp", "bpc"]
     }
 }
 
@@ -223,6 +282,7 @@ pub fn update_universe(
 ) {
     let mut universe = universe.single_mut();
     let sim_time = sim_time.single();
+    universe.epoch = sim_time.now();
     if let Some(eph) = eph_loader.get_mut(&universe.ephem) {
         let frame_id = anise::constants::frames::LUNA_J2000;
         let epoch = sim_time.now();
@@ -238,4 +298,10 @@ pub fn update_universe(
             }
         }
     }
+    if let Some(eop) = universe.earth_orientation.clone().and_then(|handle| eph_loader.get(&handle)) {
+        match eop.rotate_from_to(EARTH_J2000, EARTH_ITRF93, universe.epoch) {
+            Ok(dcm) => universe.j2000_to_itrf = Some(Matrix3::from_column_slice(dcm.rot_mat.as_slice())),
+            Err(e) => log::error!("Failed to get the orientation of the Earth: {}", e),
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use bevy_ecs::prelude::*;
 use hard_xml::XmlRead;
 use nalgebra as na;
@@ -7,6 +9,14 @@ use crate::CelestialBodyType;
 
 use crate::simulation::SimulationConfig;
 
+/// Seed of a noise generator. Uses the configured seed if given, otherwise one derived from the
+/// name of the sensor (FNV-1a hash) so that sensors of the same type do not draw identical noise.
+pub fn noise_seed(seed: Option<u64>, name: &str) -> u64 {
+    seed.unwrap_or_else(|| {
+        name.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    })
+}
+
 #[derive(Debug, XmlRead, PartialEq)]
 #[xml(tag = "Config")]
 pub struct Config {
@@ -18,6 +28,16 @@ pub struct Config {
     pub simulation: SimulationConfig,
     #[xml(child = "GncConfig")]
     pub gnc: GncConfig,
+    #[xml(child = "FaultSchedule")]
+    pub faults: Option<FaultScheduleConfig>,
+    #[xml(child = "Tracking")]
+    pub tracking: Option<TrackingConfig>,
+}
+
+/// Configuration of the simulation and the spacecraft in `surveyor-physics/simulation.xml`. The
+/// simulation and the GNC plugin are built from the same file.
+pub fn load_config() -> Config {
+    Config::from_str(include_str!("../../surveyor-physics/simulation.xml")).unwrap()
 }
 
 
@@ -26,6 +46,348 @@ pub struct Config {
 pub struct GncConfig {
     #[xml(flatten_text = "UpdateRateHz")]
     pub update_rate_hz: f64,
+    /// Gains and limits of the attitude controller (defaults are used if not given)
+    #[xml(child = "AttitudeController")]
+    pub attitude_controller: Option<AttitudeControllerConfig>,
+    /// Converts the RCS torque demands into thruster pulses (the thrusters are throttled
+    /// continuously if not given)
+    #[xml(child = "RcsModulator")]
+    pub rcs_modulator: Option<RcsModulatorConfig>,
+    /// Descent and landing guidance (defaults are used if not given)
+    #[xml(child = "DescentGuidance")]
+    pub descent_guidance: Option<DescentGuidanceConfig>,
+    /// Source of the attitude measurements and noise of the attitude estimator
+    #[xml(child = "AttitudeEstimator")]
+    pub attitude_estimator: Option<AttitudeEstimatorParams>,
+    /// Noise of the navigation filter
+    #[xml(child = "NavigationFilter")]
+    pub navigation_filter: Option<NavigationFilterParams>,
+}
+
+/// Attitude controller made up of a proportional attitude loop that commands a body rate and a
+/// PI rate loop that commands a torque (together a PID on the attitude error). All the vectors are
+/// per body axis.
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "AttitudeController")]
+pub struct AttitudeControllerConfig {
+    /// Body rate commanded per radian of attitude error (1/s)
+    #[xml(flatten_text = "attitude_gain")]
+    pub attitude_gain: Vector3,
+    /// Torque per unit body rate error (N-m-s/rad)
+    #[xml(flatten_text = "rate_gain")]
+    pub rate_gain: Vector3,
+    /// Torque per unit integrated body rate error (N-m/rad)
+    #[xml(flatten_text = "integral_gain")]
+    pub integral_gain: Vector3,
+    /// Largest body rate commanded by the attitude loop (rad/s)
+    #[xml(flatten_text = "max_rate")]
+    pub max_rate: Vector3,
+    /// Largest torque requested by the controller (N-m)
+    #[xml(flatten_text = "max_torque")]
+    pub max_torque: Vector3,
+    /// Largest torque from the integral term (N-m)
+    #[xml(flatten_text = "max_integral_torque")]
+    pub max_integral_torque: Vector3,
+    /// Attitude errors smaller than this are ignored (rad)
+    #[xml(flatten_text = "attitude_deadband")]
+    pub attitude_deadband: f64,
+    /// Body rate errors smaller than this are ignored (rad/s)
+    #[xml(flatten_text = "rate_deadband")]
+    pub rate_deadband: Vector3,
+}
+impl Default for AttitudeControllerConfig {
+    fn default() -> Self {
+        Self {
+            attitude_gain: Vector3(na::Vector3::repeat(1.0)),
+            rate_gain: Vector3(na::Vector3::repeat(10.0)),
+            integral_gain: Vector3(na::Vector3::zeros()),
+            max_rate: Vector3(na::Vector3::repeat(0.1)),
+            max_torque: Vector3(na::Vector3::repeat(1.0)),
+            max_integral_torque: Vector3(na::Vector3::repeat(0.1)),
+            attitude_deadband: 1e-4,
+            rate_deadband: Vector3(na::Vector3::repeat(1e-4)),
+        }
+    }
+}
+
+/// Source of the attitude measurements used by the attitude estimator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttitudeSource {
+    /// Star tracker attitudes, with the star and sun vectors as separate measurements
+    #[default]
+    StarTracker,
+    /// Attitude determined from the star sensor and sun vectors with TRIAD (for spacecraft
+    /// without a star tracker)
+    Triad,
+    /// Attitude determined from the star sensor and sun vectors with QUEST
+    Quest,
+}
+impl FromStr for AttitudeSource {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "StarTracker" => Ok(Self::StarTracker),
+            "Triad" => Ok(Self::Triad),
+            "Quest" => Ok(Self::Quest),
+            _ => Err("Attitude source not supported"),
+        }
+    }
+}
+
+/// Settings of the attitude estimator. The FSW defaults are used for anything that is not given,
+/// and the attitude source defaults to QUEST when the spacecraft has no star tracker.
+#[derive(Debug, XmlRead, PartialEq, Clone, Default)]
+#[xml(tag = "AttitudeEstimator")]
+pub struct AttitudeEstimatorParams {
+    #[xml(attr = "attitude_source")]
+    pub attitude_source: Option<AttitudeSource>,
+    /// Gyro angle random walk (rad/s^0.5)
+    #[xml(flatten_text = "gyro_angle_random_walk")]
+    pub gyro_angle_random_walk: Option<f64>,
+    /// Gyro rate random walk (rad/s^1.5)
+    #[xml(flatten_text = "gyro_rate_random_walk")]
+    pub gyro_rate_random_walk: Option<f64>,
+    /// 1-sigma errors of the star tracker attitude and the star and sun sensor directions (rad)
+    #[xml(flatten_text = "star_tracker_std")]
+    pub star_tracker_std: Option<f64>,
+    #[xml(flatten_text = "star_sensor_std")]
+    pub star_sensor_std: Option<f64>,
+    #[xml(flatten_text = "sun_sensor_std")]
+    pub sun_sensor_std: Option<f64>,
+    /// Initial 1-sigma errors of the attitude (rad) and the gyro bias (rad/s)
+    #[xml(flatten_text = "initial_attitude_std")]
+    pub initial_attitude_std: Option<f64>,
+    #[xml(flatten_text = "initial_bias_std")]
+    pub initial_bias_std: Option<f64>,
+    /// Measurements with a normalized innovation squared above this are rejected
+    #[xml(flatten_text = "innovation_gate")]
+    pub innovation_gate: Option<f64>,
+}
+
+/// Settings of the navigation filter. The FSW defaults are used for anything that is not given.
+#[derive(Debug, XmlRead, PartialEq, Clone, Default)]
+#[xml(tag = "NavigationFilter")]
+pub struct NavigationFilterParams {
+    /// Accelerometer noise and unmodelled accelerations (m/s^1.5)
+    #[xml(flatten_text = "accel_noise_density")]
+    pub accel_noise_density: Option<f64>,
+    /// 1-sigma errors of the uplinked position (m) and velocity (m/s)
+    #[xml(flatten_text = "uplink_position_std")]
+    pub uplink_position_std: Option<f64>,
+    #[xml(flatten_text = "uplink_velocity_std")]
+    pub uplink_velocity_std: Option<f64>,
+    /// 1-sigma error of the radar slant range: a constant part (m) and a part proportional to the range
+    #[xml(flatten_text = "altimeter_std")]
+    pub altimeter_std: Option<f64>,
+    #[xml(flatten_text = "altimeter_std_per_range")]
+    pub altimeter_std_per_range: Option<f64>,
+    /// 1-sigma error of the Doppler velocity along each axis (m/s)
+    #[xml(flatten_text = "doppler_std")]
+    pub doppler_std: Option<f64>,
+}
+
+/// Surveyor descent and landing guidance: retro attitude until retro ignition (a fixed delay after
+/// the AMR mark), a gravity turn that tracks a speed-vs-altitude descent contour with the vernier
+/// engines, a constant-speed vertical terminal descent and engine cutoff just above the surface
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "DescentGuidance")]
+pub struct DescentGuidanceConfig {
+    /// Mass of the spacecraft at the start of the descent, used to convert accelerations into
+    /// thrust (kg)
+    #[xml(flatten_text = "mass")]
+    pub mass: f64,
+    /// Specific impulse of the vernier engines, used to estimate the mass during the descent (s).
+    /// The 287 s of the Surveyor verniers is used if not given.
+    #[xml(flatten_text = "isp")]
+    pub isp: Option<f64>,
+    /// Time from the AMR mark to retro ignition (s)
+    #[xml(flatten_text = "retro_delay")]
+    pub retro_delay: f64,
+    /// Thrust per unit speed error along the descent contour, as an acceleration (1/s)
+    #[xml(flatten_text = "speed_gain")]
+    pub speed_gain: f64,
+    /// Speed (m/s) at each altitude above the surface (m), in order of increasing altitude. The
+    /// descent contour starts at the highest point if the AMR mark is missed.
+    #[xml(child = "ContourPoint")]
+    pub contour: Vec<ContourPointConfig>,
+    /// The spacecraft descends vertically at the terminal speed (m/s) below the terminal
+    /// altitude (m)
+    #[xml(flatten_text = "terminal_altitude")]
+    pub terminal_altitude: f64,
+    #[xml(flatten_text = "terminal_speed")]
+    pub terminal_speed: f64,
+    /// The vernier engines are shut down below this altitude (m)
+    #[xml(flatten_text = "cutoff_altitude")]
+    pub cutoff_altitude: f64,
+    /// Guidance laws flown in the descent contour and terminal descent phases
+    #[xml(default, flatten_text = "descent_law")]
+    pub descent_law: DescentGuidanceLaw,
+    #[xml(default, flatten_text = "terminal_law")]
+    pub terminal_law: DescentGuidanceLaw,
+    /// Parameters of the E-guidance and G-FOLD laws (defaults are used if not given)
+    #[xml(child = "PoweredDescent")]
+    pub powered_descent: Option<PoweredDescentConfig>,
+}
+impl Default for DescentGuidanceConfig {
+    fn default() -> Self {
+        // Speeds for a constant deceleration of about 1 m/s^2, and the 5 ft/s terminal descent
+        // from 40 ft with cutoff at 14 ft that Surveyor flew
+        let contour = [(12.2, 1.524), (100.0, 14.0), (1000.0, 45.0), (10000.0, 140.0)].iter()
+            .map(|&(altitude, speed)| ContourPointConfig { altitude, speed }).collect();
+        Self {
+            mass: 300.0,
+            isp: None,
+            retro_delay: 8.0,
+            speed_gain: 0.5,
+            contour,
+            terminal_altitude: 12.2,
+            terminal_speed: 1.524,
+            cutoff_altitude: 4.267,
+            descent_law: DescentGuidanceLaw::Surveyor,
+            terminal_law: DescentGuidanceLaw::Surveyor,
+            powered_descent: None,
+        }
+    }
+}
+
+/// Guidance law for a phase of the descent. The E-guidance and G-FOLD laws fly to the end state of
+/// the phase above the landing site: the terminal altitude and speed for the descent contour and
+/// the cutoff altitude and terminal speed for the terminal descent.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DescentGuidanceLaw {
+    /// Historical Surveyor guidance: gravity turn along the descent contour and vertical terminal
+    /// descent
+    #[default]
+    Surveyor,
+    /// Apollo-style polynomial guidance (E-guidance) with a linearly varying acceleration
+    EGuidance,
+    /// Fuel-optimal powered descent with thrust and glide-slope constraints, solved as a
+    /// second-order cone program (G-FOLD)
+    Gfold,
+}
+
+impl FromStr for DescentGuidanceLaw {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Surveyor" => Ok(Self::Surveyor),
+            "EGuidance" => Ok(Self::EGuidance),
+            "GFOLD" => Ok(Self::Gfold),
+            _ => Err("Descent guidance law not supported"),
+        }
+    }
+}
+
+/// Parameters of the E-guidance and G-FOLD descent guidance laws
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "PoweredDescent")]
+pub struct PoweredDescentConfig {
+    /// Smallest elevation of the spacecraft above the horizon seen from the landing site (rad).
+    /// Only used by G-FOLD.
+    #[xml(flatten_text = "glide_slope_angle")]
+    pub glide_slope_angle: f64,
+    /// Largest angle between the thrust and the local vertical (rad). Only used by G-FOLD.
+    #[xml(flatten_text = "max_tilt_angle")]
+    pub max_tilt_angle: f64,
+    /// Number of intervals with constant thrust in the G-FOLD trajectory
+    #[xml(flatten_text = "num_nodes")]
+    pub num_nodes: usize,
+    /// Time between G-FOLD solutions (s)
+    #[xml(flatten_text = "replan_interval")]
+    pub replan_interval: f64,
+    /// Landing site in the Moon-fixed (mean Earth/polar axis) frame (m). The site below the
+    /// predicted ground track is used if not given.
+    #[xml(flatten_text = "landing_site_f")]
+    pub landing_site_f: Option<Vector3>,
+}
+impl Default for PoweredDescentConfig {
+    fn default() -> Self {
+        Self {
+            glide_slope_angle: 0.035,
+            max_tilt_angle: 0.785,
+            num_nodes: 20,
+            replan_interval: 2.0,
+            landing_site_f: None,
+        }
+    }
+}
+impl PoweredDescentConfig {
+    /// Checks that the G-FOLD trajectory can be set up from these parameters
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_nodes == 0 {
+            return Err("The G-FOLD trajectory needs at least one node (num_nodes)".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "ContourPoint")]
+pub struct ContourPointConfig {
+    #[xml(attr = "altitude")]
+    pub altitude: f64,
+    #[xml(attr = "speed")]
+    pub speed: f64,
+}
+
+/// Type of modulator used to pulse the RCS thrusters
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RcsModulatorType {
+    /// Pulse-width pulse-frequency modulator (a first-order filter followed by a Schmitt trigger
+    /// with the trigger output fed back into the filter)
+    Pwpf,
+    /// Schmitt trigger acting directly on the torque demand
+    Schmitt,
+}
+impl FromStr for RcsModulatorType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "PWPF" | "Pwpf" => Ok(Self::Pwpf),
+            "Schmitt" => Ok(Self::Schmitt),
+            _ => Err("RCS modulator type not supported"),
+        }
+    }
+}
+
+/// Modulator for the RCS thrusters. The torque demand on each body axis is divided by the largest
+/// torque that the thrusters can produce about that axis before it is modulated, so the thresholds
+/// are fractions of that torque. All the vectors are per body axis.
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "RcsModulator")]
+pub struct RcsModulatorConfig {
+    #[xml(attr = "type")]
+    pub modulator_type: RcsModulatorType,
+    /// The trigger turns on when its input exceeds this threshold
+    #[xml(flatten_text = "on_threshold")]
+    pub on_threshold: Vector3,
+    /// The trigger turns off when its input drops below this threshold
+    #[xml(flatten_text = "off_threshold")]
+    pub off_threshold: Vector3,
+    /// Gain of the PWPF filter (not used by the Schmitt trigger)
+    #[xml(flatten_text = "filter_gain")]
+    pub filter_gain: Vector3,
+    /// Time constant of the PWPF filter in seconds (not used by the Schmitt trigger)
+    #[xml(flatten_text = "filter_time_constant")]
+    pub filter_time_constant: Vector3,
+    /// Shortest pulse that the thrusters can fire (s)
+    #[xml(flatten_text = "min_pulse_width")]
+    pub min_pulse_width: f64,
+}
+impl Default for RcsModulatorConfig {
+    fn default() -> Self {
+        Self {
+            modulator_type: RcsModulatorType::Pwpf,
+            on_threshold: Vector3(na::Vector3::repeat(0.45)),
+            off_threshold: Vector3(na::Vector3::repeat(0.15)),
+            filter_gain: Vector3(na::Vector3::repeat(4.5)),
+            filter_time_constant: Vector3(na::Vector3::repeat(0.15)),
+            min_pulse_width: 0.02,
+        }
+    }
 }
 
 #[derive(Debug, XmlRead, PartialEq)]
@@ -33,8 +395,79 @@ pub struct GncConfig {
 pub struct UniverseConfig {
     #[xml(flatten_text = "Ephemerides")]
     pub ephemerides_path: String,
+    /// High-precision Earth orientation kernel (BPC) that defines the ITRF93 frame. The Earth
+    /// rotation is approximated by the Greenwich mean sidereal time if not given.
+    #[xml(flatten_text = "EarthOrientation")]
+    pub earth_orientation_path: Option<String>,
     #[xml(child = "CelestialBodies", child = "CelestialBody")]
     pub celestial_bodies: Vec<CelestialBodyConfig>,
+    #[xml(child = "MagneticField")]
+    pub magnetic_field: Option<MagneticFieldConfig>,
+    #[xml(child = "Terrain")]
+    pub terrain: Option<TerrainConfig>,
+}
+
+/// Type of geomagnetic field model
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MagneticFieldModelType {
+    /// Tilted dipole (first degree of the IGRF)
+    Dipole,
+    /// International Geomagnetic Reference Field
+    Igrf,
+}
+impl FromStr for MagneticFieldModelType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Dipole" => Ok(Self::Dipole),
+            "IGRF" | "Igrf" => Ok(Self::Igrf),
+            _ => Err("Magnetic field model not supported"),
+        }
+    }
+}
+
+/// Earth magnetic field model
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "MagneticField")]
+pub struct MagneticFieldConfig {
+    #[xml(attr = "model")]
+    pub model: MagneticFieldModelType,
+    /// Maximum degree of the spherical harmonic expansion (only used by the IGRF model). All the
+    /// tabulated coefficients are used if not given.
+    #[xml(attr = "degree")]
+    pub degree: Option<usize>,
+}
+
+/// Type of lunar terrain model
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TerrainModelType {
+    /// Smooth sphere with the radius of the Moon
+    Sphere,
+    /// Digital elevation model (heights above the radius of the Moon on a latitude/longitude grid)
+    Dem,
+}
+impl FromStr for TerrainModelType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Sphere" => Ok(Self::Sphere),
+            "DEM" | "Dem" => Ok(Self::Dem),
+            _ => Err("Terrain model not supported"),
+        }
+    }
+}
+
+/// Lunar surface model used by the radar and camera models
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "Terrain")]
+pub struct TerrainConfig {
+    #[xml(attr = "model")]
+    pub model: TerrainModelType,
+    /// Path to the elevation data (only used by the DEM model)
+    #[xml(attr = "path")]
+    pub path: Option<String>,
 }
 
 #[derive(Debug, XmlRead, PartialEq)]
@@ -67,6 +500,9 @@ pub struct SpacecraftConfig {
     // pub mass: f64,
     // #[xml(attr = "inertia_com_b")]
     // pub inertia: SMatrix<f64, 3, 3>,
+    /// What the flight software knows about the sensors and actuators
+    #[xml(child = "FSW")]
+    pub fsw: Option<FswConfig>,
     #[xml(
         child = "Subsystems",
         child = "EngineSubsystem",
@@ -74,11 +510,104 @@ pub struct SpacecraftConfig {
         child = "ImuSubsystem",
         child = "StarTrackerSubsystem",
         child = "StarSensorSubsystem",
+        child = "ReactionWheelSubsystem",
+        child = "MagnetometerSubsystem",
+        child = "MagnetorquerSubsystem",
+        child = "SunSensorSubsystem",
+        child = "AmrSubsystem",
+        child = "RadvsSubsystem",
+        child = "CameraSubsystem",
     )]
     pub subsystems: Vec<SubsystemConfig>,
 }
 
-#[derive(Debug, XmlRead, PartialEq)]
+impl SpacecraftConfig {
+    /// Sensor and actuator configuration used by the flight software. Subsystems listed in the
+    /// `FSW` section replace the spacecraft subsystems of the same type. The others are copied from
+    /// the spacecraft with the knowledge errors applied to their geometry. Knowledge errors that
+    /// do not match any of these sensors or actuators are an error.
+    pub fn fsw_subsystems(&self) -> Result<Vec<SubsystemConfig>, String> {
+        let fsw = self.fsw.clone().unwrap_or_default();
+        let mut subsystems = fsw.subsystems.clone();
+        let mut applied = vec![false; fsw.knowledge_errors.len()];
+        for subsystem in &self.subsystems {
+            if subsystems.iter().any(|s| s.to_string() == subsystem.to_string()) {
+                continue;
+            }
+            let mut subsystem = subsystem.clone();
+            let subsystem_name = subsystem.to_string();
+            for (name, geometry) in subsystem.geometries_mut() {
+                let errors = fsw.knowledge_errors.iter().zip(applied.iter_mut())
+                    .filter(|(e, _)| e.subsystem == subsystem_name && e.target == name);
+                for (error, applied) in errors {
+                    error.apply(geometry);
+                    *applied = true;
+                }
+            }
+            subsystems.push(subsystem);
+        }
+        if let Some((error, _)) = fsw.knowledge_errors.iter().zip(applied).find(|(_, applied)| !applied) {
+            return Err(format!("Knowledge error for '{}' in subsystem '{}' does not match any sensor or actuator \
+                (subsystems listed in the FSW section take no knowledge errors)", error.target, error.subsystem));
+        }
+        Ok(subsystems)
+    }
+}
+
+/// Flight software view of the spacecraft. Subsystems may be listed here with the geometry that
+/// the FSW assumes, otherwise it uses the spacecraft geometry with the given knowledge errors.
+#[derive(Debug, XmlRead, PartialEq, Clone, Default)]
+#[xml(tag = "FSW")]
+pub struct FswConfig {
+    #[xml(child = "KnowledgeError")]
+    pub knowledge_errors: Vec<KnowledgeErrorConfig>,
+    #[xml(
+        child = "EngineSubsystem",
+        child = "RcsSubsystem",
+        child = "ImuSubsystem",
+        child = "StarTrackerSubsystem",
+        child = "StarSensorSubsystem",
+        child = "ReactionWheelSubsystem",
+        child = "MagnetometerSubsystem",
+        child = "MagnetorquerSubsystem",
+        child = "SunSensorSubsystem",
+        child = "AmrSubsystem",
+        child = "RadvsSubsystem",
+        child = "CameraSubsystem",
+    )]
+    pub subsystems: Vec<SubsystemConfig>,
+}
+
+/// Error in the FSW knowledge of the geometry of a named sensor or actuator of a subsystem
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "KnowledgeError")]
+pub struct KnowledgeErrorConfig {
+    /// Name of the subsystem (e.g. "Imu" or "Rcs")
+    #[xml(attr = "subsystem")]
+    pub subsystem: String,
+    /// Name of the sensor or actuator within the subsystem
+    #[xml(attr = "target")]
+    pub target: String,
+    /// Rotation vector (body frame, radians) from the true to the assumed orientation
+    #[xml(flatten_text = "misalignment")]
+    pub misalignment: Option<Vector3>,
+    /// Assumed minus true position in the body frame in metres
+    #[xml(flatten_text = "offset")]
+    pub offset: Option<Vector3>,
+}
+
+impl KnowledgeErrorConfig {
+    pub fn apply(&self, geometry: &mut GeometryParams) {
+        if let Some(misalignment) = &self.misalignment {
+            geometry.q_cf2b.0 = na::UnitQuaternion::from_scaled_axis(misalignment.0) * geometry.q_cf2b.0;
+        }
+        if let Some(offset) = &self.offset {
+            geometry.cf_offset_com_b.0 += offset.0;
+        }
+    }
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 pub enum SubsystemConfig {
     #[xml(tag = "EngineSubsystem")]
     Propulsion(EngineSubsystemConfig),
@@ -90,6 +619,20 @@ pub enum SubsystemConfig {
     StarTracker(StarTrackerSubsystemConfig),
     #[xml(tag = "StarSensorSubsystem")]
     StarSensor(StarSensorSubsystemConfig),
+    #[xml(tag = "ReactionWheelSubsystem")]
+    ReactionWheel(ReactionWheelSubsystemConfig),
+    #[xml(tag = "MagnetometerSubsystem")]
+    Magnetometer(MagnetometerSubsystemConfig),
+    #[xml(tag = "MagnetorquerSubsystem")]
+    Magnetorquer(MagnetorquerSubsystemConfig),
+    #[xml(tag = "SunSensorSubsystem")]
+    SunSensor(SunSensorSubsystemConfig),
+    #[xml(tag = "AmrSubsystem")]
+    Amr(AmrSubsystemConfig),
+    #[xml(tag = "RadvsSubsystem")]
+    Radvs(RadvsSubsystemConfig),
+    #[xml(tag = "CameraSubsystem")]
+    Camera(CameraSubsystemConfig),
 }
 impl ToString for SubsystemConfig {
     fn to_string(&self) -> String {
@@ -99,36 +642,189 @@ impl ToString for SubsystemConfig {
             SubsystemConfig::Imu(_) => "Imu".to_string(),
             SubsystemConfig::StarTracker(_) => "StarTracker".to_string(),
             SubsystemConfig::StarSensor(_) => "StarSensor".to_string(),
+            SubsystemConfig::ReactionWheel(_) => "ReactionWheel".to_string(),
+            SubsystemConfig::Magnetometer(_) => "Magnetometer".to_string(),
+            SubsystemConfig::Magnetorquer(_) => "Magnetorquer".to_string(),
+            SubsystemConfig::SunSensor(_) => "SunSensor".to_string(),
+            SubsystemConfig::Amr(_) => "Amr".to_string(),
+            SubsystemConfig::Radvs(_) => "Radvs".to_string(),
+            SubsystemConfig::Camera(_) => "Camera".to_string(),
         }
     }
 }
 
-#[derive(Debug, XmlRead, PartialEq)]
+impl SubsystemConfig {
+    /// Sampling and latency of the sensors in the subsystem (None for actuators)
+    pub fn sensor_timing(&self) -> Option<SensorTimingConfig> {
+        let timing = match self {
+            SubsystemConfig::Imu(config) => &config.timing,
+            SubsystemConfig::StarTracker(config) => &config.timing,
+            SubsystemConfig::StarSensor(config) => &config.timing,
+            SubsystemConfig::Magnetometer(config) => &config.timing,
+            SubsystemConfig::SunSensor(config) => &config.timing,
+            SubsystemConfig::Amr(config) => &config.timing,
+            SubsystemConfig::Radvs(config) => &config.timing,
+            SubsystemConfig::Camera(config) => &config.timing,
+            _ => return None,
+        };
+        Some(timing.clone().unwrap_or_default())
+    }
+}
+
+impl SubsystemConfig {
+    /// Names of the sensors or actuators in the subsystem, in the order they are configured
+    pub fn component_names(&self) -> Vec<String> {
+        match self {
+            SubsystemConfig::Propulsion(config) => config.thrusters.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Rcs(config) => config.thrusters.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Imu(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::StarTracker(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::StarSensor(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::ReactionWheel(config) => config.wheels.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Magnetometer(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Magnetorquer(config) => config.torquers.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::SunSensor(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Amr(config) => config.sensors.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Radvs(config) => config.beams.iter().map(|c| c.name.clone()).collect(),
+            SubsystemConfig::Camera(config) => config.cameras.iter().map(|c| c.name.clone()).collect(),
+        }
+    }
+}
+
+impl SubsystemConfig {
+    /// Geometry of each sensor or actuator in the subsystem along with its name
+    pub fn geometries_mut(&mut self) -> Vec<(String, &mut GeometryParams)> {
+        fn named<'a, T>(components: &'a mut [T], f: impl Fn(&'a mut T) -> (String, &'a mut GeometryParams))
+            -> Vec<(String, &'a mut GeometryParams)> {
+            components.iter_mut().map(f).collect()
+        }
+        match self {
+            SubsystemConfig::Propulsion(config) => named(&mut config.thrusters, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Rcs(config) => named(&mut config.thrusters, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Imu(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::StarTracker(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::StarSensor(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::ReactionWheel(config) => named(&mut config.wheels, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Magnetometer(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Magnetorquer(config) => named(&mut config.torquers, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::SunSensor(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Amr(config) => named(&mut config.sensors, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Radvs(config) => named(&mut config.beams, |c| (c.name.clone(), &mut c.geometry)),
+            SubsystemConfig::Camera(config) => named(&mut config.cameras, |c| (c.name.clone(), &mut c.geometry)),
+        }
+    }
+}
+
+/// When the sensors of a subsystem are sampled and how long it takes for their measurements to
+/// reach FSW
+#[derive(Debug, XmlRead, PartialEq, Clone, Default)]
+#[xml(tag = "Timing")]
+pub struct SensorTimingConfig {
+    /// Samples at every GNC update if not specified
+    #[xml(attr = "sample_rate_hz")]
+    pub sample_rate_hz: Option<f64>,
+    /// Time of the first sample in seconds after the start of the simulation
+    #[xml(default, attr = "phase")]
+    pub phase: f64,
+    /// Transport delay between sampling and delivery to FSW in seconds
+    #[xml(default, attr = "latency")]
+    pub latency: f64,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "RcsSubsystem")]
 pub struct RcsSubsystemConfig {
     #[xml(child = "thruster")]
     pub thrusters: Vec<ThrusterConfig>,
 }
 
-#[derive(Debug, XmlRead, PartialEq)]
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "ImuSubsystem")]
 pub struct ImuSubsystemConfig {
     #[xml(child = "Imu")]
     pub sensors: Vec<ImuConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
 }
 
-#[derive(Debug, XmlRead, PartialEq)]
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "StarTrackerSubsystem")]
 pub struct StarTrackerSubsystemConfig {
     #[xml(child = "StarTracker")]
     pub sensors: Vec<StarTrackerConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
 }
 
-#[derive(Debug, XmlRead, PartialEq)]
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "StarSensorSubsystem")]
 pub struct StarSensorSubsystemConfig {
     #[xml(child = "StarSensor")]
     pub sensors: Vec<StarSensorConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "ReactionWheelSubsystem")]
+pub struct ReactionWheelSubsystemConfig {
+    #[xml(child = "ReactionWheel")]
+    pub wheels: Vec<ReactionWheelConfig>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "MagnetometerSubsystem")]
+pub struct MagnetometerSubsystemConfig {
+    #[xml(child = "Magnetometer")]
+    pub sensors: Vec<MagnetometerConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "MagnetorquerSubsystem")]
+pub struct MagnetorquerSubsystemConfig {
+    #[xml(child = "Magnetorquer")]
+    pub torquers: Vec<MagnetorquerConfig>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "SunSensorSubsystem")]
+pub struct SunSensorSubsystemConfig {
+    #[xml(child = "SunSensor")]
+    pub sensors: Vec<SunSensorConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "AmrSubsystem")]
+pub struct AmrSubsystemConfig {
+    #[xml(child = "Amr")]
+    pub sensors: Vec<AmrConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
+}
+
+/// Radar altimeter and Doppler velocity sensor (RADVS)
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "RadvsSubsystem")]
+pub struct RadvsSubsystemConfig {
+    #[xml(child = "Beam")]
+    pub beams: Vec<RadvsBeamConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
+}
+
+/// Cameras that render grayscale images of the lunar surface. The frame rate is set by the
+/// sample rate of the subsystem.
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "CameraSubsystem")]
+pub struct CameraSubsystemConfig {
+    #[xml(child = "Camera")]
+    pub cameras: Vec<CameraConfig>,
+    #[xml(child = "Timing")]
+    pub timing: Option<SensorTimingConfig>,
 }
 
 #[derive(Debug, XmlRead, PartialEq, Clone)]
@@ -142,7 +838,7 @@ pub struct EngineSubsystemConfig {
 #[xml(tag = "tvc")]
 // Assume single-axis TVC for now
 pub struct TVCConfig {
-    /// Configuration parameters for the servo
+    /// Largest deflection of the servo in degrees
     #[xml(flatten_text = "max_deflection")]
     pub max_deflection: f64,
     /// Axis of rotation of the servo in the component frame
@@ -154,6 +850,8 @@ pub struct TVCConfig {
 #[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "thruster")]
 pub struct ThrusterConfig {
+    #[xml(default, attr = "name")]
+    pub name: String,
     #[xml(child = "geometry")]
     pub geometry: GeometryParams,
     #[xml(flatten_text = "max_thrust")]
@@ -182,6 +880,118 @@ pub struct ImuConfig {
     pub name: String,
     #[xml(child = "geometry")]
     pub geometry: GeometryParams,
+    /// Gyro error model (ideal gyro if not specified)
+    #[xml(child = "gyro")]
+    pub gyro: Option<GyroErrorConfig>,
+    /// Accelerometer error model (ideal accelerometer if not specified)
+    #[xml(child = "accelerometer")]
+    pub accelerometer: Option<AccelerometerErrorConfig>,
+    /// Seed for the error model random number generator so that runs are reproducible. Derived
+    /// from the name of the IMU if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// Error parameters shared by the gyro and the accelerometer of an IMU
+///
+/// Units are given for the gyro (rad) and, in brackets, for the accelerometer (m).
+/// All parameters default to zero (i.e. an ideal sensor).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InertialSensorErrorParams {
+    /// Angle (velocity) random walk in rad/s/sqrt(Hz) [m/s^2/sqrt(Hz)]
+    pub noise_density: f64,
+    /// Standard deviation of the turn-on bias in rad/s [m/s^2]
+    pub bias_std: f64,
+    /// Bias instability modeled as a first-order Gauss-Markov process in rad/s [m/s^2]
+    pub bias_instability: f64,
+    /// Correlation time of the bias instability in seconds
+    pub bias_correlation_time: f64,
+    /// Rate (acceleration) random walk in rad/s^2/sqrt(Hz) [m/s^3/sqrt(Hz)]
+    pub random_walk: f64,
+    /// Standard deviation of the scale-factor error on each axis (unitless)
+    pub scale_factor_std: f64,
+    /// Standard deviation of the axis misalignment in radians
+    pub misalignment_std: f64,
+    /// Measurement range in rad/s [m/s^2]. Measurements are clipped to this value.
+    pub saturation: Option<f64>,
+    /// Value of the least significant bit in rad/s [m/s^2]
+    pub quantization: Option<f64>,
+}
+
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "gyro")]
+pub struct GyroErrorConfig {
+    #[xml(default, flatten_text = "angle_random_walk")]
+    pub angle_random_walk: f64,
+    #[xml(default, flatten_text = "bias_std")]
+    pub bias_std: f64,
+    #[xml(default, flatten_text = "bias_instability")]
+    pub bias_instability: f64,
+    #[xml(default, flatten_text = "bias_correlation_time")]
+    pub bias_correlation_time: f64,
+    #[xml(default, flatten_text = "rate_random_walk")]
+    pub rate_random_walk: f64,
+    #[xml(default, flatten_text = "scale_factor_std")]
+    pub scale_factor_std: f64,
+    #[xml(default, flatten_text = "misalignment_std")]
+    pub misalignment_std: f64,
+    #[xml(flatten_text = "saturation")]
+    pub saturation: Option<f64>,
+    #[xml(flatten_text = "quantization")]
+    pub quantization: Option<f64>,
+}
+impl From<&GyroErrorConfig> for InertialSensorErrorParams {
+    fn from(config: &GyroErrorConfig) -> Self {
+        Self {
+            noise_density: config.angle_random_walk,
+            bias_std: config.bias_std,
+            bias_instability: config.bias_instability,
+            bias_correlation_time: config.bias_correlation_time,
+            random_walk: config.rate_random_walk,
+            scale_factor_std: config.scale_factor_std,
+            misalignment_std: config.misalignment_std,
+            saturation: config.saturation,
+            quantization: config.quantization,
+        }
+    }
+}
+
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "accelerometer")]
+pub struct AccelerometerErrorConfig {
+    #[xml(default, flatten_text = "velocity_random_walk")]
+    pub velocity_random_walk: f64,
+    #[xml(default, flatten_text = "bias_std")]
+    pub bias_std: f64,
+    #[xml(default, flatten_text = "bias_instability")]
+    pub bias_instability: f64,
+    #[xml(default, flatten_text = "bias_correlation_time")]
+    pub bias_correlation_time: f64,
+    #[xml(default, flatten_text = "acceleration_random_walk")]
+    pub acceleration_random_walk: f64,
+    #[xml(default, flatten_text = "scale_factor_std")]
+    pub scale_factor_std: f64,
+    #[xml(default, flatten_text = "misalignment_std")]
+    pub misalignment_std: f64,
+    #[xml(flatten_text = "saturation")]
+    pub saturation: Option<f64>,
+    #[xml(flatten_text = "quantization")]
+    pub quantization: Option<f64>,
+}
+impl From<&AccelerometerErrorConfig> for InertialSensorErrorParams {
+    fn from(config: &AccelerometerErrorConfig) -> Self {
+        Self {
+            noise_density: config.velocity_random_walk,
+            bias_std: config.bias_std,
+            bias_instability: config.bias_instability,
+            bias_correlation_time: config.bias_correlation_time,
+            random_walk: config.acceleration_random_walk,
+            scale_factor_std: config.scale_factor_std,
+            misalignment_std: config.misalignment_std,
+            saturation: config.saturation,
+            quantization: config.quantization,
+        }
+    }
 }
 
 #[derive(Debug, XmlRead, Clone, PartialEq)]
@@ -191,6 +1001,271 @@ pub struct StarTrackerConfig {
     pub name: String,
     #[xml(child = "geometry")]
     pub geometry: GeometryParams,
+    /// Noise (1-sigma) about the axes perpendicular to the boresight (Z-axis) in radians
+    #[xml(default, flatten_text = "cross_boresight_noise")]
+    pub cross_boresight_noise: f64,
+    /// Noise (1-sigma) about the boresight in radians
+    #[xml(default, flatten_text = "about_boresight_noise")]
+    pub about_boresight_noise: f64,
+    /// Minimum angle between the boresight and the limb of each body for a valid solution
+    #[xml(default, flatten_text = "sun_exclusion_deg")]
+    pub sun_exclusion_deg: f64,
+    #[xml(default, flatten_text = "moon_exclusion_deg")]
+    pub moon_exclusion_deg: f64,
+    #[xml(default, flatten_text = "earth_exclusion_deg")]
+    pub earth_exclusion_deg: f64,
+    /// Angular rate above which the sensor cannot track stars
+    #[xml(flatten_text = "max_rate_deg_s")]
+    pub max_rate_deg_s: Option<f64>,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// A single reaction wheel. The spin axis is the Z-axis of the component frame.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "ReactionWheel")]
+pub struct ReactionWheelConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Moment of inertia of the rotor about its spin axis in kg-m^2
+    #[xml(flatten_text = "inertia")]
+    pub inertia: f64,
+    /// Maximum wheel speed in rad/s
+    #[xml(flatten_text = "max_speed")]
+    pub max_speed: f64,
+    /// Maximum motor torque in N-m
+    #[xml(flatten_text = "max_torque")]
+    pub max_torque: f64,
+    /// Viscous friction coefficient in N-m/(rad/s)
+    #[xml(default, flatten_text = "viscous_friction")]
+    pub viscous_friction: f64,
+    /// Coulomb (constant) friction torque in N-m
+    #[xml(default, flatten_text = "coulomb_friction")]
+    pub coulomb_friction: f64,
+    /// Wheel speed at the start of the simulation in rad/s
+    #[xml(default, flatten_text = "initial_speed")]
+    pub initial_speed: f64,
+}
+
+/// A three-axis magnetometer
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "Magnetometer")]
+pub struct MagnetometerConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Standard deviation of the measurement noise on each axis in Tesla
+    #[xml(default, flatten_text = "noise_std")]
+    pub noise_std: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// A magnetorquer rod. The dipole is generated along the Z-axis of the component frame.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "Magnetorquer")]
+pub struct MagnetorquerConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Maximum magnetic dipole moment in A-m^2
+    #[xml(flatten_text = "max_dipole")]
+    pub max_dipole: f64,
+}
+
+/// Type of sun sensor
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SunSensorType {
+    /// Photocell whose output current follows the cosine of the angle to the Sun
+    Coarse,
+    /// Two-axis sensor that measures the direction to the Sun within its field of view
+    Fine,
+}
+impl FromStr for SunSensorType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Coarse" => Ok(Self::Coarse),
+            "Fine" => Ok(Self::Fine),
+            _ => Err("Sun sensor type not supported"),
+        }
+    }
+}
+
+/// A sun sensor. The boresight is the Z-axis of the component frame.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "SunSensor")]
+pub struct SunSensorConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(attr="type")]
+    pub sensor_type: SunSensorType,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Field of view of the sensor in degrees (half-angle)
+    #[xml(flatten_text = "fov_deg")]
+    pub fov_deg: f64,
+    /// Noise (1-sigma) on the normalized output current (coarse) or on the measured angles in
+    /// radians (fine)
+    #[xml(default, flatten_text = "noise_std")]
+    pub noise_std: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// Altitude marking radar. The beam is along the Z-axis of the component frame.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "Amr")]
+pub struct AmrConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Slant range in metres at which the mark is generated
+    #[xml(flatten_text = "mark_range")]
+    pub mark_range: f64,
+    /// Maximum slant range in metres at which the surface is detected
+    #[xml(flatten_text = "max_range")]
+    pub max_range: f64,
+    /// Noise (1-sigma) on the measured slant range in metres
+    #[xml(default, flatten_text = "range_noise_std")]
+    pub range_noise_std: f64,
+    /// Delay between crossing the mark range and generating the mark in seconds
+    #[xml(default, flatten_text = "mark_delay")]
+    pub mark_delay: f64,
+    /// Random error (1-sigma) on the time of the mark in seconds
+    #[xml(default, flatten_text = "mark_timing_std")]
+    pub mark_timing_std: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// Reflectance model used to shade the lunar surface in camera images
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ShadingModel {
+    /// Diffuse surface with a constant albedo
+    #[default]
+    Lambert,
+    /// Hapke model with an opposition surge (brightens towards zero phase angle)
+    Hapke,
+}
+impl FromStr for ShadingModel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Lambert" => Ok(Self::Lambert),
+            "Hapke" => Ok(Self::Hapke),
+            _ => Err("Shading model not supported"),
+        }
+    }
+}
+
+/// Pinhole camera looking along the Z-axis of the component frame. Image columns go along the
+/// X-axis and rows along the Y-axis.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "Camera")]
+pub struct CameraConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(default, attr="shading")]
+    pub shading: ShadingModel,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Horizontal field of view in degrees
+    #[xml(flatten_text = "fov_deg")]
+    pub fov_deg: f64,
+    /// Image size in pixels
+    #[xml(flatten_text = "width")]
+    pub width: usize,
+    #[xml(flatten_text = "height")]
+    pub height: usize,
+    /// Normal albedo (Lambert) or single-scattering albedo (Hapke) of the surface
+    #[xml(flatten_text = "albedo")]
+    pub albedo: Option<f64>,
+    /// Reflectance (I/F) that saturates the pixels
+    #[xml(flatten_text = "saturation")]
+    pub saturation: Option<f64>,
+    /// Read noise (1-sigma) in pixel intensity levels (0-255)
+    #[xml(default, flatten_text = "noise_std")]
+    pub noise_std: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+    /// Directory where the images are saved as PGM files. Not saved if not specified.
+    #[xml(flatten_text = "output_dir")]
+    pub output_dir: Option<String>,
+}
+impl CameraConfig {
+    /// Focal length in pixels
+    pub fn focal_length_px(&self) -> f64 {
+        0.5 * self.width as f64 / (0.5 * self.fov_deg.to_radians()).tan()
+    }
+}
+
+/// Type of RADVS beam
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RadvsBeamType {
+    /// Measures the velocity along the beam
+    Doppler,
+    /// Measures the slant range along the beam
+    Range,
+}
+impl FromStr for RadvsBeamType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Doppler" => Ok(Self::Doppler),
+            "Range" => Ok(Self::Range),
+            _ => Err("RADVS beam type not supported"),
+        }
+    }
+}
+
+/// A single RADVS beam. The beam is along the Z-axis of the component frame.
+#[derive(Debug, XmlRead, Clone, PartialEq)]
+#[xml(tag = "Beam")]
+pub struct RadvsBeamConfig {
+    #[xml(attr="name")]
+    pub name: String,
+    #[xml(attr="type")]
+    pub beam_type: RadvsBeamType,
+    #[xml(child = "geometry")]
+    pub geometry: GeometryParams,
+    /// Slant range in metres below which the beam acquires lock
+    #[xml(flatten_text = "lock_range")]
+    pub lock_range: f64,
+    /// Slant range in metres above which the beam loses lock (defaults to the lock range)
+    #[xml(flatten_text = "unlock_range")]
+    pub unlock_range: Option<f64>,
+    /// Time in seconds that the return has to be present before the beam locks
+    #[xml(default, flatten_text = "lock_delay")]
+    pub lock_delay: f64,
+    /// Constant part of the noise (1-sigma) in metres (range) or m/s (Doppler)
+    #[xml(default, flatten_text = "noise_std")]
+    pub noise_std: f64,
+    /// Part of the noise (1-sigma) that grows with the slant range, per metre of slant range
+    #[xml(default, flatten_text = "noise_per_range")]
+    pub noise_per_range: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
 }
 
 /// Structure defining geometry of any spacecraft component
@@ -223,7 +1298,7 @@ impl GeometryConfig {
 }
 
 
-#[derive(Debug, XmlRead, PartialEq)]
+#[derive(Debug, XmlRead, PartialEq, Clone)]
 #[xml(tag = "StarSensor")]
 pub struct StarSensorConfig {
     #[xml(attr="name")]
@@ -239,4 +1314,149 @@ pub struct StarSensorConfig {
     /// Field of view of the sensor in degrees (half-angle)
     #[xml(flatten_text = "fov_deg")]
     pub fov_deg: f64,
+    /// Dimmest star (relative to Canopus) that the sensor will lock on to
+    #[xml(flatten_text = "min_intensity")]
+    pub min_intensity: Option<f64>,
+    /// Brightest star (relative to Canopus) that the sensor will lock on to
+    #[xml(flatten_text = "max_intensity")]
+    pub max_intensity: Option<f64>,
+    /// Noise (1-sigma) on the roll and cone errors in radians
+    #[xml(default, flatten_text = "noise_std")]
+    pub noise_std: f64,
+    /// Seed for the noise generator so that runs are reproducible. Derived from the name of the
+    /// sensor if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// Type of fault that can be injected into a sensor or actuator. Sensor faults that overlap all
+/// apply, while an actuator follows the last injected of its active faults (apart from `TvcStuck`,
+/// which acts on the gimbal alongside the others).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FaultType {
+    /// Sensor output stuck at the fault value (or at its first output after the fault if no value
+    /// is given)
+    StuckAt,
+    /// Sensor stops delivering measurements
+    Dropout,
+    /// Fault value added to the sensor outputs
+    BiasStep,
+    /// Additional white noise on the sensor outputs with the fault value as the standard deviation
+    NoiseIncrease,
+    /// Actuator produces no thrust, torque or dipole
+    FailedOff,
+    /// Actuator produces its maximum thrust, torque or dipole regardless of the commands
+    FailedOn,
+    /// Gimbal stuck at the fault value in radians (or where it was when the fault occurred)
+    TvcStuck,
+    /// Actuator produces only `1 - value` of the commanded thrust, torque or dipole
+    ThrustLoss,
+}
+impl FromStr for FaultType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "StuckAt" => Ok(Self::StuckAt),
+            "Dropout" => Ok(Self::Dropout),
+            "BiasStep" => Ok(Self::BiasStep),
+            "NoiseIncrease" => Ok(Self::NoiseIncrease),
+            "FailedOff" => Ok(Self::FailedOff),
+            "FailedOn" => Ok(Self::FailedOn),
+            "TvcStuck" => Ok(Self::TvcStuck),
+            "ThrustLoss" => Ok(Self::ThrustLoss),
+            _ => Err("Fault type not supported"),
+        }
+    }
+}
+
+/// A fault injected into a named sensor or actuator of a subsystem
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "Fault")]
+pub struct FaultConfig {
+    /// Name of the subsystem (e.g. "Imu" or "Rcs")
+    #[xml(attr = "subsystem")]
+    pub subsystem: String,
+    /// Name of the sensor or actuator within the subsystem
+    #[xml(attr = "target")]
+    pub target: String,
+    #[xml(attr = "type")]
+    pub fault_type: FaultType,
+    /// Simulation time at which the fault is injected in seconds
+    #[xml(default, attr = "start")]
+    pub start: f64,
+    /// Simulation time at which the fault is cleared. Permanent if not specified.
+    #[xml(attr = "end")]
+    pub end: Option<f64>,
+    #[xml(attr = "value")]
+    pub value: Option<f64>,
+    /// Seed for the noise generator (only used by `NoiseIncrease`). Derived from the subsystem and
+    /// target names if not given.
+    #[xml(attr = "seed")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, XmlRead, PartialEq, Clone, Default)]
+#[xml(tag = "FaultSchedule")]
+pub struct FaultScheduleConfig {
+    #[xml(child = "Fault")]
+    pub faults: Vec<FaultConfig>,
+}
+
+/// Earth ground station that tracks the spacecraft
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "GroundStation")]
+pub struct GroundStationConfig {
+    #[xml(attr = "name")]
+    pub name: String,
+    /// Position of the antenna in the ITRF (Earth-fixed) frame in metres
+    #[xml(flatten_text = "position_itrf")]
+    pub position_itrf: Vector3,
+    /// Minimum elevation above the local horizon at which the spacecraft can be tracked
+    #[xml(default, flatten_text = "elevation_mask_deg")]
+    pub elevation_mask_deg: f64,
+    /// 1-sigma noise on the (one-way equivalent) two-way range in metres
+    #[xml(default, flatten_text = "range_noise_std")]
+    pub range_noise_std: f64,
+    /// 1-sigma noise on the two-way range rate in m/s
+    #[xml(default, flatten_text = "range_rate_noise_std")]
+    pub range_rate_noise_std: f64,
+    /// 1-sigma noise on the azimuth and elevation in degrees
+    #[xml(default, flatten_text = "angle_noise_std")]
+    pub angle_noise_std: f64,
+    /// Seed for the noise generator. Derived from the name of the station if not given.
+    #[xml(flatten_text = "seed")]
+    pub seed: Option<u64>,
+}
+
+/// Ground-station tracking used for orbit determination
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "Tracking")]
+pub struct TrackingConfig {
+    /// Time between measurements from each station in seconds
+    #[xml(attr = "interval")]
+    pub interval: f64,
+    /// Path of the CCSDS Tracking Data Message written when the simulation finishes
+    #[xml(attr = "output")]
+    pub output: Option<String>,
+    #[xml(child = "GroundStation")]
+    pub stations: Vec<GroundStationConfig>,
+    #[xml(child = "StateVectorUplink")]
+    pub uplink: Option<StateVectorUplinkConfig>,
+}
+
+/// Orbit determination solutions uplinked to the spacecraft as state vectors
+#[derive(Debug, XmlRead, PartialEq, Clone)]
+#[xml(tag = "StateVectorUplink")]
+pub struct StateVectorUplinkConfig {
+    /// Time between uplinks in seconds
+    #[xml(attr = "interval")]
+    pub interval: f64,
+    /// 1-sigma error of the uplinked position in metres and velocity in m/s
+    #[xml(default, flatten_text = "position_noise_std")]
+    pub position_noise_std: f64,
+    #[xml(default, flatten_text = "velocity_noise_std")]
+    pub velocity_noise_std: f64,
+    #[xml(default, flatten_text = "seed")]
+    pub seed: u64,
 }